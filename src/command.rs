@@ -1,15 +1,25 @@
+pub mod analyze;
 pub mod args;
 pub mod auto_encode;
+pub mod bd_rate;
 pub mod crf_search;
 pub mod encode;
+pub mod info;
 pub mod print_completions;
+pub mod print_vmaf_scale;
+pub mod rd_curve;
 pub mod sample_encode;
 pub mod vmaf;
 
+pub use analyze::analyze;
 pub use auto_encode::auto_encode;
+pub use bd_rate::bd_rate;
 pub use crf_search::crf_search;
 pub use encode::encode;
+pub use info::info;
 pub use print_completions::print_completions;
+pub use print_vmaf_scale::print_vmaf_scale;
+pub use rd_curve::rd_curve;
 pub use sample_encode::sample_encode;
 pub use vmaf::vmaf;
 
@@ -0,0 +1,172 @@
+use crate::{
+    command::{args, crf_search::complexity, sample_encode, PROGRESS_CHARS},
+    ffprobe, temporary,
+};
+use anyhow::ensure;
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{fmt, time::Duration};
+
+/// Quickly classify input complexity as low, medium or high without a crf search.
+///
+/// Samples are fast encoded at a fixed crf & preset. The complexity score (0-100)
+/// is derived from the mean encoded bits per pixel & the variation between samples.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub args: args::Encode,
+
+    #[clap(flatten)]
+    pub sample: args::Sample,
+}
+
+pub async fn analyze(
+    Args {
+        mut args,
+        mut sample,
+    }: Args,
+) -> anyhow::Result<()> {
+    let bar = ProgressBar::new(1).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
+            .progress_chars(PROGRESS_CHARS)
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    args.prepare().await?;
+    let probe = ffprobe::probe(&args.input);
+    probe.ensure_video(&args.input)?;
+    ensure!(!probe.is_image, "analyze requires a video input");
+    sample.set_extension_from_input(&args.input, &probe);
+    sample.set_auto_sample_duration(&args, &probe);
+    sample.set_avoid_ranges(&args.input)?;
+    let duration = probe.duration.clone()?;
+    let fps = probe.fps.clone()?;
+
+    let (samples, sample_duration, _) = sample_encode::sample_plan(&sample, duration, fps, false);
+    bar.set_length(samples);
+    bar.set_message("sampling, ");
+    let mut bpps = Vec::with_capacity(samples as _);
+    for sample_idx in 0..samples {
+        bar.set_prefix(format!("Sample {}/{samples}", sample_idx + 1));
+        let planned = sample_encode::plan_sample(
+            sample_idx,
+            samples,
+            sample_duration,
+            duration,
+            fps,
+            &sample.avoid_ranges,
+            sample.sample_seed,
+        );
+        let bpp = complexity::sample_bpp(
+            &args,
+            &sample,
+            &probe,
+            planned.start,
+            planned.frames,
+            planned.floor_to_sec,
+        )
+        .await?;
+        bpps.push(bpp);
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+    temporary::clean_all().await;
+
+    println!("{}", Complexity::from_sample_bpps(&bpps));
+    Ok(())
+}
+
+/// Input complexity derived from fast fixed-crf sample encodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Complexity {
+    /// Mean encoded bits per pixel.
+    mean_bpp: f64,
+    /// Coefficient of variation of the sample bits per pixel.
+    variation: f64,
+    /// 0-100, higher is more complex.
+    score: f64,
+}
+
+impl Complexity {
+    fn from_sample_bpps(bpps: &[f64]) -> Self {
+        let n = bpps.len().max(1) as f64;
+        let mean_bpp = bpps.iter().sum::<f64>() / n;
+        let variance = bpps.iter().map(|b| (b - mean_bpp).powi(2)).sum::<f64>() / n;
+        let variation = match mean_bpp > 0.0 {
+            true => variance.sqrt() / mean_bpp,
+            false => 0.0,
+        };
+        // bitrate dominates, highly varying samples indicate some harder scenes
+        let score = 100.0 * (0.8 * complexity::bpp_complexity(mean_bpp) + 0.2 * variation.min(1.0));
+        Self {
+            mean_bpp,
+            variation,
+            score,
+        }
+    }
+
+    fn class(&self) -> ComplexityClass {
+        match self.score {
+            s if s < 100.0 / 3.0 => ComplexityClass::Low,
+            s if s < 200.0 / 3.0 => ComplexityClass::Medium,
+            _ => ComplexityClass::High,
+        }
+    }
+}
+
+impl fmt::Display for Complexity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} complexity (score {:.0}), {:.4} bpp, sample variation {:.0}%",
+            self.class(),
+            self.score,
+            self.mean_bpp,
+            self.variation * 100.0,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComplexityClass {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for ComplexityClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        })
+    }
+}
+
+#[test]
+fn classify_complexity() {
+    let class = |bpps: &[f64]| Complexity::from_sample_bpps(bpps).class();
+
+    // static, cheap to encode
+    assert_eq!(class(&[0.005, 0.006, 0.005]), ComplexityClass::Low);
+    assert_eq!(class(&[0.04, 0.045, 0.05]), ComplexityClass::Medium);
+    // grainy / high motion
+    assert_eq!(class(&[0.3, 0.25, 0.35]), ComplexityClass::High);
+
+    // same mean, variation between samples raises the score
+    let steady = Complexity::from_sample_bpps(&[0.05, 0.05]);
+    let varied = Complexity::from_sample_bpps(&[0.02, 0.08]);
+    assert!((steady.mean_bpp - varied.mean_bpp).abs() < 1e-9);
+    assert_eq!(steady.variation, 0.0);
+    assert!(varied.score > steady.score, "{varied:?} {steady:?}");
+
+    assert_eq!(
+        Complexity::from_sample_bpps(&[0.005, 0.005]).to_string(),
+        "low complexity (score 0), 0.0050 bpp, sample variation 0%"
+    );
+    assert_eq!(class(&[]), ComplexityClass::Low);
+}
@@ -1,13 +1,23 @@
 //! Shared argument logic.
 mod encode;
+mod gate;
+mod growing;
 mod vmaf;
 
 pub use encode::*;
+pub use gate::*;
 pub use vmaf::*;
 
-use crate::{command::encode::default_output_ext, ffprobe::Ffprobe};
+use crate::{
+    command::encode::default_output_ext,
+    ffprobe::{self, Chapter, Ffprobe},
+};
+use anyhow::Context;
 use clap::{Parser, ValueHint};
+use log::info;
 use std::{
+    fmt,
+    ops::Range,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
@@ -41,6 +51,193 @@ pub struct EncodeToOutput {
     /// The output will be a single video stream.
     #[arg(long)]
     pub video_only: bool,
+
+    /// Only keep audio streams with these comma separated language tags,
+    /// dropping others. E.g. `--audio-lang eng,jpn`.
+    ///
+    /// Audio streams without a language tag are dropped unless `und` is included.
+    #[arg(long, value_delimiter = ',', conflicts_with = "video_only")]
+    pub audio_lang: Vec<String>,
+
+    /// Closed captions (CEA-608/708) embedded in the input video stream handling.
+    /// `copy` passes them through into the output video stream, where the encoder
+    /// supports it (e.g. libx264, libx265), otherwise a warning is printed.
+    ///
+    /// By default captions are not explicitly handled and may be lost.
+    #[arg(long, value_enum)]
+    pub captions: Option<Captions>,
+
+    /// HDR10+ & Dolby Vision dynamic metadata in the input video stream handling.
+    /// `copy` passes it through into the output, where the encoder & container
+    /// support it (e.g. libsvtav1 or libx265 to mkv), otherwise a warning is printed.
+    ///
+    /// By default dynamic metadata is not explicitly handled and may be lost.
+    #[arg(long, value_enum)]
+    pub dynamic_hdr: Option<DynamicHdr>,
+
+    /// Write a `<output>.abav1.json` sidecar file next to the output containing
+    /// the encoder args, crf, VMAF args & score (if searched) and ab-av1 version.
+    #[arg(long)]
+    pub sidecar: bool,
+
+    /// Show ffmpeg's own `-stats` output during the encode instead of the progress bar.
+    #[arg(long)]
+    pub raw_stats: bool,
+
+    /// Force keyframes at these times, passed to ffmpeg `-force_key_frames`.
+    /// A comma separated list of times, `expr:<expression>` or `chapters[delta]`.
+    ///
+    /// E.g. `--force-keyframes 0,10,20` or `--force-keyframes "expr:gte(t,n_forced*10)"`.
+    #[arg(long)]
+    pub force_keyframes: Option<ForceKeyframes>,
+}
+
+/// Validated ffmpeg `-force_key_frames` value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForceKeyframes(Arc<str>);
+
+impl ForceKeyframes {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for ForceKeyframes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if let Some(expr) = s.strip_prefix("expr:") {
+            anyhow::ensure!(!expr.trim().is_empty(), "empty force keyframes expression");
+            let mut depth = 0_i32;
+            for c in expr.chars() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                anyhow::ensure!(depth >= 0, "unbalanced `)` in `{expr}`");
+            }
+            anyhow::ensure!(depth == 0, "unclosed `(` in `{expr}`");
+        } else if let Some(delta) = s.strip_prefix("chapters") {
+            anyhow::ensure!(
+                delta.is_empty() || delta.parse::<f64>().is_ok(),
+                "invalid chapters delta `{delta}`"
+            );
+        } else {
+            for time in s.split(',').map(str::trim) {
+                anyhow::ensure!(
+                    is_timestamp(time),
+                    "invalid force keyframes time `{time}`, expected e.g. `10.5` or `00:01:10`"
+                );
+            }
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+/// Returns true for ffmpeg time durations, seconds or `[hh:]mm:ss[.m]`.
+fn is_timestamp(time: &str) -> bool {
+    let parts: Vec<_> = time.split(':').collect();
+    parts.len() <= 3
+        && parts
+            .iter()
+            .enumerate()
+            .all(|(idx, part)| match idx == parts.len() - 1 {
+                true => part.parse::<f64>().is_ok_and(|s| s >= 0.0),
+                false => !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()),
+            })
+}
+
+/// `--sample-duration` value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleDuration {
+    /// Resolve from the GOP duration.
+    Auto,
+    Fixed(Duration),
+}
+
+impl fmt::Display for SampleDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::Fixed(d) => write!(f, "{}", humantime::format_duration(*d)),
+        }
+    }
+}
+
+impl std::str::FromStr for SampleDuration {
+    type Err = humantime::DurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            s => humantime::parse_duration(s).map(Self::Fixed),
+        }
+    }
+}
+
+/// Smallest whole number of `gop` durations covering at least the default sample duration.
+fn auto_sample_duration(gop: Option<Duration>) -> Duration {
+    match gop.filter(|g| !g.is_zero()) {
+        Some(gop) => {
+            let gops = (DEFAULT_SAMPLE_DURATION.as_secs_f64() / gop.as_secs_f64()).ceil();
+            gop.mul_f64(gops.max(1.0))
+        }
+        None => DEFAULT_SAMPLE_DURATION,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Captions {
+    /// Pass through embedded closed captions.
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DynamicHdr {
+    /// Pass through HDR10+ & Dolby Vision dynamic metadata.
+    Copy,
+}
+
+/// Sample extraction seek behaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SeekMode {
+    /// Seek before the input to a keyframe, stream copy.
+    Fast,
+    /// Seek frame accurately, lossless re-encode.
+    Accurate,
+}
+
+/// Lossless codec used to extract re-encoded samples & intermediates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExtractCodec {
+    /// Lossless ffv1, compact but slower to write.
+    Ffv1,
+    /// Uncompressed rawvideo, fastest to write but uses the most disk space.
+    Raw,
+    /// Lossless libx264 `-qp 0 -preset ultrafast`, fast & smaller than raw.
+    H264Lossless,
+}
+
+impl ExtractCodec {
+    /// Ffmpeg output video codec args.
+    pub fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Ffv1 => &["-c:v", "ffv1"],
+            Self::Raw => &["-c:v", "rawvideo"],
+            Self::H264Lossless => &["-c:v", "libx264", "-qp", "0", "-preset", "ultrafast"],
+        }
+    }
+
+    /// Extracted file name suffix, distinguishing non-default codecs.
+    pub fn file_suffix(self) -> &'static str {
+        match self {
+            Self::Ffv1 => "",
+            Self::Raw => ".raw",
+            Self::H264Lossless => ".h264",
+        }
+    }
 }
 
 /// Sampling arguments.
@@ -64,8 +261,16 @@ pub struct Sample {
     pub min_samples: Option<u64>,
 
     /// Duration of each sample.
-    #[arg(long, default_value = "20s", value_parser = humantime::parse_duration)]
-    pub sample_duration: Duration,
+    ///
+    /// `auto` uses the smallest whole number of GOPs (keyframe intervals) covering
+    /// at least 20s, so samples reflect steady-state encoding.
+    #[arg(long, default_value_t = SampleDuration::Fixed(DEFAULT_SAMPLE_DURATION))]
+    pub sample_duration: SampleDuration,
+
+    /// Minimum time between sample start times, e.g. "1m". If the input is too short
+    /// to space samples this far apart fewer samples are used.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub min_sample_spacing: Option<Duration>,
 
     /// Keep temporary files after exiting.
     #[arg(long)]
@@ -76,12 +281,88 @@ pub struct Sample {
     #[arg(long, env = "AB_AV1_TEMP_DIR", value_hint = ValueHint::DirPath)]
     pub temp_dir: Option<PathBuf>,
 
+    /// Directory to cache encoded sample files in, keyed by input, sample,
+    /// encoder args & crf. Cached samples are reused by later runs, e.g. to
+    /// re-measure VMAF with different vmaf args without re-encoding.
+    #[arg(long, env = "AB_AV1_SAMPLE_CACHE_DIR", value_hint = ValueHint::DirPath)]
+    pub sample_cache_dir: Option<PathBuf>,
+
+    /// Sample extraction seeking.
+    /// `fast` stream copies from the nearest keyframe before each sample start.
+    /// `accurate` decodes from the exact sample start into a lossless sample, slower
+    /// & using more disk space.
+    #[arg(long, value_enum, default_value_t = SeekMode::Fast)]
+    pub seek_mode: SeekMode,
+
+    /// Lossless codec used to extract samples with `--seek-mode accurate` & to create
+    /// a `--lossless-intermediate`. Trades extraction speed against disk use, all
+    /// choices preserve quality exactly for VMAF.
+    #[arg(long, value_enum, default_value_t = ExtractCodec::Ffv1)]
+    pub sample_extract_codec: ExtractCodec,
+
+    /// Lossless intermediate of the input to cut samples from & so use as the VMAF
+    /// reference, instead of the input. E.g. so a lossy input's artifacts are
+    /// not measured relative to a re-decode of the input.
+    ///
+    /// Must have identical frames & timing to the input's main video stream.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub intermediate: Option<PathBuf>,
+
+    /// Create a lossless ffv1 --intermediate of the input's main video stream first.
+    #[arg(long, conflicts_with = "intermediate")]
+    pub lossless_intermediate: bool,
+
+    /// Run a throwaway short encode before the first timed sample encode, so
+    /// cold start costs (e.g. disk cache, GPU init) don't skew predicted encode
+    /// times. Runs once per process.
+    #[arg(long)]
+    pub warmup: bool,
+
+    /// Print each sample's start, VMAF & encoded size once sampled, worst VMAF first,
+    /// to show which samples drove the result.
+    #[arg(long)]
+    pub sample_report: bool,
+
+    /// Avoid placing samples in input chapters with a title containing any of these,
+    /// ignoring case. E.g. `--avoid-chapters intro,credits` to sample only
+    /// representative episode content.
+    #[arg(long, value_delimiter = ',')]
+    pub avoid_chapters: Vec<String>,
+
+    /// Randomly jitter each sample's position within its even spacing, seeded by this
+    /// value so runs with the same seed use identical samples.
+    /// E.g. to avoid samples aliasing with regular scene structure.
+    #[arg(long)]
+    pub sample_seed: Option<u64>,
+
+    /// Sorted non-overlapping input ranges to avoid placing samples in.
+    #[arg(skip)]
+    pub avoid_ranges: Vec<Range<Duration>>,
+
     /// Extension preference for encoded samples (ffmpeg encoder only).
     #[arg(skip)]
     pub extension: Option<Arc<str>>,
 }
 
+const DEFAULT_SAMPLE_DURATION: Duration = Duration::from_secs(20);
+
 impl Sample {
+    /// Returns the duration of each sample. Unresolved `auto` uses the default 20s.
+    pub fn sample_duration(&self) -> Duration {
+        match self.sample_duration {
+            SampleDuration::Fixed(d) => d,
+            SampleDuration::Auto => DEFAULT_SAMPLE_DURATION,
+        }
+    }
+
+    /// Resolve `--sample-duration auto` using the encode GOP duration, if known.
+    pub fn set_auto_sample_duration(&mut self, args: &Encode, probe: &Ffprobe) {
+        if self.sample_duration == SampleDuration::Auto {
+            let gop = args.gop_duration(probe).ok().flatten();
+            self.sample_duration = SampleDuration::Fixed(auto_sample_duration(gop));
+        }
+    }
+
     /// Calculate the desired sample count using `samples` or `sample_every` & `min_samples`.
     pub fn sample_count(&self, input_duration: Duration) -> u64 {
         match self.samples {
@@ -95,6 +376,19 @@ impl Sample {
         .max(1)
     }
 
+    /// Resolve `--avoid-chapters` into `avoid_ranges` using the input's chapters.
+    pub fn set_avoid_ranges(&mut self, input: &Path) -> anyhow::Result<()> {
+        if self.avoid_chapters.is_empty() {
+            return Ok(());
+        }
+        let chapters = ffprobe::probe_chapters(input).context("--avoid-chapters")?;
+        self.avoid_ranges = chapter_ranges(&chapters, &self.avoid_chapters);
+        if self.avoid_ranges.is_empty() {
+            info!("--avoid-chapters: no matching chapters");
+        }
+        Ok(())
+    }
+
     pub fn set_extension_from_input(&mut self, input: &Path, probe: &Ffprobe) {
         self.extension = Some(default_output_ext(input, probe.is_image).into());
     }
@@ -103,3 +397,119 @@ impl Sample {
         self.extension = output.extension().and_then(|e| e.to_str().map(Into::into));
     }
 }
+
+/// Returns the sorted & merged ranges of `chapters` with a title containing any of
+/// `patterns`, ignoring case.
+fn chapter_ranges(chapters: &[Chapter], patterns: &[String]) -> Vec<Range<Duration>> {
+    let patterns: Vec<_> = patterns.iter().map(|p| p.trim().to_lowercase()).collect();
+    let mut ranges: Vec<_> = chapters
+        .iter()
+        .filter(|c| {
+            let title = c.title.as_deref().unwrap_or_default().to_lowercase();
+            patterns.iter().any(|p| !p.is_empty() && title.contains(p))
+        })
+        .map(|c| c.start..c.end)
+        .filter(|r| !r.is_empty())
+        .collect();
+    ranges.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<Range<Duration>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Parse a byte size, e.g. "500MB", "1.5GiB" or "1000000".
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: f64 = n.parse().with_context(|| format!("invalid size `{s}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000_u64.pow(2),
+        "g" | "gb" => 1000_u64.pow(3),
+        "kib" => 1024,
+        "mib" => 1024_u64.pow(2),
+        "gib" => 1024_u64.pow(3),
+        _ => anyhow::bail!("invalid size unit `{unit}`, e.g. use `500MB` or `1.5GiB`"),
+    };
+    Ok((n * multiplier as f64).round() as u64)
+}
+
+#[test]
+fn avoided_chapter_ranges() {
+    let secs = Duration::from_secs;
+    let chapter = |start, end, title: &str| Chapter {
+        start: secs(start),
+        end: secs(end),
+        title: Some(title.into()),
+    };
+    let chapters = [
+        chapter(0, 60, "Cold open"),
+        chapter(60, 90, "Intro"),
+        chapter(90, 1200, "Part 1"),
+        chapter(1200, 1300, "Opening Credits Reprise"),
+        chapter(1300, 1380, "End Credits"),
+    ];
+    assert_eq!(
+        chapter_ranges(&chapters, &["intro".into(), "credits".into()]),
+        [secs(60)..secs(90), secs(1200)..secs(1380)]
+    );
+    assert_eq!(chapter_ranges(&chapters, &["recap".into()]), []);
+}
+
+#[test]
+fn parse_sizes() {
+    assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+    assert_eq!(parse_size("1.5GiB").unwrap(), 1_610_612_736);
+    assert_eq!(parse_size("1234").unwrap(), 1234);
+    assert!(parse_size("5 parsecs").is_err());
+}
+
+#[test]
+fn auto_sample_duration_covers_gop() {
+    let gop = |keyint: u32, fps: f64| Some(Duration::from_secs_f64(f64::from(keyint) / fps));
+
+    for (keyint, fps) in [
+        (240, 24.0),
+        (300, 24.0),
+        (250, 25.0),
+        (600, 60.0),
+        (1800, 30.0),
+    ] {
+        let gop = gop(keyint, fps).unwrap();
+        let auto = auto_sample_duration(Some(gop));
+        assert!(auto >= gop, "{auto:?} < gop {gop:?}");
+        assert!(auto >= DEFAULT_SAMPLE_DURATION, "{auto:?}");
+        // whole gops
+        let gops = auto.as_secs_f64() / gop.as_secs_f64();
+        assert!((gops - gops.round()).abs() < 1e-6, "{gops}");
+    }
+    assert_eq!(
+        auto_sample_duration(gop(300, 24.0)),
+        Duration::from_secs(25)
+    );
+    assert_eq!(
+        auto_sample_duration(gop(240, 24.0)),
+        Duration::from_secs(20)
+    );
+    assert_eq!(
+        auto_sample_duration(gop(1800, 30.0)),
+        Duration::from_secs(60)
+    );
+    assert_eq!(auto_sample_duration(None), DEFAULT_SAMPLE_DURATION);
+
+    assert_eq!("auto".parse(), Ok(SampleDuration::Auto));
+    assert_eq!(
+        "15s".parse(),
+        Ok(SampleDuration::Fixed(Duration::from_secs(15)))
+    );
+}
@@ -1,9 +1,14 @@
+mod raw;
+
+pub use raw::RawEncoderArgs;
+
 use crate::{
-    ffmpeg::FfmpegEncodeArgs,
+    console_ext::style,
+    ffmpeg::{self, FfmpegEncodeArgs},
     ffprobe::{Ffprobe, ProbeError},
     float::TerseF32,
 };
-use anyhow::ensure;
+use anyhow::{bail, ensure, Context};
 use clap::{Parser, ValueHint};
 use std::{
     collections::HashMap,
@@ -37,6 +42,13 @@ pub struct Encode {
     #[arg(long)]
     pub vfilter: Option<String>,
 
+    /// Output frame rate conversion, e.g. `--fps 30` or `--fps 30000/1001`.
+    ///
+    /// Appends an `fps` filter to the --vfilter, so also to the VMAF reference
+    /// including any --reference-vfilter, for a like-for-like comparison.
+    #[arg(long, value_parser = parse_fps_arg)]
+    pub fps: Option<Arc<str>>,
+
     /// Pixel format. svt-av1 default yuv420p10le.
     #[arg(value_enum, long)]
     pub pix_format: Option<PixelFormat>,
@@ -51,6 +63,20 @@ pub struct Encode {
     #[arg(long)]
     pub preset: Option<Preset>,
 
+    /// Encoder agnostic speed (0-10), instead of --preset.
+    /// Higher speeds means faster encodes, but with a quality tradeoff.
+    ///
+    /// Mapped to the encoder's native speed arg:
+    /// * libsvtav1: `-preset` speed×1.3, e.g. 6 -> 8.
+    /// * libaom-av1: `-cpu-used` speed×0.8, e.g. 6 -> 5.
+    /// * libvpx-vp9: `-cpu-used` speed÷2, e.g. 6 -> 3.
+    /// * librav1e: `-speed` speed, e.g. 6 -> 6.
+    /// * libx264, libx265: `-preset` placebo (0) to ultrafast (10), e.g. 6 -> fast.
+    ///
+    /// Values are rounded. Other encoders are not supported.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=10), conflicts_with = "preset")]
+    pub speed: Option<u8>,
+
     /// Interval between keyframes. Can be specified as a number of frames, or a duration.
     /// E.g. "300" or "10s". Defaults to 10s if the input duration is over 3m.
     ///
@@ -68,8 +94,11 @@ pub struct Encode {
 
     /// Additional svt-av1 arg(s). E.g. --svt mbr=2000 --svt film-grain=8
     ///
+    /// These are combined into `-svtav1-params`. If a key is repeated the last value is used.
+    /// Likely misspellings of known keys are rejected.
+    ///
     /// See https://gitlab.com/AOMediaCodec/SVT-AV1/-/blob/master/Docs/svt-av1_encoder_user_guide.md#options
-    #[arg(long = "svt", value_parser = parse_svt_arg)]
+    #[arg(long = "svt", visible_alias = "svtav1-param", value_parser = parse_svt_arg)]
     pub svt_args: Vec<Arc<str>>,
 
     /// Additional ffmpeg encoder arg(s). E.g. `--enc x265-params=lossless=1`
@@ -86,6 +115,95 @@ pub struct Encode {
     /// See --enc docs.
     #[arg(long = "enc-input", allow_hyphen_values = true, value_parser = parse_enc_arg)]
     pub enc_input_args: Vec<String>,
+
+    /// Full ffmpeg style encoder args, instead of --encoder, --preset, --pix-format & --enc.
+    /// E.g. `--enc-raw "-c:v libsvtav1 -preset 6 -svtav1-params tune=0:film-grain=8"`
+    ///
+    /// Any crf arg, e.g. `-crf 30`, is replaced by the crf being used.
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        conflicts_with_all = ["encoder", "preset", "speed", "pix_format", "enc_args"],
+    )]
+    pub enc_raw: Option<RawEncoderArgs>,
+
+    /// Encoder to use instead if the --encoder is not available in ffmpeg or fails
+    /// to initialize, e.g. a hardware encoder without a supported device.
+    /// E.g. `-e libsvtav1 --enc-fallback libaom-av1`
+    ///
+    /// The fallback uses its default args, i.e. --preset, --svt, --enc & --enc-input are dropped.
+    #[arg(long, conflicts_with = "enc_raw")]
+    pub enc_fallback: Option<Encoder>,
+
+    /// Input is still being written, e.g. a live recording. Only the portion written
+    /// when probed is used.
+    ///
+    /// Optionally set a poll interval, e.g. `--growing-input 10s`, to first wait until
+    /// the input stops growing.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "0s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub growing_input: Option<Duration>,
+
+    /// Constant quantizer mode. Encode with a fixed quantizer (QP) instead of a constant
+    /// rate factor, --crf values (& crf-search --min-crf, --max-crf) are QP values.
+    ///
+    /// Fixed per-frame quantizers give deterministic behaviour at the cost of efficiency.
+    #[arg(long, conflicts_with = "enc_raw")]
+    pub qp: bool,
+
+    /// Encode deadline/usage, composing with --preset or --speed.
+    /// Mapped to libvpx-vp9 `-deadline` & libaom-av1 `-usage`, which has no `best`.
+    ///
+    /// Unsupported by other encoders, ignored with a warning.
+    #[arg(long, value_enum, conflicts_with = "enc_raw")]
+    pub deadline: Option<Deadline>,
+
+    /// Max consecutive B-frames, e.g. `--bframes 0` for low latency encodes.
+    /// Mapped to `-bf` for libx264, libx265 & hardware encoders (max 16).
+    ///
+    /// For libsvtav1 mapped to hierarchical levels, so must be one of 3, 7, 15 or 31,
+    /// i.e. a mini-gop of 4, 8, 16 or 32 frames.
+    #[arg(long, conflicts_with = "enc_raw")]
+    pub bframes: Option<u8>,
+
+    /// Scalable video coding layers in `L<spatial>T<temporal>` form, e.g. `--svc L1T3`
+    /// for 3 temporal layers decodable at 1/4, 1/2 & full frame rate.
+    ///
+    /// Mapped to libvpx-vp9 & libvpx `-ts-parameters`. Spatial layers are not
+    /// supported by ffmpeg encoders.
+    #[arg(long, value_parser = parse_svc_arg, conflicts_with = "enc_raw")]
+    pub svc: Option<Svc>,
+}
+
+fn parse_fps_arg(fps: &str) -> anyhow::Result<Arc<str>> {
+    let fps = fps.trim();
+    ensure!(
+        try_parse_fps_vfilter(&format!("fps={fps}")).is_some_and(|f| f > 0.0),
+        "invalid fps '{fps}', e.g. '30', '30000/1001' or 'ntsc'"
+    );
+    Ok(fps.into())
+}
+
+fn parse_svc_arg(arg: &str) -> anyhow::Result<Svc> {
+    const ERR: &str = "svc must be in L<spatial>T<temporal> format, e.g. 'L1T3'";
+    let (spatial, temporal) = arg
+        .trim()
+        .strip_prefix('L')
+        .and_then(|lt| lt.split_once('T'))
+        .context(ERR)?;
+    let svc = Svc {
+        spatial: spatial.parse().context(ERR)?,
+        temporal: temporal.parse().context(ERR)?,
+    };
+    ensure!(
+        (1..=3).contains(&svc.spatial) && (1..=3).contains(&svc.temporal),
+        "svc layers must be 1-3"
+    );
+    Ok(svc)
 }
 
 fn parse_svt_arg(arg: &str) -> anyhow::Result<Arc<str>> {
@@ -95,9 +213,151 @@ fn parse_svt_arg(arg: &str) -> anyhow::Result<Arc<str>> {
         ensure!(!arg.starts_with(deny), "'{deny}' cannot be used here");
     }
 
+    let (key, _) = arg
+        .split_once('=')
+        .context("svt-av1 args must be in key=value format e.g. 'film-grain=8'")?;
+    // short keys are too close to each other to reliably suggest a correction
+    if key.len() > 3 && !SVT_KNOWN_PARAMS.contains(&key) {
+        let typo_of = SVT_KNOWN_PARAMS
+            .iter()
+            .find(|known| edit_distance(key, known) <= if key.len() > 4 { 2 } else { 1 });
+        if let Some(known) = typo_of {
+            bail!("unknown svt-av1 param '{key}', did you mean '{known}'?");
+        }
+    }
+
     Ok(arg.into())
 }
 
+/// Common svt-av1 params, used to catch misspellings. Other params are passed as-is.
+const SVT_KNOWN_PARAMS: &[&str] = &[
+    "ac-bias",
+    "adaptive-film-grain",
+    "aq-mode",
+    "asm",
+    "avif",
+    "buf-initial-sz",
+    "buf-optimal-sz",
+    "buf-sz",
+    "chroma-qindex-offsets",
+    "chroma-qm-max",
+    "chroma-qm-min",
+    "chroma-sample-position",
+    "chroma-u-ac-qindex-offset",
+    "chroma-u-dc-qindex-offset",
+    "chroma-v-ac-qindex-offset",
+    "chroma-v-dc-qindex-offset",
+    "color-primaries",
+    "color-range",
+    "content-light",
+    "enable-cdef",
+    "enable-dg",
+    "enable-dlf",
+    "enable-hdr",
+    "enable-mfmv",
+    "enable-overlays",
+    "enable-qm",
+    "enable-restoration",
+    "enable-stat-report",
+    "enable-tf",
+    "enable-tpl-la",
+    "enable-variance-boost",
+    "fast-decode",
+    "fgs-table",
+    "film-grain",
+    "film-grain-denoise",
+    "frame-luma-bias",
+    "hbd-mds",
+    "hierarchical-levels",
+    "irefresh-type",
+    "key-frame-chroma-qindex-offset",
+    "key-frame-qindex-offset",
+    "lambda-scale-factors",
+    "level",
+    "lookahead",
+    "lp",
+    "luma-y-dc-qindex-offset",
+    "luminance-qp-bias",
+    "mastering-display",
+    "matrix-coefficients",
+    "max-qp",
+    "max-tx-size",
+    "mbr",
+    "min-qp",
+    "overshoot-pct",
+    "passes",
+    "pin",
+    "pred-struct",
+    "profile",
+    "progress",
+    "qindex-offsets",
+    "qm-max",
+    "qm-min",
+    "qp",
+    "rc",
+    "recode-loop",
+    "resize-mode",
+    "rtc",
+    "scm",
+    "sframe-dist",
+    "sframe-mode",
+    "sharpness",
+    "startup-mg-size",
+    "stat-file",
+    "superres-mode",
+    "tbr",
+    "tf-strength",
+    "tile-columns",
+    "tile-rows",
+    "transfer-characteristics",
+    "tune",
+    "undershoot-pct",
+    "use-fixed-qindex-offsets",
+    "variance-boost-strength",
+    "variance-octile",
+];
+
+/// Edit distance between `a` & `b`, counting adjacent transpositions as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j] = distance between a[..i] & b[..j]
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            d[i][j] = match (i, j) {
+                (0, j) => j,
+                (i, 0) => i,
+                _ => {
+                    let mut dist = (d[i - 1][j] + 1)
+                        .min(d[i][j - 1] + 1)
+                        .min(d[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]));
+                    if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                        dist = dist.min(d[i - 2][j - 2] + 1);
+                    }
+                    dist
+                }
+            };
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Returns `:` separated `key=value` svt-av1 params with repeated keys deduped to the
+/// last value, at the position of the first.
+fn dedup_svtav1_params(params: &[String]) -> Vec<&str> {
+    fn key(param: &str) -> &str {
+        param.split_once('=').map_or(param, |(k, _)| k)
+    }
+    let mut deduped: Vec<&str> = vec![];
+    for param in params.iter().flat_map(|p| p.split(':')) {
+        match deduped.iter_mut().find(|p| key(p) == key(param)) {
+            Some(existing) => *existing = param,
+            None => deduped.push(param),
+        }
+    }
+    deduped
+}
+
 fn parse_enc_arg(arg: &str) -> anyhow::Result<String> {
     let mut arg = arg.to_owned();
     if !arg.starts_with('-') {
@@ -113,6 +373,113 @@ fn parse_enc_arg(arg: &str) -> anyhow::Result<String> {
 }
 
 impl Encode {
+    /// Returns the `--enc-raw` encoder, if set, otherwise `--encoder`.
+    pub fn encoder(&self) -> &Encoder {
+        self.enc_raw
+            .as_ref()
+            .and_then(|raw| raw.encoder.as_ref())
+            .unwrap_or(&self.encoder)
+    }
+
+    /// Switch to the `--enc-fallback` encoder, with its default args, if the
+    /// `--encoder` is not in the `available` (and working) ffmpeg encoders.
+    ///
+    /// Returns the unavailable encoder that was replaced, if any.
+    pub fn use_fallback_encoder(&mut self, available: &[&str]) -> Option<Encoder> {
+        if available.contains(&self.encoder.as_str()) {
+            return None;
+        }
+        let fallback = self.enc_fallback.take()?;
+        self.preset = None;
+        self.svt_args.clear();
+        self.enc_args.clear();
+        self.enc_input_args.clear();
+        Some(std::mem::replace(&mut self.encoder, fallback))
+    }
+
+    /// Probe ffmpeg encoders & switch to the `--enc-fallback` if the `--encoder` is not
+    /// available or fails to initialize, noting the substitution.
+    async fn apply_encoder_fallback(&mut self) -> anyhow::Result<()> {
+        if self.enc_fallback.is_none() {
+            return Ok(());
+        }
+        let encoders = ffmpeg::encoders().await?;
+        let mut available = ffmpeg::parse_video_encoders(&encoders);
+        let mut reason = "is not available";
+        let vcodec = self.encoder.as_str().to_owned();
+        if available.contains(&vcodec.as_str()) && !ffmpeg::encoder_initializes(&vcodec).await {
+            available.retain(|enc| *enc != vcodec);
+            reason = "failed to initialize";
+        }
+        if let Some(unusable) = self.use_fallback_encoder(&available) {
+            eprintln!(
+                "{}",
+                style!(
+                    "Note: {} {reason}, using --enc-fallback {}",
+                    unusable.as_str(),
+                    self.encoder.as_str()
+                )
+                .yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Prepares args for use: applies any --enc-fallback, waits for a --growing-input
+    /// & appends the --fps filter to the --vfilter.
+    pub async fn prepare(&mut self) -> anyhow::Result<()> {
+        self.apply_encoder_fallback().await?;
+        if let Some(warning) = self.deadline_warning() {
+            eprintln!("{}", console::style(warning).yellow());
+        }
+        self.wait_for_growing_input().await?;
+        self.apply_fps();
+        Ok(())
+    }
+
+    /// Warning if the `--deadline` is not supported by the encoder & so ignored.
+    fn deadline_warning(&self) -> Option<String> {
+        let deadline = self.deadline?;
+        let encoder = self.encoder();
+        if encoder.deadline_arg(deadline).is_some() {
+            return None;
+        }
+        Some(format!(
+            "Warning: --deadline {} not supported by {}, ignored",
+            deadline.as_str(),
+            encoder.as_str()
+        ))
+    }
+
+    /// Appends `fps=N` to the vfilter.
+    fn apply_fps(&mut self) {
+        if let Some(fps) = &self.fps {
+            self.vfilter = Some(match self.vfilter.take() {
+                Some(vf) => format!("{vf},fps={fps}"),
+                None => format!("fps={fps}"),
+            });
+        }
+    }
+
+    /// With `--growing-input POLL` wait until the input stops growing.
+    async fn wait_for_growing_input(&self) -> anyhow::Result<()> {
+        let Some(poll) = self.growing_input.filter(|p| !p.is_zero()) else {
+            return Ok(());
+        };
+        eprintln!(
+            "{}",
+            style!("Waiting for {} to stop growing", self.input.display()).dim()
+        );
+        super::growing::wait_until_stable(
+            || std::fs::metadata(&self.input).map(|m| m.len()),
+            tokio::time::sleep,
+            poll,
+        )
+        .await
+        .with_context(|| format!("--growing-input {}", self.input.display()))?;
+        Ok(())
+    }
+
     pub fn to_encoder_args(
         &self,
         crf: f32,
@@ -121,33 +488,157 @@ impl Encode {
         self.to_ffmpeg_args(crf, probe)
     }
 
+    /// Returns `--name=value` command line args that reproduce these args, excluding the crf.
+    ///
+    /// Should be used after [`Self::prepare`] as, like the encode hint, --fps is
+    /// then included in the vfilter & --enc-fallback already applied.
+    pub fn to_cli_args(&self) -> Vec<String> {
+        let Self {
+            encoder,
+            input,
+            vfilter,
+            preset,
+            speed,
+            pix_format,
+            keyint,
+            scd,
+            svt_args,
+            enc_args,
+            enc_input_args,
+            enc_raw,
+            enc_fallback: _,
+            growing_input: _,
+            // included in the vfilter
+            fps: _,
+            qp,
+            deadline,
+            bframes,
+            svc,
+        } = self;
+
+        let mut args = vec![];
+        match enc_raw {
+            Some(raw) => args.push(format!(
+                "--enc-raw={}",
+                (raw.tokens.iter())
+                    .map(|t| shell_escape::escape(t.into()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )),
+            None => args.push(format!("--encoder={}", encoder.as_str())),
+        }
+        args.push(format!("--input={}", input.display()));
+        if let Some(vfilter) = vfilter {
+            args.push(format!("--vfilter={vfilter}"));
+        }
+        if let Some(preset) = preset {
+            args.push(format!("--preset={preset}"));
+        }
+        if let Some(speed) = speed {
+            args.push(format!("--speed={speed}"));
+        }
+        if let Some(pix_fmt) = pix_format {
+            args.push(format!("--pix-format={pix_fmt}"));
+        }
+        if let Some(keyint) = keyint {
+            args.push(format!("--keyint={keyint}"));
+        }
+        if let Some(scd) = scd {
+            args.push(format!("--scd={scd}"));
+        }
+        args.extend(svt_args.iter().map(|a| format!("--svt={a}")));
+        args.extend(enc_input_args.iter().map(|a| format!("--enc-input={a}")));
+        args.extend(enc_args.iter().map(|a| format!("--enc={a}")));
+        if *qp {
+            args.push("--qp".into());
+        }
+        if let Some(deadline) = deadline {
+            args.push(format!("--deadline={}", deadline.as_str()));
+        }
+        if let Some(bframes) = bframes {
+            args.push(format!("--bframes={bframes}"));
+        }
+        if let Some(svc) = svc {
+            args.push(format!("--svc={svc}"));
+        }
+        args
+    }
+
     pub fn encode_hint(&self, crf: f32) -> String {
         let Self {
             encoder,
             input,
             vfilter,
             preset,
+            speed,
             pix_format,
             keyint,
             scd,
             svt_args,
             enc_args,
             enc_input_args,
+            enc_raw,
+            enc_fallback: _,
+            growing_input: _,
+            // included in the vfilter
+            fps: _,
+            qp,
+            deadline,
+            bframes,
+            svc,
         } = self;
 
         let input = shell_escape::escape(input.display().to_string().into());
 
         let mut hint = "ab-av1 encode".to_owned();
 
+        if let Some(raw) = enc_raw {
+            write!(hint, " -i {input} --crf {}", TerseF32(crf)).unwrap();
+            let crf_arg = ffmpeg::crf_arg(&self.encoder().0);
+            let raw = raw
+                .with_crf(crf_arg, TerseF32(crf))
+                .into_iter()
+                .map(|t| shell_escape::escape(t.into()).into_owned())
+                .collect::<Vec<_>>()
+                .join(" ");
+            write!(hint, " --enc-raw {}", shell_escape::escape(raw.into())).unwrap();
+            if let Some(keyint) = keyint {
+                write!(hint, " --keyint {keyint}").unwrap();
+            }
+            if let Some(filter) = vfilter {
+                write!(hint, " --vfilter {filter:?}").unwrap();
+            }
+            for arg in enc_input_args {
+                let arg = arg.trim_start_matches('-');
+                write!(hint, " --enc-input {arg}").unwrap();
+            }
+            return hint;
+        }
+
         let vcodec = encoder.as_str();
         if vcodec != "libsvtav1" {
             write!(hint, " -e {vcodec}").unwrap();
         }
         write!(hint, " -i {input} --crf {}", TerseF32(crf)).unwrap();
+        if *qp {
+            hint.push_str(" --qp");
+        }
 
         if let Some(preset) = preset {
             write!(hint, " --preset {preset}").unwrap();
         }
+        if let Some(speed) = speed {
+            write!(hint, " --speed {speed}").unwrap();
+        }
+        if let Some(deadline) = deadline {
+            write!(hint, " --deadline {}", deadline.as_str()).unwrap();
+        }
+        if let Some(bframes) = bframes {
+            write!(hint, " --bframes {bframes}").unwrap();
+        }
+        if let Some(svc) = svc {
+            write!(hint, " --svc {svc}").unwrap();
+        }
         if let Some(keyint) = keyint {
             write!(hint, " --keyint {keyint}").unwrap();
         }
@@ -176,14 +667,29 @@ impl Encode {
     }
 
     fn to_ffmpeg_args(&self, crf: f32, probe: &Ffprobe) -> anyhow::Result<FfmpegEncodeArgs<'_>> {
-        let vcodec = &self.encoder.0;
+        let vcodec = &self.encoder().0;
         let svtav1 = vcodec.as_ref() == "libsvtav1";
+        let raw = self.enc_raw.as_ref();
         ensure!(
             svtav1 || self.svt_args.is_empty(),
             "--svt may only be used with svt-av1"
         );
+        if self.qp {
+            ensure!(
+                ffmpeg::supports_qp(vcodec),
+                "--qp not supported by {vcodec}"
+            );
+            ensure!(crf.fract() == 0.0, "--qp values must be whole numbers");
+        }
 
-        let preset = match &self.preset {
+        let speed_preset = self
+            .speed
+            .map(|s| self.encoder().speed_preset(s))
+            .transpose()?;
+        let preset = match (raw.and_then(|r| r.preset.as_ref()))
+            .or(self.preset.as_ref())
+            .or(speed_preset.as_ref())
+        {
             Some(Preset::Number(n)) => Some(n.to_string().into()),
             Some(Preset::Name(n)) => Some(n.clone()),
             None if svtav1 => Some("8".into()),
@@ -192,6 +698,11 @@ impl Encode {
 
         let keyint = self.keyint(probe)?;
 
+        let bframes = self
+            .bframes
+            .map(|b| self.encoder().bframes_arg(b))
+            .transpose()?;
+
         let mut svtav1_params = vec![];
         if svtav1 {
             let scd = match (self.scd, self.keyint, keyint) {
@@ -199,9 +710,13 @@ impl Encode {
                 _ => 0,
             };
             svtav1_params.push(format!("scd={scd}"));
+            if let Some(BframesArg::SvtParam(param)) = &bframes {
+                svtav1_params.push(param.clone());
+            }
             // add all --svt args
             svtav1_params.extend(self.svt_args.iter().map(|a| a.to_string()));
         }
+        svtav1_params.extend(raw.and_then(|r| r.svtav1_params.clone()));
 
         let mut args: Vec<Arc<String>> = self
             .enc_args
@@ -219,7 +734,9 @@ impl Encode {
                 }
             })
             .collect();
+        args.extend(raw.iter().flat_map(|r| &r.args).map(|a| a.clone().into()));
 
+        let svtav1_params = dedup_svtav1_params(&svtav1_params);
         if !svtav1_params.is_empty() {
             args.push("-svtav1-params".to_owned().into());
             args.push(svtav1_params.join(":").into());
@@ -233,19 +750,46 @@ impl Encode {
             }
         }
 
-        for (name, val) in self.encoder.default_ffmpeg_args() {
+        if let Some(BframesArg::Bf(bf)) = bframes {
+            if !args.iter().any(|arg| arg.as_str() == "-bf") {
+                args.push("-bf".to_owned().into());
+                args.push(bf.to_string().into());
+            }
+        }
+
+        let svc = self
+            .svc
+            .map(|svc| self.encoder().svc_args(svc))
+            .transpose()?;
+        if let Some((name, val)) = svc.flatten() {
+            if !args.iter().any(|arg| arg.as_str() == name) {
+                args.push(name.to_owned().into());
+                args.push(val.into());
+            }
+        }
+
+        if let Some((name, val)) = self.deadline.and_then(|d| self.encoder().deadline_arg(d)) {
+            if !args.iter().any(|arg| arg.as_str() == name) {
+                args.push(name.to_owned().into());
+                args.push(val.to_owned().into());
+            }
+        }
+
+        for (name, val) in self.encoder().default_ffmpeg_args() {
             if !args.iter().any(|arg| &**arg == name) {
                 args.push(name.to_string().into());
                 args.push(val.to_string().into());
             }
         }
 
-        let pix_fmt = self.pix_format.unwrap_or(match vcodec {
+        let pix_format = raw.and_then(|r| r.pix_fmt).or(self.pix_format);
+        let pix_fmt = pix_format.unwrap_or(match vcodec {
             vc if vc.contains("av1") => PixelFormat::Yuv420p10le,
             _ => PixelFormat::Yuv420p,
         });
+        self.encoder().ensure_supports(pix_fmt)?;
 
-        let input_args: Vec<Arc<String>> = self
+        let mut input_args: Vec<Arc<String>> = self
             .enc_input_args
             .iter()
             .flat_map(|arg| {
@@ -256,6 +800,13 @@ impl Encode {
                 }
             })
             .collect();
+        if self.growing_input.is_some() {
+            let written = probe
+                .duration
+                .clone()
+                .context("--growing-input requires the written duration to be known")?;
+            input_args.extend(super::growing::written_duration_input_args(written).map(Arc::new));
+        }
 
         // ban usage of the bits we already set via other args & logic
         let reserved = HashMap::from([
@@ -286,6 +837,7 @@ impl Encode {
             pix_fmt,
             vfilter: self.vfilter.as_deref(),
             crf,
+            qp: self.qp,
             preset,
             output_args: args,
             input_args,
@@ -293,6 +845,38 @@ impl Encode {
         })
     }
 
+    /// Returns the default crf-search increment, 1 for `--qp`.
+    pub fn default_crf_increment(&self) -> f32 {
+        match self.qp {
+            true => 1.0,
+            false => self.encoder().default_crf_increment(),
+        }
+    }
+
+    /// Returns the default crf-search max crf, or max QP for `--qp`.
+    pub fn default_max_crf(&self) -> f32 {
+        match self.qp {
+            true => self.encoder().default_max_qp(),
+            false => self.encoder().default_max_crf(),
+        }
+    }
+
+    /// Returns the keyframe interval duration, if known.
+    pub fn gop_duration(&self, probe: &Ffprobe) -> anyhow::Result<Option<Duration>> {
+        let fps = self
+            .vfilter
+            .as_deref()
+            .and_then(try_parse_fps_vfilter)
+            .or(probe.fps.clone().ok())
+            .filter(|fps| *fps > 0.0);
+        Ok(match (self.keyint(probe)?, fps) {
+            (Some(keyint), Some(fps)) if keyint > 0 => {
+                Some(Duration::from_secs_f64(f64::from(keyint) / fps))
+            }
+            _ => None,
+        })
+    }
+
     fn keyint(&self, probe: &Ffprobe) -> anyhow::Result<Option<i32>> {
         const KEYINT_DEFAULT_INPUT_MIN: Duration = Duration::from_secs(60 * 3);
         const KEYINT_DEFAULT: Duration = Duration::from_secs(10);
@@ -345,6 +929,135 @@ impl Encoder {
         }
     }
 
+    /// Returns the max `--qp` value.
+    pub fn default_max_qp(&self) -> f32 {
+        match self.as_str() {
+            "librav1e" | "av1_vaapi" | "av1_nvenc" => 255.0,
+            e if e.contains("264") || e.contains("265") || e.contains("hevc") => 51.0,
+            _ => 63.0,
+        }
+    }
+
+    /// Returns the native preset for the encoder agnostic `--speed`.
+    pub fn speed_preset(&self, speed: u8) -> anyhow::Result<Preset> {
+        const X26X_PRESETS: [&str; 10] = [
+            "placebo",
+            "veryslow",
+            "slower",
+            "slow",
+            "medium",
+            "fast",
+            "faster",
+            "veryfast",
+            "superfast",
+            "ultrafast",
+        ];
+        let scaled = |factor: f32| (f32::from(speed) * factor).round() as u8;
+        Ok(match self.as_str() {
+            "libsvtav1" => Preset::Number(scaled(1.3)),
+            "libaom-av1" => Preset::Number(scaled(0.8)),
+            "libvpx-vp9" => Preset::Number(scaled(0.5)),
+            "librav1e" => Preset::Number(speed),
+            "libx264" | "libx265" => Preset::Name(X26X_PRESETS[usize::from(scaled(0.9))].into()),
+            vcodec => bail!("--speed not supported by {vcodec}, use --preset"),
+        })
+    }
+
+    /// Returns the native `--deadline` arg, `None` if unsupported.
+    pub fn deadline_arg(&self, deadline: Deadline) -> Option<(&'static str, &'static str)> {
+        match (self.as_str(), deadline) {
+            ("libvpx-vp9", d) => Some(("-deadline", d.as_str())),
+            ("libaom-av1", Deadline::Best) => None,
+            ("libaom-av1", d) => Some(("-usage", d.as_str())),
+            _ => None,
+        }
+    }
+
+    /// Returns the native `--bframes` arg, erroring if unsupported or out of range.
+    pub fn bframes_arg(&self, bframes: u8) -> anyhow::Result<BframesArg> {
+        const MAX_BF: u8 = 16;
+        match self.as_str() {
+            "libsvtav1" => {
+                // mini-gop of 2^levels frames, all but the last are B-frames
+                let mini_gop = u32::from(bframes) + 1;
+                let levels = mini_gop.trailing_zeros();
+                ensure!(
+                    mini_gop.is_power_of_two() && (2..=5).contains(&levels),
+                    "--bframes {bframes} not supported by libsvtav1, use 3, 7, 15 or 31"
+                );
+                Ok(BframesArg::SvtParam(format!(
+                    "hierarchical-levels={levels}"
+                )))
+            }
+            e if e.starts_with("libx26")
+                || ["nvenc", "qsv", "vaapi", "amf"]
+                    .iter()
+                    .any(|hw| e.ends_with(hw)) =>
+            {
+                ensure!(
+                    bframes <= MAX_BF,
+                    "--bframes {bframes} out of range for {e}, max {MAX_BF}"
+                );
+                Ok(BframesArg::Bf(bframes))
+            }
+            e => bail!("--bframes not supported by {e}"),
+        }
+    }
+
+    /// Returns the native `--svc` args, erroring if unsupported.
+    pub fn svc_args(&self, svc: Svc) -> anyhow::Result<Option<(&'static str, String)>> {
+        let e = self.as_str();
+        ensure!(
+            matches!(e, "libvpx-vp9" | "libvpx"),
+            "--svc not supported by {e}, use libvpx-vp9 or libvpx"
+        );
+        ensure!(
+            svc.spatial == 1,
+            "--svc {svc} spatial layers not supported by {e}, use L1T{}",
+            svc.temporal
+        );
+        // each layer doubles the frame rate of those below
+        let pattern = match svc.temporal {
+            1 => return Ok(None),
+            2 => "ts_rate_decimator=2,1:ts_periodicity=2:ts_layer_id=0,1",
+            _ => "ts_rate_decimator=4,2,1:ts_periodicity=4:ts_layer_id=0,2,1,2",
+        };
+        Ok(Some((
+            "-ts-parameters",
+            format!("ts_number_layers={}:{pattern}", svc.temporal),
+        )))
+    }
+
+    /// Returns known supported pixel formats, `None` if unknown.
+    fn supported_pixel_formats(&self) -> Option<&'static [PixelFormat]> {
+        use PixelFormat::*;
+        match self.as_str() {
+            "libsvtav1" => Some(&[Yuv420p, Yuv420p10le]),
+            "libaom-av1" | "librav1e" | "libvpx-vp9" | "libx264" | "libx265" => {
+                Some(&[Yuv420p, Yuv420p10le, Yuv444p10le])
+            }
+            e if e.ends_with("_nvenc") => Some(&[Yuv420p, Yuv420p10le]),
+            _ => None,
+        }
+    }
+
+    /// Error if the encoder is known not to support `pix_fmt`.
+    pub fn ensure_supports(&self, pix_fmt: PixelFormat) -> anyhow::Result<()> {
+        if let Some(supported) = self.supported_pixel_formats() {
+            ensure!(
+                supported.contains(&pix_fmt),
+                "{} does not support pixel format {pix_fmt}, supported: {}",
+                self.as_str(),
+                supported
+                    .iter()
+                    .map(|p| p.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+        Ok(())
+    }
+
     /// Additional encoder specific ffmpeg arg defaults.
     fn default_ffmpeg_args(&self) -> &[(&'static str, &'static str)] {
         match self.as_str() {
@@ -439,6 +1152,45 @@ impl std::str::FromStr for KeyInterval {
     }
 }
 
+/// `--svc` scalable layers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Svc {
+    pub spatial: u8,
+    pub temporal: u8,
+}
+
+impl fmt::Display for Svc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{}T{}", self.spatial, self.temporal)
+    }
+}
+
+/// Native encoder arg for `--bframes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BframesArg {
+    /// `-bf N`
+    Bf(u8),
+    /// svt-av1 param, e.g. `hierarchical-levels=3`.
+    SvtParam(String),
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Deadline {
+    Good,
+    Best,
+    Realtime,
+}
+
+impl Deadline {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Good => "good",
+            Self::Best => "best",
+            Self::Realtime => "realtime",
+        }
+    }
+}
+
 /// Ordered by ascending quality.
 #[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[clap(rename_all = "lower")]
@@ -448,6 +1200,32 @@ pub enum PixelFormat {
     Yuv444p10le,
 }
 
+/// Returns true if `args` contain the consecutive `pair`, e.g. `["-g", "240"]`.
+#[cfg(test)]
+pub(crate) fn has_arg_pair(args: &[impl AsRef<str>], pair: [&str; 2]) -> bool {
+    args.windows(2)
+        .any(|w| w[0].as_ref() == pair[0] && w[1].as_ref() == pair[1])
+}
+
+#[test]
+fn encoder_pixel_format_support() {
+    let svt = Encoder("libsvtav1".into());
+    assert!(svt.ensure_supports(PixelFormat::Yuv420p10le).is_ok());
+    let err = svt.ensure_supports(PixelFormat::Yuv444p10le).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "libsvtav1 does not support pixel format yuv444p10le, supported: yuv420p, yuv420p10le"
+    );
+
+    assert!(Encoder("libx265".into())
+        .ensure_supports(PixelFormat::Yuv444p10le)
+        .is_ok());
+    // unknown encoders are not validated
+    assert!(Encoder("av1_vaapi".into())
+        .ensure_supports(PixelFormat::Yuv444p10le)
+        .is_ok());
+}
+
 #[test]
 fn pixel_format_order() {
     use PixelFormat::*;
@@ -486,7 +1264,7 @@ impl TryFrom<&str> for PixelFormat {
 
 fn try_parse_fps_vfilter(vfilter: &str) -> Option<f64> {
     let fps_filter = vfilter
-        .split(',')
+        .rsplit(',')
         .find_map(|vf| vf.trim().strip_prefix("fps="))?
         .trim();
 
@@ -522,6 +1300,36 @@ fn duration_interval_from_str() {
     assert_eq!(from_10s, KeyInterval::Duration(Duration::from_secs(10)));
 }
 
+#[test]
+fn qp_search_over_whole_quantizers() {
+    let x264 = |cli: &[&str]| {
+        Encode::parse_from(
+            ["ab-av1", "-e", "libx264", "-i", "vid.mp4"]
+                .iter()
+                .chain(cli),
+        )
+    };
+    let crf = x264(&[]);
+    assert_eq!(crf.default_crf_increment(), 0.1);
+    assert_eq!(crf.default_max_crf(), 46.0);
+
+    let qp = x264(&["--qp"]);
+    assert_eq!(qp.default_crf_increment(), 1.0);
+    assert_eq!(qp.default_max_crf(), 51.0);
+    assert!(qp.encode_hint(30.0).ends_with("--crf 30 --qp"));
+
+    let probe = crate::ffprobe::test_probe();
+    let enc = qp.to_encoder_args(30.0, &probe).unwrap();
+    assert!(enc.qp);
+    assert_eq!(enc.crf, 30.0);
+    let err = qp.to_encoder_args(30.5, &probe).unwrap_err();
+    assert_eq!(err.to_string(), "--qp values must be whole numbers");
+
+    let qsv = Encode::parse_from(["ab-av1", "-e", "av1_qsv", "-i", "vid.mp4", "--qp"]);
+    let err = qsv.to_encoder_args(30.0, &probe).unwrap_err();
+    assert_eq!(err.to_string(), "--qp not supported by av1_qsv");
+}
+
 /// Should use keyint & scd defaults for >3m inputs.
 #[test]
 fn svtav1_to_ffmpeg_args_default_over_3m() {
@@ -536,16 +1344,22 @@ fn svtav1_to_ffmpeg_args_default_over_3m() {
         svt_args: vec!["film-grain=30".into()],
         enc_args: <_>::default(),
         enc_input_args: <_>::default(),
+        enc_raw: None,
+        enc_fallback: None,
+        growing_input: None,
+        fps: None,
+        qp: false,
+        speed: None,
+        deadline: None,
+        bframes: None,
+        svc: None,
     };
 
     let probe = Ffprobe {
         duration: Ok(Duration::from_secs(300)),
         has_audio: true,
-        max_audio_channels: None,
         fps: Ok(30.0),
-        resolution: Some((1280, 720)),
-        is_image: false,
-        pix_fmt: None,
+        ..crate::ffprobe::test_probe()
     };
 
     let FfmpegEncodeArgs {
@@ -554,6 +1368,7 @@ fn svtav1_to_ffmpeg_args_default_over_3m() {
         vfilter,
         pix_fmt,
         crf,
+        qp,
         preset,
         output_args,
         input_args,
@@ -564,14 +1379,16 @@ fn svtav1_to_ffmpeg_args_default_over_3m() {
     assert_eq!(input, enc.input);
     assert_eq!(vfilter, Some("scale=320:-1,fps=film"));
     assert_eq!(crf, 32.0);
+    assert!(!qp);
     assert_eq!(preset, Some("8".into()));
     assert_eq!(pix_fmt, PixelFormat::Yuv420p10le);
     assert!(!video_only);
 
     assert!(
-        output_args
-            .windows(2)
-            .any(|w| w[0].as_str() == "-g" && w[1].as_str() == "240"),
+        has_arg_pair(
+            &output_args.iter().map(|a| a.as_str()).collect::<Vec<_>>(),
+            ["-g", "240"]
+        ),
         "expected -g in {output_args:?}"
     );
     let svtargs_idx = output_args
@@ -599,16 +1416,21 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
         svt_args: vec![],
         enc_args: <_>::default(),
         enc_input_args: <_>::default(),
+        enc_raw: None,
+        enc_fallback: None,
+        growing_input: None,
+        fps: None,
+        qp: false,
+        speed: None,
+        deadline: None,
+        bframes: None,
+        svc: None,
     };
 
     let probe = Ffprobe {
         duration: Ok(Duration::from_secs(179)),
         has_audio: true,
-        max_audio_channels: None,
-        fps: Ok(24.0),
-        resolution: Some((1280, 720)),
-        is_image: false,
-        pix_fmt: None,
+        ..crate::ffprobe::test_probe()
     };
 
     let FfmpegEncodeArgs {
@@ -617,6 +1439,7 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
         vfilter,
         pix_fmt,
         crf,
+        qp,
         preset,
         output_args,
         input_args,
@@ -627,6 +1450,7 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
     assert_eq!(input, enc.input);
     assert_eq!(vfilter, None);
     assert_eq!(crf, 32.0);
+    assert!(!qp);
     assert_eq!(preset, Some("7".into()));
     assert_eq!(pix_fmt, PixelFormat::Yuv420p);
     assert!(!video_only);
@@ -646,3 +1470,278 @@ fn svtav1_to_ffmpeg_args_default_under_3m() {
     assert_eq!(svtargs, "scd=0");
     assert!(input_args.is_empty());
 }
+
+#[test]
+fn enc_fallback_when_unavailable() {
+    let args = |cli: &[&str]| {
+        crate::command::encode::Args::try_parse_from(
+            ["encode", "-i", "vid.mkv", "--crf", "30"].iter().chain(cli),
+        )
+        .unwrap()
+        .args
+    };
+    let available = ["libaom-av1", "libx264"];
+
+    let mut enc = args(&[
+        "--preset",
+        "4",
+        "--svt",
+        "tune=0",
+        "--enc-fallback",
+        "libaom-av1",
+    ]);
+    assert_eq!(
+        enc.use_fallback_encoder(&available),
+        Some(Encoder("libsvtav1".into()))
+    );
+    assert_eq!(enc.encoder.as_str(), "libaom-av1");
+    assert!(enc.preset.is_none());
+    assert!(enc.svt_args.is_empty());
+
+    // primary available
+    let mut enc = args(&[
+        "-e",
+        "libx264",
+        "--preset",
+        "slow",
+        "--enc-fallback",
+        "libaom-av1",
+    ]);
+    assert_eq!(enc.use_fallback_encoder(&available), None);
+    assert_eq!(enc.encoder.as_str(), "libx264");
+    assert!(enc.preset.is_some());
+
+    // no fallback
+    let mut enc = args(&[]);
+    assert_eq!(enc.use_fallback_encoder(&available), None);
+    assert_eq!(enc.encoder.as_str(), "libsvtav1");
+}
+
+#[test]
+fn svtav1_params_assembled_and_deduped() {
+    let enc = Encode::try_parse_from([
+        "ab-av1",
+        "-i",
+        "vid.mkv",
+        "--svt",
+        "tune=0",
+        "--svtav1-param",
+        "film-grain=8",
+        "--svtav1-param",
+        "tune=2",
+        "--svt",
+        "new-param=1",
+    ])
+    .unwrap();
+    assert_eq!(enc.svt_args.len(), 4);
+
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_secs(60)),
+        fps: Ok(24.0),
+        ..crate::ffprobe::unknown("vid.mkv")
+    };
+    let args = enc.to_ffmpeg_args(32.0, &probe).unwrap().output_args;
+    let idx = args
+        .iter()
+        .position(|a| a.as_str() == "-svtav1-params")
+        .unwrap();
+    assert_eq!(
+        args[idx + 1].as_str(),
+        "scd=0:tune=2:film-grain=8:new-param=1"
+    );
+
+    assert_eq!(
+        dedup_svtav1_params(&["lp=2:tune=0".into(), "tune=1".into()]),
+        ["lp=2", "tune=1"]
+    );
+}
+
+#[test]
+fn svtav1_param_typos_rejected() {
+    let err = parse_svt_arg("film-grian=8").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown svt-av1 param 'film-grian', did you mean 'film-grain'?"
+    );
+    assert!(parse_svt_arg("tnue=0").is_err());
+    assert!(parse_svt_arg("film-grain").is_err(), "missing value");
+    assert!(parse_svt_arg("--enable-qm=1").is_ok());
+    assert!(parse_svt_arg("some-future-option=1").is_ok());
+    assert!(parse_svt_arg("qp=30").is_ok());
+    // no suggestions for short keys, e.g. "lq" isn't necessarily "lp"
+    assert!(parse_svt_arg("lq=4").is_ok());
+}
+
+#[test]
+fn growing_input_limits_to_written_duration() {
+    let enc = Encode::try_parse_from(["ab-av1", "-i", "live.ts", "--growing-input"]).unwrap();
+    assert_eq!(enc.growing_input, Some(Duration::ZERO));
+
+    let probe = Ffprobe {
+        duration: Ok(Duration::from_millis(61_500)),
+        fps: Ok(25.0),
+        ..crate::ffprobe::unknown("live.ts")
+    };
+    let input_args = enc.to_ffmpeg_args(32.0, &probe).unwrap().input_args;
+    assert_eq!(
+        input_args,
+        [Arc::new("-t".to_owned()), Arc::new("61.5".to_owned())]
+    );
+
+    // the written duration must be known
+    assert!(enc
+        .to_ffmpeg_args(32.0, &crate::ffprobe::unknown("live.ts"))
+        .is_err());
+}
+
+#[test]
+fn deadline_args_per_encoder() {
+    let probe = crate::ffprobe::test_probe();
+    let output_args = |encoder: &str, deadline: &str| {
+        let enc = Encode::parse_from([
+            "ab-av1",
+            "-e",
+            encoder,
+            "-i",
+            "vid.mp4",
+            "--speed",
+            "6",
+            "--deadline",
+            deadline,
+        ]);
+        assert_eq!(enc.deadline_warning(), None);
+        let args = enc.to_encoder_args(30.0, &probe).unwrap();
+        assert!(args.preset.is_some(), "composes with --speed");
+        args.output_args
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+    };
+    let vp9 = output_args("libvpx-vp9", "best");
+    assert!(has_arg_pair(&vp9, ["-deadline", "best"]), "{vp9:?}");
+    let aom = output_args("libaom-av1", "realtime");
+    assert!(has_arg_pair(&aom, ["-usage", "realtime"]), "{aom:?}");
+
+    let unsupported = |encoder: &str, deadline: &str| {
+        let enc = Encode::parse_from([
+            "ab-av1",
+            "-e",
+            encoder,
+            "-i",
+            "vid.mp4",
+            "--deadline",
+            deadline,
+        ]);
+        let args = enc.to_encoder_args(30.0, &probe).unwrap();
+        assert!(!args
+            .output_args
+            .iter()
+            .any(|a| a.as_str() == "-deadline" || a.as_str() == "-usage"));
+        enc.deadline_warning()
+    };
+    assert_eq!(
+        unsupported("libaom-av1", "best").as_deref(),
+        Some("Warning: --deadline best not supported by libaom-av1, ignored")
+    );
+    assert_eq!(
+        unsupported("libsvtav1", "good").as_deref(),
+        Some("Warning: --deadline good not supported by libsvtav1, ignored")
+    );
+}
+
+#[test]
+fn bframes_args_per_encoder() {
+    let probe = crate::ffprobe::test_probe();
+    let output_args = |encoder: &str, bframes: &str| {
+        let enc = Encode::parse_from([
+            "ab-av1",
+            "-e",
+            encoder,
+            "-i",
+            "vid.mp4",
+            "--bframes",
+            bframes,
+        ]);
+        enc.to_encoder_args(30.0, &probe).map(|args| {
+            args.output_args
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        })
+    };
+    let x264 = output_args("libx264", "0").unwrap();
+    assert!(has_arg_pair(&x264, ["-bf", "0"]), "{x264:?}");
+    let nvenc = output_args("hevc_nvenc", "4").unwrap();
+    assert!(has_arg_pair(&nvenc, ["-bf", "4"]), "{nvenc:?}");
+    let svt = output_args("libsvtav1", "7").unwrap();
+    let params = &svt[svt.iter().position(|a| a == "-svtav1-params").unwrap() + 1];
+    assert!(params.contains("hierarchical-levels=3"), "{params}");
+    assert!(!svt.contains(&"-bf".to_owned()));
+
+    let err = |encoder, bframes| output_args(encoder, bframes).unwrap_err().to_string();
+    assert_eq!(
+        err("libx265", "17"),
+        "--bframes 17 out of range for libx265, max 16"
+    );
+    assert_eq!(
+        err("libsvtav1", "4"),
+        "--bframes 4 not supported by libsvtav1, use 3, 7, 15 or 31"
+    );
+    assert_eq!(
+        err("libsvtav1", "1"),
+        "--bframes 1 not supported by libsvtav1, use 3, 7, 15 or 31"
+    );
+    assert_eq!(
+        err("libaom-av1", "3"),
+        "--bframes not supported by libaom-av1"
+    );
+}
+
+#[test]
+fn svc_layer_args() {
+    let probe = crate::ffprobe::test_probe();
+    let output_args = |encoder: &str, svc: &str| {
+        let enc = Encode::try_parse_from(["ab-av1", "-e", encoder, "-i", "vid.mp4", "--svc", svc])?;
+        enc.to_encoder_args(30.0, &probe).map(|args| {
+            args.output_args
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        })
+    };
+    let vp9 = output_args("libvpx-vp9", "L1T3").unwrap();
+    assert!(
+        has_arg_pair(
+            &vp9,
+            [
+                "-ts-parameters",
+                "ts_number_layers=3:ts_rate_decimator=4,2,1:ts_periodicity=4:ts_layer_id=0,2,1,2"
+            ]
+        ),
+        "{vp9:?}"
+    );
+    let vp8 = output_args("libvpx", "L1T2").unwrap();
+    assert!(
+        has_arg_pair(
+            &vp8,
+            [
+                "-ts-parameters",
+                "ts_number_layers=2:ts_rate_decimator=2,1:ts_periodicity=2:ts_layer_id=0,1"
+            ]
+        ),
+        "{vp8:?}"
+    );
+    let single = output_args("libvpx-vp9", "L1T1").unwrap();
+    assert!(!single.contains(&"-ts-parameters".to_owned()), "{single:?}");
+
+    let err = |encoder, svc| output_args(encoder, svc).unwrap_err().to_string();
+    assert_eq!(
+        err("libsvtav1", "L1T3"),
+        "--svc not supported by libsvtav1, use libvpx-vp9 or libvpx"
+    );
+    assert_eq!(
+        err("libvpx-vp9", "L2T3"),
+        "--svc L2T3 spatial layers not supported by libvpx-vp9, use L1T3"
+    );
+    assert!(output_args("libvpx-vp9", "T3").is_err());
+}
@@ -0,0 +1,139 @@
+//! `--enc-raw` ffmpeg style encoder arg string logic.
+use super::{Encoder, PixelFormat, Preset};
+use anyhow::{anyhow, bail, Context};
+
+/// Args that set the crf (or crf-like) value, these are replaced with the crf being used.
+const CRF_ARGS: &[&str] = &["-crf", "-cq", "-qp", "-q", "-q:v", "-global_quality"];
+
+/// Parsed ffmpeg style encoder args, e.g. "-c:v libsvtav1 -preset 6 -svtav1-params tune=0".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawEncoderArgs {
+    /// Original arg tokens.
+    pub tokens: Vec<String>,
+    pub encoder: Option<Encoder>,
+    pub preset: Option<Preset>,
+    pub pix_fmt: Option<PixelFormat>,
+    pub svtav1_params: Option<String>,
+    /// Remaining ffmpeg output args, excluding crf args.
+    pub args: Vec<String>,
+}
+
+impl RawEncoderArgs {
+    /// Returns the original args with any crf arg replaced with `crf_arg crf`,
+    /// or appended if not present.
+    pub fn with_crf(&self, crf_arg: &str, crf: impl std::fmt::Display) -> Vec<String> {
+        let mut tokens = self.tokens.clone();
+        match tokens.iter().position(|t| CRF_ARGS.contains(&t.as_str())) {
+            Some(idx) if idx + 1 < tokens.len() => {
+                tokens[idx] = crf_arg.into();
+                tokens[idx + 1] = crf.to_string();
+            }
+            _ => tokens.extend([crf_arg.into(), crf.to_string()]),
+        }
+        tokens
+    }
+}
+
+impl std::str::FromStr for RawEncoderArgs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let tokens = split_args(s)?;
+        let mut raw = Self {
+            tokens: tokens.clone(),
+            encoder: None,
+            preset: None,
+            pix_fmt: None,
+            svtav1_params: None,
+            args: vec![],
+        };
+
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            let mut value = || {
+                tokens
+                    .next()
+                    .with_context(|| format!("--enc-raw missing {token} value"))
+            };
+            match token.as_str() {
+                "-c:v" | "-codec:v" | "-vcodec" => raw.encoder = Some(value()?.parse()?),
+                "-preset" => raw.preset = Some(value()?.parse()?),
+                "-pix_fmt" => {
+                    let pix_fmt = value()?;
+                    raw.pix_fmt = Some(
+                        PixelFormat::try_from(pix_fmt.as_str())
+                            .map_err(|_| anyhow!("--enc-raw unsupported -pix_fmt {pix_fmt}"))?,
+                    );
+                }
+                "-svtav1-params" => raw.svtav1_params = Some(value()?),
+                crf if CRF_ARGS.contains(&crf) => _ = value()?,
+                _ => raw.args.push(token),
+            }
+        }
+        Ok(raw)
+    }
+}
+
+/// Split on whitespace except within single or double quotes.
+fn split_args(s: &str) -> anyhow::Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut token: Option<String> = None;
+    let mut quote = None;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => token.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                token.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => tokens.extend(token.take()),
+            (None, c) => token.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        bail!("--enc-raw unclosed {q} quote");
+    }
+    tokens.extend(token);
+    Ok(tokens)
+}
+
+#[test]
+fn parse_raw_args() {
+    let raw: RawEncoderArgs =
+        "-c:v libsvtav1 -preset 6 -crf 30 -svtav1-params tune=0:film-grain=8 -g 240"
+            .parse()
+            .unwrap();
+    assert_eq!(raw.encoder, Some(Encoder("libsvtav1".into())));
+    assert_eq!(raw.preset, Some(Preset::Number(6)));
+    assert_eq!(raw.svtav1_params.as_deref(), Some("tune=0:film-grain=8"));
+    assert_eq!(raw.args, ["-g", "240"]);
+
+    assert!("-c:v".parse::<RawEncoderArgs>().is_err());
+    assert!("-x265-params 'a=1".parse::<RawEncoderArgs>().is_err());
+}
+
+#[test]
+fn raw_args_crf_substitution() {
+    let raw: RawEncoderArgs = "-c:v libx265 -crf 30 -x265-params \"aq-mode=3:psy-rd=2\""
+        .parse()
+        .unwrap();
+    assert_eq!(
+        raw.with_crf("-crf", 24.5),
+        [
+            "-c:v",
+            "libx265",
+            "-crf",
+            "24.5",
+            "-x265-params",
+            "aq-mode=3:psy-rd=2"
+        ]
+    );
+
+    // encoder specific crf arg replaces the user's, appended if missing
+    let raw: RawEncoderArgs = "-c:v hevc_nvenc -preset p7".parse().unwrap();
+    assert_eq!(
+        raw.with_crf("-cq", 28),
+        ["-c:v", "hevc_nvenc", "-preset", "p7", "-cq", "28"]
+    );
+}
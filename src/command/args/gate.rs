@@ -0,0 +1,90 @@
+use crate::command::args::parse_size;
+use clap::Parser;
+use indicatif::HumanBytes;
+use std::fmt;
+
+/// Quality gate exit policy, e.g. for CI.
+#[derive(Parser, Clone, Debug, Default)]
+pub struct Gate {
+    /// Exit with code 3 if the resulting VMAF is below this score, e.g. `--fail-below-vmaf 94`.
+    #[arg(long)]
+    pub fail_below_vmaf: Option<f32>,
+
+    /// Exit with code 4 if the resulting size exceeds this, e.g. `--fail-above-size 500MB`.
+    #[arg(long, value_parser = parse_size)]
+    pub fail_above_size: Option<u64>,
+}
+
+impl Gate {
+    /// Check a result `vmaf` score & `size`, if known, against the policy.
+    pub fn check(&self, vmaf: f32, size: Option<u64>) -> Result<(), GateFailure> {
+        if let Some(min) = self.fail_below_vmaf.filter(|min| vmaf < *min) {
+            return Err(GateFailure::VmafBelow { vmaf, min });
+        }
+        match (size, self.fail_above_size) {
+            (Some(size), Some(max)) if size > max => Err(GateFailure::SizeAbove { size, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Result failed the `Gate` policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GateFailure {
+    VmafBelow { vmaf: f32, min: f32 },
+    SizeAbove { size: u64, max: u64 },
+}
+
+impl GateFailure {
+    /// Process exit code for this failure, distinct from the general error code 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::VmafBelow { .. } => 3,
+            Self::SizeAbove { .. } => 4,
+        }
+    }
+}
+
+impl fmt::Display for GateFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VmafBelow { vmaf, min } => {
+                write!(f, "VMAF {vmaf:.2} below --fail-below-vmaf {min}")
+            }
+            Self::SizeAbove { size, max } => write!(
+                f,
+                "size {} above --fail-above-size {}",
+                HumanBytes(*size),
+                HumanBytes(*max)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GateFailure {}
+
+/// Returns the process exit code for a command error.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.downcast_ref::<GateFailure>()
+        .map_or(1, GateFailure::exit_code)
+}
+
+#[test]
+fn gate_exit_codes() {
+    let gate = Gate {
+        fail_below_vmaf: Some(94.0),
+        fail_above_size: Some(500_000_000),
+    };
+    assert_eq!(gate.check(95.1, Some(400_000_000)), Ok(()));
+    // unknown size passes
+    assert_eq!(gate.check(95.1, None), Ok(()));
+
+    let code = |vmaf, size| exit_code(&gate.check(vmaf, size).unwrap_err().into());
+    assert_eq!(code(93.9, Some(400_000_000)), 3);
+    assert_eq!(code(95.1, Some(600_000_000)), 4);
+    // vmaf failure takes precedence
+    assert_eq!(code(90.0, Some(600_000_000)), 3);
+
+    assert_eq!(exit_code(&anyhow::anyhow!("ffmpeg failed")), 1);
+    assert_eq!(Gate::default().check(0.0, Some(u64::MAX)), Ok(()));
+}
@@ -0,0 +1,44 @@
+//! `--growing-input` logic for inputs still being written.
+use std::{future::Future, io, time::Duration};
+
+/// Polls the input `len` every `poll` until it stops changing, returning the stable length.
+pub async fn wait_until_stable<Sleep: Future<Output = ()>>(
+    mut len: impl FnMut() -> io::Result<u64>,
+    mut sleep: impl FnMut(Duration) -> Sleep,
+    poll: Duration,
+) -> io::Result<u64> {
+    let mut last = len()?;
+    loop {
+        sleep(poll).await;
+        match len()? {
+            current if current == last => return Ok(current),
+            current => last = current,
+        }
+    }
+}
+
+/// Ffmpeg input args limiting reading to the written `duration` of a growing input,
+/// so data appended after probing isn't read.
+pub fn written_duration_input_args(duration: Duration) -> [String; 2] {
+    ["-t".into(), format!("{}", duration.as_secs_f64())]
+}
+
+#[tokio::test]
+async fn growing_input_waits_until_stable() {
+    let mut lens = [100, 250, 400, 400, 900].into_iter();
+    let mut sleeps = 0;
+    let len = wait_until_stable(
+        || Ok(lens.next().expect("read past stable length")),
+        |_| {
+            sleeps += 1;
+            async {}
+        },
+        Duration::from_secs(5),
+    )
+    .await
+    .unwrap();
+    assert_eq!(len, 400);
+    assert_eq!(sleeps, 3);
+    // the later growth isn't observed
+    assert_eq!(lens.next(), Some(900));
+}
@@ -1,14 +1,23 @@
-use crate::command::args::PixelFormat;
-use anyhow::Context;
-use clap::Parser;
-use std::{borrow::Cow, fmt::Display, sync::Arc, thread};
+mod blend;
+
+pub use blend::MetricBlend;
+
+use crate::{
+    command::args::PixelFormat,
+    ffprobe::{self, Ffprobe},
+    process::CpuList,
+    threads,
+};
+use anyhow::{ensure, Context};
+use clap::{Parser, ValueEnum};
+use std::{borrow::Cow, fmt::Display, sync::Arc, time::Duration};
 
 /// Common vmaf options.
-#[derive(Parser, Clone, Hash)]
+#[derive(Parser, Clone, Hash, Default)]
 pub struct Vmaf {
     /// Additional vmaf arg(s). E.g. --vmaf n_threads=8 --vmaf n_subsample=4
     ///
-    /// By default `n_threads` is set to available system threads.
+    /// By default `n_threads` is set to available system threads, unless --no-auto-threads.
     ///
     /// Also see https://ffmpeg.org/ffmpeg-filters.html#libvmaf.
     #[arg(long = "vmaf", value_parser = parse_vmaf_arg)]
@@ -28,6 +37,9 @@ pub struct Vmaf {
     /// The auto behaviour is based on the distorted video dimensions, equivalent
     /// to post input/reference vfilter dimensions.
     ///
+    /// A factor of the distorted dimensions may also be used, e.g. "2x" or "1.5x".
+    /// Resulting dimensions are rounded to even numbers.
+    ///
     /// Scaling happens after any input/reference vfilters.
     #[arg(long, default_value_t = VmafScale::Auto, value_parser = parse_vmaf_scale)]
     pub vmaf_scale: VmafScale,
@@ -39,25 +51,431 @@ pub struct Vmaf {
     #[arg(long)]
     pub reference_vfilter: Option<String>,
 
+    /// Ffmpeg video filter applied to the distorted, after any VMAF scaling, modelling
+    /// player post-processing. E.g. --player-vfilter "unsharp=5:5:0.8" to mimic a
+    /// sharpening player.
+    ///
+    /// Not applied to a --vmaf-lavfi-template.
+    #[arg(long, conflicts_with = "cuda")]
+    pub player_vfilter: Option<String>,
+
+    /// Video stream index of the distorted to analyse, e.g. `--distorted-stream 1`
+    /// for the 2nd rendition of an encode with multiple video streams.
+    ///
+    /// Auto scaling uses the first video stream resolution, so set --vmaf-scale
+    /// if renditions differ in resolution. Not applied to a --vmaf-lavfi-template.
+    /// [default: 0]
+    #[arg(long)]
+    pub distorted_stream: Option<u32>,
+
     /// Use libvmaf_cuda instead of libvmaf for analysis.
     /// E.g. --cuda
     #[arg(long)]
     pub cuda: bool,
+
+    /// CUDA device index to decode & analyse on with --cuda, e.g. `--cuda-device 1`
+    /// to keep analysis off a GPU used by other work.
+    #[arg(long, requires = "cuda", value_parser = parse_cuda_number)]
+    pub cuda_device: Option<Arc<str>>,
+
+    /// Decoder threads with --cuda. Decoded frames in flight, so VRAM usage, grow
+    /// with decoder threads. E.g. `--cuda-decode-threads 1` to limit VRAM usage
+    /// of 4k analysis on a shared GPU.
+    #[arg(long, requires = "cuda", value_parser = parse_cuda_number)]
+    pub cuda_decode_threads: Option<Arc<str>>,
+
+    /// Maximum number of frames to analyse, e.g. `--vmaf-frames 600`.
+    ///
+    /// The cap applies to source frames before any libvmaf `n_subsample`,
+    /// so `--vmaf-frames 600 --vmaf n_subsample=4` scores 150 frames.
+    #[arg(long)]
+    pub vmaf_frames: Option<u32>,
+
+    /// Skip the first N frames of both streams before analysis, e.g. to exclude
+    /// atypical encoder ramp-up frames at the start of each sample.
+    ///
+    /// Any --vmaf-frames cap applies to frames after those skipped.
+    #[arg(long)]
+    pub skip_frames: Option<u32>,
+
+    /// Scaling algorithm used for the VMAF reference when scaling,
+    /// ffmpeg `flags`, or `interp_algo` with --cuda. E.g. `lanczos`.
+    ///
+    /// [default: area when downscaling, otherwise bicubic]
+    #[arg(long)]
+    pub vmaf_scale_flags_ref: Option<Arc<str>>,
+
+    /// Scaling algorithm used for the VMAF distorted when scaling,
+    /// ffmpeg `flags`, or `interp_algo` with --cuda. E.g. `lanczos`.
+    ///
+    /// [default: area when downscaling, otherwise bicubic]
+    #[arg(long)]
+    pub vmaf_scale_flags_dis: Option<Arc<str>>,
+
+    /// Custom lavfi graph used for VMAF instead of the default construction.
+    /// Input 0 is the distorted, input 1 the reference. Must contain `[dis]` & `[ref]` pads.
+    ///
+    /// Placeholders are filled in:
+    /// * `{pix_fmt}` common pixel format, e.g. "yuv420p10le".
+    /// * `{scale}` model scale "w:h" or "iw:ih" if not scaling.
+    /// * `{ref_vf}` reference vfilter or "null".
+    /// * `{libvmaf_args}` libvmaf args including defaults, e.g. n_threads & model.
+    ///
+    /// E.g. "[0:v]format={pix_fmt},scale={scale}:flags=lanczos[dis];\
+    /// [1:v]format={pix_fmt},{ref_vf},scale={scale}:flags=lanczos[ref];\
+    /// [dis][ref]libvmaf={libvmaf_args}"
+    #[arg(long, value_parser = parse_lavfi_template)]
+    pub vmaf_lavfi_template: Option<Arc<str>>,
+
+    /// Use a weighted blend of VMAF model scores as the score, computed in a
+    /// single multi-model pass. E.g. "0.7*vmaf+0.3*vmaf_neg".
+    ///
+    /// Models are `vmaf`, `vmaf_neg`, `vmaf_4k`, `vmaf_nomotion`, `vmaf_4k_nomotion`
+    /// or a libvmaf model version.
+    #[arg(long)]
+    pub metric_blend: Option<MetricBlend>,
+
+    /// Also compute this metric in the VMAF pass & report its correlation with VMAF
+    /// across samples, warning when they disagree as one may be missing a distortion.
+    #[arg(long, value_enum)]
+    pub extra_metric: Option<ExtraMetric>,
+
+    /// Only analyse frames at scene changes, where quality dips are most visible,
+    /// to measure worst-case quality at cuts.
+    ///
+    /// Frames are selected from distorted & reference stacked together so both
+    /// streams keep exactly the same frames.
+    #[arg(long, conflicts_with_all = ["cuda", "vmaf_lavfi_template"])]
+    pub scene_frames_only: bool,
+
+    /// Drop duplicate frames, e.g. from telecine or encode stalls, so repeats don't
+    /// bias the mean score. Duplicates are detected with ffmpeg mpdecimate.
+    ///
+    /// Frames are dropped from distorted & reference stacked together so both
+    /// streams keep exactly the same frames.
+    #[arg(long, conflicts_with_all = ["cuda", "vmaf_lavfi_template"])]
+    pub drop_duplicate_frames: bool,
+
+    /// Pad a distorted stream with fewer frames than the reference, e.g. with dropped
+    /// frames, by repeating its last frame so the full reference is scored. Dropped
+    /// frames are then penalized instead of only the overlap being scored.
+    #[arg(long, conflicts_with = "vmaf_lavfi_template")]
+    pub pad_distorted: bool,
+
+    /// Normalize reference & distorted identically before any other analysis filtering,
+    /// resetting timestamps to start at zero & converting to the common pixel format
+    /// (& --vmaf-fps rate if set). So both go through equivalent decode paths, isolating
+    /// encode quality from container or decode quirks of the reference.
+    ///
+    /// Only adds these filters to each analysis chain, the reference input itself is
+    /// not re-wrapped or re-encoded.
+    #[arg(long, conflicts_with_all = ["cuda", "vmaf_lavfi_template"])]
+    pub normalize_reference: bool,
+
+    /// Restrict the VMAF analysis ffmpeg process to these cpus, e.g. `0-7` or `0,2,4-6`.
+    /// Useful to keep analysis from competing with concurrent encodes. Linux only.
+    #[arg(long)]
+    pub vmaf_cpu_affinity: Option<CpuList>,
+
+    /// Resample distorted & reference to a common frame rate before analysis. E.g. to
+    /// compare 24fps with 23.976fps which would otherwise drift out of sync.
+    ///
+    /// `auto` uses the least common multiple of the two frame rates if no more than
+    /// 120fps, otherwise the higher rate. Or set a rate, e.g. `--vmaf-fps 24000/1001`.
+    #[arg(long, value_parser = parse_vmaf_fps)]
+    pub vmaf_fps: Option<VmafFps>,
+
+    /// Cap the wall time of any single VMAF analysis, e.g. `--max-analysis-duration 30m`.
+    /// Guards against analyses that stall or run far longer than expected.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub max_analysis_duration: Option<Duration>,
+
+    /// Action when an analysis exceeds `--max-analysis-duration`.
+    /// `retry` retries with halved n_threads, `abort` fails.
+    #[arg(long, value_enum, default_value_t)]
+    pub analysis_overrun: AnalysisOverrunAction,
+
+    /// Common frame rate resolved for `--vmaf-fps auto`.
+    #[arg(skip)]
+    pub auto_fps: Option<Arc<str>>,
+
+    /// Reference resolution, if known, used to pick default scale flags.
+    /// Otherwise the reference is assumed to match the distorted resolution.
+    #[arg(skip)]
+    pub reference_res: Option<(u32, u32)>,
+
+    /// Filter applied first to the distorted stream, e.g. to normalize anamorphic pixels.
+    #[arg(skip)]
+    pub distorted_prefilter: Option<Arc<str>>,
+
+    /// Filter applied first to the reference stream, e.g. to normalize anamorphic pixels.
+    #[arg(skip)]
+    pub reference_prefilter: Option<Arc<str>>,
+}
+
+/// Secondary metric computed alongside VMAF, see `--extra-metric`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum ExtraMetric {
+    Psnr,
+    Ssim,
+}
+
+impl ExtraMetric {
+    /// libvmaf feature name.
+    pub fn feature_name(self) -> &'static str {
+        match self {
+            Self::Psnr => "psnr",
+            Self::Ssim => "float_ssim",
+        }
+    }
+
+    /// Pooled metric name in libvmaf logs.
+    pub fn log_name(self) -> &'static str {
+        match self {
+            Self::Psnr => "psnr_y",
+            Self::Ssim => "float_ssim",
+        }
+    }
+}
+
+impl Display for ExtraMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Psnr => "PSNR".fmt(f),
+            Self::Ssim => "SSIM".fmt(f),
+        }
+    }
+}
+
+/// Action when an analysis exceeds `--max-analysis-duration`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, ValueEnum)]
+pub enum AnalysisOverrunAction {
+    /// Retry with halved n_threads, reducing contention with other processes.
+    Retry,
+    #[default]
+    Abort,
+}
+
+/// Default libvmaf `n_threads`, `None` with `--no-auto-threads`.
+fn default_n_threads() -> Option<usize> {
+    threads::auto_budget()
+}
+
+/// Parse a non-negative integer keeping the string for use as an ffmpeg arg.
+fn parse_cuda_number(arg: &str) -> anyhow::Result<Arc<str>> {
+    let n: u32 = arg
+        .trim()
+        .parse()
+        .context("must be a non-negative integer")?;
+    Ok(n.to_string().into())
 }
 
 fn parse_vmaf_arg(arg: &str) -> anyhow::Result<Arc<str>> {
+    // check model files early, rather than failing once ffmpeg is running
+    for path in VmafModel::paths(arg) {
+        std::fs::File::open(&path)
+            .with_context(|| format!("vmaf model path={path} is not a readable file"))?;
+    }
     Ok(arg.to_owned().into())
 }
 
+/// Fewer scored frames than this make VMAF unreliable.
+const MIN_SCORED_FRAMES: u64 = 24;
+
+const LAVFI_TEMPLATE_PLACEHOLDERS: &[&str] = &["pix_fmt", "scale", "ref_vf", "libvmaf_args"];
+
+fn parse_lavfi_template(template: &str) -> anyhow::Result<Arc<str>> {
+    for pad in ["[dis]", "[ref]"] {
+        ensure!(template.contains(pad), "lavfi template missing {pad} pad");
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("lavfi template unclosed `{}`", &rest[start..]))?;
+        let name = &rest[start + 1..start + end];
+        ensure!(
+            LAVFI_TEMPLATE_PLACEHOLDERS.contains(&name),
+            "lavfi template unknown placeholder `{{{name}}}`, expected one of {}",
+            LAVFI_TEMPLATE_PLACEHOLDERS
+                .iter()
+                .map(|p| format!("{{{p}}}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        rest = &rest[start + end + 1..];
+    }
+    Ok(template.into())
+}
+
+fn fill_lavfi_template(
+    template: &str,
+    pix_fmt: PixelFormat,
+    scale: Option<(i32, i32)>,
+    ref_vf: Option<&str>,
+    libvmaf_args: &str,
+) -> String {
+    let scale = match scale {
+        Some((w, h)) => format!("{w}:{h}"),
+        None => "iw:ih".into(),
+    };
+    template
+        .replace("{pix_fmt}", pix_fmt.as_str())
+        .replace("{scale}", &scale)
+        .replace("{ref_vf}", ref_vf.unwrap_or("null"))
+        .replace("{libvmaf_args}", libvmaf_args)
+}
+
 impl Vmaf {
     pub fn is_default(&self) -> bool {
         let Self {
             vmaf_args,
             vmaf_scale,
             reference_vfilter,
+            player_vfilter,
+            distorted_stream,
             cuda,
+            vmaf_frames,
+            skip_frames,
+            vmaf_scale_flags_ref,
+            vmaf_scale_flags_dis,
+            vmaf_lavfi_template,
+            metric_blend,
+            extra_metric,
+            scene_frames_only,
+            drop_duplicate_frames,
+            pad_distorted,
+            normalize_reference,
+            // affinity & gpu resources don't affect scores
+            vmaf_cpu_affinity: _,
+            cuda_device: _,
+            cuda_decode_threads: _,
+            max_analysis_duration: _,
+            analysis_overrun: _,
+            vmaf_fps,
+            auto_fps,
+            reference_res,
+            distorted_prefilter,
+            reference_prefilter,
         } = self;
-        vmaf_args.is_empty() && *vmaf_scale == VmafScale::Auto && reference_vfilter.is_none() && !*cuda
+        vmaf_args.is_empty()
+            && *vmaf_scale == VmafScale::Auto
+            && reference_vfilter.is_none()
+            && player_vfilter.is_none()
+            && distorted_stream.is_none()
+            && !*cuda
+            && vmaf_frames.is_none()
+            && skip_frames.is_none()
+            && vmaf_scale_flags_ref.is_none()
+            && vmaf_scale_flags_dis.is_none()
+            && vmaf_lavfi_template.is_none()
+            && metric_blend.is_none()
+            && extra_metric.is_none()
+            && !*scene_frames_only
+            && !*drop_duplicate_frames
+            && !*pad_distorted
+            && !*normalize_reference
+            && vmaf_fps.is_none()
+            && auto_fps.is_none()
+            && reference_res.is_none()
+            && distorted_prefilter.is_none()
+            && reference_prefilter.is_none()
+    }
+
+    /// If the distorted & reference sample aspect ratios differ set prefilters converting
+    /// both to square display pixels, so they are compared at display aspect ratio.
+    ///
+    /// Returns the distorted & reference resolutions after prefiltering.
+    /// Does nothing with --cuda.
+    pub fn match_display_aspect(
+        &mut self,
+        distorted: &Ffprobe,
+        reference: &Ffprobe,
+    ) -> [Option<(u32, u32)>; 2] {
+        if self.cuda || ffprobe::same_sar(distorted.sar, reference.sar) {
+            return [distorted.resolution, reference.resolution];
+        }
+        self.distorted_prefilter = ffprobe::square_pixel_vfilter(distorted.sar).map(Into::into);
+        self.reference_prefilter = ffprobe::square_pixel_vfilter(reference.sar).map(Into::into);
+        [
+            distorted.display_resolution(),
+            reference.display_resolution(),
+        ]
+    }
+
+    /// Resolve a `--vmaf-fps auto` common frame rate from the distorted & reference rates.
+    /// Matching rates need no resampling.
+    pub fn set_auto_fps(&mut self, distorted_fps: Option<f64>, reference_fps: Option<f64>) {
+        if self.vmaf_fps != Some(VmafFps::Auto) {
+            return;
+        }
+        self.auto_fps = match (distorted_fps, reference_fps) {
+            (Some(d), Some(r)) if fps_rational(d) != fps_rational(r) => {
+                Some(common_fps(d, r).into())
+            }
+            _ => None,
+        };
+    }
+
+    /// Returns the libvmaf `n_threads` that will be used, `None` with --cuda or if left
+    /// to the libvmaf default with `--no-auto-threads`.
+    pub fn n_threads(&self) -> Option<usize> {
+        if self.cuda {
+            return None;
+        }
+        let explicit = self
+            .vmaf_args
+            .iter()
+            .find_map(|a| a.strip_prefix("n_threads=")?.parse().ok());
+        explicit.or_else(default_n_threads)
+    }
+
+    /// Returns the libvmaf `n_subsample`, 1 if not set.
+    pub fn n_subsample(&self) -> u64 {
+        self.vmaf_args
+            .iter()
+            .find_map(|a| a.strip_prefix("n_subsample=")?.parse().ok())
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Returns the number of frames libvmaf will score out of `frames` source frames,
+    /// after any --skip-frames, --vmaf-frames cap & `n_subsample`.
+    pub fn scored_frames(&self, frames: u64) -> u64 {
+        let frames = frames.saturating_sub(self.skip_frames.unwrap_or(0).into());
+        let frames = self
+            .vmaf_frames
+            .map_or(frames, |max| frames.min(max.into()));
+        frames.div_ceil(self.n_subsample())
+    }
+
+    /// Returns a warning if `n_subsample` leaves so few of `frames` scored that
+    /// the VMAF score is unreliable.
+    pub fn few_scored_frames_warning(&self, frames: u64) -> Option<String> {
+        let n_subsample = self.n_subsample();
+        let scored = self.scored_frames(frames);
+        (n_subsample > 1 && scored < MIN_SCORED_FRAMES).then(|| {
+            format!(
+                "Warning: only {scored} of {frames} frames will be scored with \
+                 n_subsample={n_subsample}, VMAF may be unreliable"
+            )
+        })
+    }
+
+    /// Set `n_threads` to use unless explicitly set or using --cuda.
+    pub fn set_default_n_threads(&mut self, n_threads: usize) {
+        if !self.cuda && !self.vmaf_args.iter().any(|a| a.starts_with("n_threads=")) {
+            self.vmaf_args.push(format!("n_threads={n_threads}").into());
+        }
+    }
+
+    /// Returns a copy using half the `n_threads`, or `None` if already single threaded.
+    pub fn with_halved_n_threads(&self) -> Option<Self> {
+        let n_threads = self.n_threads().filter(|n| *n > 1)? / 2;
+        let mut vmaf = self.clone();
+        vmaf.vmaf_args.retain(|a| !a.starts_with("n_threads="));
+        vmaf.vmaf_args.push(format!("n_threads={n_threads}").into());
+        Some(vmaf)
     }
 
     /// Returns ffmpeg `filter_complex`/`lavfi` value for calculating vmaf.
@@ -68,43 +486,53 @@ impl Vmaf {
         distorted_res: Option<(u32, u32)>,
         pix_fmt: PixelFormat,
         ref_vfilter: Option<&str>,
+    ) -> String {
+        self.ffmpeg_lavfi_with_n_threads(distorted_res, pix_fmt, ref_vfilter, default_n_threads())
+    }
+
+    /// [`Self::ffmpeg_lavfi`] defaulting `n_threads` to `default_n_threads`, if any.
+    fn ffmpeg_lavfi_with_n_threads(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+        pix_fmt: PixelFormat,
+        ref_vfilter: Option<&str>,
+        default_n_threads: Option<usize>,
     ) -> String {
         let mut args = self.vmaf_args.clone();
-        if !self.cuda {
-            if !args.iter().any(|a| a.contains("n_threads")) {
+        if let Some(n_threads) = default_n_threads {
+            if !self.cuda && !args.iter().any(|a| a.contains("n_threads")) {
                 // default n_threads to all cores
-                args.push(
-                    format!(
-                        "n_threads={}",
-                        thread::available_parallelism().map_or(1, |p| p.get())
-                    )
-                    .into(),
-                );
+                args.push(format!("n_threads={n_threads}").into());
             }
         }
 
-        let mut lavfi = args.join(":");
-
-        if self.cuda {
-            lavfi.insert_str(0, "libvmaf_cuda=shortest=true:ts_sync_mode=nearest:");
-        } else {
-            lavfi.insert_str(0, "libvmaf=shortest=true:ts_sync_mode=nearest:");
+        if let Some(metric) = self.extra_metric {
+            // libvmaf takes a single `feature` arg, so merge with any in --vmaf
+            let feature = format!("name={}", metric.feature_name());
+            match args.iter_mut().find(|a| a.starts_with("feature=")) {
+                Some(existing) if existing.split('|').any(|f| f.ends_with(&feature)) => {}
+                Some(existing) => *existing = format!("{existing}|{feature}").into(),
+                None => args.push(format!("feature={feature}").into()),
+            }
         }
 
-        let mut model = VmafModel::from_args(&args);
-        if let (None, Some((w, h))) = (model, distorted_res) {
-            if w > 2560 && h > 1440 {
-                // for >2k resolutions use 4k model
-                lavfi.push_str(":model=version=vmaf_4k_v0.6.1");
-                model = Some(VmafModel::Vmaf4K);
-            }
+        let mut libvmaf_args = format!("shortest=true:ts_sync_mode=nearest:{}", args.join(":"));
+
+        let (model, scale) = self.model_and_scale(distorted_res);
+        if let Some(blend) = &self.metric_blend {
+            libvmaf_args.push(':');
+            libvmaf_args.push_str(&blend.model_arg());
+        } else if VmafModel::from_args(&args).is_none() && model == VmafModel::Vmaf4K {
+            // for >2k resolutions use 4k model
+            libvmaf_args.push_str(":model=version=vmaf_4k_v0.6.1");
         }
 
-        let ref_vf: Cow<_> = match self.reference_vfilter.as_deref().or(ref_vfilter) {
-            None => "".into(),
-            Some(vf) if vf.ends_with(',') => vf.into(),
-            Some(vf) => format!("{vf},").into(),
-        };
+        let ref_vf = self
+            .reference_vfilter
+            .as_deref()
+            .or(ref_vfilter)
+            .map(|vf| vf.trim_end_matches(','))
+            .filter(|vf| !vf.is_empty());
 
         let pix_fmt = if self.cuda {
             if pix_fmt != PixelFormat::Yuv420p {
@@ -117,41 +545,191 @@ impl Vmaf {
             pix_fmt
         };
 
-        // prefix:
+        if let Some(template) = &self.vmaf_lavfi_template {
+            return fill_lavfi_template(template, pix_fmt, scale, ref_vf, &libvmaf_args);
+        }
+
+        // filter chains of each stream:
+        // * cap analysed frames if necessary
+        // * normalize both streams if --normalize-reference
+        // * Add prefilters if any
         // * Add reference-vfilter if any
         // * convert both streams to common pixel format
         // * scale to vmaf width if necessary
+        // * Add distorted player-vfilter if any
+        // * pad distorted if --pad-distorted
         // * sync presentation timestamp
-        let pts_fixiation = "settb=AVTB,setpts=N/FRAME_RATE/TB";
-        let prefix = if let Some((w, h)) = self.vf_scale(model.unwrap_or_default(), distorted_res) {
-            let interp_algo = "bicubic";
-            if self.cuda {
-                format!(
-                    "[0:v]scale_cuda=format={pix_fmt}:w={w}:h={h}:interp_algo={interp_algo},{pts_fixiation}[dis];\
-                     [1:v]scale_cuda=format={pix_fmt}:w={w}:h={h}:interp_algo={interp_algo},{ref_vf}{pts_fixiation}[ref];[dis][ref]"
-                )
-            } else {
-                format!(
-                    "[0:v]format={pix_fmt},scale={w}:{h}:flags={interp_algo},{pts_fixiation}[dis];\
-                     [1:v]format={pix_fmt},{ref_vf}scale={w}:{h}:flags={interp_algo},{pts_fixiation}[ref];[dis][ref]"
-                )
+        let mut dis_chain: Vec<Cow<str>> = vec![];
+        let mut ref_chain: Vec<Cow<str>> = vec![];
+
+        // skip leading frames & cap source frames before any libvmaf n_subsample
+        let trim = match (self.skip_frames.filter(|s| *s > 0), self.vmaf_frames) {
+            (Some(skip), Some(frames)) => Some(format!(
+                "trim=start_frame={skip}:end_frame={}",
+                u64::from(skip) + u64::from(frames)
+            )),
+            (Some(skip), None) => Some(format!("trim=start_frame={skip}")),
+            (None, Some(frames)) => Some(format!("trim=end_frame={frames}")),
+            (None, None) => None,
+        };
+        if let Some(trim) = trim {
+            dis_chain.push(trim.clone().into());
+            ref_chain.push(trim.into());
+        }
+        let common_fps = match &self.vmaf_fps {
+            Some(VmafFps::Rate(fps)) => Some(fps),
+            Some(VmafFps::Auto) => self.auto_fps.as_ref(),
+            None => None,
+        };
+        if self.normalize_reference {
+            let mut normalize = format!("setpts=PTS-STARTPTS,format={pix_fmt}");
+            if let Some(fps) = common_fps {
+                normalize.push_str(&format!(",fps={fps}"));
             }
-        } else {
-            if self.cuda {
-                format!(
-                    "[0:v]scale_cuda=format={pix_fmt},{pts_fixiation}[dis];\
-                     [1:v]scale_cuda=format={pix_fmt},{ref_vf}{pts_fixiation}[ref];[dis][ref]"
-                )
-            } else {
-                format!(
-                    "[0:v]format={pix_fmt},{pts_fixiation}[dis];\
-                     [1:v]format={pix_fmt},{ref_vf}{pts_fixiation}[ref];[dis][ref]"
-                )
+            dis_chain.push(normalize.clone().into());
+            ref_chain.push(normalize.into());
+        }
+        dis_chain.extend(self.distorted_prefilter.as_deref().map(Cow::from));
+        ref_chain.extend(self.reference_prefilter.as_deref().map(Cow::from));
+
+        // after a vfilter the reference is expected to match the distorted resolution
+        let reference_res = match ref_vf {
+            Some(_) => distorted_res,
+            None => self.reference_res.or(distorted_res),
+        };
+        let default_algo = |from: Option<(u32, u32)>| match (from, scale) {
+            (Some(from), Some(to)) => default_scale_flags(from, to),
+            _ => "bicubic",
+        };
+        let dis_algo = self
+            .vmaf_scale_flags_dis
+            .as_deref()
+            .unwrap_or(default_algo(distorted_res));
+        let ref_algo = self
+            .vmaf_scale_flags_ref
+            .as_deref()
+            .unwrap_or(default_algo(reference_res));
+        // skip repeating a `--normalize-reference` format conversion, unless a
+        // prefilter has since been applied
+        let format = format!("format={pix_fmt}");
+        let push_format = |chain: &mut Vec<Cow<'_, str>>, prefilter: &Option<Arc<str>>| {
+            if !self.normalize_reference || prefilter.is_some() {
+                chain.push(format.clone().into());
+            }
+        };
+        match (self.cuda, scale) {
+            (true, Some((w, h))) => {
+                dis_chain.push(
+                    format!("scale_cuda=format={pix_fmt}:w={w}:h={h}:interp_algo={dis_algo}")
+                        .into(),
+                );
+                ref_chain.push(
+                    format!("scale_cuda=format={pix_fmt}:w={w}:h={h}:interp_algo={ref_algo}")
+                        .into(),
+                );
+                ref_chain.extend(ref_vf.map(Cow::from));
+            }
+            (true, None) => {
+                let scale = format!("scale_cuda=format={pix_fmt}");
+                dis_chain.push(scale.clone().into());
+                ref_chain.push(scale.into());
+                ref_chain.extend(ref_vf.map(Cow::from));
             }
+            (false, Some((w, h))) => {
+                push_format(&mut dis_chain, &self.distorted_prefilter);
+                dis_chain.push(format!("scale={w}:{h}:flags={dis_algo}").into());
+                push_format(&mut ref_chain, &self.reference_prefilter);
+                ref_chain.extend(ref_vf.map(Cow::from));
+                ref_chain.push(format!("scale={w}:{h}:flags={ref_algo}").into());
+            }
+            (false, None) => {
+                push_format(&mut dis_chain, &self.distorted_prefilter);
+                push_format(&mut ref_chain, &self.reference_prefilter);
+                ref_chain.extend(ref_vf.map(Cow::from));
+            }
+        }
+
+        dis_chain.extend(
+            self.player_vfilter
+                .as_deref()
+                .map(|vf| vf.trim_end_matches(','))
+                .filter(|vf| !vf.is_empty())
+                .map(Cow::from),
+        );
+
+        if let Some(fps) = common_fps.filter(|_| !self.normalize_reference) {
+            dis_chain.push(format!("fps={fps}").into());
+            ref_chain.push(format!("fps={fps}").into());
+        }
+
+        if self.pad_distorted {
+            // repeat the last frame indefinitely, `shortest` then ends with the reference
+            dis_chain.push("tpad=stop=-1:stop_mode=clone".into());
+        }
+
+        let pts_fixiation = "settb=AVTB,setpts=N/FRAME_RATE/TB";
+        dis_chain.push(pts_fixiation.into());
+        ref_chain.push(pts_fixiation.into());
+
+        let dis_pad = match self.distorted_stream {
+            Some(idx) => format!("[0:v:{idx}]"),
+            None => "[0:v]".into(),
         };
+        let libvmaf = if self.cuda { "libvmaf_cuda" } else { "libvmaf" };
+        let (dis_chain, ref_chain) = (dis_chain.join(","), ref_chain.join(","));
+        let mut stacked_filters = vec![];
+        if self.drop_duplicate_frames {
+            stacked_filters.push(DECIMATE_FILTER.to_owned());
+        }
+        if self.scene_frames_only {
+            stacked_filters.push(format!("select='gt(scene,{SCENE_THRESHOLD})'"));
+        }
+        if !stacked_filters.is_empty() {
+            return format!(
+                "{dis_pad}{dis_chain}[dis];[1:v]{ref_chain}[ref];{}[sdis][sref]{libvmaf}={libvmaf_args}",
+                stacked_select_lavfi(&stacked_filters.join(","))
+            );
+        }
+        format!(
+            "{dis_pad}{dis_chain}[dis];[1:v]{ref_chain}[ref];[dis][ref]{libvmaf}={libvmaf_args}"
+        )
+    }
 
-        lavfi.insert_str(0, &prefix);
-        lavfi
+    /// Returns the model & `(w, h)` scale (if any) that will be used for analysis
+    /// given the distorted resolution.
+    pub fn model_and_scale(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+    ) -> (VmafModel, Option<(i32, i32)>) {
+        let model = VmafModel::from_args(&self.vmaf_args).unwrap_or(match distorted_res {
+            // for >2k resolutions use 4k model
+            Some((w, h)) if w > 2560 && h > 1440 => VmafModel::Vmaf4K,
+            _ => VmafModel::Vmaf1K,
+        });
+        (model, self.vf_scale(model, distorted_res))
+    }
+
+    /// Check distorted & reference resolutions are compatible with the configured scaling.
+    ///
+    /// With `--vmaf-scale none` nothing is scaled so, unless a reference vfilter is
+    /// used, the resolutions must already match otherwise libvmaf fails cryptically.
+    pub fn ensure_compatible_resolutions(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+        reference_res: Option<(u32, u32)>,
+    ) -> anyhow::Result<()> {
+        if self.vmaf_scale != VmafScale::None || self.reference_vfilter.is_some() {
+            return Ok(());
+        }
+        if let (Some((dw, dh)), Some((rw, rh))) = (distorted_res, reference_res) {
+            ensure!(
+                (dw, dh) == (rw, rh),
+                "distorted resolution {dw}x{dh} does not match reference {rw}x{rh} \
+                 which is required by --vmaf-scale none, \
+                 try --vmaf-scale auto or --vmaf-scale WxH e.g. `--vmaf-scale {rw}x{rh}`"
+            );
+        }
+        Ok(())
     }
 
     pub fn reference_input_args(&self) -> Vec<&str> {
@@ -171,7 +749,14 @@ impl Vmaf {
     }
 
     fn cuda_output_format_input_args(&self) -> Vec<&str> {
-        vec!["-hwaccel", "cuda", "-hwaccel_output_format", "cuda"]
+        let mut args = vec!["-hwaccel", "cuda", "-hwaccel_output_format", "cuda"];
+        if let Some(device) = &self.cuda_device {
+            args.extend(["-hwaccel_device", device]);
+        }
+        if let Some(threads) = &self.cuda_decode_threads {
+            args.extend(["-threads", threads]);
+        }
+        args
     }
 
     fn vf_scale(&self, model: VmafModel, distorted_res: Option<(u32, u32)>) -> Option<(i32, i32)> {
@@ -191,11 +776,32 @@ impl Vmaf {
                 Some(minimally_scale((w, h), (width, height)))
             }
             (VmafScale::Custom { width, height }, None) => Some((width as _, height as _)),
+            (VmafScale::Factor(factor), Some((w, h))) => {
+                Some((scale_even(w, factor), scale_even(h, factor)))
+            }
             _ => None,
         }
     }
 }
 
+/// Scene change score above which a frame is analysed with `--scene-frames-only`.
+const SCENE_THRESHOLD: f32 = 0.3;
+
+/// Filter dropping duplicate frames with `--drop-duplicate-frames`.
+const DECIMATE_FILTER: &str = "mpdecimate";
+
+/// Select frames from `[dis]` & `[ref]` into `[sdis]` & `[sref]` using `filters`,
+/// e.g. scene change frames.
+///
+/// The streams are stacked side by side so a single select keeps them aligned,
+/// then cropped back apart. Scene scores & duplicates are thus of the combined frame.
+fn stacked_select_lavfi(filters: &str) -> String {
+    format!(
+        "[dis][ref]hstack=shortest=1,{filters},split[sdis0][sref0];\
+         [sdis0]crop=iw/2:ih:0:0[sdis];[sref0]crop=iw/2:ih:iw/2:0[sref];"
+    )
+}
+
 /// Return the smallest ffmpeg vf `(w, h)` scale values so that at least one of the
 /// `target_w` or `target_h` bounds are met.
 fn minimally_scale((from_w, from_h): (u32, u32), (target_w, target_h): (u32, u32)) -> (i32, i32) {
@@ -208,38 +814,151 @@ fn minimally_scale((from_w, from_h): (u32, u32), (target_w, target_h): (u32, u32
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Default scaling algorithm when scaling `from` to the ffmpeg vf `(w, h)` scale values.
+/// `area` avoids aliasing when downscaling, `bicubic` is used otherwise.
+pub fn default_scale_flags((from_w, from_h): (u32, u32), (w, h): (i32, i32)) -> &'static str {
+    let downscale = match (w, h) {
+        (w, _) if w > 0 => (w as u32) < from_w,
+        (_, h) if h > 0 => (h as u32) < from_h,
+        _ => false,
+    };
+    match downscale {
+        true => "area",
+        false => "bicubic",
+    }
+}
+
+/// Scale `dim` by `factor` rounding to the nearest even number.
+fn scale_even(dim: u32, factor: f32) -> i32 {
+    ((f64::from(dim) * f64::from(factor) / 2.0).round() as i32 * 2).max(2)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub enum VmafScale {
     None,
+    #[default]
     Auto,
-    Custom { width: u32, height: u32 },
+    Custom {
+        width: u32,
+        height: u32,
+    },
+    /// Multiple of the distorted resolution, e.g. `2x`.
+    Factor(f32),
+}
+
+impl std::hash::Hash for VmafScale {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::None | Self::Auto => {}
+            Self::Custom { width, height } => (width, height).hash(state),
+            Self::Factor(factor) => factor.to_bits().hash(state),
+        }
+    }
 }
 
-fn parse_vmaf_scale(vs: &str) -> anyhow::Result<VmafScale> {
-    const ERR: &str = "vmaf-scale must be 'none', 'auto' or WxH format e.g. '1920x1080'";
+pub fn parse_vmaf_scale(vs: &str) -> anyhow::Result<VmafScale> {
+    const ERR: &str =
+        "vmaf-scale must be 'none', 'auto', WxH format e.g. '1920x1080' or a factor e.g. '2x'";
     match vs {
         "none" => Ok(VmafScale::None),
         "auto" => Ok(VmafScale::Auto),
+        _ if vs.ends_with('x') => {
+            let factor: f32 = vs[..vs.len() - 1].trim().parse().context(ERR)?;
+            ensure!(
+                factor.is_finite() && factor > 0.0,
+                "vmaf-scale factor must be > 0"
+            );
+            Ok(VmafScale::Factor(factor))
+        }
         _ => {
-            let (w, h) = vs.split_once('x').context(ERR)?;
-            let (width, height) = (w.parse().context(ERR)?, h.parse().context(ERR)?);
+            let (width, height) = parse_resolution(vs).context(ERR)?;
             Ok(VmafScale::Custom { width, height })
         }
     }
 }
 
+/// Parse `WxH` format, e.g. "1920x1080".
+pub fn parse_resolution(res: &str) -> anyhow::Result<(u32, u32)> {
+    let (w, h) = res
+        .split_once('x')
+        .context("resolution must be in WxH format e.g. '1920x1080'")?;
+    Ok((w.trim().parse()?, h.trim().parse()?))
+}
+
 impl Display for VmafScale {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::None => "none".fmt(f),
             Self::Auto => "auto".fmt(f),
             Self::Custom { width, height } => write!(f, "{width}x{height}"),
+            Self::Factor(factor) => write!(f, "{}x", crate::float::TerseF32(*factor)),
+        }
+    }
+}
+
+/// `--vmaf-fps` common frame rate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VmafFps {
+    Auto,
+    Rate(Arc<str>),
+}
+
+fn parse_vmaf_fps(fps: &str) -> anyhow::Result<VmafFps> {
+    match fps.trim() {
+        "auto" => Ok(VmafFps::Auto),
+        rate => {
+            ensure!(
+                ffprobe::parse_frame_rate(rate).is_some(),
+                "vmaf-fps must be 'auto' or a frame rate e.g. '24' or '24000/1001'"
+            );
+            Ok(VmafFps::Rate(rate.into()))
         }
     }
 }
 
+/// Returns the reduced `(num, den)` rational of a frame rate, recognising NTSC
+/// style `N*1000/1001` rates.
+fn fps_rational(fps: f64) -> (u64, u64) {
+    let ntsc = fps * 1.001;
+    let (num, den) = if (fps - fps.round()).abs() < 0.001 {
+        (fps.round() as u64, 1)
+    } else if (ntsc - ntsc.round()).abs() < 0.001 {
+        (ntsc.round() as u64 * 1000, 1001)
+    } else {
+        ((fps * 1000.0).round() as u64, 1000)
+    };
+    let gcd = gcd(num, den).max(1);
+    (num / gcd, den / gcd)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+/// Returns the ffmpeg `fps` value both rates can be resampled to without drift,
+/// the least common multiple if no more than 120fps, otherwise the higher rate.
+fn common_fps(a: f64, b: f64) -> String {
+    let ((an, ad), (bn, bd)) = (fps_rational(a), fps_rational(b));
+    // lcm(an/ad, bn/bd) = lcm(an, bn) / gcd(ad, bd)
+    let num = an / gcd(an, bn).max(1) * bn;
+    let den = gcd(ad, bd).max(1);
+    let (num, den) = match num as f64 / den as f64 <= 120.0 {
+        true => (num, den),
+        false if a >= b => (an, ad),
+        false => (bn, bd),
+    };
+    match den {
+        1 => num.to_string(),
+        _ => format!("{num}/{den}"),
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
-enum VmafModel {
+pub enum VmafModel {
     /// Default 1080p model.
     #[default]
     Vmaf1K,
@@ -249,6 +968,16 @@ enum VmafModel {
     Custom,
 }
 
+impl Display for VmafModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vmaf1K => "vmaf_v0.6.1".fmt(f),
+            Self::Vmaf4K => "vmaf_4k_v0.6.1".fmt(f),
+            Self::Custom => "custom".fmt(f),
+        }
+    }
+}
+
 impl VmafModel {
     fn from_args(args: &[Arc<str>]) -> Option<Self> {
         let mut using_custom_model: Vec<_> = args.iter().filter(|v| v.contains("model")).collect();
@@ -263,15 +992,115 @@ impl VmafModel {
             _ => Some(Self::Custom),
         }
     }
+
+    /// Returns model file paths of a vmaf `arg`, e.g. "/m.json" for "model=path=/m.json".
+    ///
+    /// Multiple models are separated by `|` & model options by an escaped `\:`,
+    /// e.g. "model=version=vmaf_v0.6.1\:name=a|path=/m.json\:name=b".
+    fn paths(arg: &str) -> Vec<String> {
+        split_unescaped(arg, ':')
+            .into_iter()
+            .filter_map(|opt| opt.strip_prefix("model="))
+            .flat_map(|models| models.split('|'))
+            .flat_map(|model| model.split("\\:"))
+            .filter_map(|opt| opt.strip_prefix("path="))
+            .map(|path| path.trim_matches('\'').to_owned())
+            .collect()
+    }
+}
+
+/// Split on `sep` when not preceded by a `\` escape.
+fn split_unescaped(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut start, mut escaped) = (0, false);
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == sep => {
+                parts.push(&s[start..idx]);
+                start = idx + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+#[test]
+fn vmaf_model_paths() {
+    assert_eq!(
+        VmafModel::paths("model=path=/models/m.json"),
+        ["/models/m.json"]
+    );
+    assert_eq!(
+        VmafModel::paths(
+            "n_threads=4:model=version=vmaf_v0.6.1\\:name=a|path='/m b.json'\\:name=b"
+        ),
+        ["/m b.json"]
+    );
+    assert!(VmafModel::paths("model=version=vmaf_v0.6.1:log_path=out.json").is_empty());
+}
+
+#[test]
+fn vmaf_model_path_validation() {
+    let dir = std::env::temp_dir().join(format!("ab-av1-vmaf-model-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let model = dir.join("m.json");
+    std::fs::write(&model, "{}").unwrap();
+
+    let ok = parse_vmaf_arg(&format!("model=path={}", model.display()));
+    let missing = format!("model=path={}", dir.join("typo.json").display());
+    let err = parse_vmaf_arg(&missing).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(ok.is_ok(), "{ok:?}");
+    assert!(
+        err.to_string().starts_with("vmaf model path=") && err.to_string().contains("typo.json"),
+        "{err}"
+    );
+}
+
+#[test]
+fn scored_frames() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_subsample=10".into()],
+        ..<_>::default()
+    };
+    assert_eq!(vmaf.n_subsample(), 10);
+    // frames 0, 10, .. 470
+    assert_eq!(vmaf.scored_frames(480), 48);
+    assert_eq!(vmaf.scored_frames(235), 24);
+    assert_eq!(Vmaf::default().scored_frames(235), 235);
+
+    let capped = Vmaf {
+        vmaf_frames: Some(100),
+        ..vmaf.clone()
+    };
+    assert_eq!(capped.scored_frames(480), 10);
+}
+
+#[test]
+fn few_scored_frames_warning() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_subsample=10".into()],
+        ..<_>::default()
+    };
+    assert!(vmaf.few_scored_frames_warning(480).is_none());
+    assert_eq!(
+        vmaf.few_scored_frames_warning(120).as_deref(),
+        Some("Warning: only 12 of 120 frames will be scored with n_subsample=10, VMAF may be unreliable")
+    );
+    // without subsampling short samples are not warned about
+    assert!(Vmaf::default().few_scored_frames_warning(12).is_none());
 }
 
 #[test]
 fn vmaf_lavfi() {
     let vmaf = Vmaf {
         vmaf_args: vec!["n_threads=5".into(), "n_subsample=4".into()],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, Some("scale=1280:-1,fps=24")),
@@ -285,9 +1114,8 @@ fn vmaf_lavfi() {
 fn vmaf_lavfi_override_reference_vfilter() {
     let vmaf = Vmaf {
         vmaf_args: vec!["n_threads=5".into(), "n_subsample=4".into()],
-        vmaf_scale: VmafScale::Auto,
         reference_vfilter: Some("scale=2560:-1".into()),
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(
@@ -305,15 +1133,13 @@ fn vmaf_lavfi_override_reference_vfilter() {
 fn vmaf_lavfi_default() {
     let vmaf = Vmaf {
         vmaf_args: vec![],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     let expected = format!(
         "[0:v]format=yuv420p10le,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
          [1:v]format=yuv420p10le,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads={}",
-        thread::available_parallelism().map_or(1, |p| p.get())
+        default_n_threads().unwrap()
     );
     assert_eq!(
         vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p10le, None),
@@ -325,15 +1151,13 @@ fn vmaf_lavfi_default() {
 fn vmaf_lavfi_include_n_threads() {
     let vmaf = Vmaf {
         vmaf_args: vec!["log_path=output.xml".into()],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     let expected = format!(
         "[0:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
          [1:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:log_path=output.xml:n_threads={}",
-        thread::available_parallelism().map_or(1, |p| p.get())
+        default_n_threads().unwrap()
     );
     assert_eq!(
         vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, None),
@@ -341,14 +1165,155 @@ fn vmaf_lavfi_include_n_threads() {
     );
 }
 
+#[test]
+fn cuda_device_input_args() {
+    #[derive(Parser)]
+    struct Args {
+        #[clap(flatten)]
+        vmaf: Vmaf,
+    }
+    let cuda = |args: &[&str]| {
+        let args = [&["ab-av1", "--cuda"], args].concat();
+        Args::try_parse_from(args).map(|a| a.vmaf)
+    };
+
+    let default = cuda(&[]).unwrap();
+    assert_eq!(
+        default.reference_input_args(),
+        ["-hwaccel", "cuda", "-hwaccel_output_format", "cuda"]
+    );
+
+    let device = cuda(&["--cuda-device", "1", "--cuda-decode-threads", "2"]).unwrap();
+    let expected = [
+        "-hwaccel",
+        "cuda",
+        "-hwaccel_output_format",
+        "cuda",
+        "-hwaccel_device",
+        "1",
+        "-threads",
+        "2",
+    ];
+    assert_eq!(device.reference_input_args(), expected);
+    assert_eq!(device.distorted_input_args(), expected);
+
+    assert!(cuda(&["--cuda-device", "gpu0"]).is_err());
+    // requires --cuda
+    assert!(Args::try_parse_from(["ab-av1", "--cuda-device", "1"]).is_err());
+}
+
+#[test]
+fn vmaf_lavfi_pad_distorted() {
+    let lavfi = |pad_distorted| {
+        Vmaf {
+            vmaf_args: vec!["n_threads=4".into()],
+            pad_distorted,
+            ..<_>::default()
+        }
+        .ffmpeg_lavfi(None, PixelFormat::Yuv420p, Some("scale=1280:-1"))
+    };
+    assert_eq!(
+        lavfi(false),
+        "[0:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=1280:-1,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+    assert_eq!(
+        lavfi(true),
+        "[0:v]format=yuv420p,tpad=stop=-1:stop_mode=clone,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=1280:-1,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_normalize_reference() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        normalize_reference: true,
+        vmaf_frames: Some(100),
+        ..<_>::default()
+    };
+    assert!(!vmaf.is_default());
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p10le, Some("scale=1280:-1")),
+        "[0:v]trim=end_frame=100,setpts=PTS-STARTPTS,format=yuv420p10le,\
+         settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]trim=end_frame=100,setpts=PTS-STARTPTS,format=yuv420p10le,\
+         scale=1280:-1,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+
+    // a common fps is part of the normalization, not repeated later
+    let vmaf = Vmaf {
+        vmaf_fps: Some(parse_vmaf_fps("24000/1001").unwrap()),
+        ..vmaf
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, None);
+    let (dis, rest) = lavfi.split_once("[dis];").unwrap();
+    let (reference, _) = rest.split_once("[ref];").unwrap();
+    let normalize = "trim=end_frame=100,setpts=PTS-STARTPTS,format=yuv420p,fps=24000/1001,";
+    assert_eq!(
+        dis.strip_prefix("[0:v]")
+            .unwrap()
+            .matches(normalize)
+            .count(),
+        1
+    );
+    assert!(dis.starts_with(&format!("[0:v]{normalize}")), "{dis}");
+    assert!(
+        reference.starts_with(&format!("[1:v]{normalize}")),
+        "{reference}"
+    );
+    assert_eq!(lavfi.matches("fps=").count(), 2, "{lavfi}");
+    assert_eq!(lavfi.matches("format=").count(), 2, "{lavfi}");
+
+    // a prefilter may change the format, so convert again after it
+    let vmaf = Vmaf {
+        distorted_prefilter: Some("hqdn3d".into()),
+        ..vmaf
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, None);
+    assert!(lavfi.contains(",hqdn3d,format=yuv420p,"), "{lavfi}");
+    assert_eq!(lavfi.matches("format=").count(), 3, "{lavfi}");
+}
+
+#[test]
+fn vmaf_lavfi_no_auto_threads() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_subsample=4".into()],
+        ..<_>::default()
+    };
+    // i.e. default_n_threads() with --no-auto-threads
+    let lavfi = vmaf.ffmpeg_lavfi_with_n_threads(
+        Some((3840, 2160)),
+        PixelFormat::Yuv420p,
+        Some("hqdn3d"),
+        None,
+    );
+    assert!(!lavfi.contains("n_threads"), "{lavfi}");
+    assert!(
+        lavfi.ends_with(
+            "libvmaf=shortest=true:ts_sync_mode=nearest:n_subsample=4:model=version=vmaf_4k_v0.6.1"
+        ),
+        "{lavfi}"
+    );
+
+    // explicit args are kept
+    let explicit = Vmaf {
+        vmaf_args: vec!["n_threads=3".into()],
+        ..<_>::default()
+    };
+    let lavfi = explicit.ffmpeg_lavfi_with_n_threads(None, PixelFormat::Yuv420p, None, None);
+    assert!(lavfi.ends_with(":n_threads=3"), "{lavfi}");
+}
+
 /// Low resolution videos should be upscaled to 1080p
 #[test]
 fn vmaf_lavfi_small_width() {
     let vmaf = Vmaf {
         vmaf_args: vec!["n_threads=5".into(), "n_subsample=4".into()],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None),
@@ -363,9 +1328,7 @@ fn vmaf_lavfi_small_width() {
 fn vmaf_lavfi_4k() {
     let vmaf = Vmaf {
         vmaf_args: vec!["n_threads=5".into(), "n_subsample=4".into()],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(Some((3840, 2160)), PixelFormat::Yuv420p, None),
@@ -380,9 +1343,7 @@ fn vmaf_lavfi_4k() {
 fn vmaf_lavfi_3k_upscale_to_4k() {
     let vmaf = Vmaf {
         vmaf_args: vec!["n_threads=5".into()],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(Some((3008, 1692)), PixelFormat::Yuv420p, None),
@@ -401,9 +1362,7 @@ fn vmaf_lavfi_small_width_custom_model() {
             "n_threads=5".into(),
             "n_subsample=4".into(),
         ],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None),
@@ -426,24 +1385,100 @@ fn vmaf_lavfi_custom_model_and_width() {
             width: 123,
             height: 720,
         },
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None),
-        "[0:v]format=yuv420p,scale=123:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
-         [1:v]format=yuv420p,scale=123:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+        "[0:v]format=yuv420p,scale=123:-1:flags=area,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=123:-1:flags=area,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:model=version=foo:n_threads=5:n_subsample=4"
     );
 }
 
+#[test]
+fn parse_vmaf_scale_factor() {
+    assert_eq!(parse_vmaf_scale("2x").unwrap(), VmafScale::Factor(2.0));
+    assert_eq!(parse_vmaf_scale("1.5x").unwrap(), VmafScale::Factor(1.5));
+    assert_eq!(
+        parse_vmaf_scale("1920x1080").unwrap().to_string(),
+        "1920x1080"
+    );
+    assert_eq!(VmafScale::Factor(1.5).to_string(), "1.5x");
+    assert!(parse_vmaf_scale("0x").is_err());
+    assert!(parse_vmaf_scale("-2x").is_err());
+    assert!(parse_vmaf_scale("ax").is_err());
+}
+
+#[test]
+fn vmaf_scale_factor_dims() {
+    let vmaf = |factor| Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        vmaf_scale: VmafScale::Factor(factor),
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf(2.0).model_and_scale(Some((960, 540))).1,
+        Some((1920, 1080))
+    );
+    // odd results round to even
+    assert_eq!(
+        vmaf(1.5).model_and_scale(Some((854, 481))).1,
+        Some((1282, 722))
+    );
+    assert_eq!(
+        vmaf(0.5).model_and_scale(Some((1278, 718))).1,
+        Some((640, 360))
+    );
+    // unknown resolution can't be scaled by a factor
+    assert_eq!(vmaf(2.0).model_and_scale(None).1, None);
+
+    assert_eq!(
+        vmaf(2.0).ffmpeg_lavfi(Some((960, 540)), PixelFormat::Yuv420p, None),
+        "[0:v]format=yuv420p,scale=1920:1080:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=1920:1080:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn scale_none_resolution_mismatch() {
+    let vmaf = Vmaf {
+        vmaf_args: vec![],
+        vmaf_scale: VmafScale::None,
+        ..<_>::default()
+    };
+    let err = vmaf
+        .ensure_compatible_resolutions(Some((1280, 720)), Some((1920, 1080)))
+        .unwrap_err();
+    assert!(err.to_string().contains("--vmaf-scale"), "{err}");
+
+    // fine with scaling
+    let vmaf = Vmaf {
+        vmaf_scale: VmafScale::Auto,
+        ..vmaf
+    };
+    vmaf.ensure_compatible_resolutions(Some((1280, 720)), Some((1920, 1080)))
+        .unwrap();
+}
+
+#[test]
+fn scale_none_resolution_match() {
+    let vmaf = Vmaf {
+        vmaf_args: vec![],
+        vmaf_scale: VmafScale::None,
+        ..<_>::default()
+    };
+    vmaf.ensure_compatible_resolutions(Some((1920, 1080)), Some((1920, 1080)))
+        .unwrap();
+    vmaf.ensure_compatible_resolutions(None, Some((1920, 1080)))
+        .unwrap();
+}
+
 #[test]
 fn vmaf_lavfi_1080p() {
     let vmaf = Vmaf {
         vmaf_args: vec!["n_threads=5".into(), "n_subsample=4".into()],
-        vmaf_scale: VmafScale::Auto,
-        reference_vfilter: None,
-        cuda: false,
+        ..<_>::default()
     };
     assert_eq!(
         vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None),
@@ -452,3 +1487,426 @@ fn vmaf_lavfi_1080p() {
          [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:n_subsample=4"
     );
 }
+
+/// `--vmaf-frames` should trim source frames before libvmaf `n_subsample`.
+#[test]
+fn vmaf_lavfi_frames_and_subsample() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into(), "n_subsample=4".into()],
+        vmaf_frames: Some(600),
+        ..<_>::default()
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, Some("fps=24"));
+    assert_eq!(
+        lavfi,
+        "[0:v]trim=end_frame=600,format=yuv420p,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]trim=end_frame=600,format=yuv420p,fps=24,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:n_subsample=4"
+    );
+    assert!(lavfi.find("trim=end_frame=600").unwrap() < lavfi.find("n_subsample=4").unwrap());
+}
+
+/// `--player-vfilter` should apply only to the distorted, after scaling.
+#[test]
+fn vmaf_lavfi_player_vfilter() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        player_vfilter: Some("unsharp=5:5:0.8".into()),
+        ..<_>::default()
+    };
+    assert!(!vmaf.is_default());
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, Some("hqdn3d")),
+        "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic,unsharp=5:5:0.8,\
+         settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,hqdn3d,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_distorted_stream() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        distorted_stream: Some(1),
+        ..<_>::default()
+    };
+    assert!(!vmaf.is_default());
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, None),
+        "[0:v:1]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+/// `--skip-frames` should trim the same leading frames from both streams.
+#[test]
+fn vmaf_lavfi_skip_frames() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        skip_frames: Some(12),
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None),
+        "[0:v]trim=start_frame=12,format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]trim=start_frame=12,format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+    assert_eq!(vmaf.scored_frames(480), 468);
+
+    // cap counts frames after those skipped
+    let vmaf = Vmaf {
+        vmaf_frames: Some(600),
+        ..vmaf
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None);
+    assert_eq!(
+        lavfi.matches("trim=start_frame=12:end_frame=612,").count(),
+        2,
+        "{lavfi}"
+    );
+    assert_eq!(vmaf.scored_frames(1000), 600);
+}
+
+/// `model_and_scale` should match the auto decisions made in `ffmpeg_lavfi`.
+#[test]
+fn model_and_scale_matches_lavfi() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        ..<_>::default()
+    };
+    for (res, expected_model, expected_scale) in [
+        ((1280, 720), VmafModel::Vmaf1K, Some((1920, -1))),
+        ((1920, 1080), VmafModel::Vmaf1K, None),
+        ((2560, 1440), VmafModel::Vmaf1K, None),
+        ((3008, 1692), VmafModel::Vmaf4K, Some((3840, -1))),
+        ((3840, 2160), VmafModel::Vmaf4K, None),
+        ((960, 720), VmafModel::Vmaf1K, Some((-1, 1080))),
+    ] {
+        let (model, scale) = vmaf.model_and_scale(Some(res));
+        assert_eq!((model, scale), (expected_model, expected_scale), "{res:?}");
+
+        let lavfi = vmaf.ffmpeg_lavfi(Some(res), PixelFormat::Yuv420p, None);
+        assert_eq!(
+            lavfi.contains("model=version=vmaf_4k_v0.6.1"),
+            model == VmafModel::Vmaf4K,
+            "{lavfi}"
+        );
+        match scale {
+            Some((w, h)) => assert!(lavfi.contains(&format!("scale={w}:{h}:")), "{lavfi}"),
+            None => assert!(!lavfi.contains("scale="), "{lavfi}"),
+        }
+    }
+}
+
+#[test]
+fn vmaf_lavfi_per_stream_scale_flags() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        vmaf_scale_flags_ref: Some("lanczos".into()),
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None),
+        "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=1920:-1:flags=lanczos,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+
+    let vmaf = Vmaf {
+        vmaf_scale_flags_dis: Some("neighbor".into()),
+        cuda: true,
+        ..vmaf
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None),
+        "[0:v]scale_cuda=format=yuv420p:w=1920:h=-1:interp_algo=neighbor,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]scale_cuda=format=yuv420p:w=1920:h=-1:interp_algo=lanczos,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf_cuda=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn halve_n_threads_to_one() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=12".into(), "n_subsample=4".into()],
+        ..<_>::default()
+    };
+    let mut sequence = vec![];
+    let mut next = vmaf.with_halved_n_threads();
+    while let Some(vmaf) = next {
+        sequence.push(vmaf.n_threads().unwrap());
+        assert!(vmaf.vmaf_args.contains(&"n_subsample=4".into()));
+        assert_eq!(
+            vmaf.vmaf_args
+                .iter()
+                .filter(|a| a.starts_with("n_threads="))
+                .count(),
+            1
+        );
+        next = vmaf.with_halved_n_threads();
+    }
+    assert_eq!(sequence, [6, 3, 1]);
+
+    let cuda = Vmaf { cuda: true, ..vmaf };
+    assert!(cuda.with_halved_n_threads().is_none());
+}
+
+#[test]
+fn vmaf_lavfi_template() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        vmaf_lavfi_template: Some(
+            parse_lavfi_template(
+                "[0:v]format={pix_fmt},scale={scale}:flags=lanczos[dis];\
+                 [1:v]format={pix_fmt},{ref_vf},scale={scale}:flags=lanczos[ref];\
+                 [dis][ref]libvmaf={libvmaf_args}",
+            )
+            .unwrap(),
+        ),
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(
+            Some((1280, 720)),
+            PixelFormat::Yuv420p,
+            Some("crop=100:100")
+        ),
+        "[0:v]format=yuv420p,scale=1920:-1:flags=lanczos[dis];\
+         [1:v]format=yuv420p,crop=100:100,scale=1920:-1:flags=lanczos[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((3840, 2160)), PixelFormat::Yuv420p10le, None),
+        "[0:v]format=yuv420p10le,scale=iw:ih:flags=lanczos[dis];\
+         [1:v]format=yuv420p10le,null,scale=iw:ih:flags=lanczos[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5:model=version=vmaf_4k_v0.6.1"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_template_malformed() {
+    let err = parse_lavfi_template("[0:v]null[dis];[dis][1:v]libvmaf").unwrap_err();
+    assert!(err.to_string().contains("[ref]"), "{err}");
+
+    let err = parse_lavfi_template("[0:v]scale={size}[dis];[1:v]null[ref];[dis][ref]libvmaf")
+        .unwrap_err();
+    assert!(err.to_string().contains("{size}"), "{err}");
+
+    assert!(
+        parse_lavfi_template("[0:v]null[dis];[1:v]null[ref];[dis][ref]libvmaf={libvmaf_args")
+            .is_err()
+    );
+}
+
+#[test]
+fn vmaf_lavfi_anamorphic_display_ar() {
+    let mut vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        ..<_>::default()
+    };
+    // 1440x1080 4:3 pixel anamorphic reference vs square pixel 1920x1080 distorted
+    let [dres, rres] = vmaf.match_display_aspect(
+        &Ffprobe {
+            resolution: Some((1920, 1080)),
+            sar: Some((1, 1)),
+            ..ffprobe::unknown("")
+        },
+        &Ffprobe {
+            resolution: Some((1440, 1080)),
+            sar: Some((4, 3)),
+            ..ffprobe::unknown("")
+        },
+    );
+    assert_eq!(dres, Some((1920, 1080)));
+    assert_eq!(dres, rres);
+
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(dres, PixelFormat::Yuv420p, None),
+        "[0:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]scale=round(iw*4/3/2)*2:ih,setsar=1,format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+}
+
+#[test]
+fn vmaf_fps_common_rate() {
+    assert_eq!(fps_rational(24000.0 / 1001.0), (24000, 1001));
+    assert_eq!(fps_rational(25.0), (25, 1));
+    assert_eq!(common_fps(24.0, 30.0), "120");
+    assert_eq!(common_fps(24.0, 48.0), "48");
+    // lcm of 25 & 30 is 150fps, too high
+    assert_eq!(common_fps(25.0, 30.0), "30");
+    // pulldown, lcm 24000fps
+    assert_eq!(common_fps(24.0, 24000.0 / 1001.0), "24");
+    assert_eq!(
+        common_fps(24000.0 / 1001.0, 30000.0 / 1001.0),
+        "120000/1001"
+    );
+
+    let mut vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=5".into()],
+        vmaf_scale: VmafScale::None,
+        vmaf_fps: Some(VmafFps::Auto),
+        ..<_>::default()
+    };
+    vmaf.set_auto_fps(Some(24.0), Some(24.0));
+    assert_eq!(vmaf.auto_fps, None);
+    vmaf.set_auto_fps(Some(24000.0 / 1001.0), Some(24.0));
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, None),
+        "[0:v]format=yuv420p,fps=24,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,fps=24,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=5"
+    );
+
+    let vmaf = Vmaf {
+        vmaf_fps: Some(parse_vmaf_fps("24000/1001").unwrap()),
+        ..vmaf
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(None, PixelFormat::Yuv420p, None);
+    assert_eq!(lavfi.matches("fps=24000/1001,settb").count(), 2, "{lavfi}");
+    assert!(parse_vmaf_fps("fast").is_err());
+}
+
+#[test]
+fn extra_metric_feature_arg() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        extra_metric: Some(ExtraMetric::Psnr),
+        ..<_>::default()
+    };
+    assert!(!vmaf.is_default());
+    let lavfi = vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None);
+    assert!(
+        lavfi.ends_with("libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4:feature=name=psnr"),
+        "{lavfi}"
+    );
+
+    // merged with a user --vmaf feature
+    let vmaf = Vmaf {
+        vmaf_args: vec!["feature=name=float_ssim".into(), "n_threads=4".into()],
+        ..vmaf
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None);
+    assert!(
+        lavfi.ends_with("libvmaf=shortest=true:ts_sync_mode=nearest:feature=name=float_ssim|name=psnr:n_threads=4"),
+        "{lavfi}"
+    );
+}
+
+#[test]
+fn vmaf_lavfi_default_scale_flags_by_direction() {
+    let vmaf = |factor| Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        vmaf_scale: VmafScale::Factor(factor),
+        ..<_>::default()
+    };
+    let lavfi = |vmaf: Vmaf| vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None);
+
+    let down = lavfi(vmaf(0.5));
+    assert!(
+        down.contains("[0:v]format=yuv420p,scale=960:540:flags=area,"),
+        "{down}"
+    );
+    assert!(
+        down.contains("[1:v]format=yuv420p,scale=960:540:flags=area,"),
+        "{down}"
+    );
+
+    let up = lavfi(vmaf(2.0));
+    assert!(
+        up.contains("[0:v]format=yuv420p,scale=3840:2160:flags=bicubic,"),
+        "{up}"
+    );
+    assert!(
+        up.contains("[1:v]format=yuv420p,scale=3840:2160:flags=bicubic,"),
+        "{up}"
+    );
+
+    // explicit flags override both directions
+    let overridden = lavfi(Vmaf {
+        vmaf_scale_flags_dis: Some("lanczos".into()),
+        vmaf_scale_flags_ref: Some("neighbor".into()),
+        ..vmaf(0.5)
+    });
+    assert!(
+        overridden.contains("[0:v]format=yuv420p,scale=960:540:flags=lanczos,"),
+        "{overridden}"
+    );
+    assert!(
+        overridden.contains("[1:v]format=yuv420p,scale=960:540:flags=neighbor,"),
+        "{overridden}"
+    );
+
+    assert_eq!(default_scale_flags((1280, 720), (-1, 480)), "area");
+    assert_eq!(default_scale_flags((1280, 720), (1920, -1)), "bicubic");
+}
+
+#[test]
+fn vmaf_lavfi_scene_frames_only() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        scene_frames_only: true,
+        ..<_>::default()
+    };
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None),
+        "[0:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]hstack=shortest=1,select='gt(scene,0.3)',split[sdis0][sref0];\
+         [sdis0]crop=iw/2:ih:0:0[sdis];[sref0]crop=iw/2:ih:iw/2:0[sref];\
+         [sdis][sref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+    assert!(!vmaf.is_default());
+
+    // scaled streams are stacked after scaling to the same resolution
+    let scaled = Vmaf {
+        vmaf_scale: VmafScale::Custom {
+            width: 1920,
+            height: 1080,
+        },
+        ..vmaf
+    }
+    .ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None);
+    let stack = scaled.find("hstack").unwrap();
+    assert!(scaled.find("[dis];").unwrap() < stack, "{scaled}");
+    assert!(scaled.find("scale=1920:-1").unwrap() < stack, "{scaled}");
+    // one select decides the frames of both streams
+    assert_eq!(scaled.matches("select=").count(), 1, "{scaled}");
+}
+
+#[test]
+fn vmaf_lavfi_drop_duplicate_frames() {
+    let vmaf = Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        drop_duplicate_frames: true,
+        ..<_>::default()
+    };
+    assert!(!vmaf.is_default());
+    assert_eq!(
+        vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None),
+        "[0:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]hstack=shortest=1,mpdecimate,split[sdis0][sref0];\
+         [sdis0]crop=iw/2:ih:0:0[sdis];[sref0]crop=iw/2:ih:iw/2:0[sref];\
+         [sdis][sref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+
+    // combined with --scene-frames-only a single stacked chain decides both streams' frames
+    let lavfi = Vmaf {
+        scene_frames_only: true,
+        ..vmaf
+    }
+    .ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None);
+    assert!(
+        lavfi.contains("hstack=shortest=1,mpdecimate,select='gt(scene,0.3)',split[sdis0][sref0];"),
+        "{lavfi}"
+    );
+    assert_eq!(lavfi.matches("mpdecimate").count(), 1, "{lavfi}");
+    let (dis, rest) = lavfi.split_once("[dis];").unwrap();
+    let (reference, _) = rest.split_once("[ref];").unwrap();
+    assert!(!dis.contains("mpdecimate") && !reference.contains("mpdecimate"));
+}
@@ -0,0 +1,147 @@
+//! `--metric-blend` weighted VMAF model combination.
+use anyhow::{ensure, Context};
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// Weighted sum of VMAF model scores, e.g. "0.7*vmaf+0.3*vmaf_neg".
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricBlend {
+    /// `(weight, model name)` terms.
+    pub terms: Vec<(f32, Arc<str>)>,
+}
+
+impl MetricBlend {
+    /// Returns the libvmaf `model` arg running every blend model in a single pass.
+    pub fn model_arg(&self) -> String {
+        let models: Vec<_> = self
+            .terms
+            .iter()
+            .map(|(_, name)| format!("version={}\\:name={name}", model_version(name)))
+            .collect();
+        format!("model='{}'", models.join("|"))
+    }
+
+    /// Returns the blended score given each model's pooled mean score.
+    pub fn score(&self, model_scores: &HashMap<String, f64>) -> anyhow::Result<f32> {
+        let mut score = 0.0;
+        for (weight, name) in &self.terms {
+            let model_score = model_scores
+                .get(&**name)
+                .with_context(|| format!("no {name} score for --metric-blend"))?;
+            score += f64::from(*weight) * model_score;
+        }
+        Ok(score as f32)
+    }
+}
+
+/// Model version for a blend term name, known shorthands or the name itself.
+///
+/// `*_nomotion` shorthands force the motion feature to zero.
+fn model_version(name: &str) -> &str {
+    match name {
+        "vmaf" => "vmaf_v0.6.1",
+        "vmaf_neg" => "vmaf_v0.6.1neg",
+        "vmaf_4k" => "vmaf_4k_v0.6.1",
+        "vmaf_nomotion" => "vmaf_v0.6.1\\:motion.motion_force_zero=true",
+        "vmaf_4k_nomotion" => "vmaf_4k_v0.6.1\\:motion.motion_force_zero=true",
+        version => version,
+    }
+}
+
+impl Hash for MetricBlend {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for (weight, name) in &self.terms {
+            weight.to_bits().hash(state);
+            name.hash(state);
+        }
+    }
+}
+
+impl fmt::Display for MetricBlend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, (weight, name)) in self.terms.iter().enumerate() {
+            if idx > 0 {
+                f.write_str("+")?;
+            }
+            write!(f, "{weight}*{name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse "0.7*vmaf+0.3*vmaf_neg", a term without a weight has weight 1.
+impl std::str::FromStr for MetricBlend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut terms: Vec<(f32, Arc<str>)> = vec![];
+        for term in s.split('+').map(str::trim) {
+            let (weight, name) = match term.split_once('*') {
+                Some((weight, name)) => (
+                    weight
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("invalid metric-blend weight in `{term}`"))?,
+                    name.trim(),
+                ),
+                None => (1.0, term),
+            };
+            ensure!(
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "_.-".contains(c)),
+                "invalid metric-blend model `{name}`"
+            );
+            ensure!(
+                terms.iter().all(|(_, n)| &**n != name),
+                "duplicate metric-blend model `{name}`"
+            );
+            terms.push((weight, name.into()));
+        }
+        Ok(Self { terms })
+    }
+}
+
+#[test]
+fn parse_metric_blend() {
+    let blend: MetricBlend = "0.7*vmaf + 0.3*vmaf_neg".parse().unwrap();
+    assert_eq!(
+        blend.terms,
+        [(0.7, "vmaf".into()), (0.3, "vmaf_neg".into())]
+    );
+    assert_eq!(blend.to_string(), "0.7*vmaf+0.3*vmaf_neg");
+    assert_eq!(
+        blend.model_arg(),
+        "model='version=vmaf_v0.6.1\\:name=vmaf|version=vmaf_v0.6.1neg\\:name=vmaf_neg'"
+    );
+
+    let blend: MetricBlend = "vmaf+0*vmaf_nomotion".parse().unwrap();
+    assert_eq!(
+        blend.model_arg(),
+        "model='version=vmaf_v0.6.1\\:name=vmaf|\
+         version=vmaf_v0.6.1\\:motion.motion_force_zero=true\\:name=vmaf_nomotion'"
+    );
+
+    let blend: MetricBlend = "vmaf_b_v0.6.3".parse().unwrap();
+    assert_eq!(blend.terms, [(1.0, "vmaf_b_v0.6.3".into())]);
+
+    assert!("0.5*vmaf+0.5*vmaf".parse::<MetricBlend>().is_err());
+    assert!("x*vmaf".parse::<MetricBlend>().is_err());
+    assert!("0.5*".parse::<MetricBlend>().is_err());
+}
+
+#[test]
+fn metric_blend_score() {
+    let blend: MetricBlend = "0.7*vmaf+0.3*vmaf_neg".parse().unwrap();
+    let scores = HashMap::from([("vmaf".to_owned(), 96.0), ("vmaf_neg".to_owned(), 92.0)]);
+    let score = blend.score(&scores).unwrap();
+    assert!((score - 94.8).abs() < 1e-4, "{score}");
+
+    let scores = HashMap::from([("vmaf".to_owned(), 96.0)]);
+    assert!(blend.score(&scores).is_err());
+}
@@ -5,49 +5,130 @@ use crate::{
         PROGRESS_CHARS,
     },
     console_ext::style,
-    ffprobe,
+    ffmpeg,
+    ffprobe::{self, Ffprobe},
     float::TerseF32,
     temporary,
+    timing::{self, PhaseTimes},
 };
-use clap::Parser;
+use anyhow::{ensure, Context};
+use clap::{Parser, ValueHint};
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::{sync::Arc, time::Duration};
+use log::info;
+use std::{
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// Automatically determine the best crf to deliver the min-vmaf and use it to encode a video or image.
 ///
 /// Two phases:
 /// * crf-search to determine the best --crf value
 /// * ffmpeg & SvtAv1EncApp to encode using the settings
+///
+/// With --batch every video file in a directory is auto-encoded in turn, failures
+/// are reported without stopping the batch.
 #[derive(Parser)]
 #[clap(verbatim_doc_comment)]
 #[group(skip)]
+#[command(mut_arg("input", |a| a.required(false).required_unless_present("batch").default_value(".").hide_default_value(true)))]
 pub struct Args {
     #[clap(flatten)]
     pub search: crf_search::Args,
 
     #[clap(flatten)]
     pub encode: args::EncodeToOutput,
+
+    /// Stop after the crf search without encoding. Prints the chosen crf, predictions
+    /// & the commands to run the final encode.
+    #[arg(long)]
+    pub no_encode: bool,
+
+    /// Print the wall time spent probing, sampling, searching & encoding once done.
+    #[arg(long)]
+    pub phase_times: bool,
+
+    #[clap(flatten)]
+    pub batch: Batch,
+}
+
+/// Batch directory args.
+#[derive(Parser, Clone)]
+pub struct Batch {
+    /// Auto-encode every video file in this directory instead of a single --input.
+    /// Failed files are reported & skipped, a summary is printed at the end.
+    #[arg(long, conflicts_with_all = ["input", "output"], value_hint = ValueHint::DirPath)]
+    pub batch: Option<PathBuf>,
+
+    /// Directory to write --batch encodes to, mirroring the input directory structure.
+    /// Default: alongside each input.
+    #[arg(long, requires = "batch", conflicts_with = "input", value_hint = ValueHint::DirPath)]
+    pub out_dir: Option<PathBuf>,
+
+    /// Include files in subdirectories of the --batch directory.
+    #[arg(long, requires = "batch", conflicts_with = "input")]
+    pub recursive: bool,
+
+    /// Comma separated file extensions to include in a --batch.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "mkv,mp4,m4v,mov,avi,webm,ts,m2ts,mpg,wmv,flv"
+    )]
+    pub batch_ext: Vec<String>,
+
+    /// Skip --batch files whose output already exists as a complete encode, i.e. has
+    /// a video stream of the input's duration. E.g. to resume an interrupted batch.
+    #[arg(long, requires = "batch", conflicts_with = "input")]
+    pub skip_existing: bool,
 }
 
-pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()> {
+pub async fn auto_encode(args: Args) -> anyhow::Result<()> {
+    match args.batch.batch.clone() {
+        Some(dir) => auto_encode_batch(args, &dir).await,
+        None => auto_encode_file(args).await,
+    }
+}
+
+async fn auto_encode_file(
+    Args {
+        mut search,
+        encode,
+        no_encode,
+        phase_times,
+        batch: _,
+    }: Args,
+) -> anyhow::Result<()> {
     const SPINNER_RUNNING: &str =
         "{spinner:.cyan.bold} {prefix} {elapsed_precise:.bold} {wide_bar:.cyan/blue} ({msg}eta {eta})";
     const SPINNER_FINISHED: &str =
         "{spinner:.cyan.bold} {prefix} {elapsed_precise:.bold} {wide_bar:.cyan/blue} ({msg})";
 
+    let mut times = PhaseTimes::default();
+    let probe_start = Instant::now();
     search.quiet = true;
+    search.args.prepare().await?;
     let defaulting_output = encode.output.is_none();
     let input_probe = Arc::new(ffprobe::probe(&search.args.input));
+    input_probe.ensure_video(&search.args.input)?;
 
-    let output = encode.output.unwrap_or_else(|| {
+    let output = encode.output.clone().unwrap_or_else(|| {
         default_output_name(
             &search.args.input,
-            &search.args.encoder,
+            search.args.encoder(),
             input_probe.is_image,
         )
     });
     search.sample.set_extension_from_output(&output);
+    search
+        .sample
+        .set_auto_sample_duration(&search.args, &input_probe);
+    search.sample.set_avoid_ranges(&search.args.input)?;
+    times.probing = probe_start.elapsed();
 
     let bar = ProgressBar::new(12).with_style(
         ProgressStyle::default_bar()
@@ -56,38 +137,51 @@ pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()
     );
 
     bar.set_prefix("Searching");
-    if defaulting_output {
+    if defaulting_output && !no_encode {
         let out = shell_escape::escape(output.display().to_string().into());
         bar.println(style!("Encoding {out}").dim().to_string());
     }
 
-    let best = match crf_search::run(&search, input_probe.clone(), bar.clone()).await {
-        Ok(best) => best,
-        Err(err) => {
-            if let crf_search::Error::NoGoodCrf { last } = &err {
-                // show last sample attempt in progress bar
-                bar.set_style(
-                    ProgressStyle::default_bar()
-                        .template(SPINNER_FINISHED)?
-                        .progress_chars(PROGRESS_CHARS),
-                );
-                let mut vmaf = style(last.enc.vmaf);
-                if last.enc.vmaf < search.min_vmaf {
-                    vmaf = vmaf.red();
+    let mut attempts = vec![];
+    let search_start = Instant::now();
+    let sampled_before = timing::sampling();
+    let best =
+        match crf_search::run_sweep(&mut search, input_probe.clone(), bar.clone(), &mut attempts)
+            .await
+        {
+            Ok(best) => best,
+            Err(err) => {
+                if let crf_search::Error::NoGoodCrf { last, .. }
+                | crf_search::Error::TargetUnreachable { best: last } = &err
+                {
+                    // show last sample attempt in progress bar
+                    bar.set_style(
+                        ProgressStyle::default_bar()
+                            .template(SPINNER_FINISHED)?
+                            .progress_chars(PROGRESS_CHARS),
+                    );
+                    let mut vmaf = style(last.enc.vmaf);
+                    if last.enc.vmaf < search.min_vmaf {
+                        vmaf = vmaf.red();
+                    }
+                    let mut percent = style!("{:.0}%", last.enc.encode_percent);
+                    if last.enc.encode_percent > search.max_encoded_percent as _ {
+                        percent = percent.red();
+                    }
+                    bar.finish_with_message(format!(
+                        "crf {}, VMAF {vmaf:.2}, size {percent}",
+                        style(TerseF32(last.crf())).red(),
+                    ));
                 }
-                let mut percent = style!("{:.0}%", last.enc.encode_percent);
-                if last.enc.encode_percent > search.max_encoded_percent as _ {
-                    percent = percent.red();
-                }
-                bar.finish_with_message(format!(
-                    "crf {}, VMAF {vmaf:.2}, size {percent}",
-                    style(TerseF32(last.crf())).red(),
-                ));
+                bar.finish();
+                return Err(err.into());
             }
-            bar.finish();
-            return Err(err.into());
-        }
-    };
+        };
+    times.add_search(
+        search_start.elapsed(),
+        timing::sampling() - sampled_before,
+        attempts.len(),
+    );
     bar.set_style(
         ProgressStyle::default_bar()
             .template(SPINNER_FINISHED)?
@@ -100,6 +194,33 @@ pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()
         style(format!("{:.0}%", best.enc.encode_percent)).green(),
     ));
     temporary::clean_all().await;
+    if let Some(path) = &search.html_report {
+        crf_search::write_html_report(path, &search, &best, &attempts)?;
+    }
+    if let Some(path) = &search.write_plan {
+        search.saved_plan(&best)?.write(path)?;
+    }
+
+    if no_encode {
+        crf_search::StdoutFormat::Human.print_result(&best, input_probe.is_image);
+        let encode::Plan {
+            enc_args,
+            has_audio,
+            audio_codec,
+            stereo_downmix,
+        } = encode::plan(&search.args, best.crf(), &encode, &input_probe, &bar)?;
+        let ffmpeg_cmd =
+            ffmpeg::encode_cmd_str(enc_args, &output, has_audio, audio_codec, stereo_downmix)?;
+        println!(
+            "{}",
+            no_encode_plan(&search.args.encode_hint(best.crf()), &output, &ffmpeg_cmd)
+        );
+        print_phase_times(&times, phase_times);
+        search
+            .gate
+            .check(best.enc.vmaf, Some(best.enc.predicted_encode_size))?;
+        return Ok(());
+    }
 
     let bar = ProgressBar::new(12).with_style(
         ProgressStyle::default_bar()
@@ -109,17 +230,361 @@ pub async fn auto_encode(Args { mut search, encode }: Args) -> anyhow::Result<()
     bar.set_prefix("Encoding ");
     bar.enable_steady_tick(Duration::from_millis(100));
 
+    let sidecar_vmaf = search.sidecar_vmaf(&best);
+    let encode_start = Instant::now();
     encode::run(
         encode::Args {
             args: search.args,
             crf: best.crf(),
             encode: args::EncodeToOutput {
-                output: Some(output),
+                output: Some(output.clone()),
                 ..encode
             },
+            from_plan: None,
+            sidecar_vmaf: Some(sidecar_vmaf),
         },
         input_probe,
         &bar,
     )
-    .await
+    .await?;
+    times.encoding = encode_start.elapsed();
+    print_phase_times(&times, phase_times);
+
+    let size = tokio::fs::metadata(&output).await?.len();
+    search.gate.check(best.enc.vmaf, Some(size))?;
+    Ok(())
+}
+
+/// Logs the phase wall times & prints them to stderr with `--phase-times`.
+fn print_phase_times(times: &PhaseTimes, phase_times: bool) {
+    info!("phase times: {times}");
+    if phase_times {
+        eprintln!("{}", style!("Phase times: {times}").dim());
+    }
+}
+
+/// Runs [`auto_encode_file`] for each video file found in the `--batch` directory.
+async fn auto_encode_batch(
+    Args {
+        search,
+        encode,
+        no_encode,
+        phase_times,
+        batch,
+    }: Args,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    let files = discover(
+        dir,
+        batch.recursive,
+        &batch.batch_ext,
+        ffmpeg::pre_extension_name(search.args.encoder().as_str()),
+        batch.out_dir.as_deref(),
+    )?;
+    ensure!(
+        !files.is_empty(),
+        "no video files found in {}",
+        dir.display()
+    );
+
+    let count = files.len();
+    let summary = run_batch(files, |idx, input| {
+        let mut search = search.clone();
+        let mut encode = encode.clone();
+        let batch = batch.clone();
+        async move {
+            eprintln!(
+                "{}",
+                style!("[{}/{count}] {}", idx + 1, input.display()).bold()
+            );
+            if let Some(out_dir) = &batch.out_dir {
+                let relative_dir = input
+                    .parent()
+                    .and_then(|p| p.strip_prefix(dir).ok())
+                    .unwrap_or(Path::new(""));
+                let out_dir = out_dir.join(relative_dir);
+                tokio::fs::create_dir_all(&out_dir).await?;
+                let name = default_output_name(&input, search.args.encoder(), false);
+                encode.output = Some(out_dir.join(name.file_name().context("output name")?));
+            }
+            if batch.skip_existing {
+                let output = encode
+                    .output
+                    .clone()
+                    .unwrap_or_else(|| default_output_name(&input, search.args.encoder(), false));
+                if is_existing_output_complete(&input, &output) {
+                    eprintln!(
+                        "{}",
+                        style!("Skipping, {} already exists", output.display()).dim()
+                    );
+                    return Ok(());
+                }
+            }
+            search.args.input = input;
+            auto_encode_file(Args {
+                search,
+                encode,
+                no_encode,
+                phase_times,
+                batch: Batch {
+                    batch: None,
+                    ..batch
+                },
+            })
+            .await
+        }
+    })
+    .await;
+
+    eprintln!("{summary}");
+    ensure!(
+        summary.failed.is_empty(),
+        "{} of {count} files failed",
+        summary.failed.len()
+    );
+    Ok(())
+}
+
+/// Returns the sorted files in `dir` with one of the `exts` (case-insensitive).
+///
+/// Skips previous outputs, i.e. files with the default output `pre_ext` like "vid.av1.mkv",
+/// and the `out_dir`.
+fn discover(
+    dir: &Path,
+    recursive: bool,
+    exts: &[String],
+    pre_ext: &str,
+    out_dir: Option<&Path>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let out_dir = out_dir.and_then(|d| d.canonicalize().ok());
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive && (out_dir.is_none() || path.canonicalize().ok() != out_dir) {
+                    dirs.push(path);
+                }
+            } else if path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| exts.iter().any(|x| x.eq_ignore_ascii_case(e)))
+                && !is_output_name(&path, pre_ext)
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns true if `path` is named like a default output with `pre_ext`, e.g. "vid.av1.mkv".
+fn is_output_name(path: &Path, pre_ext: &str) -> bool {
+    path.file_stem()
+        .map(Path::new)
+        .and_then(Path::extension)
+        .is_some_and(|e| e == pre_ext)
+}
+
+/// Returns true if `output` exists & probes as a complete encode of `input`.
+fn is_existing_output_complete(input: &Path, output: &Path) -> bool {
+    output.exists() && is_complete_encode(&ffprobe::probe(input), &ffprobe::probe(output))
+}
+
+/// Returns true if `output` has a video stream with the `input` duration, allowing for
+/// container rounding. Partial encodes, e.g. from an interrupted run, are shorter.
+fn is_complete_encode(input: &Ffprobe, output: &Ffprobe) -> bool {
+    match (&input.duration, &output.duration) {
+        (Ok(input_d), Ok(output_d)) if output.has_video => {
+            let tolerance = Duration::from_secs(1).max(input_d.mul_f64(0.01));
+            input_d.abs_diff(*output_d) <= tolerance
+        }
+        _ => false,
+    }
+}
+
+/// Runs `run` for each file, isolating failures so one file can't abort the batch.
+/// Failures are collected for reporting in the [`BatchSummary`].
+async fn run_batch<F, Fut>(files: Vec<PathBuf>, mut run: F) -> BatchSummary
+where
+    F: FnMut(usize, PathBuf) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut summary = BatchSummary::default();
+    for (idx, file) in files.into_iter().enumerate() {
+        match run(idx, file.clone()).await {
+            Ok(()) => summary.succeeded.push(file),
+            Err(err) => summary.failed.push((file, err)),
+        }
+    }
+    summary
+}
+
+#[derive(Debug, Default)]
+struct BatchSummary {
+    succeeded: Vec<PathBuf>,
+    failed: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Batch complete: {} succeeded, {} failed",
+            self.succeeded.len(),
+            self.failed.len()
+        )?;
+        for (file, err) in &self.failed {
+            write!(f, "\n  {}: {err:#}", file.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the `--no-encode` plan commands to run the final encode.
+fn no_encode_plan(encode_hint: &str, output: &Path, ffmpeg_cmd: &str) -> String {
+    let output = shell_escape::escape(output.display().to_string().into());
+    format!("Encode with:\n  {encode_hint} -o {output}\nor ffmpeg:\n  {ffmpeg_cmd}")
+}
+
+#[test]
+fn no_encode_prints_plan() {
+    let args = Args::try_parse_from([
+        "auto-encode",
+        "-i",
+        "vid.mkv",
+        "--preset",
+        "6",
+        "--no-encode",
+    ])
+    .unwrap();
+    assert!(args.no_encode);
+
+    let probe = ffprobe::unknown("test");
+    let bar = ProgressBar::hidden();
+    let output = Path::new("my vid.av1.mkv");
+    let plan = encode::plan(&args.search.args, 32.0, &args.encode, &probe, &bar).unwrap();
+    let ffmpeg_cmd = ffmpeg::encode_cmd_str(
+        plan.enc_args,
+        output,
+        plan.has_audio,
+        plan.audio_codec,
+        plan.stereo_downmix,
+    )
+    .unwrap();
+
+    let printed = no_encode_plan(&args.search.args.encode_hint(32.0), output, &ffmpeg_cmd);
+    assert!(
+        printed.contains("ab-av1 encode -i vid.mkv --crf 32 --preset 6 -o 'my vid.av1.mkv'"),
+        "{printed}"
+    );
+    assert!(printed.contains("-crf 32"), "{printed}");
+    assert!(printed.contains("-preset 6"), "{printed}");
+}
+
+#[test]
+fn batch_discover_filters_extensions() {
+    let dir = std::env::temp_dir().join(format!("ab-av1-batch-test-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    for f in [
+        "b.mkv",
+        "a.MP4",
+        "a.av1.mkv",
+        "notes.txt",
+        "sub/c.mkv",
+        "sub/d.srt",
+        "out/b.mkv",
+    ] {
+        std::fs::write(dir.join(f), b"").unwrap();
+    }
+    let exts = ["mkv".to_owned(), "mp4".to_owned()];
+    let out_dir = dir.join("out");
+
+    let flat = discover(&dir, false, &exts, "av1", Some(&out_dir)).unwrap();
+    let recursive = discover(&dir, true, &exts, "av1", Some(&out_dir)).unwrap();
+    let x264 = discover(&dir, false, &exts, "x264", None).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(flat, [dir.join("a.MP4"), dir.join("b.mkv")]);
+    assert_eq!(
+        x264,
+        [dir.join("a.MP4"), dir.join("a.av1.mkv"), dir.join("b.mkv")]
+    );
+    assert_eq!(
+        recursive,
+        [dir.join("a.MP4"), dir.join("b.mkv"), dir.join("sub/c.mkv")]
+    );
+}
+
+#[tokio::test]
+async fn batch_failure_does_not_abort() {
+    let files = vec!["a.mkv".into(), "bad.mkv".into(), "c.mkv".into()];
+    let mut ran = vec![];
+    let summary = run_batch(files, |_, file: PathBuf| {
+        ran.push(file.clone());
+        async move {
+            ensure!(file != Path::new("bad.mkv"), "boom");
+            Ok(())
+        }
+    })
+    .await;
+
+    assert_eq!(ran.len(), 3);
+    assert_eq!(summary.succeeded, [PathBuf::from("a.mkv"), "c.mkv".into()]);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, Path::new("bad.mkv"));
+    assert_eq!(
+        summary.to_string(),
+        "Batch complete: 2 succeeded, 1 failed\n  bad.mkv: boom"
+    );
+}
+
+#[test]
+fn batch_args_without_input() {
+    let args = Args::try_parse_from(["auto-encode", "--batch", "in", "--out-dir", "out"]).unwrap();
+    assert_eq!(args.batch.batch, Some("in".into()));
+    assert!(Args::try_parse_from(["auto-encode"]).is_err());
+    assert!(Args::try_parse_from(["auto-encode", "--batch", "in", "-i", "vid.mkv"]).is_err());
+}
+
+#[test]
+fn skip_existing_complete_output() {
+    let probe = |secs: Option<u64>, has_video| Ffprobe {
+        duration: match secs {
+            Some(secs) => Ok(Duration::from_secs(secs)),
+            None => ffprobe::unknown("test").duration,
+        },
+        has_video,
+        ..ffprobe::unknown("test")
+    };
+    let input = probe(Some(600), true);
+
+    assert!(is_complete_encode(&input, &probe(Some(600), true)));
+    assert!(
+        is_complete_encode(&input, &probe(Some(595), true)),
+        "within 1%"
+    );
+    assert!(
+        !is_complete_encode(&input, &probe(Some(300), true)),
+        "partial"
+    );
+    assert!(
+        !is_complete_encode(&input, &probe(Some(600), false)),
+        "no video"
+    );
+    assert!(!is_complete_encode(&input, &probe(None, true)), "invalid");
+    assert!(!is_complete_encode(
+        &probe(None, true),
+        &probe(Some(600), true)
+    ));
+
+    let missing = std::env::temp_dir().join("ab-av1-skip-existing-missing.av1.mkv");
+    assert!(!is_existing_output_complete(Path::new("vid.mkv"), &missing));
+
+    let args = Args::try_parse_from(["auto-encode", "--batch", "in", "--skip-existing"]).unwrap();
+    assert!(args.batch.skip_existing);
+    assert!(Args::try_parse_from(["auto-encode", "-i", "vid.mkv", "--skip-existing"]).is_err());
 }
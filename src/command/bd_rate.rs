@@ -0,0 +1,208 @@
+use crate::command::rd_curve::RdPoint;
+use anyhow::{ensure, Context};
+use clap::{Parser, ValueHint};
+use std::path::{Path, PathBuf};
+
+/// Compute the Bjøntegaard delta rate between two rate-distortion curves.
+///
+/// Curves are `rd-curve --stdout-format json` outputs, e.g. of two encoders or settings.
+/// The result is the mean bitrate difference of the test curve relative to the
+/// reference over their overlapping VMAF range, negative meaning the test curve
+/// needs less bitrate for the same quality.
+///
+/// Uses piecewise cubic (pchip) interpolation of log-bitrate against VMAF.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+pub struct Args {
+    /// Reference rd-curve json file.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub reference: PathBuf,
+
+    /// Test rd-curve json file.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub test: PathBuf,
+}
+
+pub async fn bd_rate(Args { reference, test }: Args) -> anyhow::Result<()> {
+    let reference_curve = read_curve(&reference)?;
+    let test_curve = read_curve(&test)?;
+    let (rate, (lo, hi)) = bd_rate_percent(&reference_curve, &test_curve)?;
+    println!("BD-rate {rate:.2}% over VMAF {lo:.2}-{hi:.2}");
+    Ok(())
+}
+
+fn read_curve(path: &Path) -> anyhow::Result<Vec<RdPoint>> {
+    let json = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    serde_json::from_slice(&json).with_context(|| format!("parse rd-curve {}", path.display()))
+}
+
+/// Returns the BD-rate percentage of `test` relative to `reference` & the overlapping
+/// VMAF interval it was computed over.
+fn bd_rate_percent(reference: &[RdPoint], test: &[RdPoint]) -> anyhow::Result<(f64, (f64, f64))> {
+    let reference = LogRateCurve::new(reference).context("reference curve")?;
+    let test = LogRateCurve::new(test).context("test curve")?;
+
+    let lo = reference.vmaf[0].max(test.vmaf[0]);
+    let hi = reference.vmaf[reference.vmaf.len() - 1].min(test.vmaf[test.vmaf.len() - 1]);
+    ensure!(lo < hi, "rd-curve VMAF ranges do not overlap");
+
+    let mean_diff = (test.integrate(lo, hi) - reference.integrate(lo, hi)) / (hi - lo);
+    Ok(((mean_diff.exp() - 1.0) * 100.0, (lo, hi)))
+}
+
+/// Piecewise cubic hermite (pchip) interpolant of ln(bitrate) as a function of VMAF.
+#[derive(Debug)]
+struct LogRateCurve {
+    vmaf: Vec<f64>,
+    log_rate: Vec<f64>,
+    /// Monotonicity preserving derivatives at each point.
+    slope: Vec<f64>,
+}
+
+impl LogRateCurve {
+    fn new(points: &[RdPoint]) -> anyhow::Result<Self> {
+        let mut points: Vec<_> = points
+            .iter()
+            .map(|p| (f64::from(p.vmaf), p.bitrate_kbps))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+        ensure!(
+            points.len() >= 2,
+            "at least 2 points with distinct VMAF required"
+        );
+        ensure!(
+            points.iter().all(|(_, rate)| *rate > 0.0),
+            "bitrates must be positive"
+        );
+
+        let vmaf: Vec<_> = points.iter().map(|(v, _)| *v).collect();
+        let log_rate: Vec<_> = points.iter().map(|(_, r)| r.ln()).collect();
+        let slope = pchip_slopes(&vmaf, &log_rate);
+        Ok(Self {
+            vmaf,
+            log_rate,
+            slope,
+        })
+    }
+
+    fn eval(&self, k: usize, x: f64) -> f64 {
+        let h = self.vmaf[k + 1] - self.vmaf[k];
+        let t = (x - self.vmaf[k]) / h;
+        let (t2, t3) = (t * t, t * t * t);
+        (2.0 * t3 - 3.0 * t2 + 1.0) * self.log_rate[k]
+            + (t3 - 2.0 * t2 + t) * h * self.slope[k]
+            + (-2.0 * t3 + 3.0 * t2) * self.log_rate[k + 1]
+            + (t3 - t2) * h * self.slope[k + 1]
+    }
+
+    /// Integral over `lo..hi`, which must be within the curve.
+    fn integrate(&self, lo: f64, hi: f64) -> f64 {
+        (0..self.vmaf.len() - 1)
+            .map(|k| {
+                let a = self.vmaf[k].max(lo);
+                let b = self.vmaf[k + 1].min(hi);
+                if a >= b {
+                    return 0.0;
+                }
+                // simpson's rule is exact for cubics
+                (b - a) / 6.0
+                    * (self.eval(k, a) + 4.0 * self.eval(k, (a + b) / 2.0) + self.eval(k, b))
+            })
+            .sum()
+    }
+}
+
+/// Fritsch-Carlson derivatives, matching scipy's `PchipInterpolator`.
+fn pchip_slopes(x: &[f64], y: &[f64]) -> Vec<f64> {
+    let n = x.len();
+    let h: Vec<_> = x.windows(2).map(|w| w[1] - w[0]).collect();
+    let delta: Vec<_> = (0..n - 1).map(|k| (y[k + 1] - y[k]) / h[k]).collect();
+    if n == 2 {
+        return vec![delta[0]; 2];
+    }
+
+    let mut d = vec![0.0; n];
+    for k in 1..n - 1 {
+        if delta[k - 1] * delta[k] > 0.0 {
+            let w1 = 2.0 * h[k] + h[k - 1];
+            let w2 = h[k] + 2.0 * h[k - 1];
+            d[k] = (w1 + w2) / (w1 / delta[k - 1] + w2 / delta[k]);
+        }
+    }
+    let end_slope = |h0: f64, h1: f64, d0: f64, d1: f64| {
+        let d = ((2.0 * h0 + h1) * d0 - h0 * d1) / (h0 + h1);
+        if d.signum() != d0.signum() {
+            0.0
+        } else if d0.signum() != d1.signum() && d.abs() > 3.0 * d0.abs() {
+            3.0 * d0
+        } else {
+            d
+        }
+    };
+    d[0] = end_slope(h[0], h[1], delta[0], delta[1]);
+    d[n - 1] = end_slope(h[n - 2], h[n - 3], delta[n - 2], delta[n - 3]);
+    d
+}
+
+#[test]
+fn bd_rate_known_curves() {
+    let curve = |points: &[(f64, f32)]| -> Vec<RdPoint> {
+        points
+            .iter()
+            .map(|&(bitrate_kbps, vmaf)| RdPoint {
+                crf: 0.0,
+                bitrate_kbps,
+                vmaf,
+            })
+            .collect()
+    };
+    let reference = curve(&[
+        (800.0, 88.0),
+        (1500.0, 92.5),
+        (3000.0, 95.8),
+        (6000.0, 98.1),
+    ]);
+    let assert_close = |actual: f64, expected: f64| {
+        assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+    };
+
+    // same curve
+    let (rate, range) = bd_rate_percent(&reference, &reference).unwrap();
+    assert_close(rate, 0.0);
+    assert_eq!(range, (88.0, f64::from(98.1_f32)));
+
+    // 10% less bitrate at every quality
+    let cheaper: Vec<_> = reference
+        .iter()
+        .map(|p| RdPoint {
+            bitrate_kbps: p.bitrate_kbps * 0.9,
+            ..*p
+        })
+        .collect();
+    assert_close(bd_rate_percent(&reference, &cheaper).unwrap().0, -10.0);
+    assert_close(
+        bd_rate_percent(&cheaper, &reference).unwrap().0,
+        100.0 / 9.0,
+    );
+
+    // log-linear curves are interpolated exactly: ln(r) = v/10 vs v/12 overlapping 30-50
+    // mean diff = -(1/60) * 40, BD-rate = e^(-2/3) - 1
+    let a = curve(&[
+        (3_f64.exp(), 30.0),
+        (4_f64.exp(), 40.0),
+        (6_f64.exp(), 60.0),
+    ]);
+    let b = curve(&[
+        (1.25_f64.exp(), 15.0),
+        (2.5_f64.exp(), 30.0),
+        (3.75_f64.exp(), 45.0),
+        ((50.0_f64 / 12.0).exp(), 50.0),
+    ]);
+    let (rate, range) = bd_rate_percent(&a, &b).unwrap();
+    assert_eq!(range, (30.0, 50.0));
+    assert_close(rate, ((-2.0_f64 / 3.0).exp() - 1.0) * 100.0);
+
+    assert!(bd_rate_percent(&reference, &curve(&[(100.0, 50.0), (200.0, 60.0)])).is_err());
+    assert!(bd_rate_percent(&reference, &curve(&[(100.0, 90.0)])).is_err());
+}
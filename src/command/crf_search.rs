@@ -1,21 +1,30 @@
+pub mod complexity;
 mod err;
+mod report;
+mod subsample;
+mod sweep;
 
-pub use err::Error;
+pub use err::{Error, SizeConstraint};
+pub use report::write as write_html_report;
+pub use sweep::run_sweep;
 
 use crate::{
-    command::{args, crf_search::err::ensure_or_no_good_crf, sample_encode, PROGRESS_CHARS},
+    command::{
+        args, crf_search::err::ensure_or_no_good_crf, encode, sample_encode, PROGRESS_CHARS,
+    },
     console_ext::style,
     ffprobe,
     ffprobe::Ffprobe,
     float::TerseF32,
 };
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, ValueHint};
 use console::style;
 use err::ensure_other;
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
 use log::info;
 use std::{
     io::{self, IsTerminal},
+    path::PathBuf,
     sync::Arc,
     time::Duration,
 };
@@ -30,7 +39,7 @@ const BAR_LEN: u64 = 1_000_000_000;
 /// * Mean sample VMAF score
 /// * Predicted full encode size
 /// * Predicted full encode time
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[clap(verbatim_doc_comment)]
 #[group(skip)]
 pub struct Args {
@@ -45,6 +54,13 @@ pub struct Args {
     #[arg(long, default_value_t = 80.0)]
     pub max_encoded_percent: f32,
 
+    /// Maximum desired predicted encode size, e.g. `500MB` or `1.5GiB`.
+    ///
+    /// Combined with --min-vmaf the smallest crf satisfying both is found,
+    /// otherwise the constraint that cannot be met is reported.
+    #[arg(long, value_parser = args::parse_size)]
+    pub max_encoded_size: Option<u64>,
+
     /// Minimum (highest quality) crf value to try.
     #[arg(long, default_value_t = 10.0)]
     pub min_crf: f32,
@@ -77,16 +93,66 @@ pub struct Args {
     )]
     pub cache: bool,
 
+    /// Experimental: Seed the search starting crf using a fast VMAF-free complexity
+    /// probe encode of a single sample, instead of starting in the middle of the crf range.
+    #[arg(long)]
+    pub complexity_seed: bool,
+
+    /// Abort sample encodes once their size, extrapolated from the partial output,
+    /// exceeds --max-encoded-percent. Aborted crf attempts skip VMAF & are treated as too large.
+    #[arg(long)]
+    pub early_size_abort: bool,
+
+    #[clap(flatten)]
+    pub sweep: sweep::SweepArgs,
+
+    #[clap(flatten)]
+    pub subsample: subsample::SubsampleArgs,
+
     #[clap(flatten)]
     pub sample: args::Sample,
 
     #[clap(flatten)]
     pub vmaf: args::Vmaf,
 
+    #[clap(flatten)]
+    pub gate: args::Gate,
+
+    /// Write a self-contained html report of the search result & VMAF curve of all
+    /// crf attempts, e.g. `--html-report report.html`.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub html_report: Option<PathBuf>,
+
+    /// Write the resulting crf & encode args to this json plan file for a later
+    /// `ab-av1 encode --from-plan`, e.g. on another machine.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub write_plan: Option<PathBuf>,
+
     #[arg(skip)]
     pub quiet: bool,
 }
 
+impl Args {
+    /// VMAF info of the `best` search result to include in an encode `--sidecar`.
+    pub fn sidecar_vmaf(&self, best: &Sample) -> encode::sidecar::Vmaf {
+        encode::sidecar::Vmaf {
+            score: best.enc.vmaf,
+            args: self.vmaf.vmaf_args.iter().map(|a| a.to_string()).collect(),
+            scale: self.vmaf.vmaf_scale.to_string(),
+        }
+    }
+
+    /// Saved plan of the `best` search result for `--write-plan`.
+    pub fn saved_plan(&self, best: &Sample) -> anyhow::Result<encode::saved_plan::SavedPlan> {
+        encode::saved_plan::SavedPlan::new(
+            &self.args,
+            best.crf(),
+            self.sidecar_vmaf(best),
+            best.enc.encode_percent,
+        )
+    }
+}
+
 pub async fn crf_search(mut args: Args) -> anyhow::Result<()> {
     let bar = ProgressBar::new(12).with_style(
         ProgressStyle::default_bar()
@@ -94,14 +160,25 @@ pub async fn crf_search(mut args: Args) -> anyhow::Result<()> {
             .progress_chars(PROGRESS_CHARS)
     );
 
+    args.args.prepare().await?;
     let probe = ffprobe::probe(&args.args.input);
+    probe.ensure_video(&args.args.input)?;
     let input_is_image = probe.is_image;
     args.sample
         .set_extension_from_input(&args.args.input, &probe);
+    args.sample.set_auto_sample_duration(&args.args, &probe);
+    args.sample.set_avoid_ranges(&args.args.input)?;
 
-    let best = run(&args, probe.into(), bar.clone()).await;
+    let mut attempts = vec![];
+    let best = run_sweep(&mut args, probe.into(), bar.clone(), &mut attempts).await;
     bar.finish();
     let best = best?;
+    if let Some(path) = &args.html_report {
+        write_html_report(path, &args, &best, &attempts)?;
+    }
+    if let Some(path) = &args.write_plan {
+        args.saved_plan(&best)?.write(path)?;
+    }
 
     if std::io::stderr().is_terminal() {
         // encode how-to hint
@@ -114,15 +191,19 @@ pub async fn crf_search(mut args: Args) -> anyhow::Result<()> {
 
     StdoutFormat::Human.print_result(&best, input_is_image);
 
+    args.gate
+        .check(best.enc.vmaf, Some(best.enc.predicted_encode_size))?;
     Ok(())
 }
 
+/// Run the crf search, pushing each crf attempt to `attempts`.
 pub async fn run(
     args: &Args,
     input_probe: Arc<Ffprobe>,
     bar: ProgressBar,
+    attempts: &mut Vec<Sample>,
 ) -> Result<Sample, Error> {
-    _run(args, input_probe, bar)
+    _run(args, input_probe, bar, attempts)
         .await
         .inspect(|s| info!("crf {} successful", s.crf()))
 }
@@ -132,28 +213,48 @@ async fn _run(
         args,
         min_vmaf,
         max_encoded_percent,
+        max_encoded_size,
         min_crf,
         max_crf,
         crf_increment,
         thorough,
+        complexity_seed,
+        early_size_abort,
         sample,
         quiet,
         cache,
         vmaf,
+        sweep: _,
+        subsample: _,
+        gate: _,
+        html_report: _,
+        write_plan: _,
     }: &Args,
     input_probe: Arc<Ffprobe>,
     bar: ProgressBar,
+    all_attempts: &mut Vec<Sample>,
 ) -> Result<Sample, Error> {
-    let max_crf = max_crf.unwrap_or_else(|| args.encoder.default_max_crf());
+    let max_crf = max_crf.unwrap_or_else(|| args.default_max_crf());
     ensure_other!(*min_crf < max_crf, "Invalid --min-crf & --max-crf");
 
     let crf_increment = crf_increment
-        .unwrap_or_else(|| args.encoder.default_crf_increment())
+        .unwrap_or_else(|| args.default_crf_increment())
         .max(0.001);
 
     let min_q = q_from_crf(*min_crf, crf_increment);
     let max_q = q_from_crf(max_crf, crf_increment);
     let mut q: u64 = (min_q + max_q) / 2;
+    if *complexity_seed {
+        bar.set_message("complexity probe, ");
+        let bpp = complexity::probe(args, sample, &input_probe).await?;
+        let seed = complexity::seed_crf(bpp, *min_crf, max_crf);
+        info!("complexity {bpp:.4} bpp, seeding crf {}", TerseF32(seed));
+        q = q_from_crf(seed, crf_increment).clamp(min_q, max_q);
+    }
+
+    if let Some(warning) = sample_encode::scored_frames_warning(sample, vmaf, &input_probe) {
+        bar.println(style(warning).yellow().to_string());
+    }
 
     let mut args = sample_encode::Args {
         args: args.clone(),
@@ -161,23 +262,26 @@ async fn _run(
         sample: sample.clone(),
         cache: *cache,
         stdout_format: sample_encode::StdoutFormat::Json,
+        list_samples: false,
+        baseline: <_>::default(),
         vmaf: vmaf.clone(),
+        abort_above_percent: early_size_abort.then_some(*max_encoded_percent),
     };
 
     bar.set_length(BAR_LEN);
     let sample_bar = ProgressBar::hidden();
-    let mut crf_attempts = Vec::new();
-
-    for run in 1.. {
-        // how much we're prepared to go higher than the min-vmaf
-        let higher_tolerance = match thorough {
-            true => 0.05,
-            // increment 1.0 => +0.1, +0.2, +0.4, +0.8 ..
-            // increment 0.1 => +0.1, +0.1, +0.1, +0.16 ..
-            _ => (crf_increment * 2_f32.powi(run as i32 - 1) * 0.1).max(0.1),
-        };
-        args.crf = q.to_crf(crf_increment);
-        bar.set_message(format!("sampling crf {}, ", TerseF32(args.crf)));
+    let search = Search {
+        min_vmaf: *min_vmaf,
+        max_encoded_percent: *max_encoded_percent,
+        max_encoded_size: *max_encoded_size,
+        thorough: *thorough,
+        quiet: *quiet,
+        crf_increment,
+        min_q,
+        max_q,
+    };
+    let sample_at = async |run, crf| {
+        args.crf = crf;
         let mut sample_task = tokio::task::spawn_local(sample_encode::run(
             args.clone(),
             input_probe.clone(),
@@ -198,74 +302,173 @@ async fn _run(
                 }
             }
         };
+        Ok(sample_task??)
+    };
+    search.run(q, &bar, all_attempts, sample_at).await
+}
+
+/// Crf search constraints & bounds.
+#[derive(Debug, Clone, Copy)]
+struct Search {
+    min_vmaf: f32,
+    max_encoded_percent: f32,
+    max_encoded_size: Option<u64>,
+    thorough: bool,
+    quiet: bool,
+    crf_increment: f32,
+    min_q: u64,
+    max_q: u64,
+}
 
-        let sample = Sample {
+impl Search {
+    /// Interpolated binary search from `q` targeting the min score, using
+    /// `sample_at(run, crf)` to sample & score each crf attempt.
+    async fn run(
+        self,
+        mut q: u64,
+        bar: &ProgressBar,
+        all_attempts: &mut Vec<Sample>,
+        mut sample_at: impl AsyncFnMut(usize, f32) -> Result<sample_encode::Output, Error>,
+    ) -> Result<Sample, Error> {
+        let Self {
+            min_vmaf,
+            max_encoded_percent,
+            max_encoded_size,
+            thorough,
+            quiet,
             crf_increment,
-            q,
-            enc: sample_task??,
-        };
-        let from_cache = sample.enc.from_cache;
-        crf_attempts.push(sample.clone());
-        let sample_small_enough = sample.enc.encode_percent <= *max_encoded_percent as _;
-
-        if sample.enc.vmaf > *min_vmaf {
-            // good
-            if sample_small_enough && sample.enc.vmaf < min_vmaf + higher_tolerance {
-                return Ok(sample);
+            min_q,
+            max_q,
+        } = self;
+        let mut crf_attempts = Vec::new();
+
+        for run in 1.. {
+            // how much we're prepared to go higher than the min-vmaf
+            let higher_tolerance = match thorough {
+                true => 0.05,
+                // increment 1.0 => +0.1, +0.2, +0.4, +0.8 ..
+                // increment 0.1 => +0.1, +0.1, +0.1, +0.16 ..
+                _ => (crf_increment * 2_f32.powi(run as i32 - 1) * 0.1).max(0.1),
+            };
+            let crf = q.to_crf(crf_increment);
+            bar.set_message(format!("sampling crf {}, ", TerseF32(crf)));
+            let sample = Sample {
+                crf_increment,
+                q,
+                enc: sample_at(run, crf).await?,
+            };
+            let from_cache = sample.enc.from_cache;
+            crf_attempts.push(sample.clone());
+            all_attempts.push(sample.clone());
+            let exceeded =
+                exceeded_size_constraint(&sample.enc, max_encoded_percent, max_encoded_size);
+            let sample_small_enough = exceeded.is_none();
+
+            if sample.enc.size_aborted {
+                // too large whatever the vmaf, try a higher crf
+                sample.print_attempt(bar, min_vmaf, max_encoded_percent, quiet, from_cache);
+                let u_bound = crf_attempts
+                    .iter()
+                    .map(|s| s.q)
+                    .filter(|q| *q > sample.q)
+                    .min();
+                q = match u_bound.unwrap_or(max_q + 1) {
+                    upper if upper == sample.q + 1 => {
+                        return Err(Error::NoGoodCrf {
+                            last: sample,
+                            exceeded: SizeConstraint::MaxEncodedPercent(max_encoded_percent),
+                        });
+                    }
+                    upper => (sample.q + upper).div_ceil(2).min(max_q),
+                };
+                continue;
             }
-            let u_bound = crf_attempts
-                .iter()
-                .filter(|s| s.q > sample.q)
-                .min_by_key(|s| s.q);
-
-            match u_bound {
-                Some(upper) if upper.q == sample.q + 1 => {
-                    ensure_or_no_good_crf!(sample_small_enough, sample);
+
+            if sample.enc.vmaf > min_vmaf {
+                // good
+                if sample_small_enough && sample.enc.vmaf < min_vmaf + higher_tolerance {
                     return Ok(sample);
                 }
-                Some(upper) => {
-                    q = vmaf_lerp_q(*min_vmaf, upper, &sample);
-                }
-                None if sample.q == max_q => {
-                    ensure_or_no_good_crf!(sample_small_enough, sample);
-                    return Ok(sample);
+                // size aborted attempts have no vmaf to interpolate with
+                let u_bound = crf_attempts
+                    .iter()
+                    .filter(|s| s.q > sample.q && !s.enc.size_aborted)
+                    .min_by_key(|s| s.q);
+
+                match u_bound {
+                    Some(upper) if upper.q == sample.q + 1 => {
+                        ensure_or_no_good_crf!(exceeded, sample);
+                        return Ok(sample);
+                    }
+                    Some(upper) => {
+                        q = vmaf_lerp_q(min_vmaf, upper, &sample);
+                    }
+                    None if sample.q == max_q => {
+                        ensure_or_no_good_crf!(exceeded, sample);
+                        return Ok(sample);
+                    }
+                    None if run == 1 && sample.q + 1 < max_q => {
+                        q = (sample.q + max_q) / 2;
+                    }
+                    None => q = max_q,
+                };
+            } else {
+                // not good enough
+                if let Some(best) = target_unreachable(&crf_attempts, min_vmaf, min_q) {
+                    best.print_attempt(bar, min_vmaf, max_encoded_percent, quiet, from_cache);
+                    return Err(Error::TargetUnreachable { best: best.clone() });
                 }
-                None if run == 1 && sample.q + 1 < max_q => {
-                    q = (sample.q + max_q) / 2;
+                if !sample_small_enough {
+                    sample.print_attempt(bar, min_vmaf, max_encoded_percent, quiet, from_cache);
+                    ensure_or_no_good_crf!(exceeded, sample);
                 }
-                None => q = max_q,
-            };
-        } else {
-            // not good enough
-            if !sample_small_enough || sample.q == min_q {
-                sample.print_attempt(&bar, *min_vmaf, *max_encoded_percent, *quiet, from_cache);
-                ensure_or_no_good_crf!(false, sample);
-            }
 
-            let l_bound = crf_attempts
-                .iter()
-                .filter(|s| s.q < sample.q)
-                .max_by_key(|s| s.q);
-
-            match l_bound {
-                Some(lower) if lower.q + 1 == sample.q => {
-                    sample.print_attempt(&bar, *min_vmaf, *max_encoded_percent, *quiet, from_cache);
-                    let lower_small_enough = lower.enc.encode_percent <= *max_encoded_percent as _;
-                    ensure_or_no_good_crf!(lower_small_enough, sample);
-                    return Ok(lower.clone());
-                }
-                Some(lower) => {
-                    q = vmaf_lerp_q(*min_vmaf, &sample, lower);
-                }
-                None if run == 1 && sample.q > min_q + 1 => {
-                    q = (min_q + sample.q) / 2;
+                let l_bound = crf_attempts
+                    .iter()
+                    .filter(|s| s.q < sample.q && !s.enc.size_aborted)
+                    .max_by_key(|s| s.q);
+
+                match l_bound {
+                    Some(lower) if lower.q + 1 == sample.q => {
+                        sample.print_attempt(bar, min_vmaf, max_encoded_percent, quiet, from_cache);
+                        let lower_exceeded = exceeded_size_constraint(
+                            &lower.enc,
+                            max_encoded_percent,
+                            max_encoded_size,
+                        );
+                        ensure_or_no_good_crf!(lower_exceeded, lower.clone());
+                        return Ok(lower.clone());
+                    }
+                    Some(lower) => {
+                        q = vmaf_lerp_q(min_vmaf, &sample, lower);
+                    }
+                    None if run == 1 && sample.q > min_q + 1 => {
+                        q = (min_q + sample.q) / 2;
+                    }
+                    None => q = min_q,
+                };
+
+                // crfs at or below a size aborted attempt are too large
+                let aborted_bound = crf_attempts
+                    .iter()
+                    .filter(|s| s.q < sample.q && s.enc.size_aborted)
+                    .map(|s| s.q)
+                    .max();
+                if let Some(aborted) = aborted_bound.filter(|aborted| q <= *aborted) {
+                    if aborted + 1 == sample.q {
+                        sample.print_attempt(bar, min_vmaf, max_encoded_percent, quiet, from_cache);
+                        return Err(Error::NoGoodCrf {
+                            last: sample,
+                            exceeded: SizeConstraint::MaxEncodedPercent(max_encoded_percent),
+                        });
+                    }
+                    q = aborted + 1;
                 }
-                None => q = min_q,
-            };
+            }
+            sample.print_attempt(bar, min_vmaf, max_encoded_percent, quiet, from_cache);
         }
-        sample.print_attempt(&bar, *min_vmaf, *max_encoded_percent, *quiet, from_cache);
+        unreachable!();
     }
-    unreachable!();
 }
 
 #[derive(Debug, Clone)]
@@ -294,7 +497,10 @@ impl Sample {
         let crf_label = style("- crf").dim();
         let mut crf = style(TerseF32(self.crf()));
         let vmaf_label = style("VMAF").dim();
-        let mut vmaf = style(self.enc.vmaf);
+        let mut vmaf = match self.enc.size_aborted {
+            true => style("-".to_owned()),
+            false => style(format!("{:.2}", self.enc.vmaf)),
+        };
         let mut percent = style!("{:.0}%", self.enc.encode_percent);
         let open = style("(").dim();
         let close = style(")").dim();
@@ -303,7 +509,7 @@ impl Sample {
             false => style(""),
         };
 
-        if self.enc.vmaf < min_vmaf {
+        if self.enc.vmaf < min_vmaf && !self.enc.size_aborted {
             crf = crf.red().bright();
             vmaf = vmaf.red().bright();
         }
@@ -313,7 +519,7 @@ impl Sample {
         }
 
         let msg =
-            format!("{crf_label} {crf} {vmaf_label} {vmaf:.2} {open}{percent}{close}{cache_msg}");
+            format!("{crf_label} {crf} {vmaf_label} {vmaf} {open}{percent}{close}{cache_msg}");
         if io::stderr().is_terminal() {
             bar.println(msg);
         } else {
@@ -328,7 +534,7 @@ pub enum StdoutFormat {
 }
 
 impl StdoutFormat {
-    fn print_result(self, sample: &Sample, image: bool) {
+    pub fn print_result(self, sample: &Sample, image: bool) {
         match self {
             Self::Human => {
                 let crf = style(TerseF32(sample.crf())).bold().green();
@@ -341,14 +547,123 @@ impl StdoutFormat {
                     true => "image",
                     false => "video stream",
                 };
+                let stddev = sample_encode::vmaf_stddev_label(enc.vmaf_stddev);
+                sample_encode::print_vmaf_lavfi(enc);
                 println!(
-                    "crf {crf} VMAF {vmaf:.2} predicted {enc_description} size {size} ({percent}) taking {time}"
+                    "crf {crf} VMAF {vmaf:.2}{stddev} predicted {enc_description} size {size} ({percent}) taking {time}"
                 );
             }
         }
     }
 }
 
+/// Returns the size constraint `enc` exceeds, if any.
+fn exceeded_size_constraint(
+    enc: &sample_encode::Output,
+    max_encoded_percent: f32,
+    max_encoded_size: Option<u64>,
+) -> Option<SizeConstraint> {
+    if enc.encode_percent > max_encoded_percent as _ {
+        return Some(SizeConstraint::MaxEncodedPercent(max_encoded_percent));
+    }
+    max_encoded_size
+        .filter(|max| enc.predicted_encode_size > *max)
+        .map(SizeConstraint::MaxEncodedSize)
+}
+
+/// Smallest file satisfying both a VMAF floor & size budget, the highest crf
+/// delivering min-vmaf, or the binding constraint if infeasible.
+#[test]
+fn combined_size_vmaf_constraints() {
+    // synthetic curve: size halves every 6 crf, vmaf drops 0.5 per crf
+    let sample = |crf: u64| Sample {
+        enc: sample_encode::Output {
+            vmaf: 100.0 - 0.5 * crf as f32,
+            vmaf_stddev: None,
+            predicted_encode_size: (1_000_000_000.0 * 0.5_f64.powf(crf as f64 / 6.0)) as _,
+            encode_percent: 100.0 * 0.5_f64.powf(crf as f64 / 6.0),
+            predicted_encode_time: Duration::from_secs(1),
+            from_cache: false,
+            size_aborted: false,
+            vmaf_lavfi: None,
+            sample_sizes: vec![],
+        },
+        crf_increment: 1.0,
+        q: crf,
+    };
+    // highest crf delivering VMAF 95
+    let best = sample(10);
+    assert!(best.enc.vmaf >= 95.0);
+
+    // feasible: ~315MB fits a 400MB budget
+    assert_eq!(
+        exceeded_size_constraint(&best.enc, 80.0, Some(400_000_000)),
+        None
+    );
+
+    // infeasible: size budget too small for the VMAF floor
+    let exceeded = exceeded_size_constraint(&best.enc, 80.0, Some(200_000_000));
+    assert_eq!(exceeded, Some(SizeConstraint::MaxEncodedSize(200_000_000)));
+    let err = Error::NoGoodCrf {
+        last: best.clone(),
+        exceeded: exceeded.unwrap(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "Failed to find a suitable crf, infeasible: predicted size 300.39 MiB exceeds --max-encoded-size 190.73 MiB"
+    );
+
+    // infeasible: percent binding before size
+    assert_eq!(
+        exceeded_size_constraint(&best.enc, 25.0, Some(200_000_000)),
+        Some(SizeConstraint::MaxEncodedPercent(25.0))
+    );
+}
+
+/// Returns the min q (highest quality) attempt if it could not reach `min_vmaf`,
+/// meaning no crf within the search range can.
+fn target_unreachable(attempts: &[Sample], min_vmaf: f32, min_q: u64) -> Option<&Sample> {
+    attempts
+        .iter()
+        .find(|s| s.q == min_q)
+        .filter(|s| s.enc.vmaf <= min_vmaf && !s.enc.size_aborted)
+}
+
+#[test]
+fn target_unreachable_plateau() {
+    let sample = |q, vmaf| Sample {
+        enc: sample_encode::Output {
+            vmaf,
+            vmaf_stddev: None,
+            predicted_encode_size: 1000,
+            encode_percent: 50.0,
+            predicted_encode_time: Duration::from_secs(1),
+            from_cache: false,
+            size_aborted: false,
+            vmaf_lavfi: None,
+            sample_sizes: vec![],
+        },
+        crf_increment: 1.0,
+        q,
+    };
+
+    // e.g. a degrading vfilter means vmaf plateaus below the target
+    let mut attempts = vec![sample(32, 92.1), sample(21, 92.8)];
+    assert!(target_unreachable(&attempts, 95.0, 10).is_none());
+
+    attempts.push(sample(10, 92.9));
+    let best = target_unreachable(&attempts, 95.0, 10).expect("unreachable");
+    assert_eq!(best.q, 10);
+    assert_eq!(
+        Error::TargetUnreachable { best: best.clone() }.to_string(),
+        "Failed to find a suitable crf, target unreachable; best achievable VMAF is 92.90 at crf 10"
+    );
+
+    // reachable at min crf
+    let attempts = vec![sample(32, 92.1), sample(10, 96.3)];
+    assert!(target_unreachable(&attempts, 95.0, 10).is_none());
+}
+
 /// Produce a q value between given samples using vmaf score linear interpolation
 /// so the output q value should produce the `min_vmaf`.
 ///
@@ -413,3 +728,151 @@ fn q_crf_conversions() {
     assert_eq!(q_from_crf(33.5, 0.1), 335);
     assert_eq!(q_from_crf(27.0, 1.0), 27);
 }
+
+#[tokio::test]
+async fn search_targets_sampled_score() {
+    let search = Search {
+        min_vmaf: 70.0,
+        max_encoded_percent: 80.0,
+        max_encoded_size: None,
+        thorough: false,
+        quiet: true,
+        crf_increment: 1.0,
+        min_q: q_from_crf(10.0, 1.0),
+        max_q: q_from_crf(55.0, 1.0),
+    };
+    let mut sampled = vec![];
+    let mut attempts = vec![];
+    let best = search
+        .run(
+            q_from_crf(32.0, 1.0),
+            &ProgressBar::hidden(),
+            &mut attempts,
+            async |_, crf| {
+                sampled.push(crf);
+                // score falls as the crf rises
+                Ok(sample_encode::Output {
+                    vmaf: 99.5 - crf,
+                    vmaf_stddev: None,
+                    predicted_encode_size: (1_000_000.0 / crf) as _,
+                    encode_percent: f64::from(60.0 - crf),
+                    predicted_encode_time: Duration::from_secs(60),
+                    from_cache: false,
+                    size_aborted: false,
+                    vmaf_lavfi: None,
+                    sample_sizes: vec![],
+                })
+            },
+        )
+        .await
+        .unwrap();
+
+    // highest crf scoring over the min-vmaf
+    assert_eq!(best.crf(), 29.0);
+    assert_eq!(best.enc.vmaf, 70.5);
+    assert_eq!(sampled.first(), Some(&32.0));
+    assert!(sampled.contains(&30.0), "{sampled:?}");
+    assert_eq!(attempts.len(), sampled.len());
+}
+
+#[tokio::test]
+async fn size_aborted_attempts_bound_search() {
+    let search = Search {
+        min_vmaf: 95.0,
+        max_encoded_percent: 80.0,
+        max_encoded_size: None,
+        thorough: false,
+        quiet: true,
+        crf_increment: 1.0,
+        min_q: 10,
+        max_q: 55,
+    };
+    let output = |vmaf, encode_percent: f64, size_aborted| sample_encode::Output {
+        vmaf,
+        vmaf_stddev: None,
+        predicted_encode_size: 1000,
+        encode_percent,
+        predicted_encode_time: Duration::from_secs(60),
+        from_cache: false,
+        size_aborted,
+        vmaf_lavfi: None,
+        sample_sizes: vec![],
+    };
+    // vmaf 95 needs crf <= 20, but crf < 28 is too large & aborts
+    let sample_at = |sampled: &mut Vec<f32>, crf: f32| {
+        sampled.push(crf);
+        match crf < 28.0 {
+            true => output(0.0, 90.0, true),
+            false => output(115.0 - crf, 70.0, false),
+        }
+    };
+
+    let mut sampled = vec![];
+    let err = search
+        .run(32, &ProgressBar::hidden(), &mut vec![], async |_, crf| {
+            Ok(sample_at(&mut sampled, crf))
+        })
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::NoGoodCrf {
+                exceeded: SizeConstraint::MaxEncodedPercent(_),
+                ..
+            }
+        ),
+        "{err}"
+    );
+    // after crf 27 aborts nothing lower is tried, crf 28 fails vmaf so no crf is good
+    assert_eq!(sampled, [32.0, 21.0, 27.0, 30.0, 28.0]);
+}
+
+#[tokio::test]
+async fn qp_search_within_encoder_range() {
+    let args = args::Encode::parse_from(["ab-av1", "-e", "av1_nvenc", "-i", "vid.mkv", "--qp"]);
+    let crf_increment = args.default_crf_increment();
+    let max_q = q_from_crf(args.default_max_crf(), crf_increment);
+    assert_eq!((crf_increment, max_q), (1.0, 255));
+
+    let search = Search {
+        min_vmaf: 95.0,
+        max_encoded_percent: 80.0,
+        max_encoded_size: None,
+        thorough: false,
+        quiet: true,
+        crf_increment,
+        min_q: 0,
+        max_q,
+    };
+    let mut sampled = vec![];
+    let best = search
+        .run(
+            max_q / 2,
+            &ProgressBar::hidden(),
+            &mut vec![],
+            async |_, qp| {
+                sampled.push(qp);
+                // vmaf 95 at qp 200
+                Ok(sample_encode::Output {
+                    vmaf: 115.0 - qp / 10.0,
+                    vmaf_stddev: None,
+                    predicted_encode_size: 1000,
+                    encode_percent: 50.0,
+                    predicted_encode_time: Duration::from_secs(60),
+                    from_cache: false,
+                    size_aborted: false,
+                    vmaf_lavfi: None,
+                    sample_sizes: vec![],
+                })
+            },
+        )
+        .await
+        .unwrap();
+
+    assert!((190.0..200.0).contains(&best.crf()), "{sampled:?}");
+    assert!(
+        sampled.iter().all(|qp| qp.fract() == 0.0 && *qp <= 255.0),
+        "{sampled:?}"
+    );
+}
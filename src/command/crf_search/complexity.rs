@@ -0,0 +1,150 @@
+//! Experimental VMAF-free complexity probing used to seed the crf search.
+use crate::{
+    command::args::{self, Preset, SeekMode},
+    ffmpeg::{self, FfmpegEncodeArgs},
+    ffprobe::Ffprobe,
+    sample,
+};
+use anyhow::Context;
+use std::time::Duration;
+use tokio::fs;
+use tokio_stream::StreamExt;
+
+/// Fraction of the encoder's default max crf used for the fast probe encode,
+/// e.g. crf 35 for svt-av1.
+const PROBE_CRF_FRACTION: f32 = 35.0 / 55.0;
+
+/// Crf used for the fast probe encode, comparable across encoder crf scales.
+fn probe_crf(args: &args::Encode) -> f32 {
+    (args.default_max_crf() * PROBE_CRF_FRACTION).round()
+}
+
+/// Fast encode a single middle sample of the input at a fixed crf & fast preset
+/// returning the encoded bits per pixel per frame.
+///
+/// Higher values indicate more complex content.
+pub async fn probe(
+    args: &args::Encode,
+    sample_args: &args::Sample,
+    input_probe: &Ffprobe,
+) -> anyhow::Result<f64> {
+    let duration = input_probe.duration.clone()?;
+    let fps = input_probe.fps.clone()?;
+
+    let sample_duration = sample_args.sample_duration().min(duration);
+    let start = duration.saturating_sub(sample_duration) / 2;
+    let frames = ((sample_duration.as_secs_f64() * fps).round() as u32).max(1);
+    sample_bpp(
+        args,
+        sample_args,
+        input_probe,
+        start,
+        frames,
+        sample_duration >= Duration::from_secs(2),
+    )
+    .await
+}
+
+/// Fast encode the sample at `start` of `frames` at a fixed crf & fast preset
+/// returning the encoded bits per pixel per frame.
+pub async fn sample_bpp(
+    args: &args::Encode,
+    sample_args: &args::Sample,
+    input_probe: &Ffprobe,
+    start: Duration,
+    frames: u32,
+    floor_to_sec: bool,
+) -> anyhow::Result<f64> {
+    let (w, h) = input_probe
+        .resolution
+        .context("complexity probe: unknown input resolution")?;
+    let sample = sample::copy(
+        &args.input,
+        start,
+        floor_to_sec,
+        frames,
+        // encoded bits per pixel isn't sensitive to keyframe snapping
+        SeekMode::Fast,
+        sample_args.sample_extract_codec,
+        sample_args.temp_dir.clone(),
+    )
+    .await?;
+
+    let mut args = args.clone();
+    if let Some(preset) = fast_preset(args.encoder().as_str()) {
+        args.preset = Some(preset);
+    }
+    let enc_args = args.to_encoder_args(probe_crf(&args), input_probe)?;
+    let (encoded, mut output) = ffmpeg::encode_sample(
+        FfmpegEncodeArgs {
+            input: &sample,
+            ..enc_args
+        },
+        sample_args.temp_dir.clone(),
+        sample_args.extension.as_deref().unwrap_or("mkv"),
+    )?;
+    while let Some(progress) = output.next().await {
+        progress?;
+    }
+    let encoded_size = fs::metadata(&encoded).await?.len();
+    let _ = fs::remove_file(&encoded).await;
+
+    Ok(encoded_size as f64 * 8.0 / (f64::from(w) * f64::from(h) * f64::from(frames)))
+}
+
+/// Fastest reasonable preset for a quick probe encode, if known for the encoder.
+fn fast_preset(encoder: &str) -> Option<Preset> {
+    match encoder {
+        "libsvtav1" => Some(Preset::Number(12)),
+        "libaom-av1" | "libvpx-vp9" => Some(Preset::Number(8)),
+        "libx264" | "libx265" => Some(Preset::Name("ultrafast".into())),
+        _ => None,
+    }
+}
+
+/// Map bits per pixel to complexity `0..=1`, log-linear between 0.01 & 0.2 bpp.
+pub fn bpp_complexity(bits_per_pixel: f64) -> f64 {
+    const LOW_BPP: f64 = 0.01;
+    const HIGH_BPP: f64 = 0.2;
+
+    ((bits_per_pixel.max(f64::MIN_POSITIVE).ln() - LOW_BPP.ln()) / (HIGH_BPP.ln() - LOW_BPP.ln()))
+        .clamp(0.0, 1.0)
+}
+
+/// Map probed complexity (bits per pixel) to a seed crf in `min_crf..=max_crf`.
+///
+/// Low complexity content (<= 0.01 bpp) seeds 3/4 of the way towards `max_crf`,
+/// high complexity content (>= 0.2 bpp) 1/4 of the way. Log-linear between.
+pub fn seed_crf(bits_per_pixel: f64, min_crf: f32, max_crf: f32) -> f32 {
+    let range_factor = 0.75 - 0.5 * bpp_complexity(bits_per_pixel);
+    min_crf + (max_crf - min_crf) * range_factor as f32
+}
+
+#[test]
+fn seed_crf_mapping() {
+    // low complexity -> higher crf
+    assert_eq!(seed_crf(0.001, 10.0, 50.0), 40.0);
+    assert_eq!(seed_crf(0.01, 10.0, 50.0), 40.0);
+    // high complexity -> lower crf
+    assert_eq!(seed_crf(0.2, 10.0, 50.0), 20.0);
+    assert_eq!(seed_crf(5.0, 10.0, 50.0), 20.0);
+
+    let mid = seed_crf(0.05, 10.0, 50.0);
+    assert!(mid > 20.0 && mid < 40.0, "{mid}");
+    assert!(seed_crf(0.03, 10.0, 50.0) > seed_crf(0.1, 10.0, 50.0));
+
+    // zero size shouldn't blow up
+    assert_eq!(seed_crf(0.0, 10.0, 50.0), 40.0);
+}
+
+#[test]
+fn probe_crf_per_encoder() {
+    use clap::Parser;
+
+    let encode =
+        |encoder: &str| args::Encode::parse_from(["ab-av1", "-e", encoder, "-i", "vid.mkv"]);
+    assert_eq!(probe_crf(&encode("libsvtav1")), 35.0);
+    assert_eq!(probe_crf(&encode("libx264")), 29.0);
+    assert_eq!(probe_crf(&encode("libx265")), 29.0);
+    assert_eq!(probe_crf(&encode("librav1e")), 162.0);
+}
@@ -1,9 +1,18 @@
-use crate::command::crf_search::Sample;
+use crate::{command::crf_search::Sample, float::TerseF32};
+use indicatif::HumanBytes;
 use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    NoGoodCrf { last: Sample },
+    /// No crf delivering the min-vmaf satisfies the `exceeded` size constraint.
+    NoGoodCrf {
+        last: Sample,
+        exceeded: SizeConstraint,
+    },
+    /// Even the min crf (highest quality) cannot reach the min-vmaf.
+    TargetUnreachable {
+        best: Sample,
+    },
     Other(anyhow::Error),
 }
 
@@ -22,7 +31,29 @@ impl From<tokio::task::JoinError> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::NoGoodCrf { .. } => "Failed to find a suitable crf".fmt(f),
+            Self::NoGoodCrf { last, exceeded } => {
+                write!(f, "Failed to find a suitable crf, infeasible: ")?;
+                match exceeded {
+                    SizeConstraint::MaxEncodedPercent(max) => write!(
+                        f,
+                        "encoded size {:.0}% exceeds --max-encoded-percent {max}%",
+                        last.enc.encode_percent
+                    ),
+                    SizeConstraint::MaxEncodedSize(max) => write!(
+                        f,
+                        "predicted size {} exceeds --max-encoded-size {}",
+                        HumanBytes(last.enc.predicted_encode_size),
+                        HumanBytes(*max)
+                    ),
+                }
+            }
+            Self::TargetUnreachable { best } => write!(
+                f,
+                "Failed to find a suitable crf, target unreachable; \
+                 best achievable VMAF is {:.2} at crf {}",
+                best.enc.vmaf,
+                TerseF32(best.crf()),
+            ),
             Self::Other(err) => err.fmt(f),
         }
     }
@@ -30,6 +61,13 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// A crf search size constraint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeConstraint {
+    MaxEncodedPercent(f32),
+    MaxEncodedSize(u64),
+}
+
 macro_rules! ensure_other {
     ($condition:expr, $reason:expr) => {
         #[allow(clippy::neg_cmp_op_on_partial_ord)]
@@ -43,9 +81,12 @@ macro_rules! ensure_other {
 pub(crate) use ensure_other;
 
 macro_rules! ensure_or_no_good_crf {
-    ($condition:expr, $last_sample:expr) => {
-        if !$condition {
-            return Err($crate::command::crf_search::err::Error::NoGoodCrf { last: $last_sample });
+    ($exceeded:expr, $last_sample:expr) => {
+        if let Some(exceeded) = $exceeded {
+            return Err($crate::command::crf_search::err::Error::NoGoodCrf {
+                last: $last_sample,
+                exceeded,
+            });
         }
     };
 }
@@ -0,0 +1,265 @@
+//! `--html-report` logic.
+use crate::{
+    command::crf_search::{Args, Sample},
+    float::TerseF32,
+};
+use anyhow::Context;
+use indicatif::{HumanBytes, HumanDuration};
+use std::{fmt::Write, path::Path};
+
+const SVG_WIDTH: f64 = 640.0;
+const SVG_HEIGHT: f64 = 360.0;
+const SVG_MARGIN: f64 = 48.0;
+
+/// Write a self-contained html report of the `best` search result & all crf `attempts`.
+pub fn write(path: &Path, args: &Args, best: &Sample, attempts: &[Sample]) -> anyhow::Result<()> {
+    let input_size = std::fs::metadata(&args.args.input)
+        .with_context(|| format!("{}", args.args.input.display()))?
+        .len();
+    let report = Report {
+        input: &args.args.input.display().to_string(),
+        input_size,
+        encoder: args.args.encoder().as_str(),
+        preset: args.args.preset.as_ref().map(|p| p.to_string()),
+        encode_hint: &args.args.encode_hint(best.crf()),
+        min_vmaf: args.min_vmaf,
+        best,
+        attempts,
+    };
+    std::fs::write(path, render(&report))
+        .with_context(|| format!("write html report {}", path.display()))
+}
+
+struct Report<'a> {
+    input: &'a str,
+    input_size: u64,
+    encoder: &'a str,
+    preset: Option<String>,
+    encode_hint: &'a str,
+    min_vmaf: f32,
+    best: &'a Sample,
+    attempts: &'a [Sample],
+}
+
+fn render(r: &Report<'_>) -> String {
+    let title = format!("ab-av1 crf-search {}", escape(r.input));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>body{{font-family:sans-serif;margin:2em}}td,th{{padding:.2em 1em;text-align:left}}\
+         code{{background:#eee;padding:.2em}}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+
+    html.push_str("<h2>Result</h2>\n<table>\n");
+    let mut row = |name: &str, value: &str| {
+        writeln!(html, "<tr><th>{name}</th><td>{}</td></tr>", escape(value)).unwrap();
+    };
+    row("Encoder", r.encoder);
+    if let Some(preset) = &r.preset {
+        row("Preset", preset);
+    }
+    row("CRF", &TerseF32(r.best.crf()).to_string());
+    row("VMAF", &format!("{:.2}", r.best.enc.vmaf));
+    row("Input size", &HumanBytes(r.input_size).to_string());
+    row(
+        "Predicted size",
+        &format!(
+            "{} ({:.0}%)",
+            HumanBytes(r.best.enc.predicted_encode_size),
+            r.best.enc.encode_percent
+        ),
+    );
+    row(
+        "Predicted encode time",
+        &HumanDuration(r.best.enc.predicted_encode_time).to_string(),
+    );
+    html.push_str("</table>\n");
+    writeln!(
+        html,
+        "<p>Encode with: <code>{}</code></p>",
+        escape(r.encode_hint)
+    )
+    .unwrap();
+
+    html.push_str("<h2>VMAF curve</h2>\n");
+    html.push_str(&curve_svg(r));
+
+    html.push_str(
+        "<h2>Attempts</h2>\n<table>\n<tr><th>CRF</th><th>VMAF</th><th>Predicted size</th></tr>\n",
+    );
+    for s in r.attempts {
+        let vmaf = match s.enc.size_aborted {
+            true => "-".to_owned(),
+            false => format!("{:.2}", s.enc.vmaf),
+        };
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{vmaf}</td><td>{} ({:.0}%)</td></tr>",
+            TerseF32(s.crf()),
+            HumanBytes(s.enc.predicted_encode_size),
+            s.enc.encode_percent,
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Inline svg plot of VMAF against predicted size, highlighting the best attempt.
+fn curve_svg(r: &Report<'_>) -> String {
+    let mut points: Vec<_> = r
+        .attempts
+        .iter()
+        .filter(|s| !s.enc.size_aborted)
+        .map(|s| (s.enc.predicted_encode_size as f64, f64::from(s.enc.vmaf)))
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let best = (
+        r.best.enc.predicted_encode_size as f64,
+        f64::from(r.best.enc.vmaf),
+    );
+    let min_vmaf = f64::from(r.min_vmaf);
+    let range = |values: &mut dyn Iterator<Item = f64>| {
+        let (lo, hi) = values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        match hi - lo {
+            d if d > 0.0 => (lo, hi),
+            _ => (lo - 1.0, hi + 1.0),
+        }
+    };
+    let (x_lo, x_hi) = range(&mut points.iter().map(|p| p.0).chain([best.0]));
+    let (y_lo, y_hi) = range(&mut points.iter().map(|p| p.1).chain([best.1, min_vmaf]));
+    let x = |v: f64| SVG_MARGIN + (v - x_lo) / (x_hi - x_lo) * (SVG_WIDTH - 2.0 * SVG_MARGIN);
+    let y = |v: f64| {
+        SVG_HEIGHT - SVG_MARGIN - (v - y_lo) / (y_hi - y_lo) * (SVG_HEIGHT - 2.0 * SVG_MARGIN)
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{SVG_WIDTH}\" height=\"{SVG_HEIGHT}\">\n"
+    );
+    let (left, right) = (SVG_MARGIN, SVG_WIDTH - SVG_MARGIN);
+    let (top, bottom) = (SVG_MARGIN, SVG_HEIGHT - SVG_MARGIN);
+    writeln!(
+        svg,
+        "<path d=\"M{left} {top}V{bottom}H{right}\" fill=\"none\" stroke=\"#444\"/>"
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<text x=\"{left}\" y=\"{}\" font-size=\"12\">{}</text>\
+         <text x=\"{right}\" y=\"{}\" font-size=\"12\" text-anchor=\"end\">{}</text>\
+         <text x=\"{}\" y=\"{bottom}\" font-size=\"12\" text-anchor=\"end\">{y_lo:.1}</text>\
+         <text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"end\">{y_hi:.1}</text>",
+        bottom + 16.0,
+        HumanBytes(x_lo as u64),
+        bottom + 16.0,
+        HumanBytes(x_hi as u64),
+        left - 4.0,
+        left - 4.0,
+        top + 4.0,
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<line x1=\"{left}\" x2=\"{right}\" y1=\"{0:.1}\" y2=\"{0:.1}\" stroke=\"#c33\" \
+         stroke-dasharray=\"4\"><title>min VMAF {min_vmaf}</title></line>",
+        y(min_vmaf),
+    )
+    .unwrap();
+    let line: Vec<_> = points
+        .iter()
+        .map(|&(px, py)| format!("{:.1},{:.1}", x(px), y(py)))
+        .collect();
+    writeln!(
+        svg,
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"#36c\" stroke-width=\"2\"/>",
+        line.join(" ")
+    )
+    .unwrap();
+    for s in r.attempts.iter().filter(|s| !s.enc.size_aborted) {
+        let (px, py) = (s.enc.predicted_encode_size as f64, f64::from(s.enc.vmaf));
+        writeln!(
+            svg,
+            "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"#36c\">\
+             <title>crf {} VMAF {:.2}</title></circle>",
+            x(px),
+            y(py),
+            TerseF32(s.crf()),
+            s.enc.vmaf,
+        )
+        .unwrap();
+    }
+    writeln!(
+        svg,
+        "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"6\" fill=\"#2a2\">\
+         <title>best crf {} VMAF {:.2}</title></circle>",
+        x(best.0),
+        y(best.1),
+        TerseF32(r.best.crf()),
+        r.best.enc.vmaf,
+    )
+    .unwrap();
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn html_report_contains_result() {
+    use crate::command::sample_encode;
+    use std::time::Duration;
+
+    let sample = |q, vmaf, predicted_encode_size| Sample {
+        enc: sample_encode::Output {
+            vmaf,
+            vmaf_stddev: None,
+            predicted_encode_size,
+            encode_percent: predicted_encode_size as f64 / 1e6,
+            predicted_encode_time: Duration::from_secs(90),
+            from_cache: false,
+            size_aborted: false,
+            vmaf_lavfi: None,
+            sample_sizes: vec![],
+        },
+        crf_increment: 1.0,
+        q,
+    };
+    let attempts = [
+        sample(32, 95.12, 30_000_000),
+        sample(40, 91.5, 12_000_000),
+        sample(28, 96.8, 45_000_000),
+    ];
+    let html = render(&Report {
+        input: "<vid>.mkv",
+        input_size: 100_000_000,
+        encoder: "libsvtav1",
+        preset: Some("6".into()),
+        encode_hint: "ab-av1 encode -i vid.mkv --crf 32",
+        min_vmaf: 95.0,
+        best: &attempts[0],
+        attempts: &attempts,
+    });
+
+    assert!(html.contains("<tr><th>CRF</th><td>32</td></tr>"), "{html}");
+    assert!(
+        html.contains("<tr><th>VMAF</th><td>95.12</td></tr>"),
+        "{html}"
+    );
+    assert!(
+        html.contains("<title>best crf 32 VMAF 95.12</title>"),
+        "{html}"
+    );
+    assert!(html.contains("<td>40</td><td>91.50</td>"), "{html}");
+    assert!(html.contains("&lt;vid&gt;.mkv"), "input should be escaped");
+    assert!(!html.contains("<vid>"));
+    assert!(
+        !html.contains("src=") && !html.contains("<link"),
+        "no external deps"
+    );
+    assert_eq!(html.matches("<polyline").count(), 1);
+}
@@ -0,0 +1,142 @@
+//! `--vmaf-subsample-candidates` logic, calibrating the libvmaf `n_subsample`.
+use crate::{
+    command::{args, crf_search::Args, sample_encode},
+    console_ext::style,
+    ffprobe::Ffprobe,
+    float::TerseF32,
+};
+use clap::Parser;
+use indicatif::ProgressBar;
+use log::info;
+use std::sync::Arc;
+
+/// VMAF subsample calibration arguments.
+#[derive(Parser, Clone, Default)]
+pub struct SubsampleArgs {
+    /// Calibrate the libvmaf `n_subsample` from these comma separated candidates,
+    /// using the largest (fastest) whose VMAF differs from the smallest candidate's
+    /// by no more than --vmaf-subsample-tolerance.
+    ///
+    /// Each candidate analyses one calibration sample encoded at the middle crf.
+    /// E.g. `--vmaf-subsample-candidates 1,2,4`.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u64).range(1..))]
+    pub vmaf_subsample_candidates: Vec<u64>,
+
+    /// Max VMAF error of a --vmaf-subsample-candidates candidate.
+    #[arg(long, default_value_t = 0.5, requires = "vmaf_subsample_candidates")]
+    pub vmaf_subsample_tolerance: f32,
+}
+
+/// With `--vmaf-subsample-candidates` measure each candidate on a calibration sample
+/// & set the selected `n_subsample` in `args.vmaf`.
+pub async fn calibrate(
+    args: &mut Args,
+    input_probe: Arc<Ffprobe>,
+    bar: &ProgressBar,
+) -> anyhow::Result<()> {
+    let mut candidates = args.subsample.vmaf_subsample_candidates.clone();
+    if candidates.is_empty() {
+        return Ok(());
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    bar.set_message("subsample calibration, ");
+    let max_crf = args.max_crf.unwrap_or_else(|| args.args.default_max_crf());
+    let mut sample = args.sample.clone();
+    sample.samples = Some(1);
+    let mut measured = Vec::with_capacity(candidates.len());
+    for n_subsample in candidates {
+        let output = sample_encode::run(
+            sample_encode::Args {
+                args: args.args.clone(),
+                crf: (args.min_crf + max_crf) / 2.0,
+                sample: sample.clone(),
+                cache: args.cache,
+                stdout_format: sample_encode::StdoutFormat::Json,
+                list_samples: false,
+                baseline: <_>::default(),
+                vmaf: with_n_subsample(&args.vmaf, n_subsample),
+                abort_above_percent: None,
+            },
+            input_probe.clone(),
+            ProgressBar::hidden(),
+            false,
+        )
+        .await?;
+        info!(
+            "n_subsample {n_subsample} calibration VMAF {:.2}",
+            output.vmaf
+        );
+        measured.push((n_subsample, output.vmaf));
+    }
+
+    let tolerance = args.subsample.vmaf_subsample_tolerance;
+    let (n_subsample, error) = select(&measured, tolerance);
+    bar.println(
+        style!(
+            "- n_subsample {n_subsample} selected, VMAF error {error:.2} (tolerance {})",
+            TerseF32(tolerance)
+        )
+        .dim()
+        .to_string(),
+    );
+    args.vmaf = with_n_subsample(&args.vmaf, n_subsample);
+    Ok(())
+}
+
+/// Select the largest `(n_subsample, vmaf)` candidate whose VMAF error, relative to
+/// the smallest candidate, is within `tolerance`. Returns it & its error.
+///
+/// `measured` must be non-empty & sorted by `n_subsample`.
+fn select(measured: &[(u64, f32)], tolerance: f32) -> (u64, f32) {
+    let (_, reference) = measured[0];
+    measured
+        .iter()
+        .map(|&(n, vmaf)| (n, (vmaf - reference).abs()))
+        .rfind(|(_, error)| *error <= tolerance)
+        .unwrap_or((measured[0].0, 0.0))
+}
+
+/// Returns `vmaf` with any `n_subsample` arg replaced by `n_subsample`,
+/// omitted if 1 as that is the libvmaf default.
+fn with_n_subsample(vmaf: &args::Vmaf, n_subsample: u64) -> args::Vmaf {
+    let mut vmaf = vmaf.clone();
+    vmaf.vmaf_args.retain(|a| !a.starts_with("n_subsample="));
+    if n_subsample > 1 {
+        vmaf.vmaf_args
+            .push(format!("n_subsample={n_subsample}").into());
+    }
+    vmaf
+}
+
+#[test]
+fn select_largest_within_tolerance() {
+    let measured = [(1, 95.0), (2, 94.8), (4, 95.4), (8, 93.9)];
+    let (n_subsample, error) = select(&measured, 0.5);
+    assert_eq!(n_subsample, 4);
+    assert!((error - 0.4).abs() < 1e-4, "{error}");
+    // error isn't necessarily monotonic, the largest acceptable is used
+    assert_eq!(select(&measured, 0.3).0, 2);
+    assert_eq!(select(&measured, 2.0).0, 8);
+    // nothing larger acceptable, keep the reference candidate
+    assert_eq!(select(&measured, 0.1), (1, 0.0));
+    assert_eq!(select(&[(2, 90.0)], 0.5), (2, 0.0));
+}
+
+#[test]
+fn n_subsample_vmaf_args() {
+    let vmaf = args::Vmaf {
+        vmaf_args: vec!["n_threads=4".into(), "n_subsample=2".into()],
+        ..<_>::default()
+    };
+    assert_eq!(
+        with_n_subsample(&vmaf, 4).vmaf_args,
+        ["n_threads=4".into(), "n_subsample=4".into()] as [Arc<str>; 2]
+    );
+    assert_eq!(with_n_subsample(&vmaf, 4).n_subsample(), 4);
+    assert_eq!(
+        with_n_subsample(&vmaf, 1).vmaf_args,
+        ["n_threads=4".into()] as [Arc<str>; 1]
+    );
+}
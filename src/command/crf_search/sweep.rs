@@ -0,0 +1,395 @@
+//! `--preset-sweep` & `--vmaf-scale-sweep` logic, crf searching multiple presets & scales.
+use crate::{
+    command::{
+        args::{parse_vmaf_scale, Preset, VmafScale},
+        crf_search::{run, subsample, Args, Error, Sample},
+    },
+    console_ext::style,
+    ffprobe::Ffprobe,
+    float::TerseF32,
+};
+use clap::Parser;
+use console::style;
+use indicatif::{HumanBytes, ProgressBar};
+use std::{
+    fmt,
+    io::{self, IsTerminal},
+    sync::Arc,
+};
+
+/// Preset sweep arguments.
+#[derive(Parser, Clone, Default)]
+pub struct SweepArgs {
+    /// Crf search each of these comma separated presets, ordered fastest to slowest,
+    /// and use the preset with the smallest predicted size.
+    ///
+    /// E.g. `--preset-sweep 10,8,6,4`.
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["preset", "enc_raw"])]
+    pub preset_sweep: Vec<Preset>,
+
+    /// Stop a --preset-sweep early once the next slower preset improves the
+    /// predicted size by less than this percentage, e.g. `2%`.
+    #[arg(long, requires = "preset_sweep", value_parser = parse_percent)]
+    pub min_improvement: Option<f64>,
+
+    /// Crf search each of these comma separated --vmaf-scale values for every
+    /// --preset-sweep preset, or the --preset, printing the matrix of chosen crfs &
+    /// predicted sizes. The result is the best preset of the first scale.
+    ///
+    /// E.g. `--preset-sweep 8,6,4 --vmaf-scale-sweep auto,none,2x`.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_vmaf_scale,
+        conflicts_with_all = ["vmaf_scale", "min_improvement"]
+    )]
+    pub vmaf_scale_sweep: Vec<VmafScale>,
+}
+
+/// Run the crf search, or with `--preset-sweep` a crf search for each preset
+/// setting `args.args.preset` to the best (smallest predicted size) preset.
+/// With `--vmaf-scale-sweep` every preset is searched at each scale, see [`run_scale_sweep`].
+///
+/// Each crf attempt of every preset is pushed to `attempts`.
+///
+/// With `--vmaf-subsample-candidates` the VMAF `n_subsample` is calibrated first.
+pub async fn run_sweep(
+    args: &mut Args,
+    input_probe: Arc<Ffprobe>,
+    bar: ProgressBar,
+    attempts: &mut Vec<Sample>,
+) -> Result<Sample, Error> {
+    subsample::calibrate(args, input_probe.clone(), &bar).await?;
+    if !args.sweep.vmaf_scale_sweep.is_empty() {
+        return run_scale_sweep(args, input_probe, bar, attempts).await;
+    }
+    if args.sweep.preset_sweep.is_empty() {
+        return run(args, input_probe, bar, attempts).await;
+    }
+
+    let mut best: Option<(Preset, Sample)> = None;
+    let mut sizes = vec![];
+    let mut last_err = None;
+    for preset in args.sweep.preset_sweep.clone() {
+        args.args.preset = Some(preset.clone());
+        bar.set_position(0);
+        let sample = match run(args, input_probe.clone(), bar.clone(), attempts).await {
+            Ok(sample) => sample,
+            Err(err @ (Error::NoGoodCrf { .. } | Error::TargetUnreachable { .. })) => {
+                print(&bar, style!("- preset {preset} {err}").dim().to_string());
+                last_err = Some(err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let size = sample.enc.predicted_encode_size;
+        print(
+            &bar,
+            format!(
+                "{} {preset} {} {} {} {:.2} {} {}",
+                style("- preset").dim(),
+                style("crf").dim(),
+                TerseF32(sample.crf()),
+                style("VMAF").dim(),
+                sample.enc.vmaf,
+                style("predicted size").dim(),
+                HumanBytes(size),
+            ),
+        );
+        sizes.push(size);
+        if best
+            .as_ref()
+            .is_none_or(|(_, b)| size < b.enc.predicted_encode_size)
+        {
+            best = Some((preset, sample));
+        }
+        if args
+            .sweep
+            .min_improvement
+            .is_some_and(|min| should_stop(&sizes, min))
+        {
+            print(
+                &bar,
+                style!("- stopping sweep, size improvement below minimum")
+                    .dim()
+                    .to_string(),
+            );
+            break;
+        }
+    }
+
+    match best {
+        Some((preset, sample)) => {
+            args.args.preset = Some(preset);
+            Ok(sample)
+        }
+        None => Err(last_err.expect("preset sweep must error if nothing succeeded")),
+    }
+}
+
+/// Crf search every preset & `--vmaf-scale-sweep` scale combination, printing the
+/// result matrix & setting `args` to the best preset of the first scale.
+async fn run_scale_sweep(
+    args: &mut Args,
+    input_probe: Arc<Ffprobe>,
+    bar: ProgressBar,
+    attempts: &mut Vec<Sample>,
+) -> Result<Sample, Error> {
+    let presets = match args.sweep.preset_sweep.as_slice() {
+        [] => vec![args.args.preset.clone()],
+        presets => presets.iter().cloned().map(Some).collect(),
+    };
+    let mut matrix = Matrix::new(presets, args.sweep.vmaf_scale_sweep.clone());
+    let mut samples = vec![];
+    let mut first_err = None;
+    let cells: Vec<_> = matrix.cells().collect();
+    for (idx, (preset, scale)) in cells.into_iter().enumerate() {
+        args.args.preset = preset.clone();
+        args.vmaf.vmaf_scale = scale;
+        let preset = preset.map_or_else(|| "default".into(), |p| p.to_string());
+        bar.set_position(0);
+        match run(args, input_probe.clone(), bar.clone(), attempts).await {
+            Ok(sample) => {
+                print(
+                    &bar,
+                    format!(
+                        "{} {preset} {} {scale} {} {} {} {}",
+                        style("- preset").dim(),
+                        style("vmaf-scale").dim(),
+                        style("crf").dim(),
+                        TerseF32(sample.crf()),
+                        style("predicted size").dim(),
+                        HumanBytes(sample.enc.predicted_encode_size),
+                    ),
+                );
+                matrix.results[idx] = Some(CellResult {
+                    crf: sample.crf(),
+                    predicted_size: sample.enc.predicted_encode_size,
+                });
+                samples.push(Some(sample));
+            }
+            Err(err @ (Error::NoGoodCrf { .. } | Error::TargetUnreachable { .. })) => {
+                print(
+                    &bar,
+                    style!("- preset {preset} vmaf-scale {scale} {err}")
+                        .dim()
+                        .to_string(),
+                );
+                if idx < matrix.presets.len() && first_err.is_none() {
+                    first_err = Some(err);
+                }
+                samples.push(None);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    print(&bar, matrix.to_string());
+
+    let best = matrix.best();
+    args.args.preset = matrix.presets[best.unwrap_or_default()].clone();
+    args.vmaf.vmaf_scale = matrix.scales[0];
+    match best.and_then(|idx| samples[idx].take()) {
+        Some(sample) => Ok(sample),
+        None => Err(first_err.expect("scale sweep must error if the first scale failed")),
+    }
+}
+
+/// Result of a single crf search of a [`Matrix`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CellResult {
+    crf: f32,
+    predicted_size: u64,
+}
+
+/// `--vmaf-scale-sweep` results per scale & preset.
+#[derive(Debug, PartialEq)]
+struct Matrix {
+    presets: Vec<Option<Preset>>,
+    scales: Vec<VmafScale>,
+    /// Results in [`Self::cells`] order, `None` if the search failed.
+    results: Vec<Option<CellResult>>,
+}
+
+impl Matrix {
+    fn new(presets: Vec<Option<Preset>>, scales: Vec<VmafScale>) -> Self {
+        let results = vec![None; presets.len() * scales.len()];
+        Self {
+            presets,
+            scales,
+            results,
+        }
+    }
+
+    /// Every preset & scale combination, all presets of each scale in turn.
+    fn cells(&self) -> impl Iterator<Item = (Option<Preset>, VmafScale)> + '_ {
+        self.scales
+            .iter()
+            .flat_map(|scale| self.presets.iter().map(|p| (p.clone(), *scale)))
+    }
+
+    fn result(&self, preset_idx: usize, scale_idx: usize) -> Option<CellResult> {
+        self.results[scale_idx * self.presets.len() + preset_idx]
+    }
+
+    /// Index of the first scale's result with the smallest predicted size.
+    fn best(&self) -> Option<usize> {
+        (0..self.presets.len())
+            .filter_map(|idx| Some((idx, self.result(idx, 0)?)))
+            .min_by_key(|(_, r)| r.predicted_size)
+            .map(|(idx, _)| idx)
+    }
+}
+
+impl fmt::Display for Matrix {
+    /// Table of a row per preset & a column per scale.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = ["preset".to_owned()]
+            .into_iter()
+            .chain(self.scales.iter().map(|s| s.to_string()));
+        let mut rows = vec![header.collect::<Vec<_>>()];
+        for (idx, preset) in self.presets.iter().enumerate() {
+            let preset = preset
+                .as_ref()
+                .map_or_else(|| "default".into(), |p| p.to_string());
+            let cells = (0..self.scales.len()).map(|scale_idx| match self.result(idx, scale_idx) {
+                Some(r) => format!("crf {} {}", TerseF32(r.crf), HumanBytes(r.predicted_size)),
+                None => "-".into(),
+            });
+            rows.push([preset].into_iter().chain(cells).collect());
+        }
+
+        let widths: Vec<_> = (0..=self.scales.len())
+            .map(|col| rows.iter().map(|r| r[col].len()).max().unwrap_or(0))
+            .collect();
+        for (idx, row) in rows.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            let line: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            write!(f, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the latest predicted size improved on the previous one by
+/// less than `min_improvement` percent.
+fn should_stop(sizes: &[u64], min_improvement: f64) -> bool {
+    let [.., prev, last] = sizes else {
+        return false;
+    };
+    let improvement = match prev {
+        0 => 0.0,
+        prev => 100.0 * (*prev as f64 - *last as f64) / *prev as f64,
+    };
+    improvement < min_improvement
+}
+
+fn parse_percent(s: &str) -> Result<f64, String> {
+    s.trim_end_matches('%')
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid percentage `{s}`"))
+}
+
+fn print(bar: &ProgressBar, msg: String) {
+    if io::stderr().is_terminal() {
+        bar.println(msg);
+    } else {
+        eprintln!("{msg}");
+    }
+}
+
+#[test]
+fn early_stop_decision() {
+    // presets fast to slow, sizes shrinking then flattening off
+    let results = [1000, 900, 850, 840, 838];
+    let stop_at = (1..=results.len()).find(|&n| should_stop(&results[..n], 2.0));
+    // 850 -> 840 is only a ~1.2% improvement
+    assert_eq!(stop_at, Some(4));
+
+    // large gains continue through the whole sweep
+    assert_eq!(
+        (1..=results.len()).find(|&n| should_stop(&results[..n], 0.1)),
+        None
+    );
+
+    // a slower preset getting larger stops immediately
+    assert!(should_stop(&[1000, 1010], 2.0));
+    assert!(!should_stop(&[1000], 2.0));
+
+    assert_eq!(parse_percent("2%"), Ok(2.0));
+    assert_eq!(parse_percent("0.5"), Ok(0.5));
+    assert!(parse_percent("two").is_err());
+}
+
+#[test]
+fn scale_sweep_matrix() {
+    let presets = vec![Some(Preset::Number(8)), Some(Preset::Number(4))];
+    let scales = vec![VmafScale::Auto, VmafScale::None, VmafScale::Factor(2.0)];
+    let mut matrix = Matrix::new(presets, scales);
+
+    let cells: Vec<_> = matrix
+        .cells()
+        .map(|(p, s)| format!("{} {s}", p.unwrap()))
+        .collect();
+    assert_eq!(
+        cells,
+        ["8 auto", "4 auto", "8 none", "4 none", "8 2x", "4 2x"]
+    );
+    assert_eq!(matrix.results.len(), 6);
+    assert_eq!(matrix.best(), None);
+
+    let result = |crf, predicted_size| {
+        Some(CellResult {
+            crf,
+            predicted_size,
+        })
+    };
+    matrix.results = vec![
+        result(30.0, 2_000_000),
+        result(32.5, 1_500_000),
+        result(28.0, 2_500_000),
+        None,
+        // smallest overall, but only the first scale chooses the result
+        result(35.0, 1_000_000),
+        result(36.0, 900_000),
+    ];
+    assert_eq!(matrix.result(1, 0), result(32.5, 1_500_000));
+    assert_eq!(matrix.result(1, 1), None);
+    assert_eq!(matrix.best(), Some(1));
+
+    assert_eq!(
+        matrix.to_string(),
+        "preset  auto               none             2x\n\
+         8       crf 30 1.91 MiB    crf 28 2.38 MiB  crf 35 976.56 KiB\n\
+         4       crf 32.5 1.43 MiB  -                crf 36 878.91 KiB"
+    );
+}
+
+#[test]
+fn scale_sweep_args() {
+    let parse = |args: &[&str]| {
+        let args = [&["crf-search", "-i", "vid.mkv"], args].concat();
+        Args::try_parse_from(args).map(|a| a.sweep.vmaf_scale_sweep)
+    };
+    assert_eq!(
+        parse(&["--vmaf-scale-sweep", "auto,none,1280x720,2x"]).unwrap(),
+        [
+            VmafScale::Auto,
+            VmafScale::None,
+            VmafScale::Custom {
+                width: 1280,
+                height: 720
+            },
+            VmafScale::Factor(2.0),
+        ]
+    );
+    assert!(parse(&["--vmaf-scale-sweep", "auto", "--vmaf-scale", "none"]).is_err());
+    assert!(parse(&["--vmaf-scale-sweep", "big"]).is_err());
+}
@@ -1,3 +1,8 @@
+pub mod saved_plan;
+pub mod sidecar;
+
+pub use sidecar::Sidecar;
+
 use crate::{
     command::{
         args::{self, Encoder},
@@ -9,10 +14,11 @@ use crate::{
     log::ProgressLogger,
     process::FfmpegOut,
     temporary::{self, TempKind},
+    threads::{self, Phase},
 };
-use clap::Parser;
+use clap::{Parser, ValueHint};
 use console::style;
-use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::info;
 use std::{
     path::{Path, PathBuf},
@@ -35,9 +41,21 @@ pub struct Args {
 
     #[clap(flatten)]
     pub encode: args::EncodeToOutput,
+
+    /// Encode using the crf & args of a plan saved by crf-search or auto-encode
+    /// `--write-plan`, e.g. on another machine. Args specified on the command
+    /// line take precedence, e.g. `--from-plan plan.json -i /other/vid.mkv`.
+    ///
+    /// The encode is not VMAF verified, the plan VMAF is the crf-search result.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub from_plan: Option<PathBuf>,
+
+    /// Crf-search VMAF info to include in a `--sidecar`.
+    #[arg(skip)]
+    pub sidecar_vmaf: Option<sidecar::Vmaf>,
 }
 
-pub async fn encode(args: Args) -> anyhow::Result<()> {
+pub async fn encode(mut args: Args) -> anyhow::Result<()> {
     let bar = ProgressBar::new(1).with_style(
         ProgressStyle::default_bar()
             .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
@@ -45,7 +63,15 @@ pub async fn encode(args: Args) -> anyhow::Result<()> {
     );
     bar.enable_steady_tick(Duration::from_millis(100));
 
+    if let Some(path) = &args.from_plan {
+        // args are already inserted, see saved_plan::apply
+        let plan = saved_plan::SavedPlan::read(path)?;
+        args.sidecar_vmaf = args.sidecar_vmaf.or(plan.vmaf);
+    }
+
+    args.args.prepare().await?;
     let probe = ffprobe::probe(&args.args.input);
+    probe.ensure_video(&args.args.input)?;
     run(args, probe.into(), &bar).await
 }
 
@@ -53,21 +79,19 @@ pub async fn run(
     Args {
         args,
         crf,
-        encode:
-            args::EncodeToOutput {
-                output,
-                audio_codec,
-                downmix_to_stereo,
-                video_only,
-            },
+        encode,
+        from_plan: _,
+        sidecar_vmaf,
     }: Args,
     probe: Arc<Ffprobe>,
     bar: &ProgressBar,
 ) -> anyhow::Result<()> {
-    let defaulting_output = output.is_none();
+    let defaulting_output = encode.output.is_none();
     // let probe = ffprobe::probe(&args.input);
-    let output =
-        output.unwrap_or_else(|| default_output_name(&args.input, &args.encoder, probe.is_image));
+    let output = encode
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output_name(&args.input, args.encoder(), probe.is_image));
     // output is temporary until encoding has completed successfully
     temporary::add(&output, TempKind::NotKeepable);
 
@@ -77,27 +101,39 @@ pub async fn run(
     }
     bar.set_message("encoding, ");
 
-    let mut enc_args = args.to_encoder_args(crf, &probe)?;
-    enc_args.video_only = video_only;
-    let has_audio = probe.has_audio;
+    let Plan {
+        enc_args,
+        has_audio,
+        audio_codec,
+        stereo_downmix,
+    } = plan(&args, crf, &encode, &probe, bar)?;
     if let Ok(d) = &probe.duration {
         bar.set_length(d.as_micros_u64().max(1));
     }
 
-    // only downmix if achannels > 3
-    let stereo_downmix = downmix_to_stereo && probe.max_audio_channels.map_or(false, |c| c > 3);
-    let audio_codec = audio_codec.as_deref();
-    if stereo_downmix && audio_codec == Some("copy") {
-        anyhow::bail!("--stereo-downmix cannot be used with --acodec copy");
-    }
-
     info!(
         "encoding {}",
         output.file_name().and_then(|n| n.to_str()).unwrap_or("")
     );
 
-    let mut enc = ffmpeg::encode(enc_args, &output, has_audio, audio_codec, stereo_downmix)?;
-    let mut logger = ProgressLogger::new(module_path!(), Instant::now());
+    let sidecar = encode
+        .sidecar
+        .then(|| Sidecar::new(&enc_args, sidecar_vmaf));
+
+    if encode.raw_stats {
+        hide_for_raw_stats(bar);
+    }
+
+    let mut enc = ffmpeg::encode(
+        enc_args,
+        &output,
+        has_audio,
+        audio_codec,
+        stereo_downmix,
+        encode.raw_stats,
+    )?;
+    let encode_start = Instant::now();
+    let mut logger = ProgressLogger::new(module_path!(), encode_start);
     let mut stream_sizes = None;
     while let Some(progress) = enc.next().await {
         match progress? {
@@ -119,10 +155,15 @@ pub async fn run(
         }
     }
     bar.finish();
+    let encode_time = encode_start.elapsed();
 
     // successful encode, so don't delete it!
     temporary::unadd(&output);
 
+    if let Some(sidecar) = sidecar {
+        sidecar.write(&output)?;
+    }
+
     // print output info
     let output_size = fs::metadata(&output).await?.len();
     let output_percent = 100.0 * output_size as f64 / fs::metadata(&args.input).await?.len() as f64;
@@ -150,9 +191,286 @@ pub async fn run(
     }
     eprintln!("{}", style(")").dim());
 
+    if let Some((duration, factor)) = probe
+        .duration
+        .as_ref()
+        .ok()
+        .and_then(|d| Some((*d, realtime_factor(*d, encode_time)?)))
+    {
+        eprintln!(
+            "{}",
+            style!(
+                "Encoded {} of video in {} ({factor:.1}x realtime)",
+                HumanDuration(duration),
+                HumanDuration(encode_time),
+            )
+            .dim()
+        );
+    }
+
     Ok(())
 }
 
+/// Final encode ffmpeg settings.
+pub struct Plan<'a> {
+    pub enc_args: ffmpeg::FfmpegEncodeArgs<'a>,
+    pub has_audio: bool,
+    pub audio_codec: Option<&'a str>,
+    pub stereo_downmix: bool,
+}
+
+/// Returns the final encode ffmpeg settings, printing any warnings to `bar`.
+pub fn plan<'a>(
+    args: &'a args::Encode,
+    crf: f32,
+    args::EncodeToOutput {
+        audio_codec,
+        downmix_to_stereo,
+        video_only,
+        audio_lang,
+        captions,
+        dynamic_hdr,
+        force_keyframes,
+        output,
+        ..
+    }: &'a args::EncodeToOutput,
+    probe: &Ffprobe,
+    bar: &ProgressBar,
+) -> anyhow::Result<Plan<'a>> {
+    let mut enc_args = args.to_encoder_args(crf, probe)?;
+    enc_args.video_only = *video_only;
+    if let Some(split) = threads::split(Phase::Encode) {
+        enc_args.set_threads(split.encoder);
+    }
+    if *captions == Some(args::Captions::Copy) {
+        match ffmpeg::caption_passthrough_args(&enc_args.vcodec) {
+            Some(caption_args) => enc_args
+                .output_args
+                .extend(caption_args.map(|a| Arc::new(a.to_owned()))),
+            None => bar.println(
+                style!(
+                    "Warning: {} cannot carry closed captions, they will be dropped",
+                    enc_args.vcodec
+                )
+                .yellow()
+                .to_string(),
+            ),
+        }
+    }
+    if *dynamic_hdr == Some(args::DynamicHdr::Copy) {
+        if let Some(warning) = dynamic_hdr_passthrough(&mut enc_args, output.as_deref(), probe) {
+            bar.println(style(warning).yellow().to_string());
+        }
+    }
+    if let Some(keyframes) = force_keyframes {
+        if enc_args
+            .output_args
+            .iter()
+            .any(|a| a.as_str() == "-force_key_frames")
+        {
+            bar.println(
+                style!("Warning: --enc force_key_frames is set, ignoring --force-keyframes")
+                    .yellow()
+                    .to_string(),
+            );
+        } else {
+            enc_args
+                .output_args
+                .push(Arc::new("-force_key_frames".into()));
+            enc_args
+                .output_args
+                .push(Arc::new(keyframes.as_str().to_owned()));
+        }
+    }
+    let mut has_audio = probe.has_audio;
+    if !audio_lang.is_empty() {
+        let selected = ffmpeg::select_audio_streams(&probe.audio_langs, audio_lang);
+        if selected.is_empty() {
+            bar.println(
+                style!(
+                    "Warning: no audio streams match --audio-lang {}, all audio will be dropped",
+                    audio_lang.join(",")
+                )
+                .yellow()
+                .to_string(),
+            );
+            has_audio = false;
+        }
+        enc_args.output_args.extend(
+            ffmpeg::drop_audio_map_args(probe.audio_langs.len(), &selected)
+                .into_iter()
+                .map(Arc::new),
+        );
+    }
+
+    // only downmix if achannels > 3
+    let stereo_downmix = *downmix_to_stereo && probe.max_audio_channels.is_some_and(|c| c > 3);
+    let audio_codec = audio_codec.as_deref();
+    if stereo_downmix && audio_codec == Some("copy") {
+        anyhow::bail!("--stereo-downmix cannot be used with --acodec copy");
+    }
+
+    Ok(Plan {
+        enc_args,
+        has_audio,
+        audio_codec,
+        stereo_downmix,
+    })
+}
+
+/// Add `--dynamic-hdr copy` passthrough args returning a warning if unsupported.
+fn dynamic_hdr_passthrough(
+    enc_args: &mut ffmpeg::FfmpegEncodeArgs<'_>,
+    output: Option<&Path>,
+    probe: &Ffprobe,
+) -> Option<String> {
+    let ext = output
+        .and_then(|o| o.extension()?.to_str())
+        .unwrap_or_else(|| default_output_ext(enc_args.input, probe.is_image));
+    match ffmpeg::dynamic_hdr_passthrough_args(&enc_args.vcodec, ext) {
+        Some(hdr_args) => {
+            enc_args
+                .output_args
+                .extend(hdr_args.into_iter().map(|a| Arc::new(a.to_owned())));
+            None
+        }
+        None => Some(format!(
+            "Warning: {} to {ext} cannot carry HDR10+/Dolby Vision dynamic metadata, it will be dropped",
+            enc_args.vcodec
+        )),
+    }
+}
+
+#[test]
+fn plan_dynamic_hdr() {
+    let plan_output_args = |cli: &[&str]| {
+        let args = Args::try_parse_from(
+            [
+                "encode",
+                "-i",
+                "vid.mkv",
+                "--crf",
+                "30",
+                "--dynamic-hdr",
+                "copy",
+            ]
+            .iter()
+            .chain(cli),
+        )
+        .unwrap();
+        let probe = ffprobe::unknown("test");
+        let mut enc_args = args.args.to_encoder_args(30.0, &probe).unwrap();
+        let warning = dynamic_hdr_passthrough(&mut enc_args, args.encode.output.as_deref(), &probe);
+        let output_args: Vec<_> = enc_args.output_args.iter().map(|a| a.to_string()).collect();
+        (output_args, warning)
+    };
+
+    let (output_args, warning) = plan_output_args(&[]);
+    assert!(warning.is_none(), "{warning:?}");
+    assert!(
+        args::has_arg_pair(&output_args, ["-dolbyvision", "1"]),
+        "{output_args:?}"
+    );
+
+    let (output_args, warning) = plan_output_args(&["-e", "libx264"]);
+    assert!(!output_args.iter().any(|a| a == "-dolbyvision"));
+    let warning = warning.expect("drop warning");
+    assert!(warning.contains("libx264 to mkv"), "{warning}");
+
+    let (_, warning) = plan_output_args(&["-o", "vid.av1.avi"]);
+    assert!(warning.is_some());
+}
+
+#[test]
+fn plan_force_keyframes() {
+    let output_args = |force: &str| {
+        let args = Args::try_parse_from([
+            "encode",
+            "-i",
+            "vid.mkv",
+            "--crf",
+            "30",
+            "--force-keyframes",
+            force,
+        ])
+        .unwrap();
+        let probe = ffprobe::unknown("test");
+        let plan = plan(
+            &args.args,
+            30.0,
+            &args.encode,
+            &probe,
+            &ProgressBar::hidden(),
+        )
+        .unwrap();
+        let output_args: Vec<_> = plan
+            .enc_args
+            .output_args
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let idx = output_args
+            .iter()
+            .position(|a| a == "-force_key_frames")
+            .expect("-force_key_frames");
+        output_args[idx + 1].clone()
+    };
+    assert_eq!(output_args("0,10,20.5,00:01:00"), "0,10,20.5,00:01:00");
+    assert_eq!(
+        output_args("expr:gte(t,n_forced*10)"),
+        "expr:gte(t,n_forced*10)"
+    );
+
+    for invalid in ["0,ten", "expr:gte(t,n_forced*10", "expr:", "1:2:3:4", "-5"] {
+        assert!(
+            invalid.parse::<args::ForceKeyframes>().is_err(),
+            "{invalid} should be invalid"
+        );
+    }
+    assert!("chapters-0.1".parse::<args::ForceKeyframes>().is_ok());
+}
+
+/// Stop drawing `bar` so ffmpeg's raw stats output is shown alone.
+fn hide_for_raw_stats(bar: &ProgressBar) {
+    bar.disable_steady_tick();
+    bar.set_draw_target(ProgressDrawTarget::hidden());
+}
+
+#[test]
+fn raw_stats_hides_bar() {
+    let bar = ProgressBar::new(1);
+    bar.enable_steady_tick(Duration::from_millis(100));
+    hide_for_raw_stats(&bar);
+    assert!(bar.is_hidden());
+}
+
+/// Returns how many times faster than realtime `source` duration was encoded in `elapsed`.
+fn realtime_factor(source: Duration, elapsed: Duration) -> Option<f64> {
+    if source.is_zero() || elapsed.is_zero() {
+        return None;
+    }
+    Some(source.as_secs_f64() / elapsed.as_secs_f64())
+}
+
+#[test]
+fn realtime_factor_calc() {
+    let factor = realtime_factor(Duration::from_secs(80 * 60), Duration::from_secs(12 * 60));
+    assert_eq!(format!("{:.1}", factor.unwrap()), "6.7");
+
+    let factor = realtime_factor(Duration::from_secs(60), Duration::from_secs(120));
+    assert_eq!(factor, Some(0.5));
+
+    // images & instant encodes
+    assert_eq!(
+        realtime_factor(Duration::ZERO, Duration::from_secs(1)),
+        None
+    );
+    assert_eq!(
+        realtime_factor(Duration::from_secs(1), Duration::ZERO),
+        None
+    );
+}
+
 /// * vid.mp4 -> "mp4"
 /// * vid.??? -> "mkv"
 /// * image.??? -> "avif"
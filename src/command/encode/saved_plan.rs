@@ -0,0 +1,234 @@
+//! `--write-plan` & `encode --from-plan` saved crf search decision logic.
+use super::sidecar;
+use crate::command::args;
+use anyhow::{ensure, Context};
+use std::{ffi::OsString, path::Path};
+
+/// Crf search decision, i.e. the crf & encode args, for a later `encode --from-plan`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SavedPlan {
+    /// ab-av1 version.
+    pub version: String,
+    pub crf: f32,
+    /// Encode `--name=value` args, see [`args::Encode::to_cli_args`].
+    pub args: Vec<String>,
+    /// VMAF info of the search, included in a `--sidecar`.
+    pub vmaf: Option<sidecar::Vmaf>,
+    /// Predicted encode size as a percentage of the input.
+    pub predicted_encode_percent: f64,
+}
+
+impl SavedPlan {
+    pub fn new(
+        args: &args::Encode,
+        crf: f32,
+        vmaf: sidecar::Vmaf,
+        predicted_encode_percent: f64,
+    ) -> anyhow::Result<Self> {
+        // plans are json, so the --input must round trip as a string
+        ensure!(
+            args.input.to_str().is_some(),
+            "input path {} is not valid UTF-8",
+            args.input.display()
+        );
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            crf,
+            args: args.to_cli_args(),
+            vmaf: Some(vmaf),
+            predicted_encode_percent,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("failed to write plan {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read(path)
+            .with_context(|| format!("failed to read plan {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| format!("invalid plan {}", path.display()))
+    }
+
+    /// Command line args of the plan `--name=value` args & crf.
+    fn cli_args(&self) -> impl Iterator<Item = String> + '_ {
+        let crf = format!("--crf={}", self.crf);
+        self.args.iter().cloned().chain([crf])
+    }
+}
+
+/// Short arg aliases of plan args.
+const SHORT_ARGS: &[(&str, &str)] = &[("-e", "encoder"), ("-i", "input")];
+
+/// If `args` are an `encode` with `--from-plan` insert its args, except those already
+/// specified, e.g. a different `--input` path when encoding on another machine.
+pub fn apply(args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let strs: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+    let Some(encode_idx) = strs.iter().skip(1).position(|a| a == "encode") else {
+        return Ok(args);
+    };
+    let encode_args = &strs[encode_idx + 2..];
+    let Some(path) = encode_args
+        .iter()
+        .enumerate()
+        .find_map(|(idx, a)| match a.as_ref() {
+            "--from-plan" => encode_args.get(idx + 1).map(|p| p.to_string()),
+            a => a.strip_prefix("--from-plan=").map(String::from),
+        })
+    else {
+        return Ok(args);
+    };
+    let plan = SavedPlan::read(Path::new(&path))?;
+
+    let specified: Vec<String> = strs
+        .iter()
+        .filter_map(
+            |a| match SHORT_ARGS.iter().find(|(s, _)| a.as_ref() == *s) {
+                Some((_, long)) => Some(long.to_string()),
+                None => a
+                    .strip_prefix("--")
+                    .map(|a| a.split('=').next().unwrap_or(a).into()),
+            },
+        )
+        .collect();
+    drop(strs);
+
+    let mut args = args;
+    for arg in plan.cli_args() {
+        let key = arg[2..].split('=').next().unwrap_or_default();
+        if !specified.iter().any(|s| s == key) {
+            args.push(arg.into());
+        }
+    }
+    Ok(args)
+}
+
+#[test]
+fn plan_round_trips_encode_args() {
+    use super::Args;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        args: args::Encode,
+    }
+    let Cli { args: searched } = Cli::parse_from([
+        "ab-av1",
+        "-e",
+        "libx265",
+        "-i",
+        "/media/vid.mkv",
+        "--vfilter",
+        "scale=1280:-1,fps=24",
+        "--preset",
+        "slow",
+        "--pix-format",
+        "yuv420p10le",
+        "--keyint",
+        "10s",
+        "--scd",
+        "true",
+        "--enc",
+        "x265-params=aq-mode=3",
+        "--enc-input",
+        "hwaccel=none",
+        "--bframes",
+        "8",
+    ]);
+    let plan = SavedPlan::new(
+        &searched,
+        24.5,
+        sidecar::Vmaf {
+            score: 95.1,
+            args: vec![],
+            scale: "auto".into(),
+        },
+        31.2,
+    )
+    .unwrap();
+
+    let dir = std::env::temp_dir().join(format!("ab-av1-plan-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("plan.json");
+    plan.write(&path).unwrap();
+    assert_eq!(SavedPlan::read(&path).unwrap(), plan);
+
+    let argv = apply(
+        [
+            "ab-av1",
+            "encode",
+            "--from-plan",
+            path.to_str().unwrap(),
+            "-o",
+            "out.mkv",
+        ]
+        .map(OsString::from)
+        .into(),
+    )
+    .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let encode = Args::parse_from(argv.iter().skip(1));
+    assert_eq!(encode.crf, 24.5);
+    assert_eq!(encode.from_plan, Some(path));
+    let enc_args = |args: &args::Encode, crf| {
+        let probe = crate::ffprobe::Ffprobe {
+            duration: Ok(std::time::Duration::from_secs(300)),
+            has_audio: true,
+            resolution: Some((1920, 1080)),
+            ..crate::ffprobe::test_probe()
+        };
+        format!("{:?}", args.to_encoder_args(crf, &probe).unwrap())
+    };
+    assert_eq!(
+        enc_args(&encode.args, encode.crf),
+        enc_args(&searched, 24.5)
+    );
+    assert_eq!(encode.args.to_cli_args(), plan.args);
+}
+
+#[test]
+fn plan_args_yield_to_specified() {
+    let dir = std::env::temp_dir().join(format!("ab-av1-plan-input-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("plan.json");
+    SavedPlan {
+        version: "0".into(),
+        crf: 30.0,
+        args: vec!["--encoder=libsvtav1".into(), "--input=/a/vid.mkv".into()],
+        vmaf: None,
+        predicted_encode_percent: 40.0,
+    }
+    .write(&path)
+    .unwrap();
+
+    let from_plan = format!("--from-plan={}", path.display());
+    // only encode supports --from-plan
+    let vmaf: Vec<OsString> = ["ab-av1", "vmaf", &from_plan].map(Into::into).into();
+    assert_eq!(apply(vmaf.clone()).unwrap(), vmaf);
+
+    let argv = apply(
+        ["ab-av1", "encode", &from_plan, "-i", "/b/vid.mkv"]
+            .map(OsString::from)
+            .into(),
+    )
+    .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        argv,
+        [
+            "ab-av1",
+            "encode",
+            &from_plan,
+            "-i",
+            "/b/vid.mkv",
+            "--encoder=libsvtav1",
+            "--crf=30"
+        ]
+        .map(OsString::from)
+    );
+}
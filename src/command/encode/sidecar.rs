@@ -0,0 +1,116 @@
+//! `<output>.abav1.json` encode provenance logic.
+use crate::ffmpeg::FfmpegEncodeArgs;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Encode provenance written next to the output.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Sidecar {
+    /// ab-av1 version.
+    pub version: &'static str,
+    pub encoder: String,
+    pub crf: f32,
+    pub preset: Option<String>,
+    pub pix_fmt: &'static str,
+    pub vfilter: Option<String>,
+    /// Additional ffmpeg input args.
+    pub input_args: Vec<String>,
+    /// Additional ffmpeg output args.
+    pub output_args: Vec<String>,
+    /// VMAF info, if the crf was chosen by a search.
+    pub vmaf: Option<Vmaf>,
+}
+
+/// VMAF info of the crf-search used to pick the encode crf.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Vmaf {
+    /// Predicted (sample mean) VMAF score.
+    pub score: f32,
+    pub args: Vec<String>,
+    pub scale: String,
+}
+
+impl Sidecar {
+    pub fn new(enc_args: &FfmpegEncodeArgs<'_>, vmaf: Option<Vmaf>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            encoder: enc_args.vcodec.to_string(),
+            crf: enc_args.crf,
+            preset: enc_args.preset.as_ref().map(|p| p.to_string()),
+            pix_fmt: enc_args.pix_fmt.as_str(),
+            vfilter: enc_args.vfilter.map(String::from),
+            input_args: enc_args.input_args.iter().map(|a| a.to_string()).collect(),
+            output_args: enc_args.output_args.iter().map(|a| a.to_string()).collect(),
+            vmaf,
+        }
+    }
+
+    /// Write to `<output>.abav1.json`, returns the written path.
+    pub fn write(&self, output: &Path) -> anyhow::Result<PathBuf> {
+        let path = path(output);
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(&path, json).with_context(|| format!("write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// E.g. vid.av1.mkv -> "vid.av1.mkv.abav1.json"
+pub fn path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".abav1.json");
+    path.into()
+}
+
+#[test]
+fn sidecar_path() {
+    assert_eq!(
+        path(Path::new("/media/vid.av1.mkv")),
+        Path::new("/media/vid.av1.mkv.abav1.json")
+    );
+}
+
+#[test]
+fn write_sidecar() {
+    use crate::command::args::PixelFormat;
+    use std::sync::Arc;
+
+    let enc_args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        vcodec: "libsvtav1".into(),
+        vfilter: Some("scale=1280:-1"),
+        pix_fmt: PixelFormat::Yuv420p10le,
+        crf: 32.0,
+        qp: false,
+        preset: Some("8".into()),
+        output_args: vec![Arc::new("-svtav1-params".into()), Arc::new("scd=0".into())],
+        input_args: vec![],
+        video_only: false,
+    };
+    let vmaf = Vmaf {
+        score: 95.12,
+        args: vec!["n_subsample=4".into()],
+        scale: "auto".into(),
+    };
+
+    let dir = std::env::temp_dir().join(format!("ab-av1-sidecar-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("vid.av1.mkv");
+
+    let written = Sidecar::new(&enc_args, Some(vmaf)).write(&output).unwrap();
+    assert_eq!(written, dir.join("vid.av1.mkv.abav1.json"));
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&written).unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["encoder"], "libsvtav1");
+    assert_eq!(json["crf"], 32.0);
+    assert_eq!(json["preset"], "8");
+    assert_eq!(json["pix_fmt"], "yuv420p10le");
+    assert_eq!(json["vfilter"], "scale=1280:-1");
+    assert_eq!(json["output_args"][1], "scd=0");
+    assert_eq!(json["vmaf"]["score"].as_f64().unwrap() as f32, 95.12);
+    assert_eq!(json["vmaf"]["args"][0], "n_subsample=4");
+    assert_eq!(json["vmaf"]["scale"], "auto");
+}
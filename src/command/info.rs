@@ -0,0 +1,255 @@
+use crate::{
+    command::{args::ExtraMetric, sample_encode},
+    ffmpeg,
+};
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use tokio::process::Command;
+
+/// Print the detected ffmpeg version, build configuration & VMAF capabilities.
+///
+/// Useful to include in bug reports as scores & behaviour depend on the ffmpeg build.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {}
+
+/// libvmaf built-in models ab-av1 uses.
+const VMAF_MODELS: &[&str] = &["vmaf_v0.6.1", "vmaf_v0.6.1neg", "vmaf_4k_v0.6.1"];
+
+pub async fn info(_: Args) -> anyhow::Result<()> {
+    let version = ffmpeg_stdout("-version").await?;
+    let filters = ffmpeg_stdout("-filters").await?;
+    let hwaccels = ffmpeg_stdout("-hwaccels").await?;
+    print!("{}", Info::parse(&version, &filters, &hwaccels));
+    Ok(())
+}
+
+/// Print `--capabilities` json describing this build & the detected ffmpeg, for wrappers.
+pub async fn capabilities() -> anyhow::Result<()> {
+    let version = ffmpeg_stdout("-version").await?;
+    let filters = ffmpeg_stdout("-filters").await?;
+    let hwaccels = ffmpeg_stdout("-hwaccels").await?;
+    let encoders = ffmpeg::encoders().await?;
+    let info = Info::parse(&version, &filters, &hwaccels);
+    let capabilities = Capabilities::new(info, &encoders);
+    println!("{}", serde_json::to_string_pretty(&capabilities)?);
+    Ok(())
+}
+
+async fn ffmpeg_stdout(arg: &str) -> anyhow::Result<String> {
+    let out = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg(arg)
+        .output()
+        .await
+        .with_context(|| format!("ffmpeg {arg}"))?;
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Info<'a> {
+    version: Option<&'a str>,
+    configuration: Option<&'a str>,
+    libvmaf: bool,
+    libvmaf_cuda: bool,
+    hwaccels: Vec<&'a str>,
+}
+
+impl<'a> Info<'a> {
+    /// Parse `ffmpeg -version`, `-filters` & `-hwaccels` outputs.
+    fn parse(version: &'a str, filters: &'a str, hwaccels: &'a str) -> Self {
+        let has_filter = |name: &str| {
+            filters
+                .lines()
+                .any(|l| l.split_whitespace().nth(1) == Some(name))
+        };
+        Self {
+            version: version
+                .lines()
+                .next()
+                .and_then(|l| l.strip_prefix("ffmpeg version "))
+                .and_then(|l| l.split_whitespace().next()),
+            configuration: version
+                .lines()
+                .find_map(|l| l.trim().strip_prefix("configuration:"))
+                .map(str::trim),
+            libvmaf: has_filter("libvmaf"),
+            libvmaf_cuda: has_filter("libvmaf_cuda"),
+            hwaccels: hwaccels
+                .lines()
+                .skip_while(|l| !l.starts_with("Hardware acceleration methods:"))
+                .skip(1)
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// `--capabilities` json.
+#[derive(Debug, serde::Serialize)]
+struct Capabilities<'a> {
+    /// ab-av1 version.
+    version: &'static str,
+    ffmpeg_version: Option<&'a str>,
+    /// Available quality metrics, empty without libvmaf.
+    metrics: Vec<String>,
+    libvmaf_cuda: bool,
+    /// Detected ffmpeg video encoders.
+    encoders: Vec<&'a str>,
+    /// sample-encode `--stdout-format` values.
+    output_formats: Vec<String>,
+    hwaccels: Vec<&'a str>,
+}
+
+impl<'a> Capabilities<'a> {
+    /// Combine ffmpeg `info` & `ffmpeg -encoders` output with supported features.
+    fn new(info: Info<'a>, encoders: &'a str) -> Self {
+        let metrics = match info.libvmaf {
+            true => ["vmaf".to_owned()]
+                .into_iter()
+                .chain(value_names(ExtraMetric::value_variants()))
+                .collect(),
+            false => vec![],
+        };
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            ffmpeg_version: info.version,
+            metrics,
+            libvmaf_cuda: info.libvmaf_cuda,
+            encoders: ffmpeg::parse_video_encoders(encoders),
+            output_formats: value_names(sample_encode::StdoutFormat::value_variants()),
+            hwaccels: info.hwaccels,
+        }
+    }
+}
+
+/// Returns the cli names of a clap value enum.
+fn value_names<T: ValueEnum>(values: &[T]) -> Vec<String> {
+    values
+        .iter()
+        .filter_map(ValueEnum::to_possible_value)
+        .map(|v| v.get_name().to_owned())
+        .collect()
+}
+
+impl std::fmt::Display for Info<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        writeln!(f, "ffmpeg {}", self.version.unwrap_or("unknown"))?;
+        if let Some(config) = self.configuration {
+            writeln!(f, "configuration: {config}")?;
+        }
+        writeln!(f, "libvmaf: {}", yes_no(self.libvmaf))?;
+        writeln!(f, "libvmaf_cuda: {}", yes_no(self.libvmaf_cuda))?;
+        match self.hwaccels.as_slice() {
+            [] => writeln!(f, "hwaccels: none")?,
+            hw => writeln!(f, "hwaccels: {}", hw.join(", "))?,
+        }
+        if self.libvmaf {
+            writeln!(f, "vmaf models (built-in): {}", VMAF_MODELS.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_ffmpeg_capabilities() {
+    const VERSION: &str = "ffmpeg version n7.0.1 Copyright (c) 2000-2024 the FFmpeg developers
+built with gcc 14.1.1 (GCC) 20240522
+configuration: --prefix=/usr --enable-libsvtav1 --enable-libvmaf
+libavutil      59.  8.100 / 59.  8.100
+";
+    const FILTERS: &str = "Filters:
+  T.. = Timeline support
+  ... = Source or sink filter
+ ... libplacebo        N->V       Apply various GPU filters from libplacebo
+ ... libvmaf           VV->V      Calculate the VMAF between two video streams.
+ ... scale             V->V       Scale the input video size and/or convert the image format.
+";
+    const HWACCELS: &str = "Hardware acceleration methods:
+cuda
+vaapi
+
+";
+    let info = Info::parse(VERSION, FILTERS, HWACCELS);
+    assert_eq!(
+        info,
+        Info {
+            version: Some("n7.0.1"),
+            configuration: Some("--prefix=/usr --enable-libsvtav1 --enable-libvmaf"),
+            libvmaf: true,
+            libvmaf_cuda: false,
+            hwaccels: vec!["cuda", "vaapi"],
+        }
+    );
+    assert_eq!(
+        info.to_string(),
+        "ffmpeg n7.0.1\n\
+         configuration: --prefix=/usr --enable-libsvtav1 --enable-libvmaf\n\
+         libvmaf: yes\n\
+         libvmaf_cuda: no\n\
+         hwaccels: cuda, vaapi\n\
+         vmaf models (built-in): vmaf_v0.6.1, vmaf_v0.6.1neg, vmaf_4k_v0.6.1\n"
+    );
+
+    let info = Info::parse("", "", "Hardware acceleration methods:\n\n");
+    assert_eq!(info.version, None);
+    assert!(info.hwaccels.is_empty());
+    assert!(!info.libvmaf);
+}
+
+#[test]
+fn capabilities_json() {
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libsvtav1            SVT-AV1(Scalable Video Technology for AV1) encoder (codec av1)
+ V....D libx265              libx265 H.265 / HEVC (codec hevc)
+ A....D libopus              libopus Opus (codec opus)
+";
+    let info = Info::parse(
+        "ffmpeg version n7.0.1 Copyright",
+        " ... libvmaf           VV->V      Calculate the VMAF",
+        "Hardware acceleration methods:\ncuda\n",
+    );
+    let json = serde_json::to_value(Capabilities::new(info, ENCODERS)).unwrap();
+
+    let keys: Vec<_> = json
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    for key in [
+        "version",
+        "ffmpeg_version",
+        "metrics",
+        "libvmaf_cuda",
+        "encoders",
+        "output_formats",
+        "hwaccels",
+    ] {
+        assert!(keys.contains(&key), "missing {key} in {keys:?}");
+    }
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["ffmpeg_version"], "n7.0.1");
+    assert_eq!(json["metrics"], serde_json::json!(["vmaf", "psnr", "ssim"]));
+    assert_eq!(json["libvmaf_cuda"], false);
+    assert_eq!(
+        json["encoders"],
+        serde_json::json!(["libsvtav1", "libx265"])
+    );
+    assert_eq!(
+        json["output_formats"],
+        serde_json::json!(["human", "json", "tsv"])
+    );
+    assert_eq!(json["hwaccels"], serde_json::json!(["cuda"]));
+
+    // no libvmaf, no metrics
+    let json = serde_json::to_value(Capabilities::new(Info::parse("", "", ""), "")).unwrap();
+    assert_eq!(json["metrics"], serde_json::json!([]));
+    assert_eq!(json["encoders"], serde_json::json!([]));
+}
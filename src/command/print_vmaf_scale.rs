@@ -0,0 +1,58 @@
+use crate::command::args::{self, parse_resolution};
+use clap::Parser;
+
+/// Print the VMAF model & scaling that would be used for given distorted resolutions.
+///
+/// Nothing is analysed, this shows the auto model (1k or 4k) & --vmaf-scale decisions
+/// taking into account any --vmaf model & --vmaf-scale args.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    /// Distorted video resolution(s) in WxH format, e.g. 1280x720.
+    #[arg(required = true, value_parser = parse_resolution)]
+    pub resolutions: Vec<(u32, u32)>,
+
+    #[clap(flatten)]
+    pub vmaf: args::Vmaf,
+}
+
+pub fn print_vmaf_scale(Args { resolutions, vmaf }: Args) {
+    for res in resolutions {
+        println!("{}", decision_line(&vmaf, res));
+    }
+}
+
+fn decision_line(vmaf: &args::Vmaf, (w, h): (u32, u32)) -> String {
+    let (model, scale) = vmaf.model_and_scale(Some((w, h)));
+    match scale {
+        Some((sw, sh)) => format!("{w}x{h} model {model} scale {sw}:{sh}"),
+        None => format!("{w}x{h} model {model} no scaling"),
+    }
+}
+
+#[test]
+fn print_decisions() {
+    let vmaf = args::Vmaf::default();
+    assert_eq!(
+        decision_line(&vmaf, (1280, 720)),
+        "1280x720 model vmaf_v0.6.1 scale 1920:-1"
+    );
+    assert_eq!(
+        decision_line(&vmaf, (3840, 2160)),
+        "3840x2160 model vmaf_4k_v0.6.1 no scaling"
+    );
+    assert_eq!(
+        decision_line(&vmaf, (3008, 1692)),
+        "3008x1692 model vmaf_4k_v0.6.1 scale 3840:-1"
+    );
+
+    let vmaf = args::Vmaf {
+        vmaf_scale: args::VmafScale::None,
+        ..<_>::default()
+    };
+    assert_eq!(
+        decision_line(&vmaf, (1280, 720)),
+        "1280x720 model vmaf_v0.6.1 no scaling"
+    );
+}
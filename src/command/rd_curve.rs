@@ -0,0 +1,205 @@
+use crate::{
+    command::{
+        args,
+        sample_encode::{self, StdoutFormat},
+        PROGRESS_CHARS,
+    },
+    console_ext::style,
+    ffprobe,
+    float::TerseF32,
+    temporary, tsv,
+};
+use anyhow::ensure;
+use clap::{ArgAction, Parser};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{sync::Arc, time::Duration};
+
+/// Sample encode at multiple crf values to produce a rate-distortion curve of
+/// predicted bitrate against VMAF, e.g. for plotting & BD-rate comparisons.
+///
+/// Outputs (bitrate, VMAF) points ordered by bitrate.
+#[derive(Parser)]
+#[clap(verbatim_doc_comment)]
+#[group(skip)]
+pub struct Args {
+    #[clap(flatten)]
+    pub args: args::Encode,
+
+    /// Comma separated crf values to measure, e.g. `--crfs 20,25,30,35,40`.
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub crfs: Vec<f32>,
+
+    #[clap(flatten)]
+    pub sample: args::Sample,
+
+    /// Enable sample-encode caching.
+    #[arg(
+        long,
+        default_value_t = true,
+        env = "AB_AV1_CACHE",
+        action(ArgAction::Set)
+    )]
+    pub cache: bool,
+
+    /// Stdout message format `human`, `json` or `tsv`.
+    #[arg(long, value_enum, default_value_t = StdoutFormat::Human)]
+    pub stdout_format: StdoutFormat,
+
+    #[clap(flatten)]
+    pub vmaf: args::Vmaf,
+}
+
+pub async fn rd_curve(
+    Args {
+        mut args,
+        crfs,
+        mut sample,
+        cache,
+        stdout_format,
+        vmaf,
+    }: Args,
+) -> anyhow::Result<()> {
+    let bar = ProgressBar::new(crfs.len() as _).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan.bold} {elapsed_precise:.bold} {prefix} {wide_bar:.cyan/blue} ({msg}eta {eta})")?
+            .progress_chars(PROGRESS_CHARS)
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    args.prepare().await?;
+    let probe = ffprobe::probe(&args.input);
+    probe.ensure_video(&args.input)?;
+    ensure!(!probe.is_image, "rd-curve requires a video input");
+    sample.set_extension_from_input(&args.input, &probe);
+    sample.set_auto_sample_duration(&args, &probe);
+    sample.set_avoid_ranges(&args.input)?;
+    let duration = probe.duration.clone()?;
+    let probe = Arc::new(probe);
+
+    let mut measured = Vec::with_capacity(crfs.len());
+    for crf in crfs {
+        bar.set_prefix(format!("crf {}", TerseF32(crf)));
+        let out = sample_encode::run(
+            sample_encode::Args {
+                args: args.clone(),
+                crf,
+                sample: sample.clone(),
+                cache,
+                stdout_format: StdoutFormat::Json,
+                list_samples: false,
+                baseline: <_>::default(),
+                vmaf: vmaf.clone(),
+                abort_above_percent: None,
+            },
+            probe.clone(),
+            ProgressBar::hidden(),
+            false,
+        )
+        .await?;
+        bar.println(
+            style!("- crf {} VMAF {:.2}", TerseF32(crf), out.vmaf)
+                .dim()
+                .to_string(),
+        );
+        measured.push((crf, out));
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+    temporary::clean_all().await;
+
+    let points = rd_points(&measured, duration);
+    match stdout_format {
+        StdoutFormat::Human => {
+            for p in &points {
+                println!(
+                    "crf {} bitrate {:.0} kb/s VMAF {:.2}",
+                    TerseF32(p.crf),
+                    p.bitrate_kbps,
+                    p.vmaf
+                );
+            }
+        }
+        StdoutFormat::Json => println!("{}", serde_json::to_string(&points)?),
+        StdoutFormat::Tsv => {
+            println!("{}", tsv::header(RdPoint::TSV_FIELDS));
+            for p in &points {
+                println!("{}", tsv::row(p, RdPoint::TSV_FIELDS)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A rate-distortion curve point.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RdPoint {
+    pub crf: f32,
+    /// Predicted video stream bitrate.
+    pub bitrate_kbps: f64,
+    pub vmaf: f32,
+}
+
+impl RdPoint {
+    const TSV_FIELDS: &[&str] = &["crf", "bitrate_kbps", "vmaf"];
+}
+
+/// Returns the curve points from per-crf sample encode results, ordered by bitrate.
+fn rd_points(measured: &[(f32, sample_encode::Output)], duration: Duration) -> Vec<RdPoint> {
+    let secs = duration.as_secs_f64().max(f64::EPSILON);
+    let mut points: Vec<_> = measured
+        .iter()
+        .map(|(crf, out)| RdPoint {
+            crf: *crf,
+            bitrate_kbps: out.predicted_encode_size as f64 * 8.0 / 1000.0 / secs,
+            vmaf: out.vmaf,
+        })
+        .collect();
+    points.sort_by(|a, b| a.bitrate_kbps.total_cmp(&b.bitrate_kbps));
+    points
+}
+
+#[test]
+fn rd_points_from_measurements() {
+    let out = |size, vmaf| sample_encode::Output {
+        vmaf,
+        vmaf_stddev: None,
+        predicted_encode_size: size,
+        encode_percent: 50.0,
+        predicted_encode_time: Duration::from_secs(1),
+        from_cache: false,
+        size_aborted: false,
+        vmaf_lavfi: None,
+        sample_sizes: vec![],
+    };
+    let measured = [
+        (20.0, out(10_000_000, 98.5)),
+        (40.0, out(1_000_000, 88.0)),
+        (30.0, out(4_000_000, 95.25)),
+    ];
+
+    let points = rd_points(&measured, Duration::from_secs(100));
+    assert_eq!(
+        points,
+        [
+            RdPoint {
+                crf: 40.0,
+                bitrate_kbps: 80.0,
+                vmaf: 88.0
+            },
+            RdPoint {
+                crf: 30.0,
+                bitrate_kbps: 320.0,
+                vmaf: 95.25
+            },
+            RdPoint {
+                crf: 20.0,
+                bitrate_kbps: 800.0,
+                vmaf: 98.5
+            },
+        ]
+    );
+    assert_eq!(
+        tsv::row(&points[1], RdPoint::TSV_FIELDS).unwrap(),
+        "30.0\t320.0\t95.25"
+    );
+}
@@ -1,16 +1,22 @@
+mod baseline;
 mod cache;
 
 use crate::{
     command::{
-        args::{self, PixelFormat},
+        args::{self, ExtractCodec, PixelFormat, SeekMode},
+        vmaf::retry_warning,
         SmallDuration, PROGRESS_CHARS,
     },
     console_ext::style,
     ffmpeg::{self, FfmpegEncodeArgs},
     ffprobe::{self, Ffprobe},
+    fingerprint,
     log::ProgressLogger,
+    metric::Metric,
     process::FfmpegOut,
     sample, temporary,
+    threads::{self, Phase},
+    timing, tsv,
     vmaf::{self, VmafOut},
 };
 use anyhow::{ensure, Context};
@@ -19,10 +25,17 @@ use console::style;
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
 use log::info;
 use std::{
+    borrow::Cow,
+    fmt,
+    future::Future,
     io::IsTerminal,
+    ops::Range,
     path::{Path, PathBuf},
     pin::pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use tokio::fs;
@@ -58,12 +71,24 @@ pub struct Args {
     )]
     pub cache: bool,
 
-    /// Stdout message format `human` or `json`.
+    /// Stdout message format `human`, `json` or `tsv`.
     #[arg(long, value_enum, default_value_t = StdoutFormat::Human)]
     pub stdout_format: StdoutFormat,
 
+    /// Print the planned sample start times & durations then exit without encoding.
+    #[arg(long)]
+    pub list_samples: bool,
+
+    #[clap(flatten)]
+    pub baseline: baseline::Args,
+
     #[clap(flatten)]
     pub vmaf: args::Vmaf,
+
+    /// Abort once the encoded percent, extrapolated from partial sample encode
+    /// output, exceeds this. Set by crf-search `--early-size-abort`.
+    #[arg(skip)]
+    pub abort_above_percent: Option<f32>,
 }
 
 pub async fn sample_encode(mut args: Args) -> anyhow::Result<()> {
@@ -74,10 +99,95 @@ pub async fn sample_encode(mut args: Args) -> anyhow::Result<()> {
     );
     bar.enable_steady_tick(Duration::from_millis(100));
 
+    args.args.prepare().await?;
     let probe = ffprobe::probe(&args.args.input);
+    probe.ensure_video(&args.args.input)?;
+    args.sample.set_avoid_ranges(&args.args.input)?;
+    if args.list_samples {
+        bar.finish_and_clear();
+        return list_samples(&args, &probe);
+    }
     args.sample
         .set_extension_from_input(&args.args.input, &probe);
-    run(args, probe.into(), bar, true).await?;
+    args.sample.set_auto_sample_duration(&args.args, &probe);
+    if let Some(warning) = scored_frames_warning(&args.sample, &args.vmaf, &probe) {
+        bar.println(style(warning).yellow().to_string());
+    }
+    let baseline = args.baseline.clone();
+    let output = run(args, probe.into(), bar, true).await?;
+    baseline.check(&output)
+}
+
+/// Print planned samples.
+fn list_samples(args: &Args, probe: &Ffprobe) -> anyhow::Result<()> {
+    let duration = probe.duration.clone()?;
+    let fps = probe.fps.clone()?;
+    let (samples, sample_duration, full_pass) =
+        sample_plan(&args.sample, duration, fps, probe.is_image);
+
+    let planned: Vec<_> = match full_pass {
+        true => vec![PlannedSample {
+            start: Duration::ZERO,
+            frames: ((duration.as_secs_f64() * fps).round() as u32).max(1),
+            floor_to_sec: false,
+        }],
+        false => (0..samples)
+            .map(|idx| {
+                plan_sample(
+                    idx,
+                    samples,
+                    sample_duration,
+                    duration,
+                    fps,
+                    &args.sample.avoid_ranges,
+                    args.sample.sample_seed,
+                )
+            })
+            .collect(),
+    };
+
+    match args.stdout_format {
+        StdoutFormat::Human => {
+            if full_pass {
+                println!("Full pass: {}", HumanDuration(duration));
+            }
+            for (idx, sample) in planned.iter().enumerate().filter(|_| !full_pass) {
+                println!(
+                    "Sample {}/{samples} start {}s duration {} ({} frames)",
+                    idx + 1,
+                    sample.start_secs(),
+                    HumanDuration(sample_duration),
+                    sample.frames,
+                );
+            }
+        }
+        StdoutFormat::Tsv => {
+            const FIELDS: &[&str] = &["start_seconds", "duration_seconds", "frames"];
+            println!("{}", tsv::header(FIELDS));
+            for s in &planned {
+                let sample = serde_json::json!({
+                    "start_seconds": s.start_secs(),
+                    "duration_seconds": s.frames as f64 / fps,
+                    "frames": s.frames,
+                });
+                println!("{}", tsv::row(&sample, FIELDS)?);
+            }
+        }
+        StdoutFormat::Json => {
+            let samples: Vec<_> = planned
+                .iter()
+                .map(|s| {
+                    serde_json::json!({
+                        "start_seconds": s.start_secs(),
+                        "duration_seconds": s.frames as f64 / fps,
+                        "frames": s.frames,
+                    })
+                })
+                .collect();
+            let json = serde_json::json!({ "full_pass": full_pass, "samples": samples });
+            println!("{}", serde_json::to_string(&json).unwrap());
+        }
+    }
     Ok(())
 }
 
@@ -88,7 +198,10 @@ pub async fn run(
         sample: sample_args,
         cache,
         stdout_format,
+        list_samples: _,
+        baseline: _,
         vmaf,
+        abort_above_percent,
     }: Args,
     input_probe: Arc<Ffprobe>,
     bar: ProgressBar,
@@ -98,39 +211,40 @@ pub async fn run(
     let input_pixel_format = input_probe.pixel_format();
     let input_is_image = input_probe.is_image;
     let input_len = fs::metadata(&*input).await?.len();
-    let enc_args = args.to_encoder_args(crf, &input_probe)?;
+    let input_fingerprint = match cache || sample_args.sample_cache_dir.is_some() {
+        true => {
+            let input = input.clone();
+            Some(tokio::task::spawn_blocking(move || fingerprint::fingerprint(&input)).await??)
+        }
+        false => None,
+    };
+    let mut enc_args = args.to_encoder_args(crf, &input_probe)?;
     let duration = input_probe.duration.clone()?;
     let input_fps = input_probe.fps.clone()?;
-    let samples = sample_args.sample_count(duration).max(1);
     let keep = sample_args.keep;
+    let warmup = sample_args.warmup;
+    let report = sample_args.sample_report;
+    let (samples, sample_duration, full_pass) =
+        sample_plan(&sample_args, duration, input_fps, input_is_image);
+    if sample_args.lossless_intermediate {
+        bar.set_message("lossless intermediate,");
+    }
+    let sample_in = sample_source(&input, &sample_args).await?;
     let temp_dir = sample_args.temp_dir;
-
-    let (samples, sample_duration, full_pass) = {
-        if input_is_image {
-            (1, duration.max(Duration::from_secs(1)), true)
-        } else if sample_args.sample_duration.is_zero()
-            || sample_args.sample_duration * samples as _ >= duration.mul_f64(0.85)
-        {
-            // if the sample time is most of the full input time just encode the whole thing
-            (1, duration, true)
-        } else {
-            let sample_duration = if input_fps > 0.0 {
-                // if sample-length is lower than a single frame use the frame time
-                let one_frame_duration = Duration::from_secs_f64(1.0 / input_fps);
-                sample_args.sample_duration.max(one_frame_duration)
-            } else {
-                sample_args.sample_duration
-            };
-            (samples, sample_duration, false)
-        }
-    };
+    let seek_mode = sample_args.seek_mode;
+    let extract_codec = sample_args.sample_extract_codec;
+    let sample_cache_dir = sample_args.sample_cache_dir;
+    let dest_ext = sample_args.extension.as_deref().unwrap_or("mkv");
     let sample_duration_us = sample_duration.as_micros_u64();
     bar.set_length(sample_duration_us * samples * 2);
 
     // Start creating copy samples async, this is IO bound & not cpu intensive
     let (tx, mut sample_tasks) = tokio::sync::mpsc::unbounded_channel();
     let sample_temp = temp_dir.clone();
-    let sample_in = input.clone();
+    let avoid = sample_args.avoid_ranges;
+    let seed = sample_args.sample_seed;
+    let sample_avoid = avoid.clone();
+    let input_in = input.clone();
     tokio::task::spawn_local(async move {
         if full_pass {
             // Use the entire video as a single sample
@@ -138,13 +252,18 @@ pub async fn run(
         } else {
             for sample_idx in 0..samples {
                 let sample = sample(
+                    input_in.clone(),
                     sample_in.clone(),
                     sample_idx,
                     samples,
                     sample_duration,
                     duration,
                     input_fps,
+                    seek_mode,
+                    extract_codec,
                     sample_temp.clone(),
+                    &sample_avoid,
+                    seed,
                 )
                 .await;
                 if tx.send((sample_idx, sample)).is_err() {
@@ -155,9 +274,24 @@ pub async fn run(
     });
 
     let mut results = Vec::new();
+    let mut starts = Vec::new();
+    let mut run_vmaf = vmaf.clone();
+    let mut vmaf_lavfi = None;
+    if let Some(split) = threads::split(Phase::Search) {
+        enc_args.set_threads(split.encoder);
+        run_vmaf.set_default_n_threads(split.vmaf);
+    }
+    // Note: log args are only added to `run_vmaf` so they don't affect caching
+    let json_log = match vmaf.metric_blend.is_some() || vmaf.extra_metric.is_some() {
+        true => Some(vmaf::frames::json_log_path(&mut run_vmaf)?),
+        false => None,
+    };
     loop {
         bar.set_message("sampling,");
-        let (sample_idx, sample) = match sample_tasks.recv().await {
+        let sampling_start = Instant::now();
+        let next_sample = sample_tasks.recv().await;
+        timing::add_sampling(sampling_start.elapsed());
+        let (sample_idx, sample) = match next_sample {
             Some(s) => s,
             None => break,
         };
@@ -177,7 +311,7 @@ pub async fn run(
             &sample,
             duration,
             input.extension(),
-            input_len,
+            input_fingerprint,
             full_pass,
             &enc_args,
             &vmaf,
@@ -205,72 +339,183 @@ pub async fn run(
                 result
             }
             (None, key) => {
-                bar.set_message("encoding,");
-                let b = Instant::now();
-                let mut logger = ProgressLogger::new(module_path!(), b);
-                let (encoded_sample, mut output) = ffmpeg::encode_sample(
-                    FfmpegEncodeArgs {
-                        input: &sample,
-                        ..enc_args.clone()
-                    },
-                    temp_dir.clone(),
-                    sample_args.extension.as_deref().unwrap_or("mkv"),
-                )?;
-                while let Some(progress) = output.next().await {
-                    if let FfmpegOut::Progress { time, fps, .. } = progress? {
-                        bar.set_position(
-                            time.as_micros_u64() + sample_idx * sample_duration_us * 2,
-                        );
-                        if fps > 0.0 {
-                            bar.set_message(format!("enc {fps} fps,"));
+                let cached_sample_file =
+                    sample_cache_dir
+                        .as_deref()
+                        .zip(input_fingerprint)
+                        .map(|(dir, fingerprint)| {
+                            cache::sample_file_path(
+                                dir,
+                                &sample,
+                                duration,
+                                input.extension(),
+                                fingerprint,
+                                full_pass,
+                                &enc_args,
+                                dest_ext,
+                            )
+                        });
+                let cached_encode_time = match &cached_sample_file {
+                    Some(file) => {
+                        let expected_frames = match full_pass {
+                            true => input_probe.nframes().ok(),
+                            false => ffprobe::probe(&sample).nframes().ok(),
+                        };
+                        cache::read_sample_file(file, expected_frames).await
+                    }
+                    None => None,
+                };
+
+                let (encoded_sample, encode_time) = match (cached_sample_file, cached_encode_time) {
+                    (Some(file), Some(encode_time)) => {
+                        info!("reusing cached sample encode {}", file.display());
+                        (file, encode_time)
+                    }
+                    (cached_sample_file, _) => {
+                        let warmup = (warmup && take_warmup(&WARMED_UP)).then(|| {
+                            bar.set_message("warmup,");
+                            warmup_encode(&sample, &enc_args, temp_dir.clone(), dest_ext)
+                        });
+                        let (encoded_sample, encode_time) = timed_after_warmup(warmup, async {
+                            bar.set_message("encoding,");
+                            let mut logger = ProgressLogger::new(module_path!(), Instant::now());
+                            let (encoded_sample, mut output) = ffmpeg::encode_sample(
+                                FfmpegEncodeArgs {
+                                    input: &sample,
+                                    ..enc_args.clone()
+                                },
+                                temp_dir.clone(),
+                                dest_ext,
+                            )?;
+                            while let Some(progress) = output.next().await {
+                                if let FfmpegOut::Progress { time, fps, .. } = progress? {
+                                    bar.set_position(
+                                        time.as_micros_u64() + sample_idx * sample_duration_us * 2,
+                                    );
+                                    if fps > 0.0 {
+                                        bar.set_message(format!("enc {fps} fps,"));
+                                    }
+                                    logger.update(sample_duration, time, fps);
+
+                                    let Some(max) = abort_above_percent else {
+                                        continue;
+                                    };
+                                    let partial_size = match fs::metadata(&encoded_sample).await {
+                                        Ok(m) => m.len(),
+                                        Err(_) => continue,
+                                    };
+                                    let projected = extrapolated_encode_percent(
+                                        &results,
+                                        (partial_size, time),
+                                        (sample_size, sample_duration),
+                                    );
+                                    if let Some(p) = projected.filter(|p| *p > max as f64) {
+                                        // dropping `output` kills the encode
+                                        return Ok((encoded_sample, Some(p)));
+                                    }
+                                }
+                            }
+                            Ok((encoded_sample, None))
+                        })
+                        .await?;
+                        let (encoded_sample, aborted_percent) = encoded_sample;
+                        if let Some(percent) = aborted_percent {
+                            info!("sample {sample_n}/{samples} crf {crf} aborted, projected {percent:.0}% exceeds size ceiling");
+                            let _ = tokio::fs::remove_file(&encoded_sample).await;
+                            bar.finish();
+                            return Ok(Output::size_aborted(percent, input_len));
+                        }
+                        match cached_sample_file {
+                            Some(file) => {
+                                cache::store_sample_file(&encoded_sample, &file, encode_time)
+                                    .await?;
+                                temporary::unadd(&encoded_sample);
+                                (file, encode_time)
+                            }
+                            None => (encoded_sample, encode_time),
                         }
-                        logger.update(sample_duration, time, fps);
                     }
-                }
-                let encode_time = b.elapsed();
+                };
                 let encoded_size = fs::metadata(&encoded_sample).await?.len();
                 let encoded_probe = ffprobe::probe(&encoded_sample);
+                let mut encoded_res = encoded_probe.resolution;
+                if args.vfilter.is_none() {
+                    // encoder changed the pixel aspect, compare at display aspect ratio
+                    [encoded_res, _] = run_vmaf.match_display_aspect(&encoded_probe, &input_probe);
+                }
+                let reference_fps = match &args.fps {
+                    Some(fps) => ffprobe::parse_frame_rate(fps),
+                    None => input_probe.fps.clone().ok(),
+                };
+                run_vmaf.set_auto_fps(encoded_probe.fps.clone().ok(), reference_fps);
 
                 // calculate vmaf
                 bar.set_message("vmaf running,");
-                let mut vmaf = pin!(vmaf::run(
-                    &sample,
-                    &encoded_sample,
-                    &vmaf.ffmpeg_lavfi(
-                        encoded_probe.resolution,
+                let mut logger = ProgressLogger::new("ab_av1::vmaf", Instant::now());
+                let mut vmaf_score = None;
+                'vmaf: loop {
+                    let lavfi = sample_vmaf_lavfi(
+                        &run_vmaf,
+                        &args,
+                        encoded_res,
                         enc_args
                             .pix_fmt
                             .max(input_pixel_format.unwrap_or(PixelFormat::Yuv444p10le)),
-                        args.vfilter.as_deref(),
-                    ),
-                    &vmaf.reference_input_args(),
-                    &vmaf.distorted_input_args(),
-                )?);
-                let mut logger = ProgressLogger::new("ab_av1::vmaf", Instant::now());
-                let mut vmaf_score = None;
-                while let Some(vmaf) = vmaf.next().await {
-                    match vmaf {
-                        VmafOut::Done(score) => {
-                            vmaf_score = Some(score);
-                            break;
-                        }
-                        VmafOut::Progress(FfmpegOut::Progress { time, fps, .. }) => {
-                            bar.set_position(
-                                sample_duration_us
-                                    // *24/fps adjusts for vmaf `-r 24`
-                                    + (time.as_micros_u64() as f64 * (24.0 / input_fps)).round() as u64
-                                    + sample_idx * sample_duration_us * 2,
-                            );
-                            if fps > 0.0 {
-                                bar.set_message(format!("vmaf {fps} fps,"));
+                    );
+                    let mut vmaf_out = pin!(vmaf::cap_duration(
+                        vmaf::run(
+                            run_vmaf.clone(),
+                            &sample,
+                            &encoded_sample,
+                            vmaf_lavfi.insert(lavfi),
+                            &vmaf.distorted_input_args(),
+                            &vmaf.reference_input_args(),
+                            vmaf.vmaf_cpu_affinity.as_ref(),
+                        )?,
+                        vmaf.max_analysis_duration,
+                    ));
+                    while let Some(out) = vmaf_out.next().await {
+                        match out {
+                            VmafOut::Done(score) => {
+                                vmaf_score = Some(score);
+                                break 'vmaf;
+                            }
+                            VmafOut::Progress(FfmpegOut::Progress { time, fps, .. }) => {
+                                bar.set_position(
+                                    sample_duration_us
+                                        // *24/fps adjusts for vmaf `-r 24`
+                                        + (time.as_micros_u64() as f64 * (24.0 / input_fps)).round() as u64
+                                        + sample_idx * sample_duration_us * 2,
+                                );
+                                if fps > 0.0 {
+                                    bar.set_message(format!("vmaf {fps} fps,"));
+                                }
+                                logger.update(sample_duration, time, fps);
                             }
-                            logger.update(sample_duration, time, fps);
+                            VmafOut::Progress(_) => {}
+                            VmafOut::Err(e) => match vmaf::retry(&run_vmaf, &e) {
+                                // keep reduced threads for subsequent samples too
+                                Some(retry) => {
+                                    bar.println(retry_warning(&retry, &e));
+                                    run_vmaf = retry;
+                                    continue 'vmaf;
+                                }
+                                None => return Err(e),
+                            },
                         }
-                        VmafOut::Progress(_) => {}
-                        VmafOut::Err(e) => return Err(e),
                     }
+                    break;
+                }
+                let mut vmaf_score = vmaf_score.context("no vmaf score")?;
+                if let (Some(blend), Some(log)) = (&vmaf.metric_blend, &json_log) {
+                    vmaf_score = vmaf::frames::read_blended_score(log, blend)?;
                 }
-                let vmaf_score = vmaf_score.context("no vmaf score")?;
+                let extra_metric = match (vmaf.extra_metric, &json_log) {
+                    (Some(metric), Some(log)) => {
+                        Some(vmaf::frames::read_pooled_metric(log, metric.log_name())?)
+                    }
+                    _ => None,
+                };
 
                 bar.println(
                     style!(
@@ -298,6 +543,7 @@ pub async fn run(
                         .filter(|d| !d.is_zero())
                         .unwrap_or(sample_duration),
                     from_cache: false,
+                    extra_metric,
                 };
 
                 if let Some(k) = key {
@@ -306,7 +552,7 @@ pub async fn run(
 
                 // Early clean. Note: Avoid cleaning copy samples
                 temporary::clean(true).await;
-                if !keep {
+                if !keep && sample_cache_dir.is_none() {
                     let _ = tokio::fs::remove_file(encoded_sample).await;
                 }
 
@@ -315,11 +561,55 @@ pub async fn run(
         };
 
         results.push(result);
+        starts.push(match full_pass {
+            true => 0.0,
+            false => plan_sample(
+                sample_idx,
+                samples,
+                sample_duration,
+                duration,
+                input_fps,
+                &avoid,
+                seed,
+            )
+            .start_secs(),
+        });
+    }
+    if let Some(metric) = vmaf.extra_metric {
+        let pairs: Vec<_> = results
+            .iter()
+            .filter_map(|r| Some((f64::from(r.vmaf_score), f64::from(r.extra_metric?))))
+            .collect();
+        if let Some(r) = correlation(&pairs) {
+            info!("crf {crf} VMAF-{metric} correlation across samples {r:.2}");
+            match r < METRIC_DISAGREEMENT {
+                true => bar.println(
+                    style!(
+                        "Warning: VMAF & {metric} disagree across samples (correlation {r:.2}), \
+                         one may be missing a distortion"
+                    )
+                    .yellow()
+                    .to_string(),
+                ),
+                false => bar.println(
+                    style!("VMAF-{metric} correlation across samples {r:.2}")
+                        .dim()
+                        .to_string(),
+                ),
+            }
+        }
+    }
+    if report && results.len() > 1 {
+        bar.println("Sample report, worst VMAF first:");
+        for row in sample_report(&starts, &results) {
+            bar.println(row.to_string());
+        }
     }
     bar.finish();
 
     let output = Output {
         vmaf: results.mean_vmaf(),
+        vmaf_stddev: results.vmaf_stddev(),
         // Using file size * encode_percent can over-estimate. However, if it ends up less
         // than the duration estimation it may turn out to be more accurate.
         predicted_encode_size: results
@@ -328,7 +618,27 @@ pub async fn run(
         encode_percent: results.encoded_percent_size(),
         predicted_encode_time: results.estimate_encode_time(duration, full_pass),
         from_cache: results.iter().all(|r| r.from_cache),
+        size_aborted: false,
+        vmaf_lavfi,
+        sample_sizes: results
+            .iter()
+            .map(|r| SampleSize {
+                sample_size: r.sample_size,
+                encoded_size: r.encoded_size,
+            })
+            .collect(),
     };
+    info!(
+        "crf {crf} sample sizes {}",
+        (output.sample_sizes.iter())
+            .map(|s| format!(
+                "{}->{}",
+                HumanBytes(s.sample_size),
+                HumanBytes(s.encoded_size)
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
     info!(
         "crf {crf} VMAF {:.2} predicted video stream size {} ({:.0}%) taking {}{}",
         output.vmaf,
@@ -348,45 +658,544 @@ pub async fn run(
             );
         }
         // stdout result
-        stdout_format.print_result(
-            output.vmaf,
-            output.predicted_encode_size,
-            output.encode_percent,
-            output.predicted_encode_time,
-            input_is_image,
-        );
+        stdout_format.print_result(&output, input_is_image);
     }
 
     Ok(output)
 }
 
+/// Returns the file samples are cut from, and so the VMAF reference. The `--intermediate`,
+/// a newly created `--lossless-intermediate` or otherwise the input.
+async fn sample_source(
+    input: &Arc<PathBuf>,
+    sample_args: &args::Sample,
+) -> anyhow::Result<Arc<PathBuf>> {
+    Ok(
+        match (&sample_args.intermediate, sample_args.lossless_intermediate) {
+            (Some(intermediate), _) => intermediate.clone().into(),
+            (None, true) => sample::lossless_intermediate(
+                input,
+                sample_args.sample_extract_codec,
+                sample_args.temp_dir.clone(),
+            )
+            .await?
+            .into(),
+            (None, false) => input.clone(),
+        },
+    )
+}
+
+#[tokio::test]
+async fn sample_source_intermediate() {
+    let input = Arc::new(PathBuf::from("vid.mp4"));
+
+    let sample_args = args::Sample::parse_from(["ab-av1"]);
+    assert_eq!(sample_source(&input, &sample_args).await.unwrap(), input);
+
+    let sample_args = args::Sample::parse_from(["ab-av1", "--intermediate", "vid.ffv1.mkv"]);
+    assert_eq!(
+        *sample_source(&input, &sample_args).await.unwrap(),
+        PathBuf::from("vid.ffv1.mkv")
+    );
+}
+
+/// Frames encoded by a `--warmup` encode.
+const WARMUP_FRAMES: &str = "24";
+
+/// Set once a `--warmup` encode has run in this process.
+static WARMED_UP: AtomicBool = AtomicBool::new(false);
+
+/// Returns true the first time called with `done`.
+fn take_warmup(done: &AtomicBool) -> bool {
+    !done.swap(true, Ordering::Relaxed)
+}
+
+/// Throwaway short encode of `sample` to warm up the encoder, see `--warmup`.
+async fn warmup_encode(
+    sample: &Path,
+    enc_args: &FfmpegEncodeArgs<'_>,
+    temp_dir: Option<PathBuf>,
+    dest_ext: &str,
+) -> anyhow::Result<()> {
+    let mut args = FfmpegEncodeArgs {
+        input: sample,
+        ..enc_args.clone()
+    };
+    args.output_args.push(Arc::new("-frames:v".into()));
+    args.output_args.push(Arc::new(WARMUP_FRAMES.into()));
+    let (dest, mut output) = ffmpeg::encode_sample(args, temp_dir, dest_ext)?;
+    while let Some(progress) = output.next().await {
+        progress?;
+    }
+    let _ = fs::remove_file(dest).await;
+    Ok(())
+}
+
+/// Runs `warmup`, if any, then `encode` returning its output & duration excluding the warmup.
+async fn timed_after_warmup<T>(
+    warmup: Option<impl Future<Output = anyhow::Result<()>>>,
+    encode: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<(T, Duration)> {
+    if let Some(warmup) = warmup {
+        warmup.await?;
+    }
+    let start = Instant::now();
+    let out = encode.await?;
+    Ok((out, start.elapsed()))
+}
+
+#[tokio::test]
+async fn warmup_timing_excluded() {
+    let done = AtomicBool::new(false);
+    assert!(take_warmup(&done));
+    assert!(!take_warmup(&done), "warmup should only run once");
+
+    let warmed = AtomicBool::new(false);
+    let warmup = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        warmed.store(true, Ordering::Relaxed);
+        Ok(())
+    };
+    let (out, encode_time) = timed_after_warmup(Some(warmup), async {
+        assert!(warmed.load(Ordering::Relaxed), "warmup should run first");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Ok("encoded")
+    })
+    .await
+    .unwrap();
+    assert_eq!(out, "encoded");
+    assert!(warmed.load(Ordering::Relaxed));
+    assert!(encode_time < Duration::from_millis(300), "{encode_time:?}");
+}
+
+/// Returns the VMAF lavfi comparing an encoded sample to its source sample.
+///
+/// The reference is the source as the encoder saw it, i.e. with the encode `--vfilter`
+/// applied, unless overridden by `--reference-vfilter`. Any `--fps` conversion is
+/// applied to the reference in either case.
+fn sample_vmaf_lavfi(
+    vmaf: &args::Vmaf,
+    args: &args::Encode,
+    encoded_res: Option<(u32, u32)>,
+    pix_fmt: PixelFormat,
+) -> String {
+    let mut vmaf = Cow::Borrowed(vmaf);
+    if let (Some(reference_vfilter), Some(fps)) = (&vmaf.reference_vfilter, &args.fps) {
+        // --reference-vfilter replaces the encode vfilter, so needs the fps conversion too
+        vmaf.to_mut().reference_vfilter = Some(format!("{reference_vfilter},fps={fps}"));
+    }
+    vmaf.lavfi(encoded_res, pix_fmt, args.vfilter.as_deref())
+}
+
+#[test]
+fn sample_vmaf_reference_is_post_vfilter() {
+    let args = Args::try_parse_from([
+        "sample-encode",
+        "-i",
+        "vid.mkv",
+        "--crf",
+        "30",
+        "--vfilter",
+        "scale=1280:-1,hqdn3d",
+    ])
+    .unwrap();
+    let lavfi = sample_vmaf_lavfi(
+        &args.vmaf,
+        &args.args,
+        Some((1280, 720)),
+        PixelFormat::Yuv420p,
+    );
+    let (dis, rest) = lavfi.split_once("[dis];").unwrap();
+    let (reference, _) = rest.split_once("[ref];").unwrap();
+    assert!(reference.starts_with("[1:v]"), "{lavfi}");
+    assert!(reference.contains("scale=1280:-1,hqdn3d"), "{lavfi}");
+    // the encoded sample already had the vfilter applied
+    assert!(!dis.contains("hqdn3d"), "{lavfi}");
+
+    let mut vmaf = args.vmaf.clone();
+    vmaf.reference_vfilter = Some("scale=1280:-1".into());
+    let lavfi = sample_vmaf_lavfi(&vmaf, &args.args, Some((1280, 720)), PixelFormat::Yuv420p);
+    assert!(!lavfi.contains("hqdn3d"), "{lavfi}");
+}
+
+#[tokio::test]
+async fn fps_applied_to_encode_and_reference() {
+    let mut args = Args::try_parse_from([
+        "sample-encode",
+        "-i",
+        "vid.mkv",
+        "--crf",
+        "30",
+        "--vfilter",
+        "scale=1280:-1",
+        "--fps",
+        "30",
+    ])
+    .unwrap();
+    args.args.prepare().await.unwrap();
+
+    let probe = Ffprobe {
+        fps: Ok(60.0),
+        ..ffprobe::unknown("vid.mkv")
+    };
+    let enc_args = args.args.to_encoder_args(30.0, &probe).unwrap();
+    assert_eq!(enc_args.vfilter, Some("scale=1280:-1,fps=30"));
+
+    let reference = |vmaf: &args::Vmaf| {
+        let lavfi = sample_vmaf_lavfi(vmaf, &args.args, Some((1280, 720)), PixelFormat::Yuv420p);
+        let (_, rest) = lavfi.split_once("[dis];").unwrap();
+        rest.split_once("[ref];").unwrap().0.to_owned()
+    };
+    assert!(reference(&args.vmaf).contains("scale=1280:-1,fps=30"));
+
+    let mut vmaf = args.vmaf.clone();
+    vmaf.reference_vfilter = Some("scale=1280:-1:flags=lanczos".into());
+    assert!(
+        reference(&vmaf).contains("scale=1280:-1:flags=lanczos,fps=30"),
+        "{}",
+        reference(&vmaf)
+    );
+}
+
+/// Returns a warning if VMAF would score too few frames of each sample, e.g. because
+/// of a large `n_subsample` with short samples.
+pub fn scored_frames_warning(
+    sample_args: &args::Sample,
+    vmaf: &args::Vmaf,
+    input_probe: &Ffprobe,
+) -> Option<String> {
+    let duration = input_probe.duration.clone().ok()?;
+    let fps = input_probe.fps.clone().ok()?;
+    let (_, sample_duration, _) = sample_plan(sample_args, duration, fps, input_probe.is_image);
+    let frames = ((sample_duration.as_secs_f64() * fps).round() as u64).max(1);
+    vmaf.few_scored_frames_warning(frames)
+}
+
+/// Returns the sample count, duration of each sample & whether to use a single full pass.
+pub fn sample_plan(
+    sample_args: &args::Sample,
+    duration: Duration,
+    input_fps: f64,
+    input_is_image: bool,
+) -> (u64, Duration, bool) {
+    let samples = sample_args.sample_count(duration).max(1);
+    if input_is_image {
+        (1, duration.max(Duration::from_secs(1)), true)
+    } else if sample_args.sample_duration().is_zero()
+        || sample_args.sample_duration() * samples as _ >= duration.mul_f64(0.85)
+    {
+        // if the sample time is most of the full input time just encode the whole thing
+        (1, duration, true)
+    } else {
+        let sample_duration = if input_fps > 0.0 {
+            // if sample-length is lower than a single frame use the frame time
+            let one_frame_duration = Duration::from_secs_f64(1.0 / input_fps);
+            sample_args.sample_duration().max(one_frame_duration)
+        } else {
+            sample_args.sample_duration()
+        };
+        let samples = match sample_args.min_sample_spacing {
+            Some(min) => spaced_sample_count(samples, sample_duration, duration, min),
+            None => samples,
+        };
+        (samples, sample_duration, false)
+    }
+}
+
+/// Reduce `samples` until evenly spaced sample starts are at least `min_spacing` apart.
+fn spaced_sample_count(
+    samples: u64,
+    sample_duration: Duration,
+    duration: Duration,
+    min_spacing: Duration,
+) -> u64 {
+    // start-to-start spacing of evenly placed samples, see `plan_sample`
+    let spacing = |n: u64| {
+        duration.saturating_sub(sample_duration * n as _) / (n as u32 + 1) + sample_duration
+    };
+    let mut n = samples;
+    while n > 1 && spacing(n) < min_spacing {
+        n -= 1;
+    }
+    if n < samples {
+        info!("using {n} samples to satisfy --min-sample-spacing");
+    }
+    n
+}
+
+/// Planned location of a sample in the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlannedSample {
+    pub start: Duration,
+    pub frames: u32,
+    pub floor_to_sec: bool,
+}
+
+impl PlannedSample {
+    /// Start seconds as used when copying the sample.
+    fn start_secs(&self) -> f32 {
+        sample::start_secs(self.start, self.floor_to_sec)
+    }
+}
+
+/// Plan sample `sample_idx` of `samples` evenly spaced across the input, outside
+/// of sorted non-overlapping `avoid` ranges.
+///
+/// With a `seed` each sample is jittered by up to half the spacing either way.
+pub fn plan_sample(
+    sample_idx: u64,
+    samples: u64,
+    sample_duration: Duration,
+    duration: Duration,
+    fps: f64,
+    avoid: &[Range<Duration>],
+    seed: Option<u64>,
+) -> PlannedSample {
+    let sample_n = sample_idx + 1;
+
+    let avoided: Duration = avoid.iter().map(|r| r.end.saturating_sub(r.start)).sum();
+    let content = duration.saturating_sub(avoided);
+    // ignore avoided ranges if there isn't room for the samples elsewhere
+    let (duration, avoid) = match content >= sample_duration * samples as _ {
+        true => (content, avoid),
+        false => (duration, &[][..]),
+    };
+
+    let spacing = duration.saturating_sub(sample_duration * samples as _) / (samples as u32 + 1);
+    let mut start = spacing * sample_n as _ + sample_duration * sample_idx as _;
+    if let Some(seed) = seed {
+        start = start - spacing / 2 + spacing.mul_f64(sample_jitter(seed, sample_idx));
+    }
+
+    PlannedSample {
+        start: skip_avoided(start, sample_duration, avoid),
+        frames: ((sample_duration.as_secs_f64() * fps).round() as u32).max(1),
+        floor_to_sec: sample_duration >= Duration::from_secs(2),
+    }
+}
+
+/// Returns a `0..1` jitter for sample `sample_idx` deterministic for the `seed`.
+fn sample_jitter(seed: u64, sample_idx: u64) -> f64 {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    (0..sample_idx).for_each(|_| _ = rng.u64(..));
+    rng.f64()
+}
+
+/// Map a sample `start` planned with `avoid` ranges removed from the input timeline
+/// back onto the input, moving a sample that would overlap a range to after it.
+fn skip_avoided(
+    mut start: Duration,
+    sample_duration: Duration,
+    avoid: &[Range<Duration>],
+) -> Duration {
+    for range in avoid {
+        if start >= range.start {
+            start += range.end - range.start;
+        } else if start + sample_duration > range.start {
+            start = range.end;
+        }
+    }
+    start
+}
+
+#[test]
+fn plan_samples_evenly_spaced() {
+    let duration = Duration::from_secs(60 * 60);
+    let sample_duration = Duration::from_secs(20);
+
+    let planned: Vec<_> = (0..3)
+        .map(|idx| plan_sample(idx, 3, sample_duration, duration, 24.0, &[], None))
+        .collect();
+
+    // (3600s - 3*20s) / 4 = 885s gaps between samples
+    let starts: Vec<_> = planned.iter().map(|s| s.start_secs()).collect();
+    assert_eq!(starts, [885.0, 1790.0, 2695.0]);
+    assert!(planned.iter().all(|s| s.frames == 480));
+}
+
+#[test]
+fn plan_samples_seeded_jitter() {
+    let duration = Duration::from_secs(60 * 60);
+    let sample_duration = Duration::from_secs(20);
+    let starts = |seed| -> Vec<_> {
+        (0..3)
+            .map(|idx| plan_sample(idx, 3, sample_duration, duration, 24.0, &[], seed).start)
+            .collect()
+    };
+
+    let seeded = starts(Some(7));
+    assert_eq!(seeded, starts(Some(7)));
+    assert_ne!(seeded, starts(Some(8)));
+    assert_ne!(seeded, starts(None));
+
+    // jitter stays within half the 885s spacing, so samples never overlap
+    for (start, even) in seeded.iter().zip(starts(None)) {
+        let offset = start.as_secs_f64() - even.as_secs_f64();
+        assert!(offset.abs() <= 442.5, "{offset}");
+    }
+}
+
+#[test]
+fn plan_samples_avoid_ranges() {
+    let secs = Duration::from_secs;
+    let duration = secs(1380);
+    let sample_duration = secs(20);
+    // intro & credits
+    let avoid = [secs(60)..secs(90), secs(1200)..secs(1380)];
+
+    for samples in 1..=8 {
+        let planned: Vec<_> = (0..samples)
+            .map(|idx| plan_sample(idx, samples, sample_duration, duration, 24.0, &avoid, None))
+            .collect();
+        for sample in &planned {
+            let range = sample.start..sample.start + sample_duration;
+            assert!(
+                avoid
+                    .iter()
+                    .all(|a| range.end <= a.start || range.start >= a.end),
+                "{samples} samples: {range:?} overlaps {avoid:?}"
+            );
+        }
+        assert!(planned.windows(2).all(|w| w[0].start < w[1].start));
+    }
+
+    // samples that would overlap the intro move after it
+    assert_eq!(skip_avoided(secs(50), sample_duration, &avoid), secs(90));
+    assert_eq!(skip_avoided(secs(30), sample_duration, &avoid), secs(30));
+    assert_eq!(skip_avoided(secs(100), sample_duration, &avoid), secs(130));
+
+    // no room elsewhere, avoided ranges are ignored
+    let all = [Duration::ZERO..duration];
+    assert_eq!(
+        plan_sample(0, 1, sample_duration, duration, 24.0, &all, None),
+        plan_sample(0, 1, sample_duration, duration, 24.0, &[], None)
+    );
+}
+
+#[test]
+fn sample_plan_full_pass() {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Args {
+        #[clap(flatten)]
+        sample: args::Sample,
+    }
+    let Args { sample } = Args::parse_from(["test", "--samples", "3"]);
+
+    let (samples, sample_duration, full_pass) =
+        sample_plan(&sample, Duration::from_secs(3600), 24.0, false);
+    assert_eq!(
+        (samples, sample_duration, full_pass),
+        (3, Duration::from_secs(20), false)
+    );
+
+    // samples would cover most of the input
+    let (samples, sample_duration, full_pass) =
+        sample_plan(&sample, Duration::from_secs(65), 24.0, false);
+    assert_eq!(
+        (samples, sample_duration, full_pass),
+        (1, Duration::from_secs(65), true)
+    );
+}
+
+#[test]
+fn sample_plan_min_spacing() {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Args {
+        #[clap(flatten)]
+        sample: args::Sample,
+    }
+    let Args { sample } = Args::parse_from([
+        "test",
+        "--samples",
+        "8",
+        "--sample-duration",
+        "5s",
+        "--min-sample-spacing",
+        "1m",
+    ]);
+    let duration = Duration::from_secs(4 * 60);
+
+    let (samples, sample_duration, full_pass) = sample_plan(&sample, duration, 24.0, false);
+    assert!(!full_pass);
+    // (240s - 3*5s) / 4 + 5s = 61.25s spacing, 4 samples would be 51s
+    assert_eq!(samples, 3);
+    let starts: Vec<_> = (0..samples)
+        .map(|idx| plan_sample(idx, samples, sample_duration, duration, 24.0, &[], None).start)
+        .collect();
+    assert!(
+        starts
+            .windows(2)
+            .all(|w| w[1] - w[0] >= Duration::from_secs(60)),
+        "{starts:?}"
+    );
+
+    // always at least 1 sample
+    assert_eq!(
+        spaced_sample_count(8, sample_duration, duration, Duration::from_secs(3600)),
+        1
+    );
+    // already satisfied
+    assert_eq!(
+        spaced_sample_count(2, sample_duration, duration, Duration::from_secs(60)),
+        2
+    );
+}
+
 /// Copy a sample from the input to the temp_dir (or input dir).
+#[allow(clippy::too_many_arguments)]
 async fn sample(
     input: Arc<PathBuf>,
+    source: Arc<PathBuf>,
     sample_idx: u64,
     samples: u64,
     sample_duration: Duration,
     duration: Duration,
     fps: f64,
+    seek_mode: SeekMode,
+    extract_codec: ExtractCodec,
     temp_dir: Option<PathBuf>,
+    avoid: &[Range<Duration>],
+    seed: Option<u64>,
 ) -> anyhow::Result<(Arc<PathBuf>, u64)> {
-    let sample_n = sample_idx + 1;
-
-    let sample_start = (duration.saturating_sub(sample_duration * samples as _)
-        / (samples as u32 + 1))
-        * sample_n as _
-        + sample_duration * sample_idx as _;
-
-    let sample_frames = ((sample_duration.as_secs_f64() * fps).round() as u32).max(1);
-    let floor_to_sec = sample_duration >= Duration::from_secs(2);
+    let PlannedSample {
+        start,
+        frames,
+        floor_to_sec,
+    } = plan_sample(
+        sample_idx,
+        samples,
+        sample_duration,
+        duration,
+        fps,
+        avoid,
+        seed,
+    );
 
-    let sample = sample::copy(&input, sample_start, floor_to_sec, sample_frames, temp_dir).await?;
-    let sample_size = fs::metadata(&sample).await?.len();
+    let sample = sample::copy(
+        &source,
+        start,
+        floor_to_sec,
+        frames,
+        seek_mode,
+        extract_codec,
+        temp_dir,
+    )
+    .await?;
+    let mut sample_size = fs::metadata(&sample).await?.len();
     ensure!(
         // ffmpeg copy may fail successfully and give us a small/empty output
         sample_size > 1024,
         "ffmpeg copy failed: encoded sample too small"
     );
+    if seek_mode == SeekMode::Accurate || source != input {
+        // lossless sample size isn't comparable to the input, use the input's proportion
+        let input_size = fs::metadata(&*input).await?.len();
+        sample_size = (input_size as f64 * frames as f64 / fps / duration.as_secs_f64()) as u64;
+    }
     Ok((sample.into(), sample_size))
 }
 
@@ -402,6 +1211,63 @@ pub struct EncodeResult {
     sample_duration: Duration,
     /// Result read from cache.
     from_cache: bool,
+    /// Pooled `--extra-metric` score.
+    #[serde(default)]
+    extra_metric: Option<f32>,
+}
+
+/// VMAF & `--extra-metric` sample correlation below this is reported as disagreement.
+const METRIC_DISAGREEMENT: f64 = 0.5;
+
+/// Returns the Pearson correlation coefficient of `pairs`. `None` with fewer than 3
+/// pairs or if either metric doesn't vary.
+fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 3 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        let (dx, dy) = (x - mean_x, y - mean_y);
+        cov += dx * dy;
+        var_x += dx * dx;
+        var_y += dy * dy;
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+#[test]
+fn vmaf_extra_metric_correlation() {
+    let assert_close = |r: Option<f64>, expected: f64| {
+        let r = r.expect("correlation");
+        assert!((r - expected).abs() < 1e-9, "{r} != {expected}");
+    };
+
+    // psnr tracking vmaf linearly
+    assert_close(
+        correlation(&[(90.0, 38.0), (94.0, 40.0), (96.0, 41.0), (98.0, 42.0)]),
+        1.0,
+    );
+    // inversely related
+    assert_close(correlation(&[(90.0, 3.0), (95.0, 2.0), (100.0, 1.0)]), -1.0);
+    // x: 1,2,3,4 y: 1,3,2,4 => cov 4, var 5, 5 => 0.8
+    let r = correlation(&[(1.0, 1.0), (2.0, 3.0), (3.0, 2.0), (4.0, 4.0)]);
+    assert_close(r, 0.8);
+    assert!(r.unwrap() >= METRIC_DISAGREEMENT);
+    let disagree = correlation(&[(95.0, 40.0), (96.0, 36.0), (94.0, 39.0), (97.0, 37.5)]);
+    assert!(disagree.unwrap() < METRIC_DISAGREEMENT, "{disagree:?}");
+
+    assert_eq!(correlation(&[(90.0, 38.0), (94.0, 40.0)]), None, "too few");
+    assert_eq!(
+        correlation(&[(95.0, 38.0), (95.0, 40.0), (95.0, 41.0)]),
+        None,
+        "no variance"
+    );
 }
 
 trait EncodeResults {
@@ -409,6 +1275,9 @@ trait EncodeResults {
 
     fn mean_vmaf(&self) -> f32;
 
+    /// Population standard deviation of per-sample VMAF, `None` for less than 2 samples.
+    fn vmaf_stddev(&self) -> Option<f32>;
+
     /// Return estimated encoded **video stream** size by multiplying sample size by duration.
     fn estimate_encode_size_by_duration(
         &self,
@@ -435,6 +1304,19 @@ impl EncodeResults for Vec<EncodeResult> {
         self.iter().map(|r| r.vmaf_score).sum::<f32>() / self.len() as f32
     }
 
+    fn vmaf_stddev(&self) -> Option<f32> {
+        if self.len() < 2 {
+            return None;
+        }
+        let mean = f64::from(self.mean_vmaf());
+        let variance = self
+            .iter()
+            .map(|r| (f64::from(r.vmaf_score) - mean).powi(2))
+            .sum::<f64>()
+            / self.len() as f64;
+        Some(variance.sqrt() as f32)
+    }
+
     fn estimate_encode_size_by_duration(
         &self,
         input_duration: Duration,
@@ -475,6 +1357,107 @@ impl EncodeResults for Vec<EncodeResult> {
     }
 }
 
+/// A `--sample-report` line.
+#[derive(Debug, PartialEq)]
+struct SampleReportRow {
+    sample_n: usize,
+    start_secs: f32,
+    vmaf: f32,
+    encoded_size: u64,
+    encoded_percent: f64,
+    worst: bool,
+    largest: bool,
+}
+
+impl fmt::Display for SampleReportRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "- Sample {} at {}s vmaf {:.2} size {} ({:.0}%)",
+            self.sample_n,
+            self.start_secs,
+            self.vmaf,
+            HumanBytes(self.encoded_size),
+            self.encoded_percent,
+        )?;
+        match (self.worst, self.largest) {
+            (true, true) => write!(f, " worst vmaf, largest"),
+            (true, false) => write!(f, " worst vmaf"),
+            (false, true) => write!(f, " largest"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+/// Returns per-sample rows ordered by VMAF ascending, then larger encoded percent first,
+/// flagging the worst scoring & largest encoded percent samples.
+fn sample_report(starts: &[f32], results: &[EncodeResult]) -> Vec<SampleReportRow> {
+    let percent = |r: &EncodeResult| r.encoded_size as f64 * 100.0 / r.sample_size.max(1) as f64;
+    let mut rows: Vec<_> = starts
+        .iter()
+        .zip(results)
+        .enumerate()
+        .map(|(idx, (start_secs, r))| SampleReportRow {
+            sample_n: idx + 1,
+            start_secs: *start_secs,
+            vmaf: r.vmaf_score,
+            encoded_size: r.encoded_size,
+            encoded_percent: percent(r),
+            worst: false,
+            largest: false,
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        a.vmaf
+            .total_cmp(&b.vmaf)
+            .then(b.encoded_percent.total_cmp(&a.encoded_percent))
+    });
+    if let Some(worst) = rows.first_mut() {
+        worst.worst = true;
+    }
+    if let Some(largest) = rows
+        .iter_mut()
+        .max_by(|a, b| a.encoded_percent.total_cmp(&b.encoded_percent))
+    {
+        largest.largest = true;
+    }
+    rows
+}
+
+#[test]
+fn sample_report_worst_and_largest() {
+    let result = |sample_size, encoded_size, vmaf_score| EncodeResult {
+        sample_size,
+        encoded_size,
+        vmaf_score,
+        encode_time: Duration::from_secs(1),
+        sample_duration: Duration::from_secs(20),
+        from_cache: false,
+        extra_metric: None,
+    };
+    let results = [
+        result(1000, 300, 96.0),
+        result(1000, 450, 93.5),
+        result(2000, 1200, 95.0),
+        result(1000, 200, 93.5),
+    ];
+    let rows = sample_report(&[10.0, 70.0, 130.0, 190.0], &results);
+
+    let order: Vec<_> = rows.iter().map(|r| r.sample_n).collect();
+    assert_eq!(order, [2, 4, 3, 1], "vmaf ascending, ties larger first");
+    let flagged: Vec<_> = rows
+        .iter()
+        .map(|r| (r.sample_n, r.worst, r.largest))
+        .filter(|(_, w, l)| *w || *l)
+        .collect();
+    assert_eq!(flagged, [(2, true, false), (3, false, true)]);
+    assert_eq!(rows[2].start_secs, 130.0);
+    assert_eq!(
+        rows[0].to_string(),
+        "- Sample 2 at 70s vmaf 93.50 size 450 B (45%) worst vmaf"
+    );
+}
+
 /// Return estimated encoded **video stream** size by applying the sample percentage
 /// change to the input file size.
 ///
@@ -499,10 +1482,20 @@ async fn estimate_encode_size_by_file_percent(
 pub enum StdoutFormat {
     Human,
     Json,
+    /// Tab separated header & value lines.
+    Tsv,
 }
 
 impl StdoutFormat {
-    fn print_result(self, vmaf: f32, size: u64, percent: f64, time: Duration, image: bool) {
+    fn print_result(self, output: &Output, image: bool) {
+        let Output {
+            vmaf,
+            vmaf_stddev,
+            predicted_encode_size: size,
+            encode_percent: percent,
+            predicted_encode_time: time,
+            ..
+        } = *output;
         match self {
             Self::Human => {
                 let vmaf = match vmaf {
@@ -526,28 +1519,123 @@ impl StdoutFormat {
                     true => "image",
                     false => "video stream",
                 };
+                let stddev = vmaf_stddev_label(vmaf_stddev);
+                print_vmaf_lavfi(output);
                 println!(
-                    "VMAF {vmaf:.2} predicted {enc_description} size {size} ({percent}) taking {time}"
+                    "VMAF {vmaf:.2}{stddev} predicted {enc_description} size {size} ({percent}) taking {time}"
                 );
             }
-            Self::Json => {
-                let json = serde_json::json!({
-                    "vmaf": vmaf,
-                    "predicted_encode_size": size,
-                    "predicted_encode_percent": percent,
-                    "predicted_encode_seconds": time.as_secs(),
-                });
-                println!("{}", serde_json::to_string(&json).unwrap());
+            Self::Json | Self::Tsv => {
+                let out = JsonOutput::from(output);
+                match self {
+                    Self::Tsv => println!("{}", out.tsv().unwrap()),
+                    _ => println!("{}", serde_json::to_string(&out).unwrap()),
+                }
             }
         }
     }
 }
 
+/// Print the `vmaf_lavfi`, if any, to stderr for reproducing the measurement.
+pub fn print_vmaf_lavfi(output: &Output) {
+    if let Some(lavfi) = &output.vmaf_lavfi {
+        eprintln!("{}", style!("VMAF lavfi {lavfi}").dim());
+    }
+}
+
+/// Returns e.g. " ±0.84" for a known VMAF standard deviation.
+pub fn vmaf_stddev_label(vmaf_stddev: Option<f32>) -> String {
+    match vmaf_stddev {
+        Some(sd) => style!(" ±{sd:.2}").dim().to_string(),
+        None => String::new(),
+    }
+}
+
+/// `--stdout-format json` & `tsv` result.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct JsonOutput {
+    vmaf: f32,
+    predicted_encode_size: u64,
+    predicted_encode_percent: f64,
+    predicted_encode_seconds: u64,
+    /// Standard deviation of per-sample VMAF, if multiple samples.
+    #[serde(default)]
+    vmaf_stddev: Option<f32>,
+    /// VMAF lavfi as passed to ffmpeg, if not cached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vmaf_lavfi: Option<String>,
+    /// Quality per byte efficiency, see [`Output::vmaf_per_mb`].
+    #[serde(default)]
+    vmaf_per_mb: Option<f64>,
+    /// Size of each sample & its encode.
+    #[serde(default)]
+    sample_sizes: Vec<SampleSize>,
+}
+
+impl From<&Output> for JsonOutput {
+    fn from(out: &Output) -> Self {
+        Self {
+            vmaf: out.vmaf,
+            predicted_encode_size: out.predicted_encode_size,
+            predicted_encode_percent: out.encode_percent,
+            predicted_encode_seconds: out.predicted_encode_time.as_secs(),
+            vmaf_stddev: out.vmaf_stddev,
+            vmaf_lavfi: out.vmaf_lavfi.clone(),
+            vmaf_per_mb: out.vmaf_per_mb(),
+            sample_sizes: out.sample_sizes.clone(),
+        }
+    }
+}
+
+impl JsonOutput {
+    const TSV_FIELDS: &[&str] = &[
+        "vmaf",
+        "predicted_encode_size",
+        "predicted_encode_percent",
+        "predicted_encode_seconds",
+        "vmaf_stddev",
+        "vmaf_per_mb",
+    ];
+
+    /// Returns the tsv header & row.
+    fn tsv(&self) -> anyhow::Result<String> {
+        Ok(format!(
+            "{}\n{}",
+            tsv::header(Self::TSV_FIELDS),
+            tsv::row(self, Self::TSV_FIELDS)?
+        ))
+    }
+}
+
+/// Returns the encoded percent projected from a partial `(size, time)` encode of a
+/// `(size, duration)` sample, combined with `completed` samples.
+///
+/// `None` until a quarter of the sample is encoded as earlier output isn't representative.
+fn extrapolated_encode_percent(
+    completed: &[EncodeResult],
+    (partial_size, elapsed): (u64, Duration),
+    (sample_size, sample_duration): (u64, Duration),
+) -> Option<f64> {
+    let progress = elapsed.as_secs_f64() / sample_duration.as_secs_f64();
+    if !(0.25..).contains(&progress) {
+        return None;
+    }
+    let projected_size = partial_size as f64 / progress.min(1.0);
+    let encoded = completed.iter().map(|r| r.encoded_size).sum::<u64>() as f64 + projected_size;
+    let sample = (completed.iter().map(|r| r.sample_size).sum::<u64>() + sample_size) as f64;
+    Some(encoded * 100.0 / sample)
+}
+
 /// Sample encode result.
 #[derive(Debug, Clone)]
 pub struct Output {
     /// Sample mean VMAF score.
     pub vmaf: f32,
+    /// Standard deviation of per-sample VMAF, `None` for less than 2 samples.
+    ///
+    /// High values indicate quality-variable content that may benefit from
+    /// per-scene encoding.
+    pub vmaf_stddev: Option<f32>,
     /// Estimated full encoded **video stream** size.
     ///
     /// Encoded sample size multiplied by duration.
@@ -560,4 +1648,211 @@ pub struct Output {
     pub predicted_encode_time: Duration,
     /// All sample results were read from the cache.
     pub from_cache: bool,
+    /// Encoding was aborted early as the projected size exceeded the ceiling.
+    /// VMAF was not calculated.
+    pub size_aborted: bool,
+    /// Lavfi of the last sample VMAF analysis exactly as passed to ffmpeg, including
+    /// resolved `n_threads`. `None` if all results were cached.
+    pub vmaf_lavfi: Option<String>,
+    /// Size of each sample & its encode, the raw data of the size prediction.
+    pub sample_sizes: Vec<SampleSize>,
+}
+
+/// Sizes of a sample & its encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SampleSize {
+    pub sample_size: u64,
+    pub encoded_size: u64,
+}
+
+impl Output {
+    /// VMAF points above a baseline of VMAF 0 at 0 bytes per predicted encoded MB,
+    /// for comparing the efficiency of configs. Higher is better.
+    ///
+    /// `None` if the size is unknown (zero) or VMAF wasn't calculated.
+    pub fn vmaf_per_mb(&self) -> Option<f64> {
+        if self.size_aborted || self.predicted_encode_size == 0 {
+            return None;
+        }
+        Some(f64::from(self.vmaf) / (self.predicted_encode_size as f64 / 1_000_000.0))
+    }
+
+    fn size_aborted(encode_percent: f64, input_len: u64) -> Self {
+        Self {
+            vmaf: 0.0,
+            vmaf_stddev: None,
+            predicted_encode_size: (input_len as f64 * encode_percent / 100.0) as _,
+            encode_percent,
+            predicted_encode_time: Duration::ZERO,
+            from_cache: false,
+            size_aborted: true,
+            vmaf_lavfi: None,
+            sample_sizes: vec![],
+        }
+    }
+}
+
+#[test]
+fn early_size_abort_extrapolation() {
+    let secs = Duration::from_secs;
+    let done = |sample_size, encoded_size| EncodeResult {
+        sample_size,
+        encoded_size,
+        vmaf_score: 95.0,
+        encode_time: secs(1),
+        sample_duration: secs(20),
+        from_cache: false,
+        extra_metric: None,
+    };
+    let abort = |completed: &[EncodeResult], partial| {
+        extrapolated_encode_percent(completed, partial, (1000, secs(20))).filter(|p| *p > 80.0)
+    };
+
+    // too early to project
+    assert_eq!(
+        extrapolated_encode_percent(&[], (900, secs(4)), (1000, secs(20))),
+        None
+    );
+    // 450 bytes at half way => 900 bytes => 90%
+    assert_eq!(
+        extrapolated_encode_percent(&[], (450, secs(10)), (1000, secs(20))),
+        Some(90.0)
+    );
+    assert_eq!(abort(&[], (450, secs(10))), Some(90.0));
+    assert_eq!(abort(&[], (350, secs(10))), None);
+
+    // small completed samples keep the combined projection under the ceiling
+    assert_eq!(abort(&[done(1000, 500)], (450, secs(10))), None);
+    assert!(abort(&[done(1000, 850)], (450, secs(10))).is_some());
+}
+
+#[test]
+fn vmaf_stddev_across_samples() {
+    let result = |vmaf_score| EncodeResult {
+        sample_size: 1000,
+        encoded_size: 500,
+        vmaf_score,
+        encode_time: Duration::from_secs(1),
+        sample_duration: Duration::from_secs(20),
+        from_cache: false,
+        extra_metric: None,
+    };
+    assert_eq!(vec![result(95.0)].vmaf_stddev(), None);
+    assert_eq!(vec![result(95.0), result(95.0)].vmaf_stddev(), Some(0.0));
+    // mean 94, deviations -2, 0, 2 => sqrt(8/3)
+    let varied = vec![result(92.0), result(94.0), result(96.0)];
+    assert!((varied.vmaf_stddev().unwrap() - 1.633).abs() < 0.001);
+
+    let json = serde_json::to_string(&JsonOutput {
+        vmaf: 94.0,
+        predicted_encode_size: 1000,
+        predicted_encode_percent: 50.0,
+        predicted_encode_seconds: 60,
+        vmaf_stddev: varied.vmaf_stddev(),
+        vmaf_lavfi: None,
+        vmaf_per_mb: None,
+        sample_sizes: vec![],
+    })
+    .unwrap();
+    assert!(json.contains(r#""vmaf_stddev":1.63"#), "{json}");
+}
+
+#[test]
+fn stdout_tsv_result() {
+    let out = JsonOutput {
+        vmaf: 95.12,
+        predicted_encode_size: 123456,
+        predicted_encode_percent: 42.0,
+        predicted_encode_seconds: 61,
+        vmaf_stddev: Some(0.84),
+        vmaf_lavfi: None,
+        vmaf_per_mb: Some(770.5),
+        sample_sizes: vec![SampleSize {
+            sample_size: 1000,
+            encoded_size: 400,
+        }],
+    };
+    assert_eq!(
+        out.tsv().unwrap(),
+        "vmaf\tpredicted_encode_size\tpredicted_encode_percent\tpredicted_encode_seconds\tvmaf_stddev\tvmaf_per_mb\n\
+         95.12\t123456\t42.0\t61\t0.84\t770.5"
+    );
+}
+
+#[test]
+fn json_result_reports_vmaf_lavfi() {
+    let vmaf = args::Vmaf {
+        vmaf_args: vec!["n_subsample=4".into()],
+        ..<_>::default()
+    };
+    let args = Args::try_parse_from(["sample-encode", "-i", "vid.mkv", "--crf", "30"]).unwrap();
+    let lavfi = sample_vmaf_lavfi(&vmaf, &args.args, Some((1280, 720)), PixelFormat::Yuv420p);
+    assert_eq!(
+        lavfi,
+        vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None)
+    );
+    assert!(
+        lavfi.contains(&format!("n_threads={}", vmaf.n_threads().unwrap())),
+        "{lavfi}"
+    );
+
+    let output = Output {
+        vmaf_lavfi: Some(lavfi.clone()),
+        ..Output::size_aborted(50.0, 1000)
+    };
+    let json: serde_json::Value = serde_json::to_value(JsonOutput::from(&output)).unwrap();
+    assert_eq!(json["vmaf_lavfi"], lavfi);
+
+    let cached = JsonOutput::from(&Output::size_aborted(50.0, 1000));
+    assert!(!serde_json::to_string(&cached)
+        .unwrap()
+        .contains("vmaf_lavfi"));
+}
+
+#[test]
+fn vmaf_per_mb_efficiency() {
+    let output = |vmaf, predicted_encode_size| Output {
+        vmaf,
+        vmaf_stddev: None,
+        predicted_encode_size,
+        encode_percent: 50.0,
+        predicted_encode_time: Duration::from_secs(60),
+        from_cache: false,
+        size_aborted: false,
+        vmaf_lavfi: None,
+        sample_sizes: vec![],
+    };
+    assert_eq!(output(95.0, 50_000_000).vmaf_per_mb(), Some(1.9));
+    assert_eq!(output(90.0, 500_000).vmaf_per_mb(), Some(180.0));
+    // smaller at the same quality is more efficient
+    assert!(output(95.0, 40_000_000).vmaf_per_mb() > output(95.0, 50_000_000).vmaf_per_mb());
+    assert_eq!(output(95.0, 0).vmaf_per_mb(), None);
+    assert_eq!(Output::size_aborted(50.0, 1000).vmaf_per_mb(), None);
+}
+
+#[test]
+fn json_result_sample_sizes() {
+    let sizes = vec![
+        SampleSize {
+            sample_size: 4_000_000,
+            encoded_size: 1_000_000,
+        },
+        SampleSize {
+            sample_size: 5_000_000,
+            encoded_size: 1_500_000,
+        },
+    ];
+    let output = Output {
+        sample_sizes: sizes.clone(),
+        ..Output::size_aborted(27.8, 1000)
+    };
+    let json = serde_json::to_string(&JsonOutput::from(&output)).unwrap();
+    assert!(
+        json.contains(
+            r#""sample_sizes":[{"sample_size":4000000,"encoded_size":1000000},{"sample_size":5000000,"encoded_size":1500000}]"#
+        ),
+        "{json}"
+    );
+    let parsed: JsonOutput = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.sample_sizes, sizes);
 }
@@ -0,0 +1,117 @@
+//! _sample-encode_ `--baseline` regression checking.
+use super::{JsonOutput, Output};
+use anyhow::{ensure, Context};
+use clap::Parser;
+use console::style;
+use std::path::{Path, PathBuf};
+
+/// Regression check arguments.
+#[derive(Parser, Clone, Default)]
+pub struct Args {
+    /// Compare the result against a previous `--stdout-format json` result file
+    /// and fail if VMAF or predicted size has regressed beyond the thresholds.
+    ///
+    /// E.g. `ab-av1 sample-encode ... --stdout-format json > baseline.json`.
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub baseline: Option<PathBuf>,
+
+    /// Maximum allowed VMAF drop below the --baseline.
+    #[arg(long, default_value_t = 0.5)]
+    pub baseline_max_vmaf_drop: f32,
+
+    /// Maximum allowed predicted size growth percentage above the --baseline.
+    #[arg(long, default_value_t = 5.0)]
+    pub baseline_max_size_growth: f64,
+}
+
+impl Args {
+    /// Compare `output` with the `--baseline`, if any, erroring on regression.
+    pub fn check(&self, output: &Output) -> anyhow::Result<()> {
+        let Some(path) = &self.baseline else {
+            return Ok(());
+        };
+        let baseline = read(path)?;
+        let delta = Delta::new(&baseline, output.vmaf, output.predicted_encode_size);
+        eprintln!(
+            "{}",
+            style(format!(
+                "Baseline VMAF {:+.2}, predicted size {:+.1}%",
+                delta.vmaf, delta.size_percent
+            ))
+            .dim()
+        );
+        delta.ensure_within(self.baseline_max_vmaf_drop, self.baseline_max_size_growth)
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<JsonOutput> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read baseline {}", path.display()))?;
+    serde_json::from_str(json.trim())
+        .with_context(|| format!("invalid baseline {}", path.display()))
+}
+
+/// Change from the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Delta {
+    /// VMAF score change, negative means worse quality.
+    vmaf: f32,
+    /// Predicted size percentage change, positive means larger.
+    size_percent: f64,
+}
+
+impl Delta {
+    fn new(baseline: &JsonOutput, vmaf: f32, predicted_encode_size: u64) -> Self {
+        let size_percent = match baseline.predicted_encode_size {
+            0 => 0.0,
+            base => 100.0 * (predicted_encode_size as f64 - base as f64) / base as f64,
+        };
+        Self {
+            vmaf: vmaf - baseline.vmaf,
+            size_percent,
+        }
+    }
+
+    fn ensure_within(&self, max_vmaf_drop: f32, max_size_growth: f64) -> anyhow::Result<()> {
+        ensure!(
+            -self.vmaf <= max_vmaf_drop,
+            "VMAF regression: dropped {:.2} from baseline, max allowed {max_vmaf_drop}",
+            -self.vmaf
+        );
+        ensure!(
+            self.size_percent <= max_size_growth,
+            "size regression: predicted size grew {:.1}% from baseline, max allowed {max_size_growth}%",
+            self.size_percent
+        );
+        Ok(())
+    }
+}
+
+#[test]
+fn baseline_regression() {
+    let baseline: JsonOutput = serde_json::from_str(
+        r#"{"vmaf":95.2,"predicted_encode_size":1000000,"predicted_encode_percent":25.0,"predicted_encode_seconds":60}"#,
+    )
+    .unwrap();
+
+    // small noise passes
+    let delta = Delta::new(&baseline, 94.9, 1_030_000);
+    assert!((delta.vmaf + 0.3).abs() < 1e-4, "{delta:?}");
+    assert!((delta.size_percent - 3.0).abs() < 1e-9, "{delta:?}");
+    delta.ensure_within(0.5, 5.0).unwrap();
+
+    // improvements always pass
+    Delta::new(&baseline, 96.0, 900_000)
+        .ensure_within(0.0, 0.0)
+        .unwrap();
+
+    let err = Delta::new(&baseline, 94.0, 1_000_000)
+        .ensure_within(0.5, 5.0)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("VMAF regression"), "{err}");
+
+    let err = Delta::new(&baseline, 95.2, 1_100_000)
+        .ensure_within(0.5, 5.0)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("size regression"), "{err}");
+}
@@ -1,10 +1,15 @@
 //! _sample-encode_ file system caching logic.
-use crate::{command::args::Vmaf, ffmpeg::FfmpegEncodeArgs};
+use crate::{
+    command::args::Vmaf,
+    ffmpeg::FfmpegEncodeArgs,
+    ffprobe::{self, ProbeError},
+};
 use anyhow::Context;
+use serde::{Deserialize, Serialize};
 use std::{
     ffi::OsStr,
     hash::Hash,
-    path::Path,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
@@ -15,24 +20,24 @@ pub async fn cached_encode(
     sample: &Path,
     input_duration: Duration,
     input_extension: Option<&OsStr>,
-    input_size: u64,
+    input_fingerprint: Option<blake3::Hash>,
     full_pass: bool,
     enc_args: &FfmpegEncodeArgs<'_>,
     vmaf_args: &Vmaf,
 ) -> (Option<super::EncodeResult>, Option<Key>) {
-    if !cache {
+    let Some(input_fingerprint) = input_fingerprint.filter(|_| cache) else {
         return (None, None);
-    }
+    };
 
     let hash = hash_encode(
         // hashing the sample file name (which includes input name, frames & start)
-        // + input duration, extension & size should be reasonably unique for an input.
-        // and is much faster than hashing the entire file.
+        // + input duration, extension & partial content fingerprint should be reasonably
+        // unique for an input and is much faster than hashing the entire file.
         (
             sample.file_name(),
             input_duration,
             input_extension,
-            input_size,
+            input_fingerprint.as_bytes(),
             full_pass,
         ),
         enc_args,
@@ -82,6 +87,105 @@ pub async fn cache_result(key: Key, result: &super::EncodeResult) -> anyhow::Res
     Ok(())
 }
 
+/// Returns the `--sample-cache-dir` path for the encoded sample file.
+///
+/// Unlike [`cached_encode`] keys vmaf args are not included, so a cached sample
+/// encode may be re-measured with different vmaf args.
+#[allow(clippy::too_many_arguments)]
+pub fn sample_file_path(
+    cache_dir: &Path,
+    sample: &Path,
+    input_duration: Duration,
+    input_extension: Option<&OsStr>,
+    input_fingerprint: blake3::Hash,
+    full_pass: bool,
+    enc_args: &FfmpegEncodeArgs<'_>,
+    dest_ext: &str,
+) -> PathBuf {
+    let hash = hash_sample_file(
+        (
+            sample.file_name(),
+            input_duration,
+            input_extension,
+            input_fingerprint.as_bytes(),
+            full_pass,
+        ),
+        enc_args,
+    );
+    cache_dir.join(format!("{}.{dest_ext}", hash.to_hex()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct SampleFileInfo {
+    encode_time: Duration,
+    /// Sample file size in bytes.
+    size: u64,
+}
+
+impl SampleFileInfo {
+    /// Returns true if the sample file looks complete, i.e. not truncated by an
+    /// interrupted write. The frame count must approximately match the expected,
+    /// allowing for container duration rounding.
+    fn is_intact(
+        &self,
+        file_len: u64,
+        frames: Result<u64, ProbeError>,
+        expected_frames: Option<u64>,
+    ) -> bool {
+        if file_len != self.size {
+            return false;
+        }
+        match (frames, expected_frames) {
+            (Ok(frames), Some(expected)) => frames.abs_diff(expected) <= (expected / 100).max(2),
+            (Err(_), _) => false,
+            (Ok(_), None) => true,
+        }
+    }
+}
+
+fn sample_file_info_path(sample_file: &Path) -> PathBuf {
+    sample_file.with_extension("json")
+}
+
+/// Returns the original encode time of a previously stored sample file, if it exists
+/// and is intact with around `expected_frames`. Otherwise it should be re-encoded.
+pub async fn read_sample_file(
+    sample_file: &Path,
+    expected_frames: Option<u64>,
+) -> Option<Duration> {
+    let info = tokio::fs::read(sample_file_info_path(sample_file))
+        .await
+        .ok()?;
+    let info: SampleFileInfo = serde_json::from_slice(&info).ok()?;
+    let file_len = tokio::fs::metadata(sample_file).await.ok()?.len();
+    let probe_file = sample_file.to_owned();
+    let frames = tokio::task::spawn_blocking(move || ffprobe::probe(&probe_file).nframes())
+        .await
+        .ok()?;
+    info.is_intact(file_len, frames, expected_frames)
+        .then_some(info.encode_time)
+}
+
+/// Move a newly encoded sample into the sample file cache.
+pub async fn store_sample_file(
+    encoded: &Path,
+    sample_file: &Path,
+    encode_time: Duration,
+) -> anyhow::Result<()> {
+    if let Some(dir) = sample_file.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    if tokio::fs::rename(encoded, sample_file).await.is_err() {
+        // e.g. across file systems
+        tokio::fs::copy(encoded, sample_file).await?;
+        tokio::fs::remove_file(encoded).await?;
+    }
+    let size = tokio::fs::metadata(sample_file).await?.len();
+    let info = serde_json::to_vec(&SampleFileInfo { encode_time, size })?;
+    tokio::fs::write(sample_file_info_path(sample_file), info).await?;
+    Ok(())
+}
+
 fn open_db() -> sled::Result<sled::Db> {
     const LOCK_MAX_WAIT: Duration = Duration::from_secs(2);
 
@@ -112,10 +216,23 @@ fn hash_encode(
     if !vmaf_args.is_default() {
         // avoid hashing if default for back compat
         vmaf_args.hash(&mut std_hasher);
+        if vmaf_args.vmaf_scale_flags_ref.is_none() || vmaf_args.vmaf_scale_flags_dis.is_none() {
+            // default scale flags depend on the scaling direction, previously always bicubic
+            "default-scale-flags-by-direction".hash(&mut std_hasher);
+        }
     }
     hasher.finalize()
 }
 
+fn hash_sample_file(input_info: impl Hash, enc_args: &FfmpegEncodeArgs<'_>) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    let mut std_hasher = BlakeStdHasher(&mut hasher);
+    "sample-file".hash(&mut std_hasher);
+    input_info.hash(&mut std_hasher);
+    enc_args.sample_encode_hash(&mut std_hasher);
+    hasher.finalize()
+}
+
 struct BlakeStdHasher<'a>(&'a mut blake3::Hasher);
 impl std::hash::Hasher for BlakeStdHasher<'_> {
     fn finish(&self) -> u64 {
@@ -127,3 +244,63 @@ impl std::hash::Hasher for BlakeStdHasher<'_> {
         self.0.update(bytes);
     }
 }
+
+#[test]
+fn sample_file_key_ignores_vmaf_args() {
+    use crate::command::args::PixelFormat;
+
+    let enc_args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        vcodec: "libx264".into(),
+        vfilter: None,
+        pix_fmt: PixelFormat::Yuv420p,
+        crf: 24.0,
+        qp: false,
+        preset: None,
+        output_args: vec![],
+        input_args: vec![],
+        video_only: false,
+    };
+    let input_info = ("vid.sample1+480f.mkv", Duration::from_secs(600));
+    let vmaf = Vmaf::default();
+    let vmaf_4k = Vmaf {
+        vmaf_args: vec!["model=version=vmaf_4k_v0.6.1".into()],
+        ..<_>::default()
+    };
+
+    // changing only vmaf args re-measures vmaf, but reuses the sample encode
+    assert_ne!(
+        hash_encode(input_info, &enc_args, &vmaf),
+        hash_encode(input_info, &enc_args, &vmaf_4k)
+    );
+    let sample_hash = hash_sample_file(input_info, &enc_args);
+    assert_ne!(sample_hash, hash_encode(input_info, &enc_args, &vmaf));
+    assert_eq!(sample_hash, hash_sample_file(input_info, &enc_args));
+
+    let crf_25 = FfmpegEncodeArgs {
+        crf: 25.0,
+        ..enc_args.clone()
+    };
+    assert_ne!(sample_hash, hash_sample_file(input_info, &crf_25));
+}
+
+#[test]
+fn sample_file_integrity() {
+    let info = SampleFileInfo {
+        encode_time: Duration::from_secs(10),
+        size: 1_000_000,
+    };
+
+    // complete, frame count within duration rounding
+    assert!(info.is_intact(1_000_000, Ok(480), Some(480)));
+    assert!(info.is_intact(1_000_000, Ok(481), Some(480)));
+
+    // truncated
+    assert!(!info.is_intact(612_000, Ok(480), Some(480)));
+    assert!(!info.is_intact(1_000_000, Ok(290), Some(480)));
+    assert!(!info.is_intact(
+        1_000_000,
+        Err(anyhow::anyhow!("no duration").into()),
+        Some(480)
+    ));
+}
@@ -3,16 +3,23 @@ use crate::{
         args::{self, PixelFormat},
         PROGRESS_CHARS,
     },
+    console_ext::style,
     ffprobe,
     log::ProgressLogger,
     process::FfmpegOut,
-    vmaf::{self, VmafOut},
+    temporary::{self, TempKind},
+    vmaf::{
+        self,
+        frames::{Histogram, Segment},
+        VmafOut,
+    },
 };
-use anyhow::Context;
+use anyhow::{ensure, Context};
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    path::PathBuf,
+    fmt,
+    path::{Path, PathBuf},
     pin::pin,
     time::{Duration, Instant},
 };
@@ -24,28 +31,125 @@ use tokio_stream::StreamExt;
 /// * Auto sets model version (4k or 1k) according to resolution.
 /// * Auto sets `n_threads` to system threads.
 /// * Auto upscales lower resolution videos to the model.
+/// * Scales a reference of a different resolution to the distorted, e.g. a 4k master.
 /// * Converts distorted & reference to appropriate format yuv streams before passing to vmaf.
 #[derive(Parser)]
 #[clap(verbatim_doc_comment)]
 #[group(skip)]
 pub struct Args {
-    /// Reference video file.
+    /// Reference video file, or `-` to read the reference from stdin.
     #[arg(long)]
     pub reference: PathBuf,
 
+    /// Ffmpeg demuxer format of the reference, e.g. `--reference-format y4m`.
+    /// Required for formats ffmpeg cannot detect, e.g. when piped.
+    #[arg(long)]
+    pub reference_format: Option<String>,
+
+    /// Additional ffmpeg input arg(s) for the reference, placed before its `-i`.
+    /// E.g. `--reference-input-arg=-video_size --reference-input-arg=1920x1080`.
+    #[arg(long, allow_hyphen_values = true)]
+    pub reference_input_arg: Vec<String>,
+
     /// Re-encoded/distorted video file.
     #[arg(long)]
     pub distorted: PathBuf,
 
+    /// Only analyse this time range of both inputs, e.g. "00:10:00-00:10:30".
+    ///
+    /// Both reference & distorted are seeked identically so frames stay aligned.
+    /// Useful to drill into a low scoring region, e.g. with
+    /// `--vmaf log_path=frames.json:log_fmt=json` for per-frame scores.
+    #[arg(long)]
+    pub range: Option<TimeRange>,
+
+    /// Print a histogram of per-frame VMAF scores, highlighting the low tail.
+    ///
+    /// Uses libvmaf per-frame logging. If `--vmaf log_path=...:log_fmt=json` is
+    /// specified that log is used, otherwise a temporary log is written.
+    #[arg(long)]
+    pub histogram: bool,
+
+    /// Print the mean & min VMAF of each fixed duration segment, e.g. `--segments 6s`,
+    /// to find problem segments for streaming.
+    ///
+    /// Segments are counted from the first analysed frame using the frame rate, so
+    /// match encodes with a keyframe at each segment boundary, e.g. `--keyint 6s`.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub segments: Option<Duration>,
+
+    /// Compare per-frame scores to a golden libvmaf json log, e.g. one written previously
+    /// with `--vmaf log_path=golden.json:log_fmt=json`. Fails if any frame diverges
+    /// by more than --golden-tolerance.
+    ///
+    /// E.g. to catch score drift across encoder or ffmpeg versions.
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub golden: Option<PathBuf>,
+
+    /// Max per-frame VMAF difference from the --golden log.
+    #[arg(long, default_value_t = 0.01, requires = "golden")]
+    pub golden_tolerance: f64,
+
+    /// Scale both streams to the resolution of the `reference`, the `distorted`
+    /// or a `WxH` resolution for analysis, instead of --vmaf-scale.
+    ///
+    /// E.g. `--scale-to reference` to compare a 720p encode upscaled to a 1080p master.
+    #[arg(long, conflicts_with = "vmaf_scale")]
+    pub scale_to: Option<ScaleTo>,
+
+    /// Also score a center crop, half the width & height, in the same pass.
+    /// The center crop score is printed on a second line.
+    ///
+    /// E.g. to distinguish center vs edge quality.
+    #[arg(long, conflicts_with = "cuda")]
+    pub center_crop: bool,
+
+    /// Also score with VMAF's motion feature forced to zero in the same pass & print
+    /// the motion contribution, i.e. how much the score is driven by temporal content.
+    #[arg(long, conflicts_with_all = ["metric_blend", "cuda"])]
+    pub motion_delta: bool,
+
+    /// Also score without any VMAF scaling, i.e. `--vmaf-scale none`, in the same pass &
+    /// print the scale impact, i.e. how much the analysis scale changes the score.
+    #[arg(long, conflicts_with_all = ["center_crop", "cuda", "vmaf_lavfi_template"])]
+    pub scale_impact: bool,
+
+    /// After analysis also decode the distorted & print its decode speed, warning if
+    /// slower than realtime. E.g. to check decodability for low-power playback targets.
+    #[arg(long)]
+    pub decode_speed: bool,
+
+    /// Write the analysis lavfi filtergraph as a graphviz dot file, e.g. `graph.dot`.
+    /// Render with e.g. `dot -Tsvg graph.dot -o graph.svg`.
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub export_filtergraph: Option<PathBuf>,
+
     #[clap(flatten)]
     pub vmaf: args::Vmaf,
+
+    #[clap(flatten)]
+    pub gate: args::Gate,
 }
 
 pub async fn vmaf(
     Args {
         reference,
+        reference_format,
+        reference_input_arg,
         distorted,
-        vmaf,
+        range,
+        histogram,
+        segments,
+        golden,
+        golden_tolerance,
+        scale_to,
+        center_crop,
+        motion_delta,
+        scale_impact,
+        decode_speed,
+        export_filtergraph,
+        mut vmaf,
+        gate,
     }: Args,
 ) -> anyhow::Result<()> {
     let bar = ProgressBar::new(1).with_style(
@@ -57,53 +161,941 @@ pub async fn vmaf(
     bar.set_message("vmaf running, ");
 
     let dprobe = ffprobe::probe(&distorted);
+    dprobe.ensure_video(&distorted)?;
     let dpix_fmt = dprobe.pixel_format().unwrap_or(PixelFormat::Yuv444p10le);
-    let rprobe = ffprobe::probe(&reference);
+    let rprobe = match is_stdin(&reference) {
+        true => ffprobe::unknown("reference piped via stdin"),
+        false => ffprobe::probe(&reference),
+    };
+    rprobe.ensure_video(&reference)?;
     let rpix_fmt = rprobe.pixel_format().unwrap_or(PixelFormat::Yuv444p10le);
-    let nframes = dprobe.nframes().or_else(|_| rprobe.nframes());
-    let duration = dprobe.duration.as_ref().or(rprobe.duration.as_ref());
+    if vmaf.cuda && !ffprobe::same_sar(dprobe.sar, rprobe.sar) {
+        bar.println(
+            style!("Warning: reference & distorted SARs differ, not supported with --cuda")
+                .yellow()
+                .to_string(),
+        );
+    }
+    // compare at display aspect ratio, not storage pixels
+    let [dres, rres] = vmaf.match_display_aspect(&dprobe, &rprobe);
+    if let Some(scale_to) = scale_to {
+        vmaf.vmaf_scale = scale_to.vmaf_scale(dres, rres)?;
+    }
+    vmaf.ensure_compatible_resolutions(dres, rres)?;
+    vmaf.set_auto_fps(dprobe.fps.clone().ok(), rprobe.fps.clone().ok());
+    let fps = dprobe.fps.clone().or(rprobe.fps.clone());
+    let mut nframes = dprobe.nframes().or_else(|_| rprobe.nframes());
+    let mut duration = dprobe.duration.clone().or(rprobe.duration.clone());
+    if let Some(range) = range {
+        nframes = fps
+            .clone()
+            .map(|fps| (range.duration().as_secs_f64() * fps).round() as _);
+        duration = Ok(range.duration());
+    }
     if let Ok(nframes) = nframes {
         bar.set_length(nframes);
     }
 
-    let mut vmaf = pin!(vmaf::run(
-        &reference,
-        &distorted,
-        &vmaf.ffmpeg_lavfi(
-            dprobe.resolution,
-            dpix_fmt.max(rpix_fmt),
-            vmaf.reference_vfilter.as_deref(),
-        ),
-        &vmaf.reference_input_args(),
-        &vmaf.distorted_input_args(),
-    )?);
+    if motion_delta {
+        vmaf.metric_blend = Some(match vmaf.model_and_scale(dres).0 {
+            args::VmafModel::Vmaf4K => "vmaf_4k+0*vmaf_4k_nomotion".parse()?,
+            _ => "vmaf+0*vmaf_nomotion".parse()?,
+        });
+    }
+
+    if segments.is_some() {
+        ensure!(fps.is_ok(), "--segments requires a known frame rate");
+    }
+    let golden = match golden {
+        Some(path) => Some(vmaf::frames::read_json_log(&path)?),
+        None => None,
+    };
+    let per_frame = histogram || segments.is_some() || golden.is_some();
+    let frame_log = match per_frame || vmaf.metric_blend.is_some() {
+        true => Some(vmaf::frames::json_log_path(&mut vmaf)?),
+        false => None,
+    };
+
+    let center_log = match center_crop {
+        true => {
+            let path = temporary::process_dir(None).join("vmaf-center.json");
+            temporary::add(&path, TempKind::NotKeepable);
+            Some(path)
+        }
+        false => None,
+    };
+
+    let unscaled_log = match scale_impact {
+        true if vmaf.model_and_scale(dres).1.is_none() => {
+            bar.println(
+                style!("Warning: --scale-impact: no scaling at this resolution")
+                    .yellow()
+                    .to_string(),
+            );
+            None
+        }
+        true => {
+            let path = temporary::process_dir(None).join("vmaf-unscaled.json");
+            temporary::add(&path, TempKind::NotKeepable);
+            Some(path)
+        }
+        false => None,
+    };
+
+    let range_args = range.map(|r| r.input_args()).unwrap_or_default();
+    let reference_input_args = reference_input_args(
+        &vmaf,
+        reference_format.as_deref(),
+        &reference_input_arg,
+        &range_args,
+    );
+    let distorted_input_args = distorted_input_args(&vmaf, &range_args);
+
     let mut logger = ProgressLogger::new(module_path!(), Instant::now());
     let mut vmaf_score = None;
-    while let Some(vmaf) = vmaf.next().await {
-        match vmaf {
-            VmafOut::Done(score) => {
-                vmaf_score = Some(score);
-                break;
-            }
-            VmafOut::Progress(FfmpegOut::Progress {
-                frame, fps, time, ..
-            }) => {
-                if fps > 0.0 {
-                    bar.set_message(format!("vmaf {fps} fps, "));
-                }
-                if nframes.is_ok() {
-                    bar.set_position(frame);
+    let mut run_vmaf = vmaf.clone();
+    'run: loop {
+        let mut lavfi = analysis_lavfi(&run_vmaf, dres, rres, dpix_fmt.max(rpix_fmt));
+        if let Some(log) = &center_log {
+            lavfi = with_center_crop(&lavfi, log)?;
+        }
+        if let Some(log) = &unscaled_log {
+            let unscaled = args::Vmaf {
+                vmaf_scale: args::VmafScale::None,
+                ..run_vmaf.clone()
+            };
+            let unscaled = analysis_lavfi(&unscaled, dres, rres, dpix_fmt.max(rpix_fmt));
+            lavfi = with_unscaled(&lavfi, &unscaled, log)?;
+        }
+        if let Some(path) = &export_filtergraph {
+            let dot = vmaf::filtergraph::Graph::parse(&lavfi).to_dot();
+            std::fs::write(path, dot)
+                .with_context(|| format!("write filtergraph {}", path.display()))?;
+        }
+        let mut vmaf_out = pin!(vmaf::cap_duration(
+            vmaf::run(
+                run_vmaf.clone(),
+                &reference,
+                &distorted,
+                &lavfi,
+                &distorted_input_args,
+                &reference_input_args,
+                vmaf.vmaf_cpu_affinity.as_ref(),
+            )?,
+            vmaf.max_analysis_duration,
+        ));
+        while let Some(out) = vmaf_out.next().await {
+            match out {
+                VmafOut::Done(score) => {
+                    vmaf_score = Some(score);
+                    if center_log.is_none() && unscaled_log.is_none() {
+                        break 'run;
+                    }
+                    // wait for ffmpeg to exit so secondary instance logs are written
                 }
-                if let Ok(total) = duration {
-                    logger.update(*total, time, fps);
+                VmafOut::Progress(FfmpegOut::Progress {
+                    frame, fps, time, ..
+                }) => {
+                    if fps > 0.0 {
+                        bar.set_message(format!("vmaf {fps} fps, "));
+                    }
+                    if nframes.is_ok() {
+                        bar.set_position(frame);
+                    }
+                    if let Ok(total) = duration {
+                        logger.update(total, time, fps);
+                    }
                 }
+                VmafOut::Progress(FfmpegOut::StreamSizes { .. }) => {}
+                VmafOut::Err(e) => match vmaf::retry(&run_vmaf, &e) {
+                    Some(retry) => {
+                        bar.println(retry_warning(&retry, &e));
+                        run_vmaf = retry;
+                        continue 'run;
+                    }
+                    None => return Err(e),
+                },
             }
-            VmafOut::Progress(FfmpegOut::StreamSizes { .. }) => {}
-            VmafOut::Err(e) => return Err(e),
         }
+        break;
     }
     bar.finish();
 
-    println!("{}", vmaf_score.context("no vmaf score")?);
+    let mut vmaf_score = vmaf_score.context("no vmaf score")?;
+    if let (Some(blend), Some(log)) = (&vmaf.metric_blend, &frame_log) {
+        vmaf_score = vmaf::frames::read_blended_score(log, blend)?;
+    }
+    println!("{vmaf_score}");
+    if let (true, Some(log)) = (motion_delta, &frame_log) {
+        let json = std::fs::read_to_string(log)?;
+        let means = vmaf::frames::parse_pooled_means(&json)?;
+        let (full, no_motion) = match vmaf.metric_blend.as_ref().map(|b| &b.terms[..]) {
+            Some([(_, full), (_, no_motion)]) => (&**full, &**no_motion),
+            _ => unreachable!("motion delta blend"),
+        };
+        let contribution = MotionContribution {
+            score: *means.get(full).context("no vmaf score")?,
+            no_motion: *means.get(no_motion).context("no no-motion vmaf score")?,
+        };
+        eprintln!("{}", style!("{contribution}").dim());
+    }
+    if let Some(log) = &center_log {
+        let center_score = vmaf::frames::read_pooled_score(log, vmaf.metric_blend.as_ref())?;
+        println!("{center_score}");
+    }
+    if let Some(log) = &unscaled_log {
+        let impact = ScaleImpact {
+            scaled: vmaf_score,
+            unscaled: vmaf::frames::read_pooled_score(log, vmaf.metric_blend.as_ref())?,
+        };
+        eprintln!("{}", style!("{impact}").dim());
+    }
+
+    if let Some(log) = frame_log.filter(|_| per_frame) {
+        let frames = vmaf::frames::read_json_log_frames(&log)?;
+        let scores: Vec<_> = frames.iter().map(|&(_, score)| score).collect();
+        if let Some(hist) = Histogram::new(&scores, HISTOGRAM_BIN_WIDTH).filter(|_| histogram) {
+            eprintln!("{hist}");
+        }
+        if let (Some(segment), Ok(fps)) = (segments, fps) {
+            for segment in Segment::from_frames(&frames, fps, segment)? {
+                eprintln!("{}", style!("{segment}").dim());
+            }
+        }
+        if let Some(golden) = golden {
+            let diverged = vmaf::frames::golden_diff(&scores, &golden, golden_tolerance)?;
+            for d in diverged.iter().take(GOLDEN_MAX_REPORTED) {
+                eprintln!(
+                    "{}",
+                    style!(
+                        "frame {} vmaf {:.4}, golden {:.4}",
+                        d.frame,
+                        d.score,
+                        d.golden
+                    )
+                    .red()
+                );
+            }
+            ensure!(
+                diverged.is_empty(),
+                "{} frames diverge from golden by more than {golden_tolerance}",
+                diverged.len()
+            );
+        }
+    }
+
+    if decode_speed {
+        let speed = measure_decode_speed(&distorted, dprobe.fps.clone().ok()).await?;
+        match speed.realtime() {
+            false => eprintln!("{}", style!("Warning: {speed}").yellow()),
+            true => eprintln!("{}", style!("{speed}").dim()),
+        }
+    }
+
+    let distorted_size = std::fs::metadata(&distorted).ok().map(|m| m.len());
+    gate.check(vmaf_score, distorted_size)?;
     Ok(())
 }
+
+/// Returns `lavfi` with a second libvmaf instance scoring a center crop of half the
+/// width & height, writing a json log to `center_log`.
+fn with_center_crop(lavfi: &str, center_log: &Path) -> anyhow::Result<String> {
+    const CROP: &str = "crop=trunc(iw/4)*2:trunc(ih/4)*2";
+
+    let (chains, libvmaf) = lavfi
+        .rsplit_once("[dis][ref]")
+        .context("--center-crop requires a `[dis][ref]libvmaf` graph")?;
+    let (filter, libvmaf_args) = libvmaf.split_once('=').unwrap_or((libvmaf, ""));
+
+    Ok(format!(
+        "{chains}[dis]split[dis_full][dis_c];[ref]split[ref_full][ref_c];\
+         [dis_c]{CROP}[dis_center];[ref_c]{CROP}[ref_center];\
+         [dis_full][ref_full]{libvmaf};\
+         [dis_center][ref_center]{filter}@{}={}",
+        vmaf::CENTER_CROP_INSTANCE,
+        secondary_instance_args(libvmaf_args, center_log),
+    ))
+}
+
+/// Returns `lavfi` with a second libvmaf instance from the `unscaled` lavfi graph,
+/// i.e. with --vmaf-scale none, writing a json log to `unscaled_log`.
+fn with_unscaled(lavfi: &str, unscaled: &str, unscaled_log: &Path) -> anyhow::Result<String> {
+    let (dis_pad, scaled) = split_distorted_input(lavfi)?;
+    let (_, unscaled) = split_distorted_input(unscaled)?;
+    let (unscaled_chains, libvmaf) = unscaled
+        .rsplit_once("[dis][ref]")
+        .context("--scale-impact requires a `[dis][ref]libvmaf` graph")?;
+    let (filter, libvmaf_args) = libvmaf.split_once('=').unwrap_or((libvmaf, ""));
+    let unscaled_chains = unscaled_chains
+        .replacen("[1:v]", "[ref_in_u]", 1)
+        .replace("[dis]", "[dis_u]")
+        .replace("[ref]", "[ref_u]");
+
+    Ok(format!(
+        "{dis_pad}split[dis_in][dis_in_u];[1:v]split[ref_in][ref_in_u];\
+         [dis_in]{};\
+         [dis_in_u]{unscaled_chains}[dis_u][ref_u]{filter}@{}={}",
+        scaled.replacen("[1:v]", "[ref_in]", 1),
+        vmaf::UNSCALED_INSTANCE,
+        secondary_instance_args(libvmaf_args, unscaled_log),
+    ))
+}
+
+/// Split a lavfi graph into the leading distorted input pad, e.g. "[0:v]", & the rest.
+fn split_distorted_input(lavfi: &str) -> anyhow::Result<(&str, &str)> {
+    let end = lavfi
+        .find(']')
+        .filter(|_| lavfi.starts_with("[0:v"))
+        .context("--scale-impact requires a `[0:v]...[dis][ref]libvmaf` graph")?;
+    Ok(lavfi.split_at(end + 1))
+}
+
+/// Returns the `libvmaf_args` of the main instance for a secondary instance
+/// writing its own json log to `log`.
+fn secondary_instance_args(libvmaf_args: &str, log: &Path) -> String {
+    let mut args: Vec<_> = split_unescaped(libvmaf_args, ':')
+        .into_iter()
+        .filter(|a| !a.is_empty() && !a.starts_with("log_path=") && !a.starts_with("log_fmt="))
+        .collect();
+    args.extend(vmaf::frames::log_args(log));
+    args.join(":")
+}
+
+/// Split on `sep` where not escaped with `\`.
+fn split_unescaped(s: &str, sep: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            c if c == sep && !escaped => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    parts
+}
+
+/// Returns true if `path` is `-` meaning stdin.
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Returns the reference ffmpeg input args, preceding its `-i`.
+fn reference_input_args<'a>(
+    vmaf: &'a args::Vmaf,
+    reference_format: Option<&'a str>,
+    reference_input_arg: &'a [String],
+    range_args: &'a [String],
+) -> Vec<&'a str> {
+    let mut args = vmaf.reference_input_args();
+    if let Some(format) = reference_format {
+        args.extend(["-f", format]);
+    }
+    args.extend(reference_input_arg.iter().map(String::as_str));
+    args.extend(range_args.iter().map(String::as_str));
+    args
+}
+
+/// Returns the distorted ffmpeg input args, preceding its `-i`.
+fn distorted_input_args<'a>(vmaf: &'a args::Vmaf, range_args: &'a [String]) -> Vec<&'a str> {
+    let mut args = vmaf.distorted_input_args();
+    args.extend(range_args.iter().map(String::as_str));
+    args
+}
+
+/// Returns the lavfi to analyse the distorted vs reference.
+///
+/// Model & scaling are chosen from the distorted resolution as in crf-search. Where
+/// crf-search compares against the reference after the encode --vfilter, here a reference
+/// of a different resolution is scaled to the distorted, if not already scaled for analysis.
+fn analysis_lavfi(
+    vmaf: &args::Vmaf,
+    dres: Option<(u32, u32)>,
+    rres: Option<(u32, u32)>,
+    pix_fmt: PixelFormat,
+) -> String {
+    let ref_scale = match (dres, rres) {
+        (Some((w, h)), Some(rres))
+            if (w, h) != rres
+                && vmaf.reference_vfilter.is_none()
+                && vmaf.model_and_scale(dres).1.is_none() =>
+        {
+            let algo = vmaf
+                .vmaf_scale_flags_ref
+                .as_deref()
+                .unwrap_or_else(|| args::default_scale_flags(rres, (w as _, h as _)));
+            Some(format!("scale={w}:{h}:flags={algo}"))
+        }
+        _ => None,
+    };
+    let vmaf = args::Vmaf {
+        reference_res: rres,
+        ..vmaf.clone()
+    };
+    vmaf.ffmpeg_lavfi(dres, pix_fmt, ref_scale.as_deref())
+}
+
+#[test]
+fn vmaf_command_auto_model_and_scale() {
+    let vmaf = args::Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        ..<_>::default()
+    };
+    let lavfi = |dres, rres| analysis_lavfi(&vmaf, Some(dres), Some(rres), PixelFormat::Yuv420p);
+    const MODEL_4K: &str = "model=version=vmaf_4k_v0.6.1";
+
+    // 4k delivery vs 4k master uses the 4k model, as in crf-search
+    let uhd = lavfi((3840, 2160), (3840, 2160));
+    assert!(uhd.ends_with(MODEL_4K), "{uhd}");
+    assert!(!uhd.contains("scale="), "{uhd}");
+    assert_eq!(
+        uhd,
+        vmaf.ffmpeg_lavfi(Some((3840, 2160)), PixelFormat::Yuv420p, None)
+    );
+
+    // 1080p delivery vs 4k master, reference scaled to the distorted with the 1k model
+    let hd = lavfi((1920, 1080), (3840, 2160));
+    assert!(!hd.contains(MODEL_4K), "{hd}");
+    assert!(
+        hd.contains("[1:v]format=yuv420p,scale=1920:1080:flags=area,"),
+        "{hd}"
+    );
+
+    // 720p delivery: distorted upscaled & reference downscaled for the 1k model
+    let sd = lavfi((1280, 720), (3840, 2160));
+    assert!(
+        sd.contains("[0:v]format=yuv420p,scale=1920:-1:flags=bicubic"),
+        "{sd}"
+    );
+    assert!(
+        sd.contains("[1:v]format=yuv420p,scale=1920:-1:flags=area"),
+        "{sd}"
+    );
+}
+
+pub fn retry_warning(retry: &args::Vmaf, err: &anyhow::Error) -> String {
+    let cause = match err.downcast_ref::<vmaf::AnalysisOverrun>() {
+        Some(_) => "exceeded --max-analysis-duration",
+        None => "ran out of memory",
+    };
+    style!(
+        "Warning: vmaf {cause}, retrying with n_threads={}",
+        retry.n_threads().unwrap_or(1)
+    )
+    .yellow()
+    .to_string()
+}
+
+const HISTOGRAM_BIN_WIDTH: f64 = 2.0;
+
+/// Max --golden divergent frames to print.
+const GOLDEN_MAX_REPORTED: usize = 10;
+
+/// VMAF score with & without the motion feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MotionContribution {
+    score: f64,
+    no_motion: f64,
+}
+
+impl MotionContribution {
+    /// Score points contributed by motion.
+    fn delta(&self) -> f64 {
+        self.score - self.no_motion
+    }
+
+    /// Percentage of the score contributed by motion.
+    fn percent(&self) -> f64 {
+        match self.score {
+            s if s > 0.0 => 100.0 * self.delta() / s,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for MotionContribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "motion contribution {:.2} ({:.1}%), VMAF {:.2} no-motion {:.2}",
+            self.delta(),
+            self.percent(),
+            self.score,
+            self.no_motion,
+        )
+    }
+}
+
+/// Decode `input` reporting the speed relative to the `source_fps`.
+async fn measure_decode_speed(
+    input: &Path,
+    source_fps: Option<f64>,
+) -> anyhow::Result<DecodeSpeed> {
+    let bar = ProgressBar::new_spinner().with_message("decoding");
+    bar.enable_steady_tick(Duration::from_millis(100));
+    let start = Instant::now();
+    let mut last = None;
+    let mut dec = pin!(crate::ffmpeg::decode(input)?);
+    while let Some(out) = dec.next().await {
+        if let FfmpegOut::Progress { frame, fps, .. } = out? {
+            last = Some((frame, fps));
+        }
+    }
+    bar.finish_and_clear();
+    let (frames, fps) = last.context("no decode progress")?;
+    Ok(DecodeSpeed::new(frames, fps, start.elapsed(), source_fps))
+}
+
+/// Decode-only speed of an encode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DecodeSpeed {
+    fps: f64,
+    source_fps: Option<f64>,
+}
+
+impl DecodeSpeed {
+    /// Use ffmpeg's reported average `fps`, or if not reported frames / elapsed.
+    fn new(frames: u64, fps: f32, elapsed: Duration, source_fps: Option<f64>) -> Self {
+        let fps = match fps {
+            fps if fps > 0.0 => f64::from(fps),
+            _ if !elapsed.is_zero() => frames as f64 / elapsed.as_secs_f64(),
+            _ => 0.0,
+        };
+        Self { fps, source_fps }
+    }
+
+    /// Returns true if decoding is at least as fast as playback, or the source fps is unknown.
+    fn realtime(&self) -> bool {
+        self.source_fps.is_none_or(|src| self.fps >= src)
+    }
+}
+
+impl fmt::Display for DecodeSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decode {:.0} fps", self.fps)?;
+        if let Some(src) = self.source_fps.filter(|s| *s > 0.0) {
+            write!(f, " ({:.1}x realtime)", self.fps / src)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn decode_speed_realtime() {
+    let line = "frame= 1440 fps=412 q=-0.0 Lsize=N/A time=00:01:00.00 bitrate=N/A speed=17.2x";
+    let Some(FfmpegOut::Progress { frame, fps, .. }) = FfmpegOut::try_parse(line) else {
+        panic!("not parsed");
+    };
+    assert_eq!((frame, fps), (1440, 412.0));
+
+    let speed = DecodeSpeed::new(frame, fps, Duration::from_secs(4), Some(24.0));
+    assert_eq!(speed.fps, 412.0);
+    assert!(speed.realtime());
+    assert_eq!(speed.to_string(), "decode 412 fps (17.2x realtime)");
+
+    // no reported fps, use frames / elapsed
+    let speed = DecodeSpeed::new(600, 0.0, Duration::from_secs(30), Some(60.0));
+    assert_eq!(speed.fps, 20.0);
+    assert!(!speed.realtime());
+    assert_eq!(speed.to_string(), "decode 20 fps (0.3x realtime)");
+
+    assert!(DecodeSpeed::new(600, 0.0, Duration::from_secs(30), None).realtime());
+}
+
+/// VMAF score at the analysis scale & unscaled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ScaleImpact {
+    scaled: f32,
+    unscaled: f32,
+}
+
+impl ScaleImpact {
+    /// Score points added by scaling.
+    fn delta(&self) -> f32 {
+        self.scaled - self.unscaled
+    }
+}
+
+impl fmt::Display for ScaleImpact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "scale impact {:+.2}, VMAF {:.2} unscaled {:.2}",
+            self.delta(),
+            self.scaled,
+            self.unscaled,
+        )
+    }
+}
+
+/// Explicit VMAF scaling target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleTo {
+    Reference,
+    Distorted,
+    Custom { width: u32, height: u32 },
+}
+
+impl ScaleTo {
+    /// Returns the equivalent custom vmaf scale given the distorted & reference resolutions.
+    fn vmaf_scale(
+        self,
+        distorted_res: Option<(u32, u32)>,
+        reference_res: Option<(u32, u32)>,
+    ) -> anyhow::Result<args::VmafScale> {
+        let (width, height) = match self {
+            Self::Reference => reference_res.context("--scale-to reference: unknown resolution")?,
+            Self::Distorted => distorted_res.context("--scale-to distorted: unknown resolution")?,
+            Self::Custom { width, height } => (width, height),
+        };
+        Ok(args::VmafScale::Custom { width, height })
+    }
+}
+
+impl std::str::FromStr for ScaleTo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "reference" => Ok(Self::Reference),
+            "distorted" => Ok(Self::Distorted),
+            _ => {
+                let (width, height) = args::parse_resolution(s)
+                    .context("scale-to must be 'reference', 'distorted' or WxH e.g. '1920x1080'")?;
+                Ok(Self::Custom { width, height })
+            }
+        }
+    }
+}
+
+/// Time range `start-end` of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl TimeRange {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Ffmpeg input args to seek to the range.
+    pub fn input_args(&self) -> Vec<String> {
+        vec![
+            "-ss".into(),
+            self.start.as_secs_f64().to_string(),
+            "-to".into(),
+            self.end.as_secs_f64().to_string(),
+        ]
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            humantime::format_duration(self.start),
+            humantime::format_duration(self.end)
+        )
+    }
+}
+
+/// Parse as "start-end", e.g. "00:10:00-00:10:30", "600-630" or "10m-10m30s".
+impl std::str::FromStr for TimeRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .context("range must be in \"start-end\" format, e.g. \"00:10:00-00:10:30\"")?;
+        let (start, end) = (parse_timestamp(start)?, parse_timestamp(end)?);
+        ensure!(start < end, "range start must be before end");
+        Ok(Self { start, end })
+    }
+}
+
+/// Parse "hh:mm:ss[.fff]", "mm:ss", seconds or a humantime duration.
+fn parse_timestamp(ts: &str) -> anyhow::Result<Duration> {
+    let ts = ts.trim();
+    if ts.contains(':') {
+        let mut secs = 0.0;
+        for part in ts.split(':') {
+            let part: f64 = part
+                .parse()
+                .with_context(|| format!("invalid timestamp {ts:?}"))?;
+            secs = secs * 60.0 + part;
+        }
+        return Duration::try_from_secs_f64(secs)
+            .with_context(|| format!("invalid timestamp {ts:?}"));
+    }
+    if let Ok(secs) = ts.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs)
+            .with_context(|| format!("invalid timestamp {ts:?}"));
+    }
+    humantime::parse_duration(ts).with_context(|| format!("invalid timestamp {ts:?}"))
+}
+
+#[test]
+fn parse_time_range() {
+    let range: TimeRange = "00:10:00-00:10:30.5".parse().unwrap();
+    assert_eq!(
+        range,
+        TimeRange {
+            start: Duration::from_secs(600),
+            end: Duration::from_millis(630_500),
+        }
+    );
+    assert_eq!(range.duration(), Duration::from_millis(30_500));
+
+    let range: TimeRange = "10m-10m30s".parse().unwrap();
+    assert_eq!(range.duration(), Duration::from_secs(30));
+
+    assert!("00:10:30-00:10:00".parse::<TimeRange>().is_err());
+    assert!("00:10:00".parse::<TimeRange>().is_err());
+}
+
+/// Reference & distorted should both seek to the same range.
+#[test]
+fn time_range_input_args() {
+    let range: TimeRange = "00:10:00-00:10:30.5".parse().unwrap();
+    assert_eq!(range.input_args(), ["-ss", "600", "-to", "630.5"]);
+
+    let range_args = range.input_args();
+    let vmaf = args::Vmaf {
+        cuda: true,
+        ..<_>::default()
+    };
+    let reference_args = reference_input_args(&vmaf, None, &[], &range_args);
+    assert_eq!(
+        reference_args,
+        [
+            "-hwaccel",
+            "cuda",
+            "-hwaccel_output_format",
+            "cuda",
+            "-ss",
+            "600",
+            "-to",
+            "630.5"
+        ]
+    );
+    assert_eq!(distorted_input_args(&vmaf, &range_args), reference_args);
+}
+
+#[test]
+fn scale_to_directions() {
+    let lavfi = |scale_to: &str| {
+        let mut vmaf = args::Vmaf {
+            vmaf_args: vec!["n_threads=4".into()],
+            ..<_>::default()
+        };
+        let dres = Some((1280, 720));
+        vmaf.vmaf_scale = scale_to
+            .parse::<ScaleTo>()
+            .unwrap()
+            .vmaf_scale(dres, Some((1920, 1080)))
+            .unwrap();
+        vmaf.reference_res = Some((1920, 1080));
+        vmaf.ffmpeg_lavfi(dres, PixelFormat::Yuv420p, None)
+    };
+
+    // upscale the 720p distorted to the 1080p reference
+    assert_eq!(
+        lavfi("reference"),
+        "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+    // downscale the 1080p reference to the 720p distorted
+    assert_eq!(
+        lavfi("distorted"),
+        "[0:v]format=yuv420p,scale=1280:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=1280:-1:flags=area,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+    assert_eq!(
+        lavfi("960x540"),
+        "[0:v]format=yuv420p,scale=960:-1:flags=area,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,scale=960:-1:flags=area,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4"
+    );
+
+    assert!("up".parse::<ScaleTo>().is_err());
+    assert!(ScaleTo::Reference
+        .vmaf_scale(Some((1280, 720)), None)
+        .is_err());
+}
+
+#[test]
+fn reference_stdin_input_args() {
+    use clap::Parser;
+
+    let args = Args::try_parse_from([
+        "vmaf",
+        "--distorted",
+        "enc.mkv",
+        "--reference",
+        "-",
+        "--reference-format",
+        "yuv4mpegpipe",
+        "--reference-input-arg=-thread_queue_size",
+        "--reference-input-arg",
+        "1024",
+    ])
+    .unwrap();
+    assert!(is_stdin(&args.reference));
+    assert!(!is_stdin(&args.distorted));
+
+    let range_args = "600-630".parse::<TimeRange>().unwrap().input_args();
+    assert_eq!(
+        reference_input_args(
+            &args.vmaf,
+            args.reference_format.as_deref(),
+            &args.reference_input_arg,
+            &range_args,
+        ),
+        [
+            "-f",
+            "yuv4mpegpipe",
+            "-thread_queue_size",
+            "1024",
+            "-ss",
+            "600",
+            "-to",
+            "630"
+        ]
+    );
+
+    // default reference input spec is unchanged
+    let args =
+        Args::try_parse_from(["vmaf", "--distorted", "e.mkv", "--reference", "r.mkv"]).unwrap();
+    assert!(reference_input_args(&args.vmaf, None, &args.reference_input_arg, &[]).is_empty());
+}
+
+/// `--cuda-device` should decode both reference & distorted on the chosen gpu.
+#[test]
+fn cuda_device_reference_distorted_input_args() {
+    use clap::Parser;
+
+    let args = Args::try_parse_from([
+        "vmaf",
+        "--distorted",
+        "e.mkv",
+        "--reference",
+        "r.mkv",
+        "--cuda",
+        "--cuda-device",
+        "1",
+    ])
+    .unwrap();
+    let has_device = |args: &[&str]| args.windows(2).any(|w| w == ["-hwaccel_device", "1"]);
+
+    let range_args = "600-630".parse::<TimeRange>().unwrap().input_args();
+    let reference = reference_input_args(&args.vmaf, None, &args.reference_input_arg, &range_args);
+    assert!(has_device(&reference), "{reference:?}");
+    let distorted = args.vmaf.distorted_input_args();
+    assert!(has_device(&distorted), "{distorted:?}");
+}
+
+#[test]
+fn scale_impact_dual_instance_lavfi() {
+    let vmaf = args::Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        ..<_>::default()
+    };
+    let unscaled = args::Vmaf {
+        vmaf_scale: args::VmafScale::None,
+        ..vmaf.clone()
+    };
+    let lavfi = vmaf.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None);
+    let unscaled = unscaled.ffmpeg_lavfi(Some((1280, 720)), PixelFormat::Yuv420p, None);
+
+    assert_eq!(
+        with_unscaled(&lavfi, &unscaled, Path::new("/tmp/unscaled.json")).unwrap(),
+        "[0:v]split[dis_in][dis_in_u];[1:v]split[ref_in][ref_in_u];\
+         [dis_in]format=yuv420p,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [ref_in]format=yuv420p,scale=1920:-1:flags=bicubic,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis][ref]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4;\
+         [dis_in_u]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis_u];\
+         [ref_in_u]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref_u];\
+         [dis_u][ref_u]libvmaf@unscaled=shortest=true:ts_sync_mode=nearest:n_threads=4\
+         :log_path='/tmp/unscaled.json':log_fmt=json"
+    );
+
+    let impact = ScaleImpact {
+        scaled: 95.5,
+        unscaled: 93.25,
+    };
+    assert_eq!(impact.delta(), 2.25);
+    assert_eq!(
+        impact.to_string(),
+        "scale impact +2.25, VMAF 95.50 unscaled 93.25"
+    );
+    let impact = ScaleImpact {
+        scaled: 90.0,
+        unscaled: 91.0,
+    };
+    assert_eq!(impact.delta(), -1.0);
+}
+
+#[test]
+fn center_crop_dual_instance_lavfi() {
+    let vmaf = args::Vmaf {
+        vmaf_args: vec!["n_threads=4".into()],
+        metric_blend: Some("0.5*vmaf+0.5*vmaf_neg".parse().unwrap()),
+        ..<_>::default()
+    };
+    let mut lavfi_vmaf = vmaf.clone();
+    lavfi_vmaf
+        .vmaf_args
+        .extend(vmaf::frames::log_args(Path::new("/tmp/frames.json")).map(Into::into));
+    let lavfi = lavfi_vmaf.ffmpeg_lavfi(Some((1920, 1080)), PixelFormat::Yuv420p, None);
+
+    assert_eq!(
+        with_center_crop(&lavfi, Path::new("/tmp/center.json")).unwrap(),
+        "[0:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[dis];\
+         [1:v]format=yuv420p,settb=AVTB,setpts=N/FRAME_RATE/TB[ref];\
+         [dis]split[dis_full][dis_c];[ref]split[ref_full][ref_c];\
+         [dis_c]crop=trunc(iw/4)*2:trunc(ih/4)*2[dis_center];\
+         [ref_c]crop=trunc(iw/4)*2:trunc(ih/4)*2[ref_center];\
+         [dis_full][ref_full]libvmaf=shortest=true:ts_sync_mode=nearest:n_threads=4\
+         :log_path='/tmp/frames.json':log_fmt=json\
+         :model='version=vmaf_v0.6.1\\:name=vmaf|version=vmaf_v0.6.1neg\\:name=vmaf_neg';\
+         [dis_center][ref_center]libvmaf@center=shortest=true:ts_sync_mode=nearest:n_threads=4\
+         :model='version=vmaf_v0.6.1\\:name=vmaf|version=vmaf_v0.6.1neg\\:name=vmaf_neg'\
+         :log_path='/tmp/center.json':log_fmt=json"
+    );
+
+    assert!(with_center_crop("[0:v][1:v]libvmaf", Path::new("c.json")).is_err());
+}
+
+#[test]
+fn motion_contribution_delta() {
+    let contribution = MotionContribution {
+        score: 95.0,
+        no_motion: 85.5,
+    };
+    assert!((contribution.delta() - 9.5).abs() < 1e-9);
+    assert!((contribution.percent() - 10.0).abs() < 1e-9);
+    assert_eq!(
+        contribution.to_string(),
+        "motion contribution 9.50 (10.0%), VMAF 95.00 no-motion 85.50"
+    );
+
+    // motion can lower the score too
+    let contribution = MotionContribution {
+        score: 80.0,
+        no_motion: 82.0,
+    };
+    assert!((contribution.delta() + 2.0).abs() < 1e-9);
+    assert_eq!(
+        MotionContribution {
+            score: 0.0,
+            no_motion: 0.0
+        }
+        .percent(),
+        0.0
+    );
+}
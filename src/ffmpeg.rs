@@ -2,7 +2,7 @@
 use crate::{
     command::args::PixelFormat,
     float::TerseF32,
-    process::{CommandExt, FfmpegOut},
+    process::{CommandExt, FfmpegOut, StderrLog},
     temporary::{self, TempKind},
 };
 use anyhow::Context;
@@ -25,6 +25,8 @@ pub struct FfmpegEncodeArgs<'a> {
     pub vfilter: Option<&'a str>,
     pub pix_fmt: PixelFormat,
     pub crf: f32,
+    /// `crf` is a constant quantizer, see `--qp`.
+    pub qp: bool,
     pub preset: Option<Arc<str>>,
     pub output_args: Vec<Arc<String>>,
     pub input_args: Vec<Arc<String>>,
@@ -32,6 +34,14 @@ pub struct FfmpegEncodeArgs<'a> {
 }
 
 impl FfmpegEncodeArgs<'_> {
+    /// Set encoder `-threads`, unless already set in the output args.
+    pub fn set_threads(&mut self, threads: usize) {
+        if !self.output_args.iter().any(|a| a.as_str() == "-threads") {
+            self.output_args.push(Arc::new("-threads".into()));
+            self.output_args.push(Arc::new(threads.to_string()));
+        }
+    }
+
     pub fn sample_encode_hash(&self, state: &mut impl Hasher) {
         static SVT_AV1_V: LazyLock<Vec<u8>> = LazyLock::new(|| {
             std::process::Command::new("SvtAv1EncApp")
@@ -51,6 +61,9 @@ impl FfmpegEncodeArgs<'_> {
         self.vfilter.hash(state);
         self.pix_fmt.hash(state);
         self.crf.to_bits().hash(state);
+        if self.qp {
+            "qp".hash(state);
+        }
         self.preset.hash(state);
         self.output_args.hash(state);
         self.input_args.hash(state);
@@ -65,6 +78,7 @@ pub fn encode_sample(
         vfilter,
         pix_fmt,
         crf,
+        qp,
         preset,
         output_args,
         input_args,
@@ -74,10 +88,14 @@ pub fn encode_sample(
     dest_ext: &str,
 ) -> anyhow::Result<(PathBuf, impl Stream<Item = anyhow::Result<FfmpegOut>>)> {
     let pre = pre_extension_name(&vcodec);
+    let q_name = match qp {
+        true => "qp",
+        false => "crf",
+    };
     let crf_str = format!("{}", TerseF32(crf)).replace('.', "_");
     let dest_file_name = match &preset {
-        Some(p) => input.with_extension(format!("{pre}.crf{crf_str}.{p}.{dest_ext}")),
-        None => input.with_extension(format!("{pre}.crf{crf_str}.{dest_ext}")),
+        Some(p) => input.with_extension(format!("{pre}.{q_name}{crf_str}.{p}.{dest_ext}")),
+        None => input.with_extension(format!("{pre}.{q_name}{crf_str}.{dest_ext}")),
     };
     let dest_file_name = dest_file_name.file_name().unwrap();
     let mut dest = temporary::process_dir(temp_dir);
@@ -93,7 +111,7 @@ pub fn encode_sample(
         .arg2("-i", input)
         .arg2("-c:v", &*vcodec)
         .args(output_args.iter().map(|a| &**a))
-        .arg2(vcodec.crf_arg(), crf)
+        .args(quantizer_args(&vcodec, crf, qp))
         .args(vcodec.pix_fmt_args(&pix_fmt)?)
         .arg2_opt(vcodec.preset_arg(), preset)
         .arg2_opt("-vf", vfilter)
@@ -107,18 +125,91 @@ pub fn encode_sample(
 
     let enc = cmd.spawn().context("ffmpeg encode_sample")?;
 
-    let stream = FfmpegOut::stream(enc, "ffmpeg encode_sample", cmd_str);
+    let log = StderrLog::new(&format!("sample-encode-crf{crf_str}"));
+    let stream = FfmpegOut::stream(enc, "ffmpeg encode_sample", cmd_str, log);
     Ok((dest, stream))
 }
 
 /// Encode to output.
+///
+/// If `raw_stats` ffmpeg's own `-stats` output is passed through to stderr instead
+/// of being parsed, so no progress is streamed.
 pub fn encode(
+    args: FfmpegEncodeArgs,
+    output: &Path,
+    has_audio: bool,
+    audio_codec: Option<&str>,
+    downmix_to_stereo: bool,
+    raw_stats: bool,
+) -> anyhow::Result<impl Stream<Item = anyhow::Result<FfmpegOut>>> {
+    let log = StderrLog::new(&format!("encode-crf{}", TerseF32(args.crf)));
+    let mut cmd = encode_command(
+        args,
+        output,
+        has_audio,
+        audio_codec,
+        downmix_to_stereo,
+        raw_stats,
+    )?;
+    let cmd_str = cmd.to_cmd_str();
+    debug!("cmd `{cmd_str}`");
+
+    let enc = cmd.spawn().context("ffmpeg encode")?;
+
+    Ok(FfmpegOut::stream(enc, "ffmpeg encode", cmd_str, log))
+}
+
+/// Decode-only pass of the main video stream of `input`, e.g. to measure decode speed.
+pub fn decode(input: &Path) -> anyhow::Result<impl Stream<Item = anyhow::Result<FfmpegOut>>> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.kill_on_drop(true)
+        .arg("-hide_banner")
+        .arg2("-i", input)
+        .arg2("-map", "0:v:0")
+        .arg2("-f", "null")
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let cmd_str = cmd.to_cmd_str();
+    debug!("cmd `{cmd_str}`");
+
+    let dec = cmd.spawn().context("ffmpeg decode")?;
+    Ok(FfmpegOut::stream(
+        dec,
+        "ffmpeg decode",
+        cmd_str,
+        StderrLog::new("decode"),
+    ))
+}
+
+/// Returns the ffmpeg command an [`encode`] would run.
+pub fn encode_cmd_str(
+    args: FfmpegEncodeArgs,
+    output: &Path,
+    has_audio: bool,
+    audio_codec: Option<&str>,
+    downmix_to_stereo: bool,
+) -> anyhow::Result<String> {
+    let cmd = encode_command(
+        args,
+        output,
+        has_audio,
+        audio_codec,
+        downmix_to_stereo,
+        false,
+    )?;
+    Ok(cmd.to_cmd_str())
+}
+
+fn encode_command(
     FfmpegEncodeArgs {
         input,
         vcodec,
         vfilter,
         pix_fmt,
         crf,
+        qp,
         preset,
         output_args,
         input_args,
@@ -128,7 +219,8 @@ pub fn encode(
     has_audio: bool,
     audio_codec: Option<&str>,
     downmix_to_stereo: bool,
-) -> anyhow::Result<impl Stream<Item = anyhow::Result<FfmpegOut>>> {
+    raw_stats: bool,
+) -> anyhow::Result<Command> {
     let oargs: HashSet<_> = output_args.iter().map(|a| a.as_str()).collect();
     let output_ext = output.extension().and_then(|e| e.to_str());
 
@@ -159,7 +251,7 @@ pub fn encode(
         .arg2("-c:v", "copy")
         .arg2("-c:v:0", &*vcodec)
         .args(output_args.iter().map(|a| &**a))
-        .arg2(vcodec.crf_arg(), crf)
+        .args(quantizer_args(&vcodec, crf, qp))
         .args(vcodec.pix_fmt_args(&pix_fmt)?)
         .arg2_opt(vcodec.preset_arg(), preset)
         .arg2_opt("-vf", vfilter)
@@ -169,16 +261,96 @@ pub fn encode(
         .arg2_if(set_ba_128k, "-b:a", "128k")
         .arg2_if(add_faststart, "-movflags", "+faststart")
         .arg2_if(add_cues_to_front, "-cues_to_front", "y")
+        .args(raw_stats.then_some("-stats"))
         .arg(output)
         .stdin(Stdio::null())
         .stdout(Stdio::null())
-        .stderr(Stdio::piped());
+        .stderr(match raw_stats {
+            true => Stdio::inherit(),
+            false => Stdio::piped(),
+        });
+    Ok(cmd)
+}
+
+#[test]
+fn encode_raw_stats() {
+    let args = FfmpegEncodeArgs {
+        input: Path::new("vid.mkv"),
+        vcodec: "libsvtav1".into(),
+        vfilter: None,
+        pix_fmt: PixelFormat::Yuv420p10le,
+        crf: 32.0,
+        qp: false,
+        preset: None,
+        output_args: vec![],
+        input_args: vec![],
+        video_only: false,
+    };
+    let out = Path::new("vid.av1.mkv");
+
+    let cmd = encode_command(args.clone(), out, true, None, false, true).unwrap();
     let cmd_str = cmd.to_cmd_str();
-    debug!("cmd `{cmd_str}`");
+    assert!(cmd_str.ends_with("-stats vid.av1.mkv"), "{cmd_str}");
 
-    let enc = cmd.spawn().context("ffmpeg encode")?;
+    let cmd = encode_command(args, out, true, None, false, false).unwrap();
+    assert!(!cmd.to_cmd_str().contains("-stats"));
+}
 
-    Ok(FfmpegOut::stream(enc, "ffmpeg encode", cmd_str))
+/// Returns `ffmpeg -encoders` output.
+pub async fn encoders() -> anyhow::Result<String> {
+    let out = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-encoders")
+        .output()
+        .await
+        .context("ffmpeg -encoders")?;
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Returns true if `vcodec` initializes & encodes a small test frame, e.g. hardware
+/// encoders listed by `ffmpeg -encoders` fail without a supported device.
+pub async fn encoder_initializes(vcodec: &str) -> bool {
+    Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(["-f", "lavfi", "-i", "color=size=256x256:duration=1"])
+        .args(["-frames:v", "1", "-c:v", vcodec, "-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+/// Parse video encoder names from `ffmpeg -encoders` output.
+pub fn parse_video_encoders(encoders: &str) -> Vec<&str> {
+    encoders
+        .lines()
+        .skip_while(|l| !l.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            let flags = parts.next()?;
+            flags.starts_with('V').then_some(parts.next()?)
+        })
+        .collect()
+}
+
+#[test]
+fn parse_ffmpeg_video_encoders() {
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libaom-av1           libaom AV1 (codec av1)
+ V....D libsvtav1            SVT-AV1(Scalable Video Technology for AV1) encoder (codec av1)
+ A....D libopus              libopus Opus (codec opus)
+";
+    assert_eq!(
+        parse_video_encoders(ENCODERS),
+        vec!["libaom-av1", "libsvtav1"]
+    );
 }
 
 pub fn pre_extension_name(vcodec: &str) -> &str {
@@ -190,6 +362,191 @@ pub fn pre_extension_name(vcodec: &str) -> &str {
     }
 }
 
+/// Returns ffmpeg output args to pass through CEA-608/708 closed captions embedded
+/// in the input video stream, or `None` if the encoder cannot carry them.
+pub fn caption_passthrough_args(vcodec: &str) -> Option<[&'static str; 2]> {
+    match vcodec {
+        "libx264" | "libx265" | "h264_nvenc" | "hevc_nvenc" | "h264_qsv" | "hevc_qsv" => {
+            Some(["-a53cc", "1"])
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn caption_args() {
+    assert_eq!(caption_passthrough_args("libx264"), Some(["-a53cc", "1"]));
+    assert_eq!(
+        caption_passthrough_args("hevc_nvenc"),
+        Some(["-a53cc", "1"])
+    );
+    assert_eq!(caption_passthrough_args("libsvtav1"), None);
+    assert_eq!(caption_passthrough_args("libaom-av1"), None);
+}
+
+/// Returns ffmpeg output args to pass through HDR10+ & Dolby Vision dynamic metadata
+/// into an `output_ext` container, `None` if the encoder or container would drop it.
+pub fn dynamic_hdr_passthrough_args(vcodec: &str, output_ext: &str) -> Option<Vec<&'static str>> {
+    let mut args = match vcodec {
+        // dovi rpu & hdr10+ side data
+        "libsvtav1" | "libx265" => vec!["-dolbyvision", "1"],
+        // hdr10+ side data is carried by default
+        "libaom-av1" | "libvpx-vp9" => vec![],
+        _ => return None,
+    };
+    match output_ext {
+        "mkv" | "webm" => {}
+        // writing the dolby vision config box
+        "mp4" => args.extend(["-strict", "unofficial"]),
+        _ => return None,
+    }
+    Some(args)
+}
+
+#[test]
+fn dynamic_hdr_args() {
+    assert_eq!(
+        dynamic_hdr_passthrough_args("libsvtav1", "mkv"),
+        Some(vec!["-dolbyvision", "1"])
+    );
+    assert_eq!(
+        dynamic_hdr_passthrough_args("libx265", "mp4"),
+        Some(vec!["-dolbyvision", "1", "-strict", "unofficial"])
+    );
+    assert_eq!(
+        dynamic_hdr_passthrough_args("libaom-av1", "mkv"),
+        Some(vec![])
+    );
+    assert_eq!(dynamic_hdr_passthrough_args("libx264", "mkv"), None);
+    assert_eq!(dynamic_hdr_passthrough_args("av1_vaapi", "mkv"), None);
+    assert_eq!(dynamic_hdr_passthrough_args("libsvtav1", "avif"), None);
+}
+
+/// Returns the input audio stream indexes to keep, those matching one of the `keep`
+/// languages. Streams without a language tag are treated as `und`.
+pub fn select_audio_streams(audio_langs: &[Option<String>], keep: &[String]) -> Vec<usize> {
+    audio_langs
+        .iter()
+        .enumerate()
+        .filter(|(_, lang)| {
+            let lang = lang.as_deref().unwrap_or("und");
+            keep.iter().any(|k| k.eq_ignore_ascii_case(lang))
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Returns negative `-map` output args dropping all audio streams not in `selected`.
+pub fn drop_audio_map_args(audio_streams: usize, selected: &[usize]) -> Vec<String> {
+    (0..audio_streams)
+        .filter(|idx| !selected.contains(idx))
+        .flat_map(|idx| ["-map".into(), format!("-0:a:{idx}")])
+        .collect()
+}
+
+#[test]
+fn audio_lang_selection() {
+    let langs = [
+        Some("eng".to_owned()),
+        Some("fre".to_owned()),
+        None,
+        Some("jpn".to_owned()),
+    ];
+    let keep = |k: &[&str]| -> Vec<String> { k.iter().map(|s| s.to_string()).collect() };
+
+    let selected = select_audio_streams(&langs, &keep(&["eng", "JPN"]));
+    assert_eq!(selected, [0, 3]);
+    assert_eq!(
+        drop_audio_map_args(langs.len(), &selected),
+        ["-map", "-0:a:1", "-map", "-0:a:2"]
+    );
+
+    // untagged streams are `und`
+    assert_eq!(select_audio_streams(&langs, &keep(&["und"])), [2]);
+    assert!(select_audio_streams(&langs, &keep(&["ger"])).is_empty());
+}
+
+/// Returns whether `vcodec` supports a `--qp` constant quantizer.
+pub fn supports_qp(vcodec: &str) -> bool {
+    !vcodec.ends_with("_qsv")
+}
+
+/// Returns args setting `crf`, or with `qp` a constant quantizer of `crf`.
+fn quantizer_args(vcodec: &Arc<str>, crf: f32, qp: bool) -> Vec<String> {
+    let q = TerseF32(crf).to_string();
+    if !qp {
+        return vec![vcodec.crf_arg().into(), q];
+    }
+    let args: &[&str] = match &**vcodec {
+        // fixed quantizer by pinning the quantizer range around the matching
+        // constant quality level, which must be within the range
+        "libaom-av1" | "libvpx-vp9" => &["-crf", &q, "-qmin", &q, "-qmax", &q],
+        e if e.ends_with("_nvenc") => &["-rc", "constqp", "-qp", &q],
+        e if e.ends_with("_vaapi") => &["-rc_mode", "CQP", "-q", &q],
+        _ => &["-qp", &q],
+    };
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn qp_args_per_encoder() {
+    let args = |vcodec: &str, qp| quantizer_args(&vcodec.into(), 30.0, qp);
+
+    assert_eq!(args("libsvtav1", false), ["-crf", "30"]);
+    assert_eq!(args("libsvtav1", true), ["-qp", "30"]);
+    assert_eq!(args("libx264", true), ["-qp", "30"]);
+    assert_eq!(args("libx265", true), ["-qp", "30"]);
+    assert_eq!(args("librav1e", true), ["-qp", "30"]);
+    assert_eq!(
+        args("libaom-av1", true),
+        ["-crf", "30", "-qmin", "30", "-qmax", "30"]
+    );
+    assert_eq!(args("hevc_nvenc", false), ["-cq", "30"]);
+    assert_eq!(args("hevc_nvenc", true), ["-rc", "constqp", "-qp", "30"]);
+    assert_eq!(args("av1_vaapi", true), ["-rc_mode", "CQP", "-q", "30"]);
+
+    assert!(supports_qp("libsvtav1"));
+    assert!(!supports_qp("av1_qsv"));
+}
+
+#[test]
+fn speed_maps_to_native_preset_arg() {
+    use crate::command::args::Encoder;
+
+    let native = |vcodec: &str| {
+        let encoder: Encoder = vcodec.parse().unwrap();
+        let preset = encoder.speed_preset(6).unwrap().to_string();
+        (Arc::<str>::from(vcodec).preset_arg().to_owned(), preset)
+    };
+    assert_eq!(native("libaom-av1"), ("-cpu-used".into(), "5".into()));
+    assert_eq!(native("libsvtav1"), ("-preset".into(), "8".into()));
+    assert_eq!(native("libx265"), ("-preset".into(), "fast".into()));
+    assert_eq!(native("libvpx-vp9"), ("-cpu-used".into(), "3".into()));
+    assert_eq!(native("librav1e"), ("-speed".into(), "6".into()));
+
+    let svt = "libsvtav1".parse::<Encoder>().unwrap();
+    assert_eq!(svt.speed_preset(0).unwrap().to_string(), "0");
+    assert_eq!(svt.speed_preset(10).unwrap().to_string(), "13");
+    let x264 = "libx264".parse::<Encoder>().unwrap();
+    assert_eq!(x264.speed_preset(0).unwrap().to_string(), "placebo");
+    assert_eq!(x264.speed_preset(10).unwrap().to_string(), "ultrafast");
+
+    let err = "hevc_nvenc"
+        .parse::<Encoder>()
+        .unwrap()
+        .speed_preset(6)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--speed not supported by hevc_nvenc, use --preset"
+    );
+}
+
+/// Returns the arg used to set crf values for `vcodec`, e.g. `-crf`.
+pub fn crf_arg(vcodec: &Arc<str>) -> &str {
+    vcodec.crf_arg()
+}
+
 trait VCodecSpecific {
     /// Arg to use preset values with, normally `-preset`.
     fn preset_arg(&self) -> &str;
@@ -250,6 +607,3 @@ fn cuda_output_format_input_args() -> Vec<&'static str> {
         "-hwaccel_output_format", "cuda",
     ]
 }
-
-
-
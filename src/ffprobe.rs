@@ -1,20 +1,26 @@
 //! ffprobe logic
-use crate::command::args::PixelFormat;
+use crate::{command::args::PixelFormat, process::StderrLog};
 use anyhow::{anyhow, Context};
 use std::{fmt, fs::File, io::Read, path::Path, time::Duration};
 
 pub struct Ffprobe {
     /// Duration of video.
     pub duration: Result<Duration, ProbeError>,
+    /// The input has a video stream.
+    pub has_video: bool,
     /// The video has audio stream(s).
     pub has_audio: bool,
     /// Audio number of channels (if multiple channel the highest).
     pub max_audio_channels: Option<i64>,
+    /// Language tag of each audio stream, in stream order.
+    pub audio_langs: Vec<Option<String>>,
     /// Video frame rate.
     pub fps: Result<f64, ProbeError>,
     pub resolution: Option<(u32, u32)>,
     pub is_image: bool,
     pub pix_fmt: Option<String>,
+    /// Video sample (pixel) aspect ratio, if known.
+    pub sar: Option<(u32, u32)>,
 }
 
 impl Ffprobe {
@@ -23,6 +29,21 @@ impl Ffprobe {
         PixelFormat::try_from(pf).ok()
     }
 
+    /// Returns an error if the input has no video stream, e.g. an audio-only file.
+    pub fn ensure_video(&self, input: &Path) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.has_video,
+            "no video stream found in {}, expected a video or image input",
+            input.display()
+        );
+        Ok(())
+    }
+
+    /// Returns the square pixel [`display_resolution`] of the video.
+    pub fn display_resolution(&self) -> Option<(u32, u32)> {
+        self.resolution.map(|res| display_resolution(res, self.sar))
+    }
+
     pub fn nframes(&self) -> Result<u64, ProbeError> {
         match (&self.fps, &self.duration) {
             (Ok(fps), Ok(duration)) => {
@@ -45,20 +66,17 @@ pub fn probe(input: &Path) -> Ffprobe {
     let probe = match ffprobe::ffprobe(input) {
         Ok(p) => p,
         Err(err) => {
-            return Ffprobe {
-                duration: Err(ProbeError(format!("ffprobe: {err}"))),
-                fps: Err(ProbeError(format!("ffprobe: {err}"))),
-                has_audio: true,
-                max_audio_channels: None,
-                resolution: None,
-                is_image: false,
-                pix_fmt: None,
-            }
+            StderrLog::new("probe").write(err.to_string().as_bytes());
+            return unknown(&format!("ffprobe: {err}"));
         }
     };
 
     let fps = read_fps(&probe);
     let duration = read_duration(&probe);
+    let has_video = probe
+        .streams
+        .iter()
+        .any(|s| s.codec_type.as_deref() == Some("video"));
     let has_audio = probe
         .streams
         .iter()
@@ -69,6 +87,12 @@ pub fn probe(input: &Path) -> Ffprobe {
         .filter(|s| s.codec_type.as_deref() == Some("audio"))
         .filter_map(|a| a.channels)
         .max();
+    let audio_langs = probe
+        .streams
+        .iter()
+        .filter(|s| s.codec_type.as_deref() == Some("audio"))
+        .map(|a| a.tags.as_ref().and_then(|t| t.language.clone()))
+        .collect();
 
     let resolution = probe
         .streams
@@ -80,6 +104,12 @@ pub fn probe(input: &Path) -> Ffprobe {
             Some((w, h))
         });
 
+    let sar = probe
+        .streams
+        .iter()
+        .filter(|s| s.codec_type.as_deref() == Some("video"))
+        .find_map(|s| parse_sar(s.sample_aspect_ratio.as_deref()?));
+
     let pix_fmt = probe
         .streams
         .into_iter()
@@ -89,11 +119,138 @@ pub fn probe(input: &Path) -> Ffprobe {
     Ffprobe {
         duration: duration.map_err(ProbeError::from),
         fps: fps.map_err(ProbeError::from),
+        has_video,
         has_audio,
         max_audio_channels,
+        audio_langs,
         resolution,
         is_image,
         pix_fmt,
+        sar,
+    }
+}
+
+/// Input chapter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub end: Duration,
+    pub title: Option<String>,
+}
+
+/// Probe the chapters of the input, empty if it has none.
+pub fn probe_chapters(input: &Path) -> anyhow::Result<Vec<Chapter>> {
+    let out = std::process::Command::new("ffprobe")
+        .args(["-v", "error", "-print_format", "json", "-show_chapters"])
+        .arg(input)
+        .output()
+        .context("ffprobe")?;
+    if !out.status.success() {
+        StderrLog::new("probe").write(&out.stderr);
+        anyhow::bail!(
+            "ffprobe chapters: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    parse_chapters(&out.stdout)
+}
+
+/// Parse `ffprobe -print_format json -show_chapters` output.
+fn parse_chapters(json: &[u8]) -> anyhow::Result<Vec<Chapter>> {
+    #[derive(serde::Deserialize)]
+    struct Probe {
+        #[serde(default)]
+        chapters: Vec<JsonChapter>,
+    }
+    #[derive(serde::Deserialize)]
+    struct JsonChapter {
+        start_time: String,
+        end_time: String,
+        #[serde(default)]
+        tags: std::collections::HashMap<String, String>,
+    }
+    let secs = |t: &str| {
+        let secs: f64 = t
+            .parse()
+            .with_context(|| format!("invalid chapter time {t:?}"))?;
+        Duration::try_from_secs_f64(secs).map_err(|e| anyhow!("{e}: chapter time {t:?}"))
+    };
+
+    let probe: Probe = serde_json::from_slice(json).context("invalid ffprobe chapters json")?;
+    probe
+        .chapters
+        .into_iter()
+        .map(|c| {
+            Ok(Chapter {
+                start: secs(&c.start_time)?,
+                end: secs(&c.end_time)?,
+                title: c.tags.get("title").cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Returns a probe of an input that could not be probed, e.g. piped via stdin.
+/// Unknown properties are assumed present, i.e. has video & audio.
+pub fn unknown(reason: &str) -> Ffprobe {
+    Ffprobe {
+        duration: Err(ProbeError(reason.into())),
+        fps: Err(ProbeError(reason.into())),
+        has_video: true,
+        has_audio: true,
+        max_audio_channels: None,
+        audio_langs: vec![],
+        resolution: None,
+        is_image: false,
+        pix_fmt: None,
+        sar: None,
+    }
+}
+
+/// A 10s 24fps 1280x720 video only probe, for tests.
+#[cfg(test)]
+pub fn test_probe() -> Ffprobe {
+    Ffprobe {
+        duration: Ok(Duration::from_secs(10)),
+        fps: Ok(24.0),
+        has_audio: false,
+        resolution: Some((1280, 720)),
+        ..unknown("test")
+    }
+}
+
+/// Parse a "n:d" sample aspect ratio, `None` if unknown e.g. "0:1".
+fn parse_sar(sar: &str) -> Option<(u32, u32)> {
+    let (n, d) = sar.split_once(':')?;
+    let (n, d) = (n.parse().ok()?, d.parse().ok()?);
+    (n > 0 && d > 0).then_some((n, d))
+}
+
+/// Returns true if both sample aspect ratios are equal, unknown treated as square `1:1`.
+pub fn same_sar(a: Option<(u32, u32)>, b: Option<(u32, u32)>) -> bool {
+    let (an, ad) = a.unwrap_or((1, 1));
+    let (bn, bd) = b.unwrap_or((1, 1));
+    u64::from(an) * u64::from(bd) == u64::from(bn) * u64::from(ad)
+}
+
+/// Returns the square pixel display resolution of `resolution` with `sar`.
+/// Width is stretched (rounded to even) and height is kept.
+pub fn display_resolution((w, h): (u32, u32), sar: Option<(u32, u32)>) -> (u32, u32) {
+    match sar {
+        Some((n, d)) if n != d => {
+            let w = (f64::from(w) * f64::from(n) / f64::from(d) / 2.0).round() as u32 * 2;
+            (w, h)
+        }
+        _ => (w, h),
+    }
+}
+
+/// Returns a vfilter converting `sar` pixels to square display pixels matching
+/// [`display_resolution`], or `None` if already square.
+pub fn square_pixel_vfilter(sar: Option<(u32, u32)>) -> Option<String> {
+    match sar {
+        Some((n, d)) if n != d => Some(format!("scale=round(iw*{n}/{d}/2)*2:ih,setsar=1")),
+        _ => None,
     }
 }
 
@@ -162,3 +319,95 @@ impl From<anyhow::Error> for ProbeError {
 }
 
 impl std::error::Error for ProbeError {}
+
+#[test]
+fn sar_display_resolution() {
+    assert_eq!(parse_sar("4:3"), Some((4, 3)));
+    assert_eq!(parse_sar("0:1"), None);
+    assert_eq!(parse_sar("N/A"), None);
+
+    // anamorphic 1440x1080 4:3 pixels displays as 16:9
+    assert_eq!(display_resolution((1440, 1080), Some((4, 3))), (1920, 1080));
+    assert_eq!(display_resolution((1920, 1080), Some((1, 1))), (1920, 1080));
+    assert_eq!(display_resolution((1920, 1080), None), (1920, 1080));
+
+    assert_eq!(
+        square_pixel_vfilter(Some((4, 3))).as_deref(),
+        Some("scale=round(iw*4/3/2)*2:ih,setsar=1")
+    );
+    assert_eq!(square_pixel_vfilter(Some((2, 2))), None);
+
+    assert!(same_sar(None, Some((1, 1))));
+    assert!(same_sar(Some((8, 6)), Some((4, 3))));
+    assert!(!same_sar(Some((4, 3)), None));
+}
+
+#[test]
+fn no_video_stream_error() {
+    let audio_only = Ffprobe {
+        duration: Ok(Duration::from_secs(180)),
+        has_video: false,
+        has_audio: true,
+        max_audio_channels: Some(2),
+        audio_langs: vec![None],
+        fps: Err(ProbeError("no video stream found".into())),
+        resolution: None,
+        is_image: false,
+        pix_fmt: None,
+        sar: None,
+    };
+    let err = audio_only
+        .ensure_video(Path::new("song.flac"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("no video stream found in song.flac"), "{err}");
+
+    let video = Ffprobe {
+        has_video: true,
+        fps: Ok(30.0),
+        resolution: Some((1280, 720)),
+        ..audio_only
+    };
+    assert!(video.ensure_video(Path::new("vid.mp4")).is_ok());
+}
+
+#[test]
+fn parse_ffprobe_chapters() {
+    let json = br#"{
+        "chapters": [
+            {
+                "id": 0,
+                "time_base": "1/1000",
+                "start": 0,
+                "start_time": "0.000000",
+                "end": 90000,
+                "end_time": "90.000000",
+                "tags": { "title": "Intro" }
+            },
+            {
+                "id": 1,
+                "time_base": "1/1000",
+                "start": 90000,
+                "start_time": "90.000000",
+                "end": 1250500,
+                "end_time": "1250.500000"
+            }
+        ]
+    }"#;
+    assert_eq!(
+        parse_chapters(json).unwrap(),
+        [
+            Chapter {
+                start: Duration::ZERO,
+                end: Duration::from_secs(90),
+                title: Some("Intro".into()),
+            },
+            Chapter {
+                start: Duration::from_secs(90),
+                end: Duration::from_millis(1_250_500),
+                title: None,
+            },
+        ]
+    );
+    assert_eq!(parse_chapters(b"{}").unwrap(), []);
+}
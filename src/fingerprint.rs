@@ -0,0 +1,112 @@
+//! Fast partial file fingerprinting.
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+/// Bytes hashed at the start & end of the file.
+const EDGE_LEN: u64 = 64 * 1024;
+/// Number of evenly spaced chunks hashed between the start & end.
+const SAMPLED_CHUNKS: u64 = 8;
+/// Bytes hashed at each sampled offset.
+const CHUNK_LEN: u64 = 4 * 1024;
+
+/// Returns a fingerprint of the file using the size, modified time & content
+/// at the start, end and a few sampled offsets.
+///
+/// Much faster than hashing large files while still detecting most content changes.
+pub fn fingerprint(path: &Path) -> io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let meta = file.metadata()?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&mtime.to_le_bytes());
+    hash_content(&mut hasher, &mut file, meta.len())?;
+    Ok(hasher.finalize())
+}
+
+/// Hash the length & sampled content of a `len` byte file.
+fn hash_content(
+    hasher: &mut blake3::Hasher,
+    file: &mut (impl Read + Seek),
+    len: u64,
+) -> io::Result<()> {
+    hasher.update(&len.to_le_bytes());
+
+    if len <= 2 * EDGE_LEN + SAMPLED_CHUNKS * CHUNK_LEN {
+        // small enough to hash everything
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut file.take(len), hasher)?;
+        return Ok(());
+    }
+
+    let mut ranges = vec![(0, EDGE_LEN)];
+    for n in 1..=SAMPLED_CHUNKS {
+        ranges.push((len * n / (SAMPLED_CHUNKS + 1), CHUNK_LEN));
+    }
+    ranges.push((len - EDGE_LEN, EDGE_LEN));
+
+    for (offset, chunk_len) in ranges {
+        file.seek(SeekFrom::Start(offset))?;
+        io::copy(&mut file.take(chunk_len), hasher)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn content_hash(data: &[u8]) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        hash_content(&mut hasher, &mut Cursor::new(data), data.len() as _).unwrap();
+        hasher.finalize()
+    }
+
+    #[test]
+    fn stable_when_unchanged() {
+        let data: Vec<u8> = (0..4_000_000_u32).map(|n| n as u8).collect();
+        assert_eq!(content_hash(&data), content_hash(&data.clone()));
+    }
+
+    #[test]
+    fn changes_at_sampled_offsets() {
+        let data: Vec<u8> = (0..4_000_000_u32).map(|n| n as u8).collect();
+        let original = content_hash(&data);
+
+        let len = data.len() as u64;
+        for offset in [0, EDGE_LEN - 1, len * 4 / 9 + 10, len - 1] {
+            let mut changed = data.clone();
+            changed[offset as usize] ^= 0xff;
+            assert_ne!(content_hash(&changed), original, "offset {offset}");
+        }
+
+        // length change
+        assert_ne!(content_hash(&data[..data.len() - 1]), original);
+    }
+
+    #[test]
+    fn ignores_unsampled_offsets() {
+        let data: Vec<u8> = (0..4_000_000_u32).map(|n| n as u8).collect();
+        let mut changed = data.clone();
+        changed[EDGE_LEN as usize + 100] ^= 0xff;
+        assert_eq!(content_hash(&changed), content_hash(&data));
+    }
+
+    #[test]
+    fn small_files_fully_hashed() {
+        let data = vec![7_u8; 1000];
+        let mut changed = data.clone();
+        changed[500] = 8;
+        assert_ne!(content_hash(&changed), content_hash(&data));
+    }
+}
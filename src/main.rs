@@ -2,22 +2,67 @@ mod command;
 mod console_ext;
 mod ffmpeg;
 mod ffprobe;
+mod fingerprint;
 mod float;
 mod log;
+mod metric;
 mod process;
+mod profile;
 mod sample;
 mod temporary;
+mod threads;
+mod timing;
+mod tsv;
 mod vmaf;
 
 use ::log::LevelFilter;
 use anyhow::anyhow;
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
 use futures_util::FutureExt;
-use std::{env, io::IsTerminal};
+use std::{env, io::IsTerminal, path::PathBuf};
 use tokio::signal;
 
 #[derive(Parser)]
 #[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print json describing the ab-av1 version, supported metrics & output formats
+    /// and the detected ffmpeg encoders & hwaccels. E.g. for wrapping tools.
+    #[arg(long, exclusive = true)]
+    capabilities: bool,
+
+    /// Write the stderr of each spawned ffmpeg process into a file in this directory,
+    /// named by timestamp, phase & crf. E.g. for attaching to bug reports.
+    #[arg(long, global = true, env = "AB_AV1_LOG_DIR", value_hint = clap::ValueHint::DirPath)]
+    log_dir: Option<PathBuf>,
+
+    /// Total threads to use, allocated across phases. During crf search sample
+    /// encoding gets half & VMAF `n_threads` the rest, the final encode gets all.
+    ///
+    /// Explicit encoder threads args or VMAF `n_threads` take precedence.
+    /// [default: all available threads]
+    #[arg(long, global = true, env = "AB_AV1_THREADS")]
+    threads: Option<usize>,
+
+    /// Disable automatically using all available threads, e.g. for VMAF `n_threads`,
+    /// leaving encoder & libvmaf defaults.
+    #[arg(long, global = true, conflicts_with = "threads")]
+    no_auto_threads: bool,
+
+    /// Load args from a profile file saved with --save-profile.
+    /// Args specified on the command line take precedence.
+    #[arg(long, global = true, value_hint = clap::ValueHint::FilePath)]
+    profile: Option<PathBuf>,
+
+    /// After completing successfully save the encoder, preset, crf, vmaf, sample
+    /// & filter args to this profile file for later use with --profile.
+    #[arg(long, global = true, value_hint = clap::ValueHint::FilePath)]
+    save_profile: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
 enum Command {
     SampleEncode(command::sample_encode::Args),
     Vmaf(command::vmaf::Args),
@@ -25,6 +70,11 @@ enum Command {
     CrfSearch(command::crf_search::Args),
     AutoEncode(command::auto_encode::Args),
     PrintCompletions(command::print_completions::Args),
+    PrintVmafScale(command::print_vmaf_scale::Args),
+    Info(command::info::Args),
+    Analyze(command::analyze::Args),
+    RdCurve(command::rd_curve::Args),
+    BdRate(command::bd_rate::Args),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -38,7 +88,66 @@ async fn main() {
         .parse_default_env()
         .init();
 
-    let action = Command::parse();
+    let args = match command::encode::saved_plan::apply(env::args_os().collect()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: --from-plan: {err:#}");
+            std::process::exit(1);
+        }
+    };
+    let args = match profile::apply(args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: --profile: {err:#}");
+            std::process::exit(1);
+        }
+    };
+    let Cli {
+        command: action,
+        log_dir,
+        threads,
+        no_auto_threads,
+        profile: _,
+        save_profile,
+        capabilities,
+    } = Cli::parse_from(&args);
+
+    let action = match action {
+        Some(_) if capabilities => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--capabilities cannot be used with a subcommand",
+            )
+            .exit(),
+        Some(action) => action,
+        None if capabilities => {
+            if let Err(err) = tokio::task::LocalSet::new()
+                .run_until(command::info::capabilities())
+                .await
+            {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => Cli::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit(),
+    };
+
+    if let Some(threads) = threads {
+        threads::set_budget(threads);
+    }
+    if no_auto_threads {
+        threads::disable_auto();
+    }
+
+    if let Some(dir) = log_dir {
+        if let Err(err) = process::set_log_dir(dir) {
+            eprintln!("Error: --log-dir: {err}");
+            std::process::exit(1);
+        }
+    }
 
     let keep = action.keep_temp_files();
 
@@ -50,7 +159,12 @@ async fn main() {
         Command::Encode(args) => command::encode(args).boxed_local(),
         Command::CrfSearch(args) => command::crf_search(args).boxed_local(),
         Command::AutoEncode(args) => command::auto_encode(args).boxed_local(),
+        Command::Info(args) => command::info(args).boxed_local(),
+        Command::Analyze(args) => command::analyze(args).boxed_local(),
+        Command::RdCurve(args) => command::rd_curve(args).boxed_local(),
+        Command::BdRate(args) => command::bd_rate(args).boxed_local(),
         Command::PrintCompletions(args) => return command::print_completions(args),
+        Command::PrintVmafScale(args) => return command::print_vmaf_scale(args),
     });
 
     let out = tokio::select! {
@@ -63,7 +177,14 @@ async fn main() {
 
     if let Err(err) = out {
         eprintln!("Error: {err}");
-        std::process::exit(1);
+        std::process::exit(command::args::exit_code(&err));
+    }
+
+    if let Some(path) = save_profile {
+        if let Err(err) = profile::save(&args, &path) {
+            eprintln!("Error: --save-profile: {err:#}");
+            std::process::exit(1);
+        }
     }
 }
 
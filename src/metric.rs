@@ -0,0 +1,61 @@
+//! Quality metric lavfi & score parsing interface, implemented by VMAF.
+use crate::{
+    command::args::{self, PixelFormat},
+    vmaf,
+};
+
+/// A quality metric computed by an ffmpeg analysis of a distorted vs reference.
+///
+/// Describes how to analyse & how to read the score, used by [`crate::vmaf::run`].
+/// VMAF is the only implementation, sample-encode & crf-search always analyse with
+/// [`args::Vmaf`] as selecting another metric is not supported yet.
+pub trait Metric {
+    /// Returns the ffmpeg `-filter_complex` analysing input 0, the distorted,
+    /// against input 1, the reference.
+    fn lavfi(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+        pix_fmt: PixelFormat,
+        ref_vfilter: Option<&str>,
+    ) -> String;
+
+    /// Parse the final pooled score from a line of ffmpeg stderr, if present.
+    fn parse_score(&self, line: &str) -> Option<f32>;
+}
+
+impl Metric for args::Vmaf {
+    fn lavfi(
+        &self,
+        distorted_res: Option<(u32, u32)>,
+        pix_fmt: PixelFormat,
+        ref_vfilter: Option<&str>,
+    ) -> String {
+        self.ffmpeg_lavfi(distorted_res, pix_fmt, ref_vfilter)
+    }
+
+    fn parse_score(&self, line: &str) -> Option<f32> {
+        const VMAF_SCORE_PRE: &str = "VMAF score: ";
+
+        // ignore secondary libvmaf instances, scores are read from their logs
+        let secondary = [vmaf::CENTER_CROP_INSTANCE, vmaf::UNSCALED_INSTANCE];
+        if secondary.iter().any(|i| line.contains(&format!("@{i} @"))) {
+            return None;
+        }
+        let idx = line.find(VMAF_SCORE_PRE)?;
+        line[idx + VMAF_SCORE_PRE.len()..].trim().parse().ok()
+    }
+}
+
+#[test]
+fn vmaf_parse_score() {
+    let vmaf = args::Vmaf::default();
+    assert_eq!(
+        vmaf.parse_score("[Parsed_libvmaf_8 @ 0x5a] VMAF score: 95.120000"),
+        Some(95.12)
+    );
+    assert_eq!(
+        vmaf.parse_score("[libvmaf@center @ 0x5b] VMAF score: 91.500000"),
+        None
+    );
+    assert_eq!(vmaf.parse_score("frame=  100 fps=50"), None);
+}
@@ -3,16 +3,63 @@ use std::{
     borrow::Cow,
     ffi::OsStr,
     fmt::Display,
-    io,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
     process::{ExitStatus, Output},
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use time::macros::format_description;
 use tokio::process::Child;
 use tokio_process_stream::{Item, ProcessChunkStream};
 use tokio_stream::{Stream, StreamExt};
 
+static LOG_DIR: OnceLock<PathBuf> = OnceLock::new();
+static LOG_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the `--log-dir` so spawned process stderr is also written to files there.
+pub fn set_log_dir(dir: PathBuf) -> io::Result<()> {
+    std::fs::create_dir_all(&dir)?;
+    _ = LOG_DIR.set(dir);
+    Ok(())
+}
+
+/// Per-process stderr log file in the `--log-dir`, if set.
+#[derive(Default)]
+pub struct StderrLog(Option<File>);
+
+impl StderrLog {
+    /// Create a new log for a process `phase`, e.g. "sample-encode-crf32".
+    /// Noop if no log dir is set.
+    pub fn new(phase: &str) -> Self {
+        match LOG_DIR.get() {
+            Some(dir) => Self::create_in(dir, phase),
+            None => Self(None),
+        }
+    }
+
+    /// Create a new timestamped log file in `dir`, failures are ignored.
+    fn create_in(dir: &Path, phase: &str) -> Self {
+        let n = LOG_COUNT.fetch_add(1, Ordering::Relaxed);
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = dir.join(format!("{millis}-{n:03}-{phase}.log"));
+        Self(File::create(path).ok())
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        if let Some(file) = &mut self.0 {
+            _ = file.write_all(data);
+        }
+    }
+}
+
 pub fn ensure_success(name: &'static str, out: &Output) -> anyhow::Result<()> {
     ensure!(
         out.status.success(),
@@ -107,10 +154,12 @@ impl FfmpegOut {
         child: Child,
         name: &'static str,
         cmd_str: String,
+        mut log: StderrLog,
     ) -> impl Stream<Item = anyhow::Result<FfmpegOut>> {
         let mut chunks = Chunks::default();
         ProcessChunkStream::from(child).filter_map(move |item| match item {
             Item::Stderr(chunk) => {
+                log.write(&chunk);
                 chunks.push(&chunk);
                 FfmpegOut::try_parse(chunks.last_line()).map(Ok)
             }
@@ -273,6 +322,135 @@ fn parse_ffmpeg_stream_sizes() {
     );
 }
 
+#[cfg(unix)]
+#[tokio::test]
+async fn stderr_logs_per_process() {
+    let dir = std::env::temp_dir().join(format!("ab-av1-test-logs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for (phase, msg) in [("sample-encode-crf32", "sample"), ("encode-crf32", "final")] {
+        let child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("echo {msg} encode log >&2"))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let log = StderrLog::create_in(&dir, phase);
+        let out: Vec<_> = FfmpegOut::stream(child, "sh", "sh".into(), log)
+            .collect()
+            .await;
+        assert!(out.is_empty(), "{out:?}");
+    }
+
+    let mut logs: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    logs.sort();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(logs.len(), 2, "{logs:?}");
+    let name = |p: &Path| p.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(
+        name(&logs[0]).ends_with("-sample-encode-crf32.log"),
+        "{logs:?}"
+    );
+    assert!(name(&logs[1]).ends_with("-encode-crf32.log"), "{logs:?}");
+}
+
+/// Set of cpu indices, e.g. "0-7" or "0,2,4-6".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList(Vec<usize>);
+
+impl CpuList {
+    pub fn cpus(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for CpuList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_cpu = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid cpu `{n}`, expected e.g. 0-7 or 0,2,4-6"))
+        };
+        let mut cpus = vec![];
+        for part in s.split(',') {
+            match part.split_once('-') {
+                Some((from, to)) => {
+                    let (from, to) = (parse_cpu(from)?, parse_cpu(to)?);
+                    ensure!(from <= to, "invalid cpu range `{part}`");
+                    cpus.extend(from..=to);
+                }
+                None => cpus.push(parse_cpu(part)?),
+            }
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(Self(cpus))
+    }
+}
+
+/// Affinity doesn't affect results so is not hashed.
+impl std::hash::Hash for CpuList {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+/// Restrict the spawned process of `cmd` to run on `cpus`.
+#[cfg(target_os = "linux")]
+pub fn set_cpu_affinity(cmd: &mut tokio::process::Command, cpus: &CpuList) -> anyhow::Result<()> {
+    // Safety: cpu_set_t is a plain bitset, zeroed is valid & equivalent to CPU_ZERO
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let max = 8 * std::mem::size_of::<libc::cpu_set_t>();
+    for &cpu in cpus.cpus() {
+        ensure!(cpu < max, "cpu {cpu} out of range, max {}", max - 1);
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // Safety: sched_setaffinity is async-signal-safe & `set` is copied into the closure
+    unsafe {
+        cmd.pre_exec(
+            move || match libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Restrict the spawned process of `cmd` to run on `cpus`.
+#[cfg(not(target_os = "linux"))]
+pub fn set_cpu_affinity(_: &mut tokio::process::Command, _: &CpuList) -> anyhow::Result<()> {
+    anyhow::bail!("cpu affinity is only supported on linux")
+}
+
+#[test]
+fn parse_cpu_list() {
+    let cpus = |s: &str| s.parse::<CpuList>().map(|c| c.0);
+    assert_eq!(cpus("0-7").unwrap(), (0..8).collect::<Vec<_>>());
+    assert_eq!(cpus("4-6,0,2,5").unwrap(), vec![0, 2, 4, 5, 6]);
+    assert_eq!(cpus("3").unwrap(), vec![3]);
+    for invalid in ["", "7-0", "a", "0-", "1,,2"] {
+        assert!(cpus(invalid).is_err(), "{invalid} should be invalid");
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn cpu_affinity_applied_to_child() {
+    let mut cmd = tokio::process::Command::new("grep");
+    cmd.args(["Cpus_allowed_list", "/proc/self/status"]);
+    set_cpu_affinity(&mut cmd, &"0".parse().unwrap()).unwrap();
+    let out = cmd.output().await.unwrap();
+    ensure_success("grep", &out).unwrap();
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(out.split_whitespace().last(), Some("0"), "{out}");
+}
+
 pub trait CommandExt {
     /// Adds two arguments.
     fn arg2(&mut self, a: impl ArgString, b: impl ArgString) -> &mut Self;
@@ -0,0 +1,342 @@
+//! `--profile` & `--save-profile` per-job settings logic.
+//!
+//! Profiles are simple toml files of long arg names to values, e.g.
+//! ```toml
+//! encoder = "libsvtav1"
+//! preset = "6"
+//! min-vmaf = "95"
+//! svt = ["tune=0", "film-grain=8"]
+//! ```
+use anyhow::{bail, ensure, Context};
+use clap::CommandFactory;
+use std::{ffi::OsString, fmt::Write, path::Path};
+
+/// Long args saved in profiles & whether they take a value.
+const PROFILE_ARGS: &[(&str, bool)] = &[
+    ("encoder", true),
+    ("preset", true),
+    ("speed", true),
+    ("deadline", true),
+    ("bframes", true),
+    ("svc", true),
+    ("pix-format", true),
+    ("vfilter", true),
+    ("fps", true),
+    ("keyint", true),
+    ("scd", true),
+    ("svt", true),
+    ("enc", true),
+    ("enc-input", true),
+    ("crf", true),
+    ("qp", false),
+    ("min-vmaf", true),
+    ("max-encoded-percent", true),
+    ("max-encoded-size", true),
+    ("min-crf", true),
+    ("max-crf", true),
+    ("crf-increment", true),
+    ("thorough", false),
+    ("samples", true),
+    ("sample-every", true),
+    ("min-samples", true),
+    ("sample-duration", true),
+    ("min-sample-spacing", true),
+    ("avoid-chapters", true),
+    ("sample-seed", true),
+    ("vmaf", true),
+    ("vmaf-scale", true),
+    ("reference-vfilter", true),
+    ("cuda", false),
+    ("acodec", true),
+    ("downmix-to-stereo", false),
+];
+
+/// Short arg aliases of [`PROFILE_ARGS`].
+const SHORT_ARGS: &[(&str, &str)] = &[("-e", "encoder")];
+
+/// If `args` include a `--profile` insert its args, except those already specified
+/// or not accepted by the subcommand.
+pub fn apply(args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let strs: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+    let Some(path) = arg_value(&strs, "profile") else {
+        return Ok(args);
+    };
+    let toml =
+        std::fs::read_to_string(&path).with_context(|| format!("failed to read profile {path}"))?;
+    let specified: Vec<_> = profile_args(&strs).into_iter().map(|(k, _)| k).collect();
+    let accepted = subcommand_args(&strs);
+
+    let mut args = args;
+    for (key, value) in parse(&toml).with_context(|| format!("invalid profile {path}"))? {
+        let accepts = accepted.as_ref().is_none_or(|a| a.iter().any(|a| a == key));
+        if accepts && !specified.contains(&key) {
+            args.push(format!("--{key}").into());
+            args.extend(value.map(Into::into));
+        }
+    }
+    Ok(args)
+}
+
+/// Write the profile args of the command line `args` to `path`.
+pub fn save(args: &[OsString], path: &Path) -> anyhow::Result<()> {
+    let strs: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+    std::fs::write(path, to_toml(&profile_args(&strs)))
+        .with_context(|| format!("failed to write profile {}", path.display()))
+}
+
+/// Returns the profile `(key, value)` args in command line `args`.
+fn profile_args(args: &[impl AsRef<str>]) -> Vec<(&'static str, Option<String>)> {
+    let mut out = vec![];
+    let mut args = args.iter().map(AsRef::as_ref);
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.strip_prefix("--") {
+            Some(long) => match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            },
+            None => match SHORT_ARGS.iter().find(|(short, _)| *short == arg) {
+                Some((_, name)) => (*name, None),
+                None => continue,
+            },
+        };
+        if let Some((key, takes_value)) = PROFILE_ARGS.iter().find(|(k, _)| *k == name) {
+            let value = match takes_value {
+                true => inline_value.or_else(|| args.next()).map(Into::into),
+                false => None,
+            };
+            out.push((*key, value));
+        }
+    }
+    out
+}
+
+/// Returns the long arg names accepted by the subcommand in command line `args`,
+/// `None` if there is no known subcommand.
+fn subcommand_args(args: &[impl AsRef<str>]) -> Option<Vec<String>> {
+    let cli = crate::Cli::command();
+    let sub = args
+        .iter()
+        .skip(1)
+        .find_map(|arg| cli.find_subcommand(arg.as_ref()))?;
+    Some(
+        sub.get_arguments()
+            .filter_map(|a| a.get_long())
+            .map(Into::into)
+            .collect(),
+    )
+}
+
+/// Returns the value of the last long `name` arg.
+fn arg_value(args: &[impl AsRef<str>], name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut value = None;
+    let mut args = args.iter().map(AsRef::as_ref);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            value = args.next().map(Into::into);
+        } else if let Some(v) = arg.strip_prefix(&flag).and_then(|a| a.strip_prefix('=')) {
+            value = Some(v.into());
+        }
+    }
+    value
+}
+
+fn to_toml(args: &[(&str, Option<String>)]) -> String {
+    let mut toml = String::from("# ab-av1 profile\n");
+    let mut written: Vec<&str> = vec![];
+    for (key, _) in args {
+        if written.contains(key) {
+            continue;
+        }
+        written.push(key);
+        let values: Vec<_> = args
+            .iter()
+            .filter(|(k, _)| k == key)
+            .filter_map(|(_, v)| v.as_deref())
+            .map(quote)
+            .collect();
+        match values.len() {
+            0 => writeln!(toml, "{key} = true"),
+            1 => writeln!(toml, "{key} = {}", values[0]),
+            _ => writeln!(toml, "{key} = [{}]", values.join(", ")),
+        }
+        .unwrap();
+    }
+    toml
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Parse profile toml into `(key, value)` args.
+fn parse(toml: &str) -> anyhow::Result<Vec<(&'static str, Option<String>)>> {
+    let mut args = vec![];
+    for line in toml.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("expected `key = value`: {line}"))?;
+        let key = key.trim();
+        let (key, takes_value) = PROFILE_ARGS
+            .iter()
+            .find(|(k, _)| *k == key)
+            .with_context(|| format!("unknown profile key `{key}`"))?;
+        let value = value.trim();
+        match (value, takes_value) {
+            ("true", false) => args.push((*key, None)),
+            ("false", false) => {}
+            (_, false) => bail!("`{key}` must be true or false"),
+            (list, true) if list.starts_with('[') => {
+                let mut rest = list
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                    .with_context(|| format!("unclosed `{key}` list"))?
+                    .trim();
+                while !rest.is_empty() {
+                    let (value, remaining) = unquote(rest)?;
+                    args.push((*key, Some(value)));
+                    rest = remaining.trim_start();
+                    rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+                }
+            }
+            (value, true) => {
+                let (value, remaining) = unquote(value)?;
+                ensure!(remaining.trim().is_empty(), "unexpected `{remaining}`");
+                args.push((*key, Some(value)));
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Parse a leading quoted string, returning it unescaped & the remaining str.
+fn unquote(s: &str) -> anyhow::Result<(String, &str)> {
+    let inner = s
+        .strip_prefix('"')
+        .with_context(|| format!("expected quoted string: {s}"))?;
+    let mut out = String::new();
+    let mut chars = inner.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next().map(|(_, c)| c)),
+            '"' => return Ok((out, &inner[idx + 1..])),
+            c => out.push(c),
+        }
+    }
+    bail!("unclosed string: {s}")
+}
+
+#[test]
+fn profile_round_trip() {
+    use crate::{Cli, Command};
+    use clap::Parser;
+
+    let original = [
+        "ab-av1",
+        "crf-search",
+        "-i",
+        "vid.mkv",
+        "-e",
+        "libx265",
+        "--preset=slow",
+        "--min-vmaf",
+        "93.5",
+        "--enc",
+        "x265-params=aq-mode=3",
+        "--enc",
+        "tune=\"grain\"",
+        "--vfilter",
+        "scale=1280:-1",
+        "--vmaf-scale",
+        "1920x1080",
+        "--thorough",
+    ];
+    let toml = to_toml(&profile_args(&original));
+    assert!(toml.contains("encoder = \"libx265\"\n"), "{toml}");
+    assert!(toml.contains("thorough = true\n"), "{toml}");
+
+    // load into a new job, user specified args take precedence
+    let mut loaded: Vec<String> = ["ab-av1", "crf-search", "-i", "other.mkv", "--min-crf", "20"]
+        .map(Into::into)
+        .into();
+    for (key, value) in parse(&toml).unwrap() {
+        loaded.push(format!("--{key}"));
+        loaded.extend(value);
+    }
+
+    let resolve = |args: &[String]| match Cli::try_parse_from(args).unwrap().command {
+        Some(Command::CrfSearch(args)) => (
+            args.args.encode_hint(30.0).replace("other.mkv", "vid.mkv"),
+            args.min_vmaf,
+            args.thorough,
+            args.vmaf.vmaf_scale,
+        ),
+        _ => unreachable!(),
+    };
+    let original: Vec<String> = original.map(Into::into).into();
+    assert_eq!(resolve(&loaded), resolve(&original));
+}
+
+#[test]
+fn profile_parse_errors() {
+    assert!(parse("unknown = \"1\"").is_err());
+    assert!(parse("preset = \"6").is_err());
+    assert!(parse("thorough = \"yes\"").is_err());
+    assert_eq!(
+        parse("# comment\n\nsvt = [\"tune=0\", \"film-grain=8\"]").unwrap(),
+        [
+            ("svt", Some("tune=0".into())),
+            ("svt", Some("film-grain=8".into()))
+        ]
+    );
+}
+
+#[test]
+fn profile_apply_per_subcommand() {
+    use crate::{Cli, Command};
+    use clap::Parser;
+
+    let path = std::env::temp_dir().join(format!("ab-av1-test-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "encoder = \"libx265\"\npreset = \"slow\"\nmin-vmaf = \"93.5\"\nvmaf-scale = \"none\"\n",
+    )
+    .unwrap();
+    let apply = |sub: &[&str]| {
+        let args = ["ab-av1"]
+            .iter()
+            .chain(sub)
+            .chain(&["-i", "vid.mkv", "--profile"])
+            .map(OsString::from)
+            .chain([path.clone().into()])
+            .collect();
+        Cli::try_parse_from(apply(args).unwrap()).unwrap().command
+    };
+
+    match apply(&["crf-search"]) {
+        Some(Command::CrfSearch(args)) => {
+            let hint = args.args.encode_hint(30.0);
+            assert!(
+                hint.contains("-e libx265") && hint.contains("--preset slow"),
+                "{hint}"
+            );
+            assert_eq!(args.min_vmaf, 93.5);
+        }
+        _ => unreachable!(),
+    }
+    // encode doesn't accept --min-vmaf or --vmaf-scale
+    match apply(&["encode", "--crf", "30"]) {
+        Some(Command::Encode(args)) => {
+            let hint = args.args.encode_hint(30.0);
+            assert!(
+                hint.contains("-e libx265") && hint.contains("--preset slow"),
+                "{hint}"
+            );
+        }
+        _ => unreachable!(),
+    }
+    std::fs::remove_file(&path).unwrap();
+}
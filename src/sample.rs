@@ -1,6 +1,7 @@
 //! ffmpeg logic
 use crate::{
-    process::{ensure_success, CommandExt},
+    command::args::{ExtractCodec, SeekMode},
+    process::{ensure_success, CommandExt, StderrLog},
     temporary::{self, TempKind},
 };
 use anyhow::Context;
@@ -11,73 +12,233 @@ use std::{
 };
 use tokio::process::Command;
 
+/// Returns the `-ss` seconds value used to copy a sample starting at `sample_start`.
+pub fn start_secs(sample_start: Duration, floor_to_sec: bool) -> f32 {
+    let sample_start_s = sample_start.as_secs_f32();
+    match floor_to_sec {
+        true => sample_start_s.floor(),
+        false => sample_start_s,
+    }
+}
+
 /// Create a sample from `sample_start` + `frames`.
 ///
-/// Fast as this uses `-c:v copy`.
+/// With [`SeekMode::Fast`] this is fast as it uses `-c:v copy`, which snaps to keyframes.
+/// [`SeekMode::Accurate`] decodes from the exact start into a lossless `codec` sample.
 pub async fn copy(
     input: &Path,
     sample_start: Duration,
     floor_to_sec: bool,
     frames: u32,
+    seek_mode: SeekMode,
+    codec: ExtractCodec,
     temp_dir: Option<PathBuf>,
 ) -> anyhow::Result<PathBuf> {
-    let mut sample_start_s = sample_start.as_secs_f32();
-    if floor_to_sec {
-        sample_start_s = sample_start_s.floor();
-    }
+    let sample_start_s = start_secs(sample_start, floor_to_sec);
 
     let mut dest = temporary::process_dir(temp_dir);
-
-    let mut input_path_hasher = blake3::Hasher::new();
-    input_path_hasher.update(input.to_string_lossy().as_bytes());
-    let input_path_hash = input_path_hasher.finalize();
-    let input_path_hash_str = input_path_hash.to_hex();
+    let input_path_hash_str = input_path_hash(input);
 
     // Always using mkv for the samples works better than, e.g. using mp4 for mp4s
     // see https://github.com/alexheretic/ab-av1/issues/82#issuecomment-1337306325
-    dest.push(format!("{input_path_hash_str}.sample{sample_start_s}+{frames}f.mkv"));
+    let (mode, codec_suffix) = match seek_mode {
+        SeekMode::Fast => ("", ""),
+        SeekMode::Accurate => (".accurate", codec.file_suffix()),
+    };
+    dest.push(format!(
+        "{input_path_hash_str}.sample{sample_start_s}+{frames}f{mode}{codec_suffix}.mkv"
+    ));
     if dest.exists() {
         return Ok(dest);
     }
     temporary::add(&dest, TempKind::Keepable);
 
-    // Note: `-ss` before `-i` & `-frames:v` instead of `-t`
-    // See https://github.com/alexheretic/ab-av1/issues/36#issuecomment-1146634936
-    let mut out = Command::new("ffmpeg")
-        .arg("-y")
-        .arg2("-ss", sample_start_s)
-        .arg2("-i", input)
-        .arg2("-frames:v", frames)
-        .arg2("-c:v", "copy")
-        .arg("-an")
-        .arg("-sn")
-        .arg(&dest)
-        .stdin(Stdio::null())
-        .output()
-        .await
-        .context("ffmpeg copy")?;
+    let mut out = copy_cmd(
+        input,
+        sample_start_s,
+        frames,
+        seek_mode,
+        codec,
+        false,
+        &dest,
+    )
+    .output()
+    .await
+    .context("ffmpeg copy")?;
 
     if !out.status.success()
         && String::from_utf8_lossy(&out.stderr)
             .contains("Can't write packet with unknown timestamp")
     {
-        out = Command::new("ffmpeg")
-            .arg("-y")
-            // try +genpts workaround
-            .arg2("-fflags", "+genpts")
-            .arg2("-ss", sample_start_s)
-            .arg2("-i", input)
-            .arg2("-frames:v", frames)
-            .arg2("-c:v", "copy")
-            .arg("-an")
-            .arg("-sn")
-            .arg(&dest)
-            .stdin(Stdio::null())
+        // try +genpts workaround
+        out = copy_cmd(input, sample_start_s, frames, seek_mode, codec, true, &dest)
             .output()
             .await
             .context("ffmpeg copy")?;
     }
 
+    StderrLog::new("sample-copy").write(&out.stderr);
     ensure_success("ffmpeg copy", &out)?;
     Ok(dest)
 }
+
+/// Create a lossless `codec` intermediate of the input's main video stream.
+pub async fn lossless_intermediate(
+    input: &Path,
+    codec: ExtractCodec,
+    temp_dir: Option<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    let mut dest = temporary::process_dir(temp_dir);
+    dest.push(format!(
+        "{}.lossless{}.mkv",
+        input_path_hash(input),
+        codec.file_suffix()
+    ));
+    if dest.exists() {
+        return Ok(dest);
+    }
+    // write to a partial file first so an interrupted run isn't mistaken as complete
+    let partial = dest.with_extension("partial.mkv");
+    temporary::add(&partial, TempKind::NotKeepable);
+
+    let out = intermediate_cmd(input, codec, &partial)
+        .output()
+        .await
+        .context("ffmpeg lossless intermediate")?;
+    StderrLog::new("lossless-intermediate").write(&out.stderr);
+    ensure_success("ffmpeg lossless intermediate", &out)?;
+
+    tokio::fs::rename(&partial, &dest).await?;
+    temporary::add(&dest, TempKind::Keepable);
+    Ok(dest)
+}
+
+fn intermediate_cmd(input: &Path, codec: ExtractCodec, dest: &Path) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-y")
+        .arg2("-i", input)
+        .arg2("-map", "0:v:0")
+        .args(codec.codec_args())
+        .arg2_if(codec == ExtractCodec::Ffv1, "-level", "3")
+        .arg("-an")
+        .arg("-sn")
+        .arg(dest)
+        .stdin(Stdio::null());
+    cmd
+}
+
+fn input_path_hash(input: &Path) -> String {
+    let mut input_path_hasher = blake3::Hasher::new();
+    input_path_hasher.update(input.to_string_lossy().as_bytes());
+    input_path_hasher.finalize().to_hex().to_string()
+}
+
+fn copy_cmd(
+    input: &Path,
+    sample_start_s: f32,
+    frames: u32,
+    seek_mode: SeekMode,
+    codec: ExtractCodec,
+    genpts: bool,
+    dest: &Path,
+) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-y").arg2_if(genpts, "-fflags", "+genpts");
+    match seek_mode {
+        // Note: `-ss` before `-i` & `-frames:v` instead of `-t`
+        // See https://github.com/alexheretic/ab-av1/issues/36#issuecomment-1146634936
+        SeekMode::Fast => cmd
+            .arg2("-ss", sample_start_s)
+            .arg2("-i", input)
+            .arg2("-frames:v", frames)
+            .arg2("-c:v", "copy"),
+        // decode & discard up to the exact start, stream copy can't start between keyframes
+        SeekMode::Accurate => cmd
+            .arg2("-i", input)
+            .arg2("-ss", sample_start_s)
+            .arg2("-frames:v", frames)
+            .args(codec.codec_args()),
+    };
+    cmd.arg("-an").arg("-sn").arg(dest).stdin(Stdio::null());
+    cmd
+}
+
+#[test]
+fn copy_cmd_seek_placement() {
+    let cmd = copy_cmd(
+        Path::new("vid.mkv"),
+        12.5,
+        480,
+        SeekMode::Fast,
+        ExtractCodec::Raw,
+        false,
+        Path::new("vid.sample.mkv"),
+    );
+    assert_eq!(
+        cmd.to_cmd_str(),
+        "ffmpeg -y -ss 12.5 -i vid.mkv -frames:v 480 -c:v copy -an -sn vid.sample.mkv"
+    );
+
+    let cmd = copy_cmd(
+        Path::new("vid.mkv"),
+        12.5,
+        480,
+        SeekMode::Accurate,
+        ExtractCodec::Ffv1,
+        true,
+        Path::new("vid.sample.mkv"),
+    );
+    assert_eq!(
+        cmd.to_cmd_str(),
+        "ffmpeg -y -fflags +genpts -i vid.mkv -ss 12.5 -frames:v 480 -c:v ffv1 -an -sn vid.sample.mkv"
+    );
+}
+
+#[test]
+fn copy_cmd_extract_codecs() {
+    let cmd = |codec| {
+        copy_cmd(
+            Path::new("vid.mkv"),
+            12.5,
+            480,
+            SeekMode::Accurate,
+            codec,
+            false,
+            Path::new("vid.sample.mkv"),
+        )
+        .to_cmd_str()
+    };
+    assert_eq!(
+        cmd(ExtractCodec::Raw),
+        "ffmpeg -y -i vid.mkv -ss 12.5 -frames:v 480 -c:v rawvideo -an -sn vid.sample.mkv"
+    );
+    assert_eq!(
+        cmd(ExtractCodec::H264Lossless),
+        "ffmpeg -y -i vid.mkv -ss 12.5 -frames:v 480 \
+         -c:v libx264 -qp 0 -preset ultrafast -an -sn vid.sample.mkv"
+    );
+
+    let cmd = intermediate_cmd(
+        Path::new("vid.mp4"),
+        ExtractCodec::H264Lossless,
+        Path::new("vid.lossless.h264.partial.mkv"),
+    );
+    assert_eq!(
+        cmd.to_cmd_str(),
+        "ffmpeg -y -i vid.mp4 -map 0:v:0 -c:v libx264 -qp 0 -preset ultrafast \
+         -an -sn vid.lossless.h264.partial.mkv"
+    );
+}
+
+#[test]
+fn lossless_intermediate_cmd() {
+    let cmd = intermediate_cmd(
+        Path::new("vid.mp4"),
+        ExtractCodec::Ffv1,
+        Path::new("vid.lossless.partial.mkv"),
+    );
+    assert_eq!(
+        cmd.to_cmd_str(),
+        "ffmpeg -y -i vid.mp4 -map 0:v:0 -c:v ffv1 -level 3 -an -sn vid.lossless.partial.mkv"
+    );
+}
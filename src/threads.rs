@@ -0,0 +1,104 @@
+//! `--threads` budget logic.
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    thread,
+};
+
+static THREADS: OnceLock<usize> = OnceLock::new();
+static NO_AUTO: AtomicBool = AtomicBool::new(false);
+
+/// Set the `--threads` budget.
+pub fn set_budget(threads: usize) {
+    _ = THREADS.set(threads.max(1));
+}
+
+/// Returns the `--threads` budget, or all available system threads if not set.
+pub fn budget() -> usize {
+    THREADS
+        .get()
+        .copied()
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |p| p.get()))
+}
+
+/// Disable automatic thread maximization, i.e. `--no-auto-threads`.
+pub fn disable_auto() {
+    NO_AUTO.store(true, Ordering::Relaxed);
+}
+
+/// Returns the [`budget`] to default thread args to, or `None` with `--no-auto-threads`
+/// leaving encoder & libvmaf defaults.
+pub fn auto_budget() -> Option<usize> {
+    (!NO_AUTO.load(Ordering::Relaxed)).then(budget)
+}
+
+/// Phases with differing thread requirements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Sample encoding & VMAF analysis.
+    Search,
+    /// Final encode.
+    Encode,
+}
+
+/// Per-phase thread allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Split {
+    pub encoder: usize,
+    pub vmaf: usize,
+}
+
+impl Split {
+    /// Allocate `total` threads for `phase`.
+    pub fn new(phase: Phase, total: usize) -> Self {
+        let total = total.max(1);
+        match phase {
+            Phase::Search => {
+                let encoder = (total / 2).max(1);
+                Self {
+                    encoder,
+                    vmaf: total.saturating_sub(encoder).max(1),
+                }
+            }
+            Phase::Encode => Self {
+                encoder: total,
+                vmaf: total,
+            },
+        }
+    }
+}
+
+/// Returns the allocation for `phase` if `--threads` was set, otherwise phases use
+/// their own defaults.
+pub fn split(phase: Phase) -> Option<Split> {
+    THREADS.get().map(|total| Split::new(phase, *total))
+}
+
+#[test]
+fn phase_splits() {
+    assert_eq!(
+        Split::new(Phase::Search, 16),
+        Split {
+            encoder: 8,
+            vmaf: 8
+        }
+    );
+    assert_eq!(
+        Split::new(Phase::Search, 7),
+        Split {
+            encoder: 3,
+            vmaf: 4
+        }
+    );
+    assert_eq!(
+        Split::new(Phase::Search, 1),
+        Split {
+            encoder: 1,
+            vmaf: 1
+        }
+    );
+    assert_eq!(Split::new(Phase::Encode, 16).encoder, 16);
+    assert_eq!(Split::new(Phase::Encode, 0).encoder, 1);
+}
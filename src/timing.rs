@@ -0,0 +1,113 @@
+//! Wall time spent in each phase, e.g. for `auto-encode --phase-times`.
+use indicatif::HumanDuration;
+use std::{fmt, sync::Mutex, time::Duration};
+
+/// Total time spent waiting for sample copies, see [`add_sampling`].
+static SAMPLING: Mutex<Duration> = Mutex::new(Duration::ZERO);
+
+/// Add time spent waiting for a sample copy to be ready for encoding.
+pub fn add_sampling(waited: Duration) {
+    *SAMPLING.lock().unwrap() += waited;
+}
+
+/// Returns the total time spent waiting for sample copies so far.
+pub fn sampling() -> Duration {
+    *SAMPLING.lock().unwrap()
+}
+
+/// Wall time breakdown of an auto-encode.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PhaseTimes {
+    /// Input probing & preparation.
+    pub probing: Duration,
+    /// Waiting for sample copies.
+    pub sampling: Duration,
+    /// Sample encoding & VMAF analysis, excluding `sampling`.
+    pub searching: Duration,
+    /// Number of crf probes, i.e. sample encode attempts.
+    pub probes: usize,
+    pub encoding: Duration,
+}
+
+impl PhaseTimes {
+    /// Add a crf search taking `elapsed` wall time, of which `sampled` was spent
+    /// waiting for samples.
+    pub fn add_search(&mut self, elapsed: Duration, sampled: Duration, probes: usize) {
+        let sampled = sampled.min(elapsed);
+        self.sampling += sampled;
+        self.searching += elapsed - sampled;
+        self.probes += probes;
+    }
+
+    pub fn total(&self) -> Duration {
+        self.probing + self.sampling + self.searching + self.encoding
+    }
+}
+
+impl fmt::Display for PhaseTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            probing,
+            sampling,
+            searching,
+            probes,
+            encoding,
+        } = self;
+        let probes_s = if *probes == 1 { "" } else { "s" };
+        write!(
+            f,
+            "probing {}, sampling {}, searching {} ({probes} probe{probes_s})",
+            HumanDuration(*probing),
+            HumanDuration(*sampling),
+            HumanDuration(*searching),
+        )?;
+        if !encoding.is_zero() {
+            write!(f, ", encoding {}", HumanDuration(*encoding))?;
+        }
+        write!(f, ", total {}", HumanDuration(self.total()))
+    }
+}
+
+#[test]
+fn phase_times_accumulate() {
+    let secs = Duration::from_secs;
+    let mut times = PhaseTimes {
+        probing: secs(2),
+        ..<_>::default()
+    };
+    // sweep of two searches
+    times.add_search(secs(60), secs(10), 4);
+    times.add_search(secs(30), secs(5), 2);
+    times.encoding += secs(600);
+
+    assert_eq!(
+        times,
+        PhaseTimes {
+            probing: secs(2),
+            sampling: secs(15),
+            searching: secs(75),
+            probes: 6,
+            encoding: secs(600),
+        }
+    );
+    assert_eq!(times.total(), secs(692));
+    assert_eq!(
+        times.to_string(),
+        "probing 2 seconds, sampling 15 seconds, searching 75 seconds (6 probes), \
+         encoding 10 minutes, total 12 minutes"
+    );
+}
+
+#[test]
+fn phase_times_sampling_within_search() {
+    let secs = Duration::from_secs;
+    let mut times = PhaseTimes::default();
+    // sampling wait cannot exceed the search wall time
+    times.add_search(secs(3), secs(5), 1);
+    assert_eq!(times.sampling, secs(3));
+    assert_eq!(times.searching, Duration::ZERO);
+    assert!(
+        times.to_string().ends_with("(1 probe), total 3 seconds"),
+        "{times}"
+    );
+}
@@ -0,0 +1,71 @@
+//! Tab separated values stdout output, rows of serializable result structs.
+use anyhow::Context;
+use serde::Serialize;
+
+/// Returns the header line for `fields`.
+pub fn header(fields: &[&str]) -> String {
+    fields.join("\t")
+}
+
+/// Returns a row of the `fields` of serializable `value`, a struct of scalars.
+///
+/// Tabs, newlines & backslashes in values are escaped as `\t`, `\n`, `\r` & `\\`.
+pub fn row(value: &impl Serialize, fields: &[&str]) -> anyhow::Result<String> {
+    // round trip via json string so f32s keep their short representation
+    let value: serde_json::Value = serde_json::from_str(&serde_json::to_string(value)?)?;
+    let cells = fields
+        .iter()
+        .map(|field| {
+            Ok(
+                match value
+                    .get(field)
+                    .with_context(|| format!("no tsv field `{field}`"))?
+                {
+                    serde_json::Value::String(s) => escape(s),
+                    serde_json::Value::Null => String::new(),
+                    v => v.to_string(),
+                },
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(cells.join("\t"))
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str(r"\\"),
+            '\t' => escaped.push_str(r"\t"),
+            '\n' => escaped.push_str(r"\n"),
+            '\r' => escaped.push_str(r"\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn tsv_row_escaping() {
+    #[derive(Serialize)]
+    struct Result {
+        vmaf: f32,
+        size: u64,
+        name: &'static str,
+        note: Option<&'static str>,
+    }
+    let result = Result {
+        vmaf: 95.12,
+        size: 1024,
+        name: "a\tb\\c\nd",
+        note: None,
+    };
+    let fields = ["name", "vmaf", "size", "note"];
+
+    assert_eq!(header(&fields), "name\tvmaf\tsize\tnote");
+    assert_eq!(
+        row(&result, &fields).unwrap(),
+        r"a\tb\\c\nd".to_owned() + "\t95.12\t1024\t"
+    );
+    assert!(row(&result, &["missing"]).is_err());
+}
@@ -1,20 +1,29 @@
 //! vmaf logic
-use crate::process::{cmd_err, exit_ok_stderr, Chunks, CommandExt, FfmpegOut};
+pub mod filtergraph;
+pub mod frames;
+
+use crate::{
+    command::args,
+    metric::Metric,
+    process::{self, cmd_err, exit_ok_stderr, Chunks, CommandExt, CpuList, FfmpegOut, StderrLog},
+};
 use anyhow::Context;
 use log::{debug, info};
-use std::path::Path;
+use std::{fmt, path::Path, pin::pin, time::Duration};
 use tokio::process::Command;
 use tokio_process_stream::{Item, ProcessChunkStream};
 use tokio_stream::{Stream, StreamExt};
 
-/// Calculate VMAF score by converting the original first to yuv.
+/// Calculate VMAF, or another `metric`, score by converting the original first to yuv.
 /// This can produce more accurate results than testing directly from original source.
 pub fn run(
+    metric: impl Metric + 'static,
     reference: &Path,
     distorted: &Path,
     filter_complex: &str,
     distorted_input_args: &[&str],
     reference_input_args: &[&str],
+    cpu_affinity: Option<&CpuList>,
 ) -> anyhow::Result<impl Stream<Item = VmafOut>> {
     info!(
         "vmaf {} vs reference {}",
@@ -31,6 +40,9 @@ pub fn run(
         .arg2("-filter_complex", filter_complex)
         .arg2("-f", "null")
         .arg("-");
+    if let Some(cpus) = cpu_affinity {
+        process::set_cpu_affinity(&mut cmd, cpus)?;
+    }
 
     let cmd_str = cmd.to_cmd_str();
     debug!("cmd `{cmd_str}`");
@@ -39,11 +51,13 @@ pub fn run(
     Ok(async_stream::stream! {
         let mut vmaf = vmaf;
         let mut chunks = Chunks::default();
+        let mut log = StderrLog::new("vmaf");
         let mut parsed_done = false;
         while let Some(next) = vmaf.next().await {
             match next {
                 Item::Stderr(chunk) => {
-                    if let Some(out) = VmafOut::try_from_chunk(&chunk, &mut chunks) {
+                    log.write(&chunk);
+                    if let Some(out) = VmafOut::try_from_chunk(&chunk, &mut chunks, &metric) {
                         if matches!(out, VmafOut::Done(_)) {
                             parsed_done = true;
                         }
@@ -68,6 +82,92 @@ pub fn run(
     })
 }
 
+/// Returns true if the ffmpeg vmaf error indicates running out of memory.
+pub fn is_oom(err: &anyhow::Error) -> bool {
+    const OOM_MESSAGES: &[&str] = &[
+        "cannot allocate memory",
+        "out of memory",
+        "memory allocation failed",
+        "failed to allocate",
+        "std::bad_alloc",
+    ];
+    let err = format!("{err:#}").to_ascii_lowercase();
+    OOM_MESSAGES.iter().any(|m| err.contains(m))
+}
+
+/// Returns `vmaf` args with halved `n_threads` to retry with if `err` is an out of memory
+/// failure and thread count can be reduced further.
+pub fn oom_retry(vmaf: &args::Vmaf, err: &anyhow::Error) -> Option<args::Vmaf> {
+    if !is_oom(err) {
+        return None;
+    }
+    vmaf.with_halved_n_threads()
+}
+
+/// Returns `vmaf` args with halved `n_threads` to retry `err` with. For out of memory
+/// failures or an [`AnalysisOverrun`] with `--analysis-overrun retry`.
+pub fn retry(vmaf: &args::Vmaf, err: &anyhow::Error) -> Option<args::Vmaf> {
+    match err.downcast_ref::<AnalysisOverrun>() {
+        Some(_) if vmaf.analysis_overrun == args::AnalysisOverrunAction::Retry => {
+            vmaf.with_halved_n_threads()
+        }
+        Some(_) => None,
+        None => oom_retry(vmaf, err),
+    }
+}
+
+/// Analysis ran longer than `--max-analysis-duration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalysisOverrun(pub Duration);
+
+impl fmt::Display for AnalysisOverrun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "vmaf analysis exceeded --max-analysis-duration {}",
+            humantime::format_duration(self.0)
+        )
+    }
+}
+
+impl std::error::Error for AnalysisOverrun {}
+
+/// Ends `vmaf_out` with an [`AnalysisOverrun`] error once it has run longer than `cap`,
+/// dropping the ffmpeg process.
+pub fn cap_duration(
+    vmaf_out: impl Stream<Item = VmafOut>,
+    cap: Option<Duration>,
+) -> impl Stream<Item = VmafOut> {
+    async_stream::stream! {
+        let mut vmaf_out = pin!(vmaf_out);
+        let deadline = cap.map(|cap| (cap, tokio::time::Instant::now() + cap));
+        loop {
+            let next = match deadline {
+                Some((cap, deadline)) => {
+                    match tokio::time::timeout_at(deadline, vmaf_out.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            yield VmafOut::Err(AnalysisOverrun(cap).into());
+                            break;
+                        }
+                    }
+                }
+                None => vmaf_out.next().await,
+            };
+            match next {
+                Some(out) => yield out,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Filter instance name of the `--center-crop` libvmaf, e.g. "libvmaf@center".
+pub const CENTER_CROP_INSTANCE: &str = "center";
+
+/// Filter instance name of the `--scale-impact` unscaled libvmaf.
+pub const UNSCALED_INSTANCE: &str = "unscaled";
+
 #[derive(Debug)]
 pub enum VmafOut {
     Progress(FfmpegOut),
@@ -76,16 +176,11 @@ pub enum VmafOut {
 }
 
 impl VmafOut {
-    fn try_from_chunk(chunk: &[u8], chunks: &mut Chunks) -> Option<Self> {
-        const VMAF_SCORE_PRE: &str = "VMAF score: ";
-
+    fn try_from_chunk(chunk: &[u8], chunks: &mut Chunks, metric: &impl Metric) -> Option<Self> {
         chunks.push(chunk);
 
-        if let Some(line) = chunks.rfind_line(|l| l.contains(VMAF_SCORE_PRE)) {
-            let idx = line.find(VMAF_SCORE_PRE).unwrap();
-            return Some(Self::Done(
-                line[idx + VMAF_SCORE_PRE.len()..].trim().parse().ok()?,
-            ));
+        if let Some(line) = chunks.rfind_line(|l| metric.parse_score(l).is_some()) {
+            return metric.parse_score(line).map(Self::Done);
         }
         if let Some(progress) = FfmpegOut::try_parse(chunks.last_line()) {
             return Some(Self::Progress(progress));
@@ -190,7 +285,8 @@ frame=  600 fps=102 q=-0.0 Lsize=N/A time=00:00:24.95 bitrate=N/A speed=4.24x"#;
             let chunk = &ffmpeg[start_idx..(start_idx + CHUNK_SIZE).min(FFMPEG_OUT.len())];
             println!("* {}", String::from_utf8_lossy(chunk).trim());
 
-            if let Some(vmaf) = VmafOut::try_from_chunk(chunk, &mut chunks) {
+            if let Some(vmaf) = VmafOut::try_from_chunk(chunk, &mut chunks, &args::Vmaf::default())
+            {
                 println!("{vmaf:?}");
                 if let VmafOut::Done(score) = vmaf {
                     vmaf_score = Some(score);
@@ -202,4 +298,110 @@ frame=  600 fps=102 q=-0.0 Lsize=N/A time=00:00:24.95 bitrate=N/A speed=4.24x"#;
 
         assert_eq!(vmaf_score, Some(94.82638), "failed to parse vmaf score");
     }
+
+    #[test]
+    fn parse_vmaf_score_ignores_center_crop() {
+        let mut chunks = Chunks::default();
+        let out = VmafOut::try_from_chunk(
+            b"[Parsed_libvmaf_8 @ 0x5a] VMAF score: 95.120000\n\
+              [libvmaf@center @ 0x5b] VMAF score: 91.500000\n\
+              [libvmaf@unscaled @ 0x5c] VMAF score: 93.000000\n",
+            &mut chunks,
+            &args::Vmaf::default(),
+        );
+        assert!(
+            matches!(out, Some(VmafOut::Done(s)) if s == 95.12),
+            "{out:?}"
+        );
+    }
+
+    #[test]
+    fn parse_other_metric_score() {
+        use crate::command::args::PixelFormat;
+
+        struct MockMetric;
+        impl Metric for MockMetric {
+            fn lavfi(&self, _: Option<(u32, u32)>, _: PixelFormat, _: Option<&str>) -> String {
+                "[0:v][1:v]mock".into()
+            }
+            fn parse_score(&self, line: &str) -> Option<f32> {
+                line.strip_prefix("MOCK score: ")?.parse().ok()
+            }
+        }
+
+        let mut chunks = Chunks::default();
+        let out = VmafOut::try_from_chunk(
+            b"[Parsed_libvmaf_8 @ 0x5a] VMAF score: 95.120000\nMOCK score: 42.5\n",
+            &mut chunks,
+            &MockMetric,
+        );
+        assert!(
+            matches!(out, Some(VmafOut::Done(score)) if score == 42.5),
+            "{out:?}"
+        );
+    }
+
+    #[test]
+    fn detect_oom() {
+        let oom = anyhow::anyhow!(
+            "ffmpeg vmaf exit code 234\n---stderr---\n[Parsed_libvmaf_6 @ 0x5a] \
+             problem initializing VMAF context\nError initializing filter 'libvmaf': \
+             Cannot allocate memory\n------------"
+        );
+        assert!(is_oom(&oom));
+        assert!(is_oom(&anyhow::anyhow!(
+            "terminate called after throwing an instance of 'std::bad_alloc'"
+        )));
+        assert!(!is_oom(&anyhow::anyhow!(
+            "ffmpeg vmaf exit code 1\n---stderr---\nNo such file or directory"
+        )));
+
+        let vmaf = args::Vmaf {
+            vmaf_args: vec!["n_threads=2".into()],
+            ..<_>::default()
+        };
+        let retry = oom_retry(&vmaf, &oom).expect("retry");
+        assert_eq!(retry.n_threads(), Some(1));
+        assert!(oom_retry(&retry, &oom).is_none(), "no retry below 1 thread");
+        assert!(oom_retry(&vmaf, &anyhow::anyhow!("other")).is_none());
+    }
+
+    #[tokio::test]
+    async fn max_analysis_duration_overrun() {
+        let cap = Some(Duration::from_millis(20));
+
+        let done = cap_duration(tokio_stream::iter([VmafOut::Done(95.0)]), cap);
+        let out: Vec<_> = done.collect().await;
+        assert!(matches!(out[..], [VmafOut::Done(s)] if s == 95.0));
+
+        let stalled = cap_duration(
+            tokio_stream::iter([VmafOut::Progress(FfmpegOut::StreamSizes {
+                video: 0,
+                audio: 0,
+                subtitle: 0,
+                other: 0,
+            })])
+            .chain(tokio_stream::pending()),
+            cap,
+        );
+        let out: Vec<_> = stalled.collect().await;
+        let [VmafOut::Progress(_), VmafOut::Err(err)] = &out[..] else {
+            panic!("expected progress then overrun");
+        };
+        assert_eq!(
+            err.downcast_ref::<AnalysisOverrun>(),
+            Some(&AnalysisOverrun(Duration::from_millis(20)))
+        );
+
+        let vmaf = args::Vmaf {
+            vmaf_args: vec!["n_threads=4".into()],
+            ..<_>::default()
+        };
+        assert!(retry(&vmaf, err).is_none(), "abort by default");
+        let vmaf = args::Vmaf {
+            analysis_overrun: args::AnalysisOverrunAction::Retry,
+            ..vmaf
+        };
+        assert_eq!(retry(&vmaf, err).and_then(|r| r.n_threads()), Some(2));
+    }
 }
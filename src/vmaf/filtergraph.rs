@@ -0,0 +1,247 @@
+//! lavfi filtergraph parsing & graphviz dot export
+use std::fmt::Write;
+
+/// Filtergraph of filter & input pad nodes linked by edges.
+#[derive(Debug, Default, PartialEq)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    /// Stream input pad not produced by a filter, e.g. `0:v`.
+    Input(String),
+    /// Output pad not consumed by a filter.
+    Output(String),
+    Filter {
+        name: String,
+        args: Option<String>,
+    },
+}
+
+/// Link between node indexes, with the pad label if any.
+#[derive(Debug, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub label: Option<String>,
+}
+
+impl Graph {
+    /// Parse a lavfi filtergraph, e.g. `[0:v]format=yuv420p[dis];[dis][1:v]libvmaf`.
+    pub fn parse(lavfi: &str) -> Self {
+        let mut graph = Self::default();
+        // label -> producing node
+        let mut produced: Vec<(String, usize)> = vec![];
+        // (label, consuming node)
+        let mut consumed: Vec<(String, usize)> = vec![];
+
+        for chain in split_unquoted(lavfi, ';') {
+            let filters = split_unquoted(chain, ',');
+            let last = filters.len().saturating_sub(1);
+            let mut prev = None;
+            for (idx, filter) in filters.into_iter().enumerate() {
+                let (inputs, filter) = take_labels(filter.trim());
+                let (filter, outputs) = match idx == last {
+                    true => take_trailing_labels(filter),
+                    false => (filter, vec![]),
+                };
+                let (name, args) = match filter.split_once('=') {
+                    Some((name, args)) => (name.into(), Some(args.into())),
+                    None => (filter.into(), None),
+                };
+                let node = graph.nodes.len();
+                graph.nodes.push(Node::Filter { name, args });
+                if let Some(from) = prev {
+                    graph.edges.push(Edge {
+                        from,
+                        to: node,
+                        label: None,
+                    });
+                }
+                consumed.extend(inputs.into_iter().map(|l| (l, node)));
+                produced.extend(outputs.into_iter().map(|l| (l, node)));
+                prev = Some(node);
+            }
+        }
+
+        for (label, to) in consumed {
+            let from = match produced.iter().position(|(l, _)| *l == label) {
+                Some(idx) => produced.remove(idx).1,
+                None => graph.add_node(Node::Input(label.clone())),
+            };
+            graph.edges.push(Edge {
+                from,
+                to,
+                label: Some(label),
+            });
+        }
+        for (label, from) in produced {
+            let to = graph.add_node(Node::Output(label.clone()));
+            graph.edges.push(Edge {
+                from,
+                to,
+                label: Some(label),
+            });
+        }
+        graph
+    }
+
+    /// Index of an existing equal node, otherwise of a newly added node.
+    fn add_node(&mut self, node: Node) -> usize {
+        if let Some(idx) = self.nodes.iter().position(|n| *n == node) {
+            return idx;
+        }
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Render as a graphviz dot digraph.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph filtergraph {\n    rankdir=LR;\n".to_owned();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let (label, shape) = match node {
+                Node::Input(pad) | Node::Output(pad) => (format!("[{pad}]"), "ellipse"),
+                Node::Filter { name, args: None } => (name.clone(), "box"),
+                Node::Filter {
+                    name,
+                    args: Some(args),
+                } => (format!("{name}\n{args}"), "box"),
+            };
+            writeln!(
+                dot,
+                "    n{idx} [label=\"{}\", shape={shape}];",
+                escape(&label)
+            )
+            .unwrap();
+        }
+        for Edge { from, to, label } in &self.edges {
+            match label {
+                Some(label) => writeln!(dot, "    n{from} -> n{to} [label=\"{}\"];", escape(label)),
+                None => writeln!(dot, "    n{from} -> n{to};"),
+            }
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Split on `sep` outside of `'` quotes & `\` escapes.
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\'' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..idx]);
+                start = idx + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts.retain(|p| !p.trim().is_empty());
+    parts
+}
+
+/// Take leading `[label]`s returning them & the remainder.
+fn take_labels(mut s: &str) -> (Vec<String>, &str) {
+    let mut labels = vec![];
+    while let Some((label, rest)) = s.strip_prefix('[').and_then(|s| s.split_once(']')) {
+        labels.push(label.into());
+        s = rest.trim_start();
+    }
+    (labels, s)
+}
+
+/// Take trailing `[label]`s returning the remainder & them.
+fn take_trailing_labels(mut s: &str) -> (&str, Vec<String>) {
+    let mut labels = vec![];
+    while let Some((rest, label)) = s.strip_suffix(']').and_then(|s| s.rsplit_once('[')) {
+        labels.insert(0, label.into());
+        s = rest.trim_end();
+    }
+    (s, labels)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn parse_vmaf_lavfi_nodes_edges() {
+    let graph = Graph::parse(
+        "[0:v]format=yuv420p,scale=1920:-1:flags=bicubic[dis];\
+         [1:v]format=yuv420p[ref];\
+         [dis][ref]hstack=shortest=1,select='gt(scene,0.3)',split[a][b];\
+         [a]crop=iw/2:ih:0:0[sdis];[b]crop=iw/2:ih:iw/2:0[sref];\
+         [sdis][sref]libvmaf=n_threads=4",
+    );
+    let filter = |name: &str, args: Option<&str>| Node::Filter {
+        name: name.into(),
+        args: args.map(Into::into),
+    };
+    assert_eq!(
+        graph.nodes,
+        [
+            filter("format", Some("yuv420p")),
+            filter("scale", Some("1920:-1:flags=bicubic")),
+            filter("format", Some("yuv420p")),
+            filter("hstack", Some("shortest=1")),
+            filter("select", Some("'gt(scene,0.3)'")),
+            filter("split", None),
+            filter("crop", Some("iw/2:ih:0:0")),
+            filter("crop", Some("iw/2:ih:iw/2:0")),
+            filter("libvmaf", Some("n_threads=4")),
+            Node::Input("0:v".into()),
+            Node::Input("1:v".into()),
+        ]
+    );
+    let edges: Vec<_> = graph
+        .edges
+        .iter()
+        .map(|e| (e.from, e.to, e.label.as_deref()))
+        .collect();
+    assert_eq!(
+        edges,
+        [
+            (0, 1, None),
+            (3, 4, None),
+            (4, 5, None),
+            (9, 0, Some("0:v")),
+            (10, 2, Some("1:v")),
+            (1, 3, Some("dis")),
+            (2, 3, Some("ref")),
+            (5, 6, Some("a")),
+            (5, 7, Some("b")),
+            (6, 8, Some("sdis")),
+            (7, 8, Some("sref")),
+        ]
+    );
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph filtergraph {"), "{dot}");
+    assert!(
+        dot.contains("n1 [label=\"scale\\n1920:-1:flags=bicubic\", shape=box];"),
+        "{dot}"
+    );
+    assert!(
+        dot.contains("n9 [label=\"[0:v]\", shape=ellipse];"),
+        "{dot}"
+    );
+    assert!(dot.contains("n1 -> n3 [label=\"dis\"];"), "{dot}");
+}
+
+#[test]
+fn parse_unconsumed_output_pad() {
+    let graph = Graph::parse("[0:v]null[out]");
+    assert_eq!(graph.nodes[2], Node::Output("out".into()));
+    assert_eq!(graph.edges.len(), 2);
+}
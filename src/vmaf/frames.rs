@@ -0,0 +1,429 @@
+//! libvmaf per-frame log parsing & analysis
+use crate::{
+    command::args,
+    temporary::{self, TempKind},
+};
+use anyhow::{ensure, Context};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Returns libvmaf args to write a json per-frame log to `path`.
+pub fn log_args(path: &Path) -> [String; 2] {
+    // quote at filtergraph level & escape ':' at the option level
+    let path = path
+        .to_string_lossy()
+        .replace('\\', "/")
+        .replace(':', "\\:");
+    [format!("log_path='{path}'"), "log_fmt=json".into()]
+}
+
+/// Returns the libvmaf json log path to read scores from, adding temporary
+/// log args if the user hasn't configured a log.
+pub fn json_log_path(vmaf: &mut args::Vmaf) -> anyhow::Result<PathBuf> {
+    let user_log = vmaf
+        .vmaf_args
+        .iter()
+        .find_map(|a| a.strip_prefix("log_path="));
+    if let Some(path) = user_log {
+        ensure!(
+            vmaf.vmaf_args.iter().any(|a| &**a == "log_fmt=json"),
+            "log_fmt=json is required when using a custom log_path with --histogram, --metric-blend or --extra-metric"
+        );
+        return Ok(path.trim_matches('\'').into());
+    }
+
+    let path = temporary::process_dir(None).join("vmaf-frames.json");
+    temporary::add(&path, TempKind::NotKeepable);
+    vmaf.vmaf_args.extend(log_args(&path).map(Into::into));
+    Ok(path)
+}
+
+/// Parse the pooled mean score of each model from a libvmaf json log.
+pub fn parse_pooled_means(json: &str) -> anyhow::Result<HashMap<String, f64>> {
+    #[derive(Deserialize)]
+    struct Log {
+        pooled_metrics: HashMap<String, Pooled>,
+    }
+    #[derive(Deserialize)]
+    struct Pooled {
+        mean: f64,
+    }
+
+    let log: Log = serde_json::from_str(json).context("invalid libvmaf json log")?;
+    Ok(log
+        .pooled_metrics
+        .into_iter()
+        .map(|(name, p)| (name, p.mean))
+        .collect())
+}
+
+/// Read a libvmaf json log file & return the `--metric-blend` score.
+pub fn read_blended_score(path: &Path, blend: &args::MetricBlend) -> anyhow::Result<f32> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read vmaf log {}", path.display()))?;
+    blend.score(&parse_pooled_means(&json)?)
+}
+
+/// Read a libvmaf json log file & return the pooled mean of metric `name`, e.g. "psnr_y".
+pub fn read_pooled_metric(path: &Path, name: &str) -> anyhow::Result<f32> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read vmaf log {}", path.display()))?;
+    let score = parse_pooled_means(&json)?
+        .get(name)
+        .copied()
+        .with_context(|| format!("no pooled {name} score in log"))?;
+    Ok(score as f32)
+}
+
+/// Read a libvmaf json log file & return the pooled `vmaf` model score,
+/// or the `--metric-blend` score if set.
+pub fn read_pooled_score(path: &Path, blend: Option<&args::MetricBlend>) -> anyhow::Result<f32> {
+    if let Some(blend) = blend {
+        return read_blended_score(path, blend);
+    }
+    read_pooled_metric(path, "vmaf")
+}
+
+/// Parse per-frame vmaf scores, in frame order, from a libvmaf json log.
+pub fn parse_json_log(json: &str) -> anyhow::Result<Vec<f64>> {
+    Ok(parse_json_log_frames(json)?
+        .into_iter()
+        .map(|(_, score)| score)
+        .collect())
+}
+
+/// Parse per-frame `(frameNum, vmaf)` scores, in frame order, from a libvmaf json log.
+pub fn parse_json_log_frames(json: &str) -> anyhow::Result<Vec<(u64, f64)>> {
+    #[derive(Deserialize)]
+    struct Log {
+        frames: Vec<Frame>,
+    }
+    #[derive(Deserialize)]
+    struct Frame {
+        #[serde(rename = "frameNum")]
+        frame_num: u64,
+        metrics: Metrics,
+    }
+    #[derive(Deserialize)]
+    struct Metrics {
+        vmaf: f64,
+    }
+
+    let mut log: Log = serde_json::from_str(json).context("invalid libvmaf json log")?;
+    log.frames.sort_by_key(|f| f.frame_num);
+    Ok(log
+        .frames
+        .into_iter()
+        .map(|f| (f.frame_num, f.metrics.vmaf))
+        .collect())
+}
+
+/// Read & parse a libvmaf json log file.
+pub fn read_json_log(path: &Path) -> anyhow::Result<Vec<f64>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read vmaf log {}", path.display()))?;
+    parse_json_log(&json)
+}
+
+/// Read & parse a libvmaf json log file into `(frameNum, vmaf)` scores.
+pub fn read_json_log_frames(path: &Path) -> anyhow::Result<Vec<(u64, f64)>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read vmaf log {}", path.display()))?;
+    parse_json_log_frames(&json)
+}
+
+/// Returns the score below which `p` (0-1) of the scores fall, e.g. `0.01` for the 1% low.
+pub fn percentile_low(scores: &[f64], p: f64) -> Option<f64> {
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let idx = ((sorted.len() as f64 * p).ceil() as usize).saturating_sub(1);
+    sorted.get(idx).copied()
+}
+
+/// Per-frame vmaf distribution in fixed width bins.
+#[derive(Debug, PartialEq)]
+pub struct Histogram {
+    /// Score width of each bin.
+    pub width: f64,
+    /// `(lower bound, frame count)` for each bin, ascending, from the lowest
+    /// scoring frame's bin up to 100.
+    pub bins: Vec<(f64, usize)>,
+    /// 1% low score.
+    pub low_1: f64,
+    /// 5% low score.
+    pub low_5: f64,
+}
+
+impl Histogram {
+    /// Bin `scores` into `width` wide bins. Returns `None` if there are no scores.
+    pub fn new(scores: &[f64], width: f64) -> Option<Self> {
+        let min = scores.iter().copied().reduce(f64::min)?;
+        let first = (min / width).floor() as i64;
+        let last = ((100.0 / width).ceil() as i64 - 1).max(first);
+
+        let mut bins: Vec<_> = (first..=last).map(|n| (n as f64 * width, 0)).collect();
+        for score in scores {
+            let idx = ((score / width).floor() as i64 - first).clamp(0, last - first);
+            bins[idx as usize].1 += 1;
+        }
+
+        Some(Self {
+            width,
+            bins,
+            low_1: percentile_low(scores, 0.01)?,
+            low_5: percentile_low(scores, 0.05)?,
+        })
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const BAR_WIDTH: usize = 40;
+        let max = self.bins.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+        for (lower, count) in self.bins.iter().rev() {
+            let bar = "█".repeat((count * BAR_WIDTH).div_ceil(max));
+            let tail = match *lower {
+                l if l <= self.low_1 => " <1% low",
+                l if l <= self.low_5 => " <5% low",
+                _ => "",
+            };
+            writeln!(
+                f,
+                "{lower:>5.1}-{:<5.1} {bar} {count}{tail}",
+                (lower + self.width).min(100.0)
+            )?;
+        }
+        write!(f, "1% low {:.2}, 5% low {:.2}", self.low_1, self.low_5)
+    }
+}
+
+/// A frame scoring differently to a golden per-frame log.
+#[derive(Debug, PartialEq)]
+pub struct FrameDivergence {
+    pub frame: usize,
+    pub score: f64,
+    pub golden: f64,
+}
+
+/// Compare per-frame `scores` to `golden` scores returning frames diverging by more than `tolerance`.
+pub fn golden_diff(
+    scores: &[f64],
+    golden: &[f64],
+    tolerance: f64,
+) -> anyhow::Result<Vec<FrameDivergence>> {
+    ensure!(
+        scores.len() == golden.len(),
+        "scored {} frames, golden has {}",
+        scores.len(),
+        golden.len()
+    );
+    Ok(scores
+        .iter()
+        .zip(golden)
+        .enumerate()
+        .filter(|(_, (score, golden))| (*score - *golden).abs() > tolerance)
+        .map(|(frame, (score, golden))| FrameDivergence {
+            frame,
+            score: *score,
+            golden: *golden,
+        })
+        .collect())
+}
+
+/// Vmaf of a fixed duration segment of frames.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    pub start: Duration,
+    pub mean: f64,
+    pub min: f64,
+}
+
+impl Segment {
+    /// Bucket per-frame `(frameNum, vmaf)` scores into `segment` duration segments
+    /// by frame time `frameNum / fps`. `frames` must be in frame order.
+    pub fn from_frames(
+        frames: &[(u64, f64)],
+        fps: f64,
+        segment: Duration,
+    ) -> anyhow::Result<Vec<Self>> {
+        ensure!(fps > 0.0, "unknown frame rate");
+        ensure!(!segment.is_zero(), "segment duration must be non-zero");
+        let frames_per_segment = segment.as_secs_f64() * fps;
+        // small tolerance so frames exactly on a boundary aren't lost to float error
+        let segment_idx = |frame_num: u64| (frame_num as f64 / frames_per_segment + 1e-9) as u32;
+
+        let mut segments: Vec<(u32, Vec<f64>)> = vec![];
+        for &(frame_num, score) in frames {
+            let idx = segment_idx(frame_num);
+            match segments.last_mut() {
+                Some((last, scores)) if *last == idx => scores.push(score),
+                _ => segments.push((idx, vec![score])),
+            }
+        }
+        Ok(segments
+            .into_iter()
+            .map(|(idx, scores)| Self {
+                start: segment * idx,
+                mean: scores.iter().sum::<f64>() / scores.len() as f64,
+                min: scores.iter().copied().fold(f64::INFINITY, f64::min),
+            })
+            .collect())
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>8} mean {:.2}, min {:.2}",
+            humantime::format_duration(self.start).to_string(),
+            self.mean,
+            self.min
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_log() {
+        let json = r#"{
+          "version": "3.0.0",
+          "frames": [
+            {"frameNum": 1, "metrics": {"integer_motion": 1.2, "vmaf": 93.5}},
+            {"frameNum": 0, "metrics": {"integer_motion": 0.0, "vmaf": 97.25}}
+          ],
+          "pooled_metrics": {"vmaf": {"min": 93.5, "max": 97.25, "mean": 95.375}}
+        }"#;
+        assert_eq!(parse_json_log(json).unwrap(), [97.25, 93.5]);
+    }
+
+    #[test]
+    fn parse_pooled() {
+        let json = r#"{
+          "frames": [],
+          "pooled_metrics": {
+            "vmaf": {"min": 93.5, "max": 97.25, "mean": 95.375},
+            "vmaf_neg": {"min": 90.0, "max": 94.0, "mean": 92.5}
+          }
+        }"#;
+        let means = parse_pooled_means(json).unwrap();
+        assert_eq!(means["vmaf"], 95.375);
+        assert_eq!(means["vmaf_neg"], 92.5);
+    }
+
+    #[test]
+    fn histogram_bins() {
+        let mut scores = vec![99.0; 90];
+        scores.extend([96.5; 5]);
+        scores.extend([91.0, 91.9, 90.0, 86.0, 82.3]);
+
+        let hist = Histogram::new(&scores, 5.0).unwrap();
+        assert_eq!(
+            hist.bins,
+            [(80.0, 1), (85.0, 1), (90.0, 3), (95.0, 95)],
+            "{hist:?}"
+        );
+        assert_eq!(hist.low_1, 82.3);
+        assert_eq!(hist.low_5, 91.9);
+
+        // a perfect score lands in the top bin
+        let hist = Histogram::new(&[100.0, 98.0], 5.0).unwrap();
+        assert_eq!(hist.bins, [(95.0, 2)]);
+
+        assert_eq!(Histogram::new(&[], 5.0), None);
+    }
+
+    #[test]
+    fn golden_comparison() {
+        let golden = [97.25, 93.5, 95.0, 96.0];
+
+        let within = [97.3, 93.45, 95.0, 96.09];
+        assert_eq!(golden_diff(&within, &golden, 0.1).unwrap(), []);
+
+        let drifted = [97.25, 92.0, 95.0, 96.5];
+        assert_eq!(
+            golden_diff(&drifted, &golden, 0.1).unwrap(),
+            [
+                FrameDivergence {
+                    frame: 1,
+                    score: 92.0,
+                    golden: 93.5
+                },
+                FrameDivergence {
+                    frame: 3,
+                    score: 96.5,
+                    golden: 96.0
+                },
+            ]
+        );
+        assert_eq!(golden_diff(&drifted, &golden, 2.0).unwrap(), []);
+
+        // frame count mismatch
+        assert!(golden_diff(&golden[1..], &golden, 0.1).is_err());
+    }
+
+    #[test]
+    fn segment_aggregation() {
+        // 4 fps, 1s segments
+        let scores = [96.0, 94.0, 98.0, 92.0, 80.0, 90.0, 85.0, 85.0, 99.0, 97.0];
+        let frames: Vec<_> = (0..).zip(scores).collect();
+        let segments = Segment::from_frames(&frames, 4.0, Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            segments,
+            [
+                Segment {
+                    start: Duration::ZERO,
+                    mean: 95.0,
+                    min: 92.0
+                },
+                Segment {
+                    start: Duration::from_secs(1),
+                    mean: 85.0,
+                    min: 80.0
+                },
+                // partial final segment
+                Segment {
+                    start: Duration::from_secs(2),
+                    mean: 98.0,
+                    min: 97.0
+                },
+            ]
+        );
+        assert_eq!(segments[1].to_string(), "      1s mean 85.00, min 80.00");
+
+        // 23.976 fps 6s segments, boundaries at frames 144 & 288 (not 143.856 rounded)
+        let frames: Vec<_> = (0..300)
+            .map(|n| (n, if n < 144 { 95.0 } else { 90.0 }))
+            .collect();
+        let segments =
+            Segment::from_frames(&frames, 24000.0 / 1001.0, Duration::from_secs(6)).unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!((segments[0].mean, segments[1].min), (95.0, 90.0));
+
+        // subsampled frames are bucketed by frameNum, not position
+        let frames = [(0, 90.0), (4, 92.0), (8, 80.0), (12, 82.0)];
+        let segments = Segment::from_frames(&frames, 8.0, Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            segments.iter().map(|s| s.mean).collect::<Vec<_>>(),
+            [91.0, 81.0]
+        );
+
+        assert!(Segment::from_frames(&frames, 0.0, Duration::from_secs(6)).is_err());
+    }
+
+    #[test]
+    fn lavfi_log_args() {
+        assert_eq!(
+            log_args(Path::new("C:\\tmp\\frames.json")),
+            ["log_path='C\\:/tmp/frames.json'", "log_fmt=json"]
+        );
+    }
+}